@@ -7,26 +7,35 @@
 
 //! This benchmark generates linear stack with specified parameters, and then
 //! measures how log it takes to convert it from Bonsai to Hg.
+//!
+//! With `--profile` it generates a history shaped by the profile instead, and
+//! reports how long it takes to derive each type of derived data as JSON.
+//!
+//! With one of the `--replay-*` options it replays the history of an existing
+//! git, hg or Mononoke repository instead, and reports percentiles of the time
+//! it takes to ingest each commit and to derive data for it as JSON.
 
 #![deny(warnings)]
 
 use anyhow::{bail, format_err, Error, Result};
-use benchmark_lib::{new_benchmark_repo, GenManifest};
+use benchmark_lib::{
+    gen_history, new_benchmark_repo, replay, DeriveFn, GenManifest, GenProfile, ReplaySource,
+};
 use blobrepo::BlobRepo;
-use clap::{App, Arg};
+use clap::{Arg, ArgGroup};
 use cmdlib::args;
 use context::CoreContext;
 use derived_data::BonsaiDerived;
 use fbinit::FacebookInit;
 use fsnodes::RootFsnodeId;
 use futures::compat::Future01CompatExt;
-use futures_ext::{BoxFuture as OldBoxFuture, FutureExt as OldFutureExt};
+use futures_ext::FutureExt as OldFutureExt;
 use futures_old::Future;
 use futures_stats::futures03::TimedFutureExt;
-use mononoke_types::ChangesetId;
 use rand::SeedableRng;
 use rand_xorshift::XorShiftRng;
-use std::sync::Arc;
+use serde_json::json;
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
 use tokio_compat::runtime::Runtime;
 use unodes::RootUnodeManifestId;
 
@@ -34,8 +43,17 @@ const HG_CHANGESET_TYPE: &str = "hg-changeset";
 const ARG_SEED: &str = "seed";
 const ARG_TYPE: &str = "type";
 const ARG_STACK_SIZE: &str = "stack-size";
-
-type DeriveFn = Arc<dyn Fn(ChangesetId) -> OldBoxFuture<String, Error> + Send + Sync + 'static>;
+const ARG_PROFILE: &str = "profile";
+const ARG_REPLAY_GIT: &str = "replay-git";
+const ARG_REPLAY_HG: &str = "replay-hg";
+const ARG_REPLAY_MONONOKE: &str = "replay-mononoke";
+const ARG_REPLAY_RANGE: &str = "replay-range";
+const ARG_GROUP_SOURCE: &str = "source";
+const DERIVED_TYPES: &[&str] = &[
+    HG_CHANGESET_TYPE,
+    RootUnodeManifestId::NAME,
+    RootFsnodeId::NAME,
+];
 
 async fn run(
     ctx: CoreContext,
@@ -70,6 +88,80 @@ async fn run(
     Ok(())
 }
 
+async fn run_profile(
+    ctx: CoreContext,
+    repo: BlobRepo,
+    rng_seed: u64,
+    profile: GenProfile,
+    derive_types: Vec<&str>,
+) -> Result<(), Error> {
+    let mut rng = XorShiftRng::seed_from_u64(rng_seed); // reproducable Rng
+
+    let (gen_stats, history) = gen_history(ctx.clone(), repo.clone(), &mut rng, &profile)
+        .timed()
+        .await;
+    let history = history?;
+
+    let mut derived = BTreeMap::new();
+    for derive_type in derive_types {
+        let derive = derive_fn(ctx.clone(), repo.clone(), Some(derive_type))?;
+        let (stats, result) = async {
+            for csid in history.heads.iter() {
+                derive(*csid).compat().await?;
+            }
+            Ok::<_, Error>(())
+        }
+        .timed()
+        .await;
+        result?;
+        derived.insert(
+            derive_type,
+            json!({
+                "total_ms": as_millis(stats.completion_time),
+                "per_commit_ms": as_millis(stats.completion_time) / history.commits.max(1) as f64,
+            }),
+        );
+    }
+
+    let report = json!({
+        "seed": rng_seed,
+        "profile": profile,
+        "history": history,
+        "generation_ms": as_millis(gen_stats.completion_time),
+        "derived": derived,
+    });
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    Ok(())
+}
+
+async fn run_replay(
+    ctx: CoreContext,
+    repo: BlobRepo,
+    source: ReplaySource,
+    range: Option<&str>,
+    derive_types: Vec<&str>,
+) -> Result<(), Error> {
+    let derive_types = derive_types
+        .into_iter()
+        .map(|derive_type| {
+            let derive = derive_fn(ctx.clone(), repo.clone(), Some(derive_type))?;
+            Ok((derive_type, derive))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let report = replay(ctx, repo, &source, range, &derive_types).await?;
+    let report = json!({
+        "range": range,
+        "replay": report,
+    });
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    Ok(())
+}
+
+fn as_millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
 fn derive_fn(ctx: CoreContext, repo: BlobRepo, derive_type: Option<&str>) -> Result<DeriveFn> {
     match derive_type {
         None => bail!("required `type` argument is missing"),
@@ -105,37 +197,73 @@ fn derive_fn(ctx: CoreContext, repo: BlobRepo, derive_type: Option<&str>) -> Res
 
 #[fbinit::main]
 fn main(fb: FacebookInit) -> Result<()> {
-    let app = {
-        let app = App::new("mononoke benchmark")
-            .arg(
-                Arg::with_name(ARG_SEED)
-                    .short("s")
-                    .long(ARG_SEED)
-                    .takes_value(true)
-                    .value_name(ARG_SEED)
-                    .help("seed changeset generator for u64 seed"),
-            )
-            .arg(
-                Arg::with_name(ARG_STACK_SIZE)
-                    .long(ARG_STACK_SIZE)
-                    .takes_value(true)
-                    .value_name(ARG_STACK_SIZE)
-                    .help("Size of the generated stack"),
-            )
-            .arg(
-                Arg::with_name(ARG_TYPE)
-                    .required(true)
-                    .index(1)
-                    .possible_values(&[
-                        HG_CHANGESET_TYPE,
-                        RootUnodeManifestId::NAME,
-                        RootFsnodeId::NAME,
-                    ])
-                    .help("derived data type"),
-            );
-        let app = args::add_logger_args(app);
-        args::add_cachelib_args(app, true /* hide_advanced_args */)
-    };
+    let app = args::MononokeApp::new("mononoke benchmark")
+        .with_advanced_args_hidden()
+        .build()
+        .arg(
+            Arg::with_name(ARG_SEED)
+                .short("s")
+                .long(ARG_SEED)
+                .takes_value(true)
+                .value_name(ARG_SEED)
+                .help("seed changeset generator for u64 seed"),
+        )
+        .arg(
+            Arg::with_name(ARG_STACK_SIZE)
+                .long(ARG_STACK_SIZE)
+                .takes_value(true)
+                .value_name(ARG_STACK_SIZE)
+                .help("Size of the generated stack"),
+        )
+        .arg(
+            Arg::with_name(ARG_PROFILE)
+                .long(ARG_PROFILE)
+                .takes_value(true)
+                .value_name("PATH")
+                .help("JSON file with the profile of the history to generate"),
+        )
+        .arg(
+            Arg::with_name(ARG_REPLAY_GIT)
+                .long(ARG_REPLAY_GIT)
+                .takes_value(true)
+                .value_name("PATH")
+                .help("replay the history of a git repository"),
+        )
+        .arg(
+            Arg::with_name(ARG_REPLAY_HG)
+                .long(ARG_REPLAY_HG)
+                .takes_value(true)
+                .value_name("PATH")
+                .help("replay the history of an hg revlog repository"),
+        )
+        .arg(
+            Arg::with_name(ARG_REPLAY_MONONOKE)
+                .long(ARG_REPLAY_MONONOKE)
+                .requires("repo")
+                .help("replay the history of the Mononoke repository selected with --repo-id or --repo-name"),
+        )
+        .group(
+            ArgGroup::with_name(ARG_GROUP_SOURCE)
+                .args(&[ARG_PROFILE, ARG_REPLAY_GIT, ARG_REPLAY_HG, ARG_REPLAY_MONONOKE]),
+        )
+        .arg(
+            Arg::with_name(ARG_REPLAY_RANGE)
+                .long(ARG_REPLAY_RANGE)
+                .takes_value(true)
+                .value_name("RANGE")
+                .help(
+                    "commits to replay: a revision range for git (default: HEAD), \
+                     FROM:TO revision numbers for hg (default: all), \
+                     FROM..TO changeset ids or bookmarks for Mononoke (default: master)",
+                ),
+        )
+        .arg(
+            Arg::with_name(ARG_TYPE)
+                .required_unless(ARG_GROUP_SOURCE)
+                .index(1)
+                .possible_values(DERIVED_TYPES)
+                .help("derived data type, all types are benchmarked with a profile or a replay if omitted"),
+        );
     let matches = app.get_matches();
 
     args::init_cachelib(fb, &matches, None);
@@ -143,11 +271,42 @@ fn main(fb: FacebookInit) -> Result<()> {
     let ctx = CoreContext::new_with_logger(fb, logger.clone());
     let repo = new_benchmark_repo(fb, Default::default())?;
 
+    let profile = match matches.value_of(ARG_PROFILE) {
+        Some(path) => Some(GenProfile::from_file(path)?),
+        None => None,
+    };
+
     let seed = matches
         .value_of(ARG_SEED)
         .and_then(|s| s.parse::<u64>().ok())
+        .or_else(|| profile.as_ref().and_then(|profile| profile.seed))
         .unwrap_or_else(|| rand::random());
 
+    let derive_types = match matches.value_of(ARG_TYPE) {
+        Some(derive_type) => vec![derive_type],
+        None => DERIVED_TYPES.to_vec(),
+    };
+
+    let mut runtime = Runtime::new()?;
+    if let Some(profile) = profile {
+        return runtime.block_on_std(run_profile(ctx, repo, seed, profile, derive_types));
+    }
+
+    let source = if let Some(path) = matches.value_of(ARG_REPLAY_GIT) {
+        Some(ReplaySource::git(path)?)
+    } else if let Some(path) = matches.value_of(ARG_REPLAY_HG) {
+        Some(ReplaySource::hg(path)?)
+    } else if matches.is_present(ARG_REPLAY_MONONOKE) {
+        let source_repo = runtime.block_on(args::open_repo(fb, &logger, &matches))?;
+        Some(ReplaySource::mononoke(source_repo))
+    } else {
+        None
+    };
+    if let Some(source) = source {
+        let range = matches.value_of(ARG_REPLAY_RANGE);
+        return runtime.block_on_std(run_replay(ctx, repo, source, range, derive_types));
+    }
+
     let stack_size: usize = matches
         .value_of(ARG_STACK_SIZE)
         .unwrap_or("50")
@@ -156,6 +315,5 @@ fn main(fb: FacebookInit) -> Result<()> {
 
     let derive = derive_fn(ctx.clone(), repo.clone(), matches.value_of(ARG_TYPE))?;
 
-    let mut runtime = Runtime::new()?;
     runtime.block_on_std(run(ctx, repo, seed, stack_size, derive))
 }
@@ -194,7 +194,7 @@ impl GenManifest {
     }
 }
 
-fn gen_ascii(len: usize, rng: &mut impl Rng) -> String {
+pub(crate) fn gen_ascii(len: usize, rng: &mut impl Rng) -> String {
     let chars = b"_abcdefghijklmnopqrstuvwxyz";
     let bytes = rng
         .sample_iter(&Uniform::from(0..chars.len()))
@@ -204,7 +204,7 @@ fn gen_ascii(len: usize, rng: &mut impl Rng) -> String {
     String::from_utf8(bytes).expect("ascii conversion failed")
 }
 
-fn gen_filename(rng: &mut impl Rng) -> MPathElement {
+pub(crate) fn gen_filename(rng: &mut impl Rng) -> MPathElement {
     let len = rng.sample(&Binomial::new(20, 0.3).expect("Binomial::new failed")) as usize;
     MPathElement::new(gen_ascii(len + 3, rng).into()).expect("failed to create mpath element")
 }
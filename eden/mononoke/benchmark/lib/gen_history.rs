@@ -0,0 +1,599 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Generator of repository histories that look like real ones: branches that
+//! get merged back, renames and copies, deep directory trees and files with
+//! a long tailed size distribution. Everything about the generated history
+//! is controlled by `GenProfile`, and the same profile and rng seed always
+//! produce the same history.
+use crate::gen::{gen_ascii, gen_filename};
+use anyhow::{bail, Context, Error, Result};
+use blobrepo::{save_bonsai_changesets, BlobRepo};
+use blobstore::Storable;
+use context::CoreContext;
+use futures::compat::Future01CompatExt;
+use mononoke_types::{
+    BlobstoreValue, BonsaiChangeset, BonsaiChangesetMut, ChangesetId, ContentId, DateTime,
+    FileChange, FileContents, FileType, MPath, MPathElement,
+};
+use rand::{seq::IteratorRandom, Rng};
+use rand_distr::{Binomial, LogNormal};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    convert::TryFrom,
+    fs,
+    path::Path,
+};
+
+/// Shape of the generated history. Profiles are read from JSON files, and
+/// fields missing from the file keep their default values.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GenProfile {
+    /// rng seed, used unless a seed is given on the command line
+    pub seed: Option<u64>,
+    /// number of commits to generate, including merges
+    pub commits: usize,
+    /// average number of file changes in a commit that is not a merge
+    pub changes_per_commit: usize,
+    /// probability that a commit merges another branch into its branch
+    pub p_merge: f64,
+    /// probability that a commit starts a new branch
+    pub p_branch: f64,
+    /// maximum number of branches that are open at the same time
+    pub max_branches: usize,
+    /// maximum depth of the directory tree
+    pub max_depth: usize,
+    /// maximum number of entries in a directory
+    pub max_width: usize,
+    /// probablity of descending one level deeper when choosing a path for a new file
+    pub p_dir_descend: f64,
+    /// probablity to create a directory instead of reusing an existing one when descending
+    pub p_dir_create: f64,
+    /// median size of a file in bytes, sizes are log-normally distributed
+    pub file_size_median: u64,
+    /// standard deviation of the logarithm of file sizes
+    pub file_size_sigma: f64,
+    /// files are never larger than this
+    pub file_size_max: u64,
+    /// probability that a file change creates a new file
+    pub p_file_create: f64,
+    /// probability that a file change renames an existing file
+    pub p_file_rename: f64,
+    /// probability that a file change copies an existing file
+    pub p_file_copy: f64,
+    /// probability that a file change deletes an existing file
+    pub p_file_delete: f64,
+}
+
+impl Default for GenProfile {
+    fn default() -> Self {
+        Self {
+            seed: None,
+            commits: 100,
+            changes_per_commit: 16,
+            p_merge: 0.05,
+            p_branch: 0.05,
+            max_branches: 4,
+            max_depth: 8,
+            max_width: 64,
+            p_dir_descend: 0.7,
+            p_dir_create: 0.2,
+            file_size_median: 4096,
+            file_size_sigma: 1.5,
+            file_size_max: 16 * 1024 * 1024,
+            p_file_create: 0.3,
+            p_file_rename: 0.02,
+            p_file_copy: 0.02,
+            p_file_delete: 0.05,
+        }
+    }
+}
+
+impl GenProfile {
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let data = fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+        let profile = serde_json::from_slice(&data)
+            .with_context(|| format!("invalid profile {}", path.display()))?;
+        Ok(profile)
+    }
+
+    fn validate(&self) -> Result<()> {
+        let probabilities = [
+            ("p_merge", self.p_merge),
+            ("p_branch", self.p_branch),
+            ("p_dir_descend", self.p_dir_descend),
+            ("p_dir_create", self.p_dir_create),
+            ("p_file_create", self.p_file_create),
+            ("p_file_rename", self.p_file_rename),
+            ("p_file_copy", self.p_file_copy),
+            ("p_file_delete", self.p_file_delete),
+        ];
+        for (name, p) in probabilities.iter() {
+            if !(0.0..=1.0).contains(p) {
+                bail!("{} must be between 0 and 1, got {}", name, p);
+            }
+        }
+        if self.p_file_create + self.p_file_rename + self.p_file_copy + self.p_file_delete > 1.0 {
+            bail!("file change probabilities must not add up to more than 1");
+        }
+        if self.max_branches == 0 || self.max_depth == 0 || self.max_width == 0 {
+            bail!("max_branches, max_depth and max_width must be positive");
+        }
+        Ok(())
+    }
+}
+
+/// Summary of a generated history
+#[derive(Clone, Debug, Serialize)]
+pub struct GenHistory {
+    /// heads of all the branches that were not merged
+    pub heads: Vec<ChangesetId>,
+    pub commits: usize,
+    pub merges: usize,
+    pub file_changes: usize,
+    pub renames: usize,
+    pub copies: usize,
+    pub deletions: usize,
+    /// number of files in the largest head
+    pub files: usize,
+    /// total size of the generated file contents
+    pub bytes: u64,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct GenFile {
+    id: ContentId,
+    size: u64,
+}
+
+#[derive(Clone, Default)]
+struct Branch {
+    head: Option<ChangesetId>,
+    files: BTreeMap<MPath, GenFile>,
+    /// files deleted or renamed on this branch, with the version they had
+    /// when they were deleted
+    deleted: BTreeMap<MPath, GenFile>,
+}
+
+impl Branch {
+    fn remove(&mut self, path: &MPath) {
+        if let Some(file) = self.files.remove(path) {
+            self.deleted.insert(path.clone(), file);
+        }
+    }
+}
+
+/// All the names ever used for directories and files. A name is never used
+/// for a file in one branch and for a directory in another, so that merges
+/// never have to resolve file/directory conflicts.
+#[derive(Default)]
+struct Namespace {
+    dirs: BTreeMap<MPathElement, Namespace>,
+    files: BTreeSet<MPathElement>,
+}
+
+impl Namespace {
+    fn width(&self) -> usize {
+        self.dirs.len() + self.files.len()
+    }
+
+    fn gen_name(&self, rng: &mut impl Rng) -> MPathElement {
+        loop {
+            let name = gen_filename(rng);
+            if !self.dirs.contains_key(&name) && !self.files.contains(&name) {
+                return name;
+            }
+        }
+    }
+
+    fn gen_file_path(&mut self, rng: &mut impl Rng, profile: &GenProfile) -> MPath {
+        let mut prefix = Vec::new();
+        let mut node = self;
+        loop {
+            let full = node.width() >= profile.max_width;
+            let descend = prefix.len() + 1 < profile.max_depth
+                && (rng.gen_bool(profile.p_dir_descend) || (full && !node.dirs.is_empty()));
+            if !descend {
+                break;
+            }
+            let dirname = match node.dirs.keys().choose(rng) {
+                Some(dirname) if full || !rng.gen_bool(profile.p_dir_create) => dirname.clone(),
+                _ => node.gen_name(rng),
+            };
+            prefix.push(dirname.clone());
+            node = node.dirs.entry(dirname).or_default();
+        }
+        let filename = node.gen_name(rng);
+        node.files.insert(filename.clone());
+        prefix.push(filename);
+        MPath::try_from(prefix).expect("prefix is empty")
+    }
+}
+
+struct Generator<'a, R> {
+    ctx: CoreContext,
+    repo: BlobRepo,
+    rng: &'a mut R,
+    profile: &'a GenProfile,
+    namespace: Namespace,
+    size_dist: LogNormal<f64>,
+    changes_dist: Binomial,
+    history: GenHistory,
+}
+
+impl<'a, R: Rng> Generator<'a, R> {
+    fn gen_size(&mut self) -> u64 {
+        let size = self.rng.sample(&self.size_dist) as u64;
+        std::cmp::min(size, self.profile.file_size_max)
+    }
+
+    /// Generates text content, so that derived data that diffs files has
+    /// some lines to work with.
+    fn gen_content(&mut self) -> Vec<u8> {
+        let size = self.gen_size() as usize;
+        let mut content = Vec::with_capacity(size);
+        while content.len() < size {
+            let line_len = self.rng.gen_range(1, 80);
+            content.extend(gen_ascii(line_len, self.rng).into_bytes());
+            content.push(b'\n');
+        }
+        content.truncate(size);
+        content
+    }
+
+    async fn store_content(&mut self, content: Vec<u8>) -> Result<GenFile> {
+        let content = FileContents::new_bytes(content);
+        let size = content.size() as u64;
+        let id = content
+            .into_blob()
+            .store(self.ctx.clone(), self.repo.blobstore())
+            .compat()
+            .await?;
+        self.history.bytes += size;
+        Ok(GenFile { id, size })
+    }
+
+    /// Generates file changes of a commit on top of `branch`, and applies them to it
+    async fn gen_changes(
+        &mut self,
+        branch: &mut Branch,
+    ) -> Result<BTreeMap<MPath, Option<FileChange>>> {
+        let count = std::cmp::max(1, self.rng.sample(&self.changes_dist) as usize);
+        let profile = self.profile;
+        let mut changes = BTreeMap::new();
+        let mut attempts = 0;
+        while changes.len() < count && attempts < 4 * count {
+            attempts += 1;
+            // Existing files are picked from the parent, so that copy sources
+            // are valid, and each file is only changed once per commit.
+            let existing = match branch.head {
+                Some(parent) => branch
+                    .files
+                    .iter()
+                    .filter(|(path, _)| !changes.contains_key(*path))
+                    .choose(self.rng)
+                    .map(|(path, file)| (parent, path.clone(), *file)),
+                None => None,
+            };
+            let p = self.rng.gen::<f64>();
+            let existing = match existing {
+                Some(existing) if p >= profile.p_file_create => existing,
+                _ => {
+                    let path = self.namespace.gen_file_path(self.rng, profile);
+                    let content = self.gen_content();
+                    let file = self.store_content(content).await?;
+                    changes.insert(path.clone(), Some(gen_file_change(file, None)));
+                    branch.files.insert(path, file);
+                    continue;
+                }
+            };
+            let (parent, path, file) = existing;
+            let p = p - profile.p_file_create;
+            if p < profile.p_file_rename + profile.p_file_copy {
+                let new_path = self.namespace.gen_file_path(self.rng, profile);
+                let copy_from = Some((path.clone(), parent));
+                changes.insert(new_path.clone(), Some(gen_file_change(file, copy_from)));
+                branch.files.insert(new_path, file);
+                if p < profile.p_file_rename {
+                    changes.insert(path.clone(), None);
+                    branch.remove(&path);
+                    self.history.renames += 1;
+                } else {
+                    self.history.copies += 1;
+                }
+            } else if p < profile.p_file_rename + profile.p_file_copy + profile.p_file_delete {
+                changes.insert(path.clone(), None);
+                branch.remove(&path);
+                self.history.deletions += 1;
+            } else {
+                let content = self.gen_content();
+                let file = self.store_content(content).await?;
+                changes.insert(path.clone(), Some(gen_file_change(file, None)));
+                branch.files.insert(path, file);
+            }
+        }
+        self.history.file_changes += changes.len();
+        Ok(changes)
+    }
+
+    fn gen_bonsai(
+        &mut self,
+        parents: Vec<ChangesetId>,
+        file_changes: BTreeMap<MPath, Option<FileChange>>,
+    ) -> Result<BonsaiChangeset> {
+        let timestamp = 60 * self.history.commits as i64;
+        self.history.commits += 1;
+        BonsaiChangesetMut {
+            parents,
+            author: "author".to_string(),
+            author_date: DateTime::from_timestamp(timestamp, 0)?,
+            committer: None,
+            committer_date: None,
+            message: format!("commit {}", self.history.commits),
+            extra: BTreeMap::new(),
+            file_changes,
+        }
+        .freeze()
+    }
+}
+
+/// Merges `other` into `branch`. A file deleted on one side stays deleted
+/// unless the other side changed it since, and files that are different in
+/// the two branches are resolved in favour of `branch`.
+fn gen_merge_changes(branch: &mut Branch, other: Branch) -> BTreeMap<MPath, Option<FileChange>> {
+    let mut changes = BTreeMap::new();
+    let mut deleted = Vec::new();
+    for (path, file) in branch.files.iter() {
+        if other.files.get(path) == Some(file) {
+            continue;
+        }
+        if !other.files.contains_key(path) && other.deleted.get(path) == Some(file) {
+            changes.insert(path.clone(), None);
+            deleted.push(path.clone());
+        } else {
+            changes.insert(path.clone(), Some(gen_file_change(*file, None)));
+        }
+    }
+    for path in deleted {
+        branch.remove(&path);
+    }
+    for (path, file) in other.files {
+        if branch.files.contains_key(&path) {
+            continue;
+        }
+        if branch.deleted.get(&path) == Some(&file) {
+            changes.insert(path, None);
+        } else {
+            changes.insert(path.clone(), Some(gen_file_change(file, None)));
+            branch.files.insert(path, file);
+        }
+    }
+    for (path, file) in other.deleted {
+        if !branch.files.contains_key(&path) {
+            branch.deleted.entry(path).or_insert(file);
+        }
+    }
+    let files = &branch.files;
+    branch.deleted.retain(|path, _| !files.contains_key(path));
+    changes
+}
+
+fn gen_file_change(file: GenFile, copy_from: Option<(MPath, ChangesetId)>) -> FileChange {
+    FileChange::new(file.id, FileType::Regular, file.size, copy_from)
+}
+
+/// Generates and saves a history shaped by `profile`.
+pub async fn gen_history(
+    ctx: CoreContext,
+    repo: BlobRepo,
+    rng: &mut impl Rng,
+    profile: &GenProfile,
+) -> Result<GenHistory, Error> {
+    profile.validate()?;
+    let size_dist = LogNormal::new(
+        (profile.file_size_median.max(1) as f64).ln(),
+        profile.file_size_sigma,
+    )
+    .map_err(|err| Error::msg(format!("invalid file size distribution: {:?}", err)))?;
+    let changes_dist = Binomial::new(2 * profile.changes_per_commit as u64, 0.5)
+        .map_err(|err| Error::msg(format!("invalid changes per commit: {:?}", err)))?;
+    let mut gen = Generator {
+        ctx: ctx.clone(),
+        repo: repo.clone(),
+        rng,
+        profile,
+        namespace: Namespace::default(),
+        size_dist,
+        changes_dist,
+        history: GenHistory {
+            heads: Vec::new(),
+            commits: 0,
+            merges: 0,
+            file_changes: 0,
+            renames: 0,
+            copies: 0,
+            deletions: 0,
+            files: 0,
+            bytes: 0,
+        },
+    };
+
+    let mut branches = vec![Branch::default()];
+    let mut changesets = Vec::new();
+    while gen.history.commits < profile.commits {
+        let mut index = gen.rng.gen_range(0, branches.len());
+        if branches.len() < profile.max_branches
+            && branches[index].head.is_some()
+            && gen.rng.gen_bool(profile.p_branch)
+        {
+            let branch = branches[index].clone();
+            branches.push(branch);
+            index = branches.len() - 1;
+        }
+
+        let mut branch = branches.swap_remove(index);
+        let merge_with = if branches.is_empty() || !gen.rng.gen_bool(profile.p_merge) {
+            None
+        } else {
+            let other = gen.rng.gen_range(0, branches.len());
+            Some(branches.swap_remove(other))
+        };
+        let (parents, file_changes) = match merge_with {
+            Some(other)
+                if branch.head.is_some() && other.head.is_some() && other.head != branch.head =>
+            {
+                gen.history.merges += 1;
+                let parents = branch.head.into_iter().chain(other.head).collect();
+                (parents, gen_merge_changes(&mut branch, other))
+            }
+            // Branches that have nothing new to merge are dropped
+            _ => {
+                let parents = branch.head.into_iter().collect();
+                (parents, gen.gen_changes(&mut branch).await?)
+            }
+        };
+        let bonsai = gen.gen_bonsai(parents, file_changes)?;
+        branch.head = Some(bonsai.get_changeset_id());
+        changesets.push(bonsai);
+        branches.push(branch);
+    }
+
+    save_bonsai_changesets(changesets, ctx, repo)
+        .compat()
+        .await?;
+
+    let mut history = gen.history;
+    history.files = branches
+        .iter()
+        .map(|branch| branch.files.len())
+        .max()
+        .unwrap_or(0);
+    history.heads = branches
+        .into_iter()
+        .filter_map(|branch| branch.head)
+        .collect();
+    Ok(history)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use fbinit::FacebookInit;
+    use mononoke_types_mocks::contentid::{FOURS_CTID, ONES_CTID, THREES_CTID, TWOS_CTID};
+    use rand::SeedableRng;
+    use rand_xorshift::XorShiftRng;
+
+    fn file(id: ContentId) -> GenFile {
+        GenFile { id, size: 1 }
+    }
+
+    fn path(path: &str) -> MPath {
+        MPath::new(path).unwrap()
+    }
+
+    fn small_profile() -> GenProfile {
+        GenProfile {
+            commits: 50,
+            changes_per_commit: 4,
+            p_merge: 0.3,
+            p_branch: 0.3,
+            file_size_median: 64,
+            file_size_max: 1024,
+            ..Default::default()
+        }
+    }
+
+    async fn gen(fb: FacebookInit, seed: u64, profile: &GenProfile) -> Result<GenHistory> {
+        let ctx = CoreContext::test_mock(fb);
+        let repo = blobrepo_factory::new_memblob_empty(None)?;
+        let mut rng = XorShiftRng::seed_from_u64(seed);
+        gen_history(ctx, repo, &mut rng, profile).await
+    }
+
+    #[test]
+    fn test_merge_keeps_deletions() {
+        let mut base = Branch::default();
+        for name in &["deleted", "deleted_other", "changed_other", "kept"] {
+            base.files.insert(path(name), file(ONES_CTID));
+        }
+        let mut branch = base.clone();
+        branch.remove(&path("deleted"));
+        branch.remove(&path("changed_other"));
+        let mut other = base;
+        other.remove(&path("deleted_other"));
+        other.files.insert(path("changed_other"), file(TWOS_CTID));
+        other.files.insert(path("added_other"), file(THREES_CTID));
+        other.files.insert(path("kept"), file(FOURS_CTID));
+
+        let changes = gen_merge_changes(&mut branch, other);
+
+        // Deletions are recorded against the side that still has the file.
+        assert_eq!(changes.get(&path("deleted")), Some(&None));
+        assert_eq!(changes.get(&path("deleted_other")), Some(&None));
+        let merged: Vec<_> = branch
+            .files
+            .iter()
+            .map(|(path, file)| (path.to_string(), file.id))
+            .collect();
+        assert_eq!(
+            merged,
+            vec![
+                ("added_other".to_string(), THREES_CTID),
+                ("changed_other".to_string(), TWOS_CTID),
+                ("kept".to_string(), ONES_CTID),
+            ]
+        );
+        assert!(branch.deleted.contains_key(&path("deleted")));
+        assert!(branch.deleted.contains_key(&path("deleted_other")));
+        assert!(!branch.deleted.contains_key(&path("changed_other")));
+    }
+
+    #[fbinit::compat_test]
+    async fn test_same_seed(fb: FacebookInit) -> Result<()> {
+        let profile = small_profile();
+        let first = gen(fb, 1, &profile).await?;
+        let second = gen(fb, 1, &profile).await?;
+        assert_eq!(first.heads, second.heads);
+        assert_eq!(first.file_changes, second.file_changes);
+        assert_eq!(first.bytes, second.bytes);
+
+        let other = gen(fb, 2, &profile).await?;
+        assert_ne!(first.heads, other.heads);
+        Ok(())
+    }
+
+    #[fbinit::compat_test]
+    async fn test_follows_profile(fb: FacebookInit) -> Result<()> {
+        let history = gen(fb, 1, &small_profile()).await?;
+        assert_eq!(history.commits, 50);
+        assert!(history.merges > 0);
+
+        // Without merges or branches, and with every change creating a new
+        // file, there is a single head with all the created files.
+        let profile = GenProfile {
+            p_merge: 0.0,
+            p_branch: 0.0,
+            p_file_create: 1.0,
+            p_file_rename: 0.0,
+            p_file_copy: 0.0,
+            p_file_delete: 0.0,
+            ..small_profile()
+        };
+        let history = gen(fb, 1, &profile).await?;
+        assert_eq!(history.commits, 50);
+        assert_eq!(history.merges, 0);
+        assert_eq!(history.heads.len(), 1);
+        assert_eq!(history.renames + history.copies + history.deletions, 0);
+        assert_eq!(history.files, history.file_changes);
+        assert!(history.file_changes >= history.commits);
+        Ok(())
+    }
+}
@@ -6,7 +6,11 @@
  */
 
 mod gen;
+mod gen_history;
+mod replay;
 mod repository;
 
 pub use gen::{GenManifest, GenSettings};
+pub use gen_history::{gen_history, GenHistory, GenProfile};
+pub use replay::{replay, DeriveFn, Percentiles, ReplayReport, ReplaySource};
 pub use repository::{new_benchmark_repo, DelaySettings};
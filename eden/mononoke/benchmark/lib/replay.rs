@@ -0,0 +1,746 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Replays the history of an existing repository into a benchmark repository,
+//! measuring how long it takes to ingest each commit and to derive data for it.
+//!
+//! Commits are replayed in topological order. Parents that are outside of the
+//! replayed range are dropped, and the first commits of the range are replayed
+//! as a snapshot of their whole tree, so that every replayed commit has the same
+//! tree as in the source repository.
+use anyhow::{bail, format_err, Error, Result};
+use blobrepo::{save_bonsai_changesets, BlobRepo};
+use blobstore::Loadable;
+use bookmarks::BookmarkName;
+use bytes::Bytes;
+use context::CoreContext;
+use derived_data::BonsaiDerived;
+use filestore::{self, StoreRequest};
+use fsnodes::RootFsnodeId;
+use futures::{
+    compat::{Future01CompatExt, Stream01CompatExt},
+    stream::{self, StreamExt, TryStreamExt},
+};
+use futures_ext::BoxFuture;
+use futures_old::stream as old_stream;
+use futures_stats::futures03::TimedFutureExt;
+use git2::{Delta, FileMode, Oid, Repository, Sort};
+use manifest::{Diff, Entry, ManifestOps};
+use mercurial_revlog::{EntryContent, RevlogEntry, RevlogRepo};
+use mercurial_types::{manifest::Type, HgChangesetId, HgManifestId, HgNodeHash};
+use mononoke_types::{
+    BonsaiChangesetMut, ChangesetId, ContentId, DateTime, FileChange, FileType, MPath,
+};
+use revset::DifferenceOfUnionsOfAncestorsNodeStream;
+use serde::Serialize;
+use skiplist::SkiplistIndex;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    path::Path,
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
+
+/// Derives one type of derived data for a changeset, and returns a description
+/// of the result.
+pub type DeriveFn = Arc<dyn Fn(ChangesetId) -> BoxFuture<String, Error> + Send + Sync + 'static>;
+
+/// Number of file contents that are uploaded concurrently
+const UPLOAD_CONCURRENCY: usize = 100;
+
+/// Bookmark that is replayed from a Mononoke repository if no range is given
+const DEFAULT_BOOKMARK: &str = "master";
+
+/// Repository whose history is replayed.
+pub enum ReplaySource {
+    /// git repository, the range is a git revision range, e.g. `v1.0..master`
+    Git(Repository),
+    /// hg revlog repository, the range is `FROM:TO` inclusive revision numbers
+    Hg(RevlogRepo),
+    /// Mononoke repository, the range is `FROM..TO` where both ends are
+    /// bonsai changeset ids or bookmarks
+    Mononoke(BlobRepo),
+}
+
+/// A commit of the source repository, with the file changes that turn the
+/// trees of its replayed parents into its tree.
+struct SourceCommit {
+    parents: Vec<String>,
+    author: String,
+    author_date: DateTime,
+    message: String,
+    changes: BTreeMap<MPath, Option<SourceFile>>,
+}
+
+struct SourceFile {
+    content: Bytes,
+    file_type: FileType,
+    copy_from: Option<(MPath, String)>,
+}
+
+/// Distribution of the time spent on each commit.
+#[derive(Clone, Debug, Serialize)]
+pub struct Percentiles {
+    pub total_ms: f64,
+    pub p50_ms: f64,
+    pub p90_ms: f64,
+    pub p99_ms: f64,
+    pub max_ms: f64,
+}
+
+impl Percentiles {
+    pub fn from_durations(durations: &[Duration]) -> Self {
+        let mut millis: Vec<f64> = durations.iter().map(|d| as_millis(*d)).collect();
+        millis.sort_by(|a, b| a.partial_cmp(b).expect("durations are never NaN"));
+        // nearest-rank percentile
+        let percentile = |p: f64| -> f64 {
+            if millis.is_empty() {
+                return 0.0;
+            }
+            let rank = (p * millis.len() as f64).ceil() as usize;
+            millis[rank.max(1).min(millis.len()) - 1]
+        };
+        Self {
+            total_ms: millis.iter().sum(),
+            p50_ms: percentile(0.5),
+            p90_ms: percentile(0.9),
+            p99_ms: percentile(0.99),
+            max_ms: millis.last().cloned().unwrap_or(0.0),
+        }
+    }
+}
+
+/// Summary of a replay.
+#[derive(Clone, Debug, Serialize)]
+pub struct ReplayReport {
+    pub commits: usize,
+    pub merges: usize,
+    pub file_changes: usize,
+    pub bytes: u64,
+    /// time to upload contents and save each commit
+    pub ingest: Percentiles,
+    /// time to derive each type of derived data for each commit
+    pub derived: BTreeMap<String, Percentiles>,
+    /// time to ingest a commit and derive all types of derived data for it
+    pub total: Percentiles,
+}
+
+impl ReplaySource {
+    pub fn git(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(ReplaySource::Git(Repository::open(path)?))
+    }
+
+    pub fn hg(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(ReplaySource::Hg(RevlogRepo::open(path.as_ref())?))
+    }
+
+    pub fn mononoke(repo: BlobRepo) -> Self {
+        ReplaySource::Mononoke(repo)
+    }
+
+    /// Ids of the commits in `range`, parents first.
+    async fn commits(&self, ctx: &CoreContext, range: Option<&str>) -> Result<Vec<String>> {
+        match self {
+            ReplaySource::Git(repo) => git_commits(repo, range),
+            ReplaySource::Hg(repo) => hg_commits(repo, range).await,
+            ReplaySource::Mononoke(repo) => mononoke_commits(ctx, repo, range).await,
+        }
+    }
+
+    async fn load(
+        &self,
+        ctx: &CoreContext,
+        id: &str,
+        replayed: &HashSet<String>,
+    ) -> Result<SourceCommit> {
+        match self {
+            ReplaySource::Git(repo) => git_commit(repo, id, replayed),
+            ReplaySource::Hg(repo) => hg_commit(repo, id, replayed).await,
+            ReplaySource::Mononoke(repo) => mononoke_commit(ctx, repo, id, replayed).await,
+        }
+    }
+}
+
+fn git_commits(repo: &Repository, range: Option<&str>) -> Result<Vec<String>> {
+    let mut walk = repo.revwalk()?;
+    walk.set_sorting(Sort::TOPOLOGICAL | Sort::REVERSE)?;
+    match range {
+        Some(range) if range.contains("..") => walk.push_range(range)?,
+        Some(rev) => walk.push(repo.revparse_single(rev)?.peel_to_commit()?.id())?,
+        None => walk.push_head()?,
+    }
+    walk.map(|oid| Ok(oid?.to_string())).collect()
+}
+
+fn git_commit(repo: &Repository, id: &str, replayed: &HashSet<String>) -> Result<SourceCommit> {
+    let commit = repo.find_commit(Oid::from_str(id)?)?;
+    let tree = commit.tree()?;
+    let parents = commit
+        .parents()
+        .filter(|parent| replayed.contains(&parent.id().to_string()))
+        .collect::<Vec<_>>();
+
+    let mut bases = Vec::new();
+    for parent in parents.iter() {
+        bases.push(Some(parent.tree()?));
+    }
+    if bases.is_empty() {
+        bases.push(None);
+    }
+
+    let mut changes = BTreeMap::new();
+    for base in bases.iter() {
+        let diff = repo.diff_tree_to_tree(base.as_ref(), Some(&tree), None)?;
+        for delta in diff.deltas() {
+            let old = delta.old_file();
+            let new = delta.new_file();
+            let file_type = match new.mode() {
+                _ if delta.status() == Delta::Deleted => None,
+                FileMode::Blob => Some(FileType::Regular),
+                FileMode::BlobExecutable => Some(FileType::Executable),
+                FileMode::Link => Some(FileType::Symlink),
+                // Submodules are not replayed
+                _ => None,
+            };
+            match file_type {
+                Some(file_type) => {
+                    let path = git_path(new.path_bytes())?;
+                    let content = Bytes::copy_from_slice(repo.find_blob(new.id())?.content());
+                    changes.insert(
+                        path,
+                        Some(SourceFile {
+                            content,
+                            file_type,
+                            copy_from: None,
+                        }),
+                    );
+                }
+                None if old.mode() != FileMode::Commit && old.mode() != FileMode::Unreadable => {
+                    changes.entry(git_path(old.path_bytes())?).or_insert(None);
+                }
+                None => {}
+            }
+        }
+    }
+
+    let time = commit.time();
+    Ok(SourceCommit {
+        parents: parents
+            .iter()
+            .map(|parent| parent.id().to_string())
+            .collect(),
+        author: String::from_utf8_lossy(commit.author().name_bytes()).into_owned(),
+        author_date: DateTime::from_timestamp(time.seconds(), time.offset_minutes() * 60)?,
+        message: String::from_utf8_lossy(commit.message_bytes()).into_owned(),
+        changes,
+    })
+}
+
+fn git_path(path: Option<&[u8]>) -> Result<MPath> {
+    MPath::new(path.ok_or_else(|| format_err!("git diff entry has no path"))?)
+}
+
+async fn hg_commits(repo: &RevlogRepo, range: Option<&str>) -> Result<Vec<String>> {
+    let (from, to) = match range {
+        Some(range) => {
+            let mut bounds = range.splitn(2, ':');
+            let from = bounds.next().unwrap_or_default();
+            let to = bounds.next().unwrap_or(from);
+            let parse = |rev: &str, default| -> Result<usize> {
+                if rev.is_empty() {
+                    Ok(default)
+                } else {
+                    rev.parse()
+                        .map_err(|_| format_err!("invalid hg revision range: {}", range))
+                }
+            };
+            (parse(from, 0)?, parse(to, usize::max_value())?)
+        }
+        None => (0, usize::max_value()),
+    };
+    // Revlog order is a topological order
+    repo.changesets()
+        .compat()
+        .skip(from)
+        .take(to.saturating_sub(from).saturating_add(1))
+        .map_ok(|(_, node)| node.to_string())
+        .try_collect()
+        .await
+}
+
+async fn hg_commit(
+    repo: &RevlogRepo,
+    id: &str,
+    replayed: &HashSet<String>,
+) -> Result<SourceCommit> {
+    let changeset = repo
+        .get_changeset(HgChangesetId::new(HgNodeHash::from_str(id)?))
+        .compat()
+        .await?;
+    let files = hg_files(repo, changeset.manifestid()).await?;
+
+    let parents = changeset
+        .parents()
+        .into_iter()
+        .map(|parent| parent.to_string())
+        .filter(|parent| replayed.contains(parent))
+        .collect::<Vec<_>>();
+    let mut bases = Vec::new();
+    for parent in parents.iter() {
+        let parent = repo
+            .get_changeset(HgChangesetId::new(HgNodeHash::from_str(parent)?))
+            .compat()
+            .await?;
+        bases.push(hg_files(repo, parent.manifestid()).await?);
+    }
+    if bases.is_empty() {
+        bases.push(BTreeMap::new());
+    }
+
+    let mut changed = BTreeMap::new();
+    let mut deleted = HashSet::new();
+    for base in bases.iter() {
+        for (path, entry) in files.iter() {
+            let unchanged = base.get(path).map_or(false, |base_entry| {
+                base_entry.get_hash() == entry.get_hash()
+                    && base_entry.get_type() == entry.get_type()
+            });
+            if !unchanged {
+                changed.insert(path.clone(), entry);
+            }
+        }
+        for path in base.keys() {
+            if !files.contains_key(path) {
+                deleted.insert(path.clone());
+            }
+        }
+    }
+
+    let mut changes = BTreeMap::new();
+    for path in deleted {
+        changes.insert(path, None);
+    }
+    for (path, entry) in changed {
+        let file_type = match entry.get_type() {
+            Type::File(file_type) => file_type,
+            Type::Tree => bail!("{} is not a file", path),
+        };
+        let content = match entry.get_content().compat().await? {
+            EntryContent::File(file)
+            | EntryContent::Executable(file)
+            | EntryContent::Symlink(file) => Bytes::copy_from_slice(file.content()),
+            EntryContent::Tree(_) => bail!("{} is not a file", path),
+        };
+        changes.insert(
+            path,
+            Some(SourceFile {
+                content,
+                file_type,
+                copy_from: None,
+            }),
+        );
+    }
+
+    Ok(SourceCommit {
+        parents,
+        author: String::from_utf8_lossy(changeset.user()).into_owned(),
+        author_date: changeset.time().clone(),
+        message: String::from_utf8_lossy(changeset.comments()).into_owned(),
+        changes,
+    })
+}
+
+/// All files of an hg manifest, including the ones in sub-trees of tree manifests.
+async fn hg_files(
+    repo: &RevlogRepo,
+    manifest_id: HgManifestId,
+) -> Result<BTreeMap<MPath, RevlogEntry>> {
+    let mut files = BTreeMap::new();
+    let mut manifests = vec![repo.get_root_manifest(manifest_id).compat().await?];
+    while let Some(manifest) = manifests.pop() {
+        let paths = manifest
+            .manifest()
+            .into_iter()
+            .map(|(path, _)| path.clone())
+            .collect::<Vec<_>>();
+        for path in paths {
+            let entry = manifest
+                .lookup(&path)
+                .compat()
+                .await?
+                .ok_or_else(|| format_err!("{} is missing from its manifest", path))?;
+            match entry.get_type() {
+                Type::Tree => match entry.get_content().compat().await? {
+                    EntryContent::Tree(manifest) => manifests.push(manifest),
+                    _ => bail!("{} is not a tree", path),
+                },
+                Type::File(_) => {
+                    files.insert(path, entry);
+                }
+            }
+        }
+    }
+    Ok(files)
+}
+
+async fn mononoke_commits(
+    ctx: &CoreContext,
+    repo: &BlobRepo,
+    range: Option<&str>,
+) -> Result<Vec<String>> {
+    let range = range.unwrap_or(DEFAULT_BOOKMARK);
+    let (from, to) = match range.find("..") {
+        Some(index) => (Some(&range[..index]), &range[index + 2..]),
+        None => (None, range),
+    };
+    let to = resolve_mononoke_commit(ctx, repo, to).await?;
+    let excludes = match from {
+        Some(from) => vec![resolve_mononoke_commit(ctx, repo, from).await?],
+        None => Vec::new(),
+    };
+    let commits = DifferenceOfUnionsOfAncestorsNodeStream::new_with_excludes(
+        ctx.clone(),
+        &repo.get_changeset_fetcher(),
+        Arc::new(SkiplistIndex::new()),
+        vec![to],
+        excludes,
+    )
+    .collect()
+    .compat()
+    .await?;
+    // The stream returns descendants before their ancestors
+    Ok(commits
+        .into_iter()
+        .rev()
+        .map(|csid| csid.to_string())
+        .collect())
+}
+
+async fn resolve_mononoke_commit(
+    ctx: &CoreContext,
+    repo: &BlobRepo,
+    commit: &str,
+) -> Result<ChangesetId> {
+    if let Ok(csid) = ChangesetId::from_str(commit) {
+        return Ok(csid);
+    }
+    repo.get_bonsai_bookmark(ctx.clone(), &BookmarkName::new(commit)?)
+        .compat()
+        .await?
+        .ok_or_else(|| format_err!("{} is neither a changeset id nor a bookmark", commit))
+}
+
+async fn mononoke_commit(
+    ctx: &CoreContext,
+    repo: &BlobRepo,
+    id: &str,
+    replayed: &HashSet<String>,
+) -> Result<SourceCommit> {
+    let csid = ChangesetId::from_str(id)?;
+    let bonsai = csid.load(ctx.clone(), repo.blobstore()).compat().await?;
+    let source_parents = bonsai.parents().collect::<Vec<_>>();
+    let parents = source_parents
+        .iter()
+        .filter(|parent| replayed.contains(&parent.to_string()))
+        .cloned()
+        .collect::<Vec<_>>();
+
+    // The bonsai changes can only be reused if all of the parents are replayed,
+    // otherwise the changes are recomputed from the trees.
+    let changes = if parents.len() == source_parents.len() {
+        bonsai
+            .file_changes()
+            .map(|(path, change)| {
+                let change = change.map(|change| {
+                    // The copy source can be any ancestor, so it may be outside of
+                    // the replayed range. Its copy info is dropped in that case.
+                    let copy_from = change
+                        .copy_from()
+                        .map(|(path, csid)| (path.clone(), csid.to_string()))
+                        .filter(|(_, csid)| replayed.contains(csid));
+                    (change.content_id(), change.file_type(), copy_from)
+                });
+                (path.clone(), change)
+            })
+            .collect::<BTreeMap<_, _>>()
+    } else {
+        let root = RootFsnodeId::derive(ctx.clone(), repo.clone(), csid)
+            .compat()
+            .await?
+            .into_fsnode_id();
+        let mut changes = BTreeMap::new();
+        if parents.is_empty() {
+            root.list_leaf_entries(ctx.clone(), repo.get_blobstore())
+                .compat()
+                .try_for_each(|(path, (content_id, file_type))| {
+                    changes.insert(path, Some((content_id, file_type, None)));
+                    futures::future::ready(Ok(()))
+                })
+                .await?;
+        }
+        for parent in parents.iter() {
+            let parent_root = RootFsnodeId::derive(ctx.clone(), repo.clone(), *parent)
+                .compat()
+                .await?
+                .into_fsnode_id();
+            parent_root
+                .diff(ctx.clone(), repo.get_blobstore(), root)
+                .compat()
+                .try_for_each(|diff| {
+                    match diff {
+                        Diff::Added(Some(path), Entry::Leaf((content_id, file_type)))
+                        | Diff::Changed(Some(path), _, Entry::Leaf((content_id, file_type))) => {
+                            changes.insert(path, Some((content_id, file_type, None)));
+                        }
+                        Diff::Removed(Some(path), Entry::Leaf(_)) => {
+                            changes.entry(path).or_insert(None);
+                        }
+                        Diff::Changed(Some(path), Entry::Leaf(_), Entry::Tree(_)) => {
+                            changes.entry(path).or_insert(None);
+                        }
+                        _ => {}
+                    }
+                    futures::future::ready(Ok(()))
+                })
+                .await?;
+        }
+        changes
+    };
+
+    let changes = stream::iter(changes)
+        .map(|(path, change)| async move {
+            let file = match change {
+                Some((content_id, file_type, copy_from)) => Some(SourceFile {
+                    content: fetch_content(ctx, repo, content_id).await?,
+                    file_type,
+                    copy_from,
+                }),
+                None => None,
+            };
+            Ok::<_, Error>((path, file))
+        })
+        .buffered(UPLOAD_CONCURRENCY)
+        .try_collect()
+        .await?;
+
+    Ok(SourceCommit {
+        parents: parents.iter().map(|parent| parent.to_string()).collect(),
+        author: bonsai.author().to_string(),
+        author_date: bonsai.author_date().clone(),
+        message: bonsai.message().to_string(),
+        changes,
+    })
+}
+
+async fn fetch_content(ctx: &CoreContext, repo: &BlobRepo, content_id: ContentId) -> Result<Bytes> {
+    filestore::fetch_concat(repo.blobstore(), ctx.clone(), content_id)
+        .compat()
+        .await
+}
+
+/// Uploads the contents of `commit` and saves it into `repo`.
+async fn ingest(
+    ctx: &CoreContext,
+    repo: &BlobRepo,
+    commit: SourceCommit,
+    ids: &HashMap<String, ChangesetId>,
+) -> Result<ChangesetId> {
+    let map_id = |id: &str| {
+        ids.get(id)
+            .cloned()
+            .ok_or_else(|| format_err!("{} has not been replayed", id))
+    };
+    let parents = commit
+        .parents
+        .iter()
+        .map(|parent| map_id(parent))
+        .collect::<Result<Vec<_>>>()?;
+
+    let file_changes = stream::iter(commit.changes)
+        .map(|(path, file)| async move {
+            let file = match file {
+                Some(file) => file,
+                None => return Ok((path, None)),
+            };
+            let copy_from = match file.copy_from {
+                Some((from_path, from_id)) => Some((from_path, map_id(&from_id)?)),
+                None => None,
+            };
+            let size = file.content.len() as u64;
+            let metadata = filestore::store(
+                repo.get_blobstore(),
+                repo.filestore_config(),
+                ctx.clone(),
+                &StoreRequest::new(size),
+                old_stream::once(Ok(file.content)),
+            )
+            .compat()
+            .await?;
+            let change = FileChange::new(metadata.content_id, file.file_type, size, copy_from);
+            Ok::<_, Error>((path, Some(change)))
+        })
+        .buffered(UPLOAD_CONCURRENCY)
+        .try_collect::<BTreeMap<_, _>>()
+        .await?;
+
+    let bonsai = BonsaiChangesetMut {
+        parents,
+        author: commit.author,
+        author_date: commit.author_date,
+        committer: None,
+        committer_date: None,
+        message: commit.message,
+        extra: BTreeMap::new(),
+        file_changes,
+    }
+    .freeze()?;
+    let csid = bonsai.get_changeset_id();
+    save_bonsai_changesets(vec![bonsai], ctx.clone(), repo.clone())
+        .compat()
+        .await?;
+    Ok(csid)
+}
+
+/// Replays the commits in `range` of `source` into `repo`, and derives each
+/// of `derive_types` for every replayed commit.
+pub async fn replay(
+    ctx: CoreContext,
+    repo: BlobRepo,
+    source: &ReplaySource,
+    range: Option<&str>,
+    derive_types: &[(&str, DeriveFn)],
+) -> Result<ReplayReport> {
+    let commits = source.commits(&ctx, range).await?;
+    if commits.is_empty() {
+        bail!("there are no commits to replay");
+    }
+
+    let mut replayed = HashSet::new();
+    let mut ids = HashMap::new();
+    let mut merges = 0;
+    let mut file_changes = 0;
+    let mut bytes = 0;
+    let mut ingest_times = Vec::new();
+    let mut derive_times = vec![Vec::new(); derive_types.len()];
+    let mut total_times = Vec::new();
+
+    for id in commits.iter() {
+        let commit = source.load(&ctx, id, &replayed).await?;
+        if commit.parents.len() > 1 {
+            merges += 1;
+        }
+        file_changes += commit.changes.len();
+        bytes += commit
+            .changes
+            .values()
+            .flatten()
+            .map(|file| file.content.len() as u64)
+            .sum::<u64>();
+
+        let (stats, csid) = ingest(&ctx, &repo, commit, &ids).timed().await;
+        let csid = csid?;
+        let mut total = stats.completion_time;
+        ingest_times.push(stats.completion_time);
+
+        for ((_, derive), times) in derive_types.iter().zip(derive_times.iter_mut()) {
+            let (stats, result) = derive(csid).compat().timed().await;
+            result?;
+            total += stats.completion_time;
+            times.push(stats.completion_time);
+        }
+        total_times.push(total);
+
+        replayed.insert(id.clone());
+        ids.insert(id.clone(), csid);
+    }
+
+    Ok(ReplayReport {
+        commits: commits.len(),
+        merges,
+        file_changes,
+        bytes,
+        ingest: Percentiles::from_durations(&ingest_times),
+        derived: derive_types
+            .iter()
+            .zip(derive_times)
+            .map(|((name, _), times)| (name.to_string(), Percentiles::from_durations(&times)))
+            .collect(),
+        total: Percentiles::from_durations(&total_times),
+    })
+}
+
+fn as_millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use fbinit::FacebookInit;
+    use tests_utils::CreateCommitContext;
+
+    #[test]
+    fn test_percentiles() {
+        let durations = (1..=100).map(Duration::from_millis).collect::<Vec<_>>();
+        let percentiles = Percentiles::from_durations(&durations);
+        assert_eq!(percentiles.total_ms, 5050.0);
+        assert_eq!(percentiles.p50_ms, 50.0);
+        assert_eq!(percentiles.p90_ms, 90.0);
+        assert_eq!(percentiles.p99_ms, 99.0);
+        assert_eq!(percentiles.max_ms, 100.0);
+
+        let percentiles = Percentiles::from_durations(&[Duration::from_millis(7)]);
+        assert_eq!(percentiles.p50_ms, 7.0);
+        assert_eq!(percentiles.p99_ms, 7.0);
+
+        let percentiles = Percentiles::from_durations(&[]);
+        assert_eq!(percentiles.total_ms, 0.0);
+        assert_eq!(percentiles.max_ms, 0.0);
+    }
+
+    #[fbinit::compat_test]
+    async fn test_replay_mononoke(fb: FacebookInit) -> Result<()> {
+        let ctx = CoreContext::test_mock(fb);
+        let source = blobrepo_factory::new_memblob_empty(None)?;
+
+        let a = CreateCommitContext::new_root(&ctx, &source)
+            .add_file("a", "content")
+            .commit()
+            .await?;
+        let b = CreateCommitContext::new(&ctx, &source, vec![a])
+            .add_file("other", "other")
+            .commit()
+            .await?;
+        // The copy source is an ancestor, but not a parent.
+        let c = CreateCommitContext::new(&ctx, &source, vec![b])
+            .add_file_with_copy_info("copy", "content", (a, "a"))?
+            .commit()
+            .await?;
+        let d = CreateCommitContext::new(&ctx, &source, vec![c])
+            .add_file("copy", "changed")
+            .delete_file("other")
+            .commit()
+            .await?;
+        let source = ReplaySource::mononoke(source);
+
+        let repo = blobrepo_factory::new_memblob_empty(None)?;
+        let report = replay(ctx.clone(), repo, &source, Some(&d.to_string()), &[]).await?;
+        assert_eq!(report.commits, 4);
+        assert_eq!(report.merges, 0);
+        assert_eq!(report.file_changes, 5);
+
+        // The range starts after the copy source, so the copy info of `c`
+        // can't be replayed.
+        let range = format!("{}..{}", a, d);
+        let repo = blobrepo_factory::new_memblob_empty(None)?;
+        let report = replay(ctx.clone(), repo, &source, Some(&range), &[]).await?;
+        assert_eq!(report.commits, 3);
+        Ok(())
+    }
+}
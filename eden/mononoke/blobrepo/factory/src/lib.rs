@@ -25,6 +25,7 @@ use dbbookmarks::SqlBookmarks;
 use deleted_files_manifest::RootDeletedManifestId;
 use derived_data::BonsaiDerived;
 use derived_data_filenodes::FilenodesOnlyPublic;
+use diffstat::ChangesetDiffstat;
 use failure_ext::FutureFailureErrorExt;
 use fastlog::RootFastlog;
 use fbinit::FacebookInit;
@@ -389,6 +390,7 @@ pub fn init_all_derived_data() -> DerivedDataConfig {
             BlameRoot::NAME.to_string(),
             FilenodesOnlyPublic::NAME.to_string(),
             ChangesetInfo::NAME.to_string(),
+            ChangesetDiffstat::NAME.to_string(),
             RootFastlog::NAME.to_string(),
             RootFsnodeId::NAME.to_string(),
             RootDeletedManifestId::NAME.to_string(),
@@ -396,6 +398,7 @@ pub fn init_all_derived_data() -> DerivedDataConfig {
             TreeHandle::NAME.to_string(),
         },
         unode_version: UnodeVersion::V2,
+        blame_ignore_revs: btreeset! {},
     }
 }
 
@@ -43,6 +43,10 @@ impl PushrebaseHook for GitMappingPushrebaseHook {
         }) as Box<dyn PushrebaseCommitHook>;
         Ok(hook)
     }
+
+    fn batch_key(&self) -> Option<String> {
+        Some(format!("git_mapping:{}", self.repository_id))
+    }
 }
 
 struct GitMappingCommitHook {
@@ -62,6 +62,10 @@ impl PushrebaseHook for GlobalrevPushrebaseHook {
 
         Ok(hook)
     }
+
+    fn batch_key(&self) -> Option<String> {
+        Some(format!("globalrev:{}", self.repository_id))
+    }
 }
 
 struct GlobalrevCommitHook {
@@ -8,7 +8,7 @@
 #![deny(warnings)]
 
 use std::collections::{HashMap, VecDeque};
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, RwLock};
 use std::time::Duration;
 
 use anyhow::Error;
@@ -45,8 +45,13 @@ define_stats! {
     max_staleness_secs: dynamic_singleton_counter("{}.max_staleness_secs", (reponame: String)),
 }
 
+/// For each bookmark, the latest commit in its history for which each derived
+/// data type is ready, keyed by derived data type name.
+type DerivedPositions = HashMap<BookmarkName, HashMap<&'static str, ChangesetId>>;
+
 pub struct WarmBookmarksCache {
     bookmarks: Arc<RwLock<HashMap<BookmarkName, ChangesetId>>>,
+    derived_positions: Arc<RwLock<DerivedPositions>>,
     terminate: Option<oneshot::Sender<()>>,
 }
 
@@ -58,6 +63,7 @@ pub type IsDerivedFn = dyn for<'a> Fn(&'a CoreContext, &'a BlobRepo, &'a Changes
     + Sync;
 
 pub struct Warmer {
+    name: &'static str,
     warmer: Box<WarmerFn>,
     is_derived: Box<IsDerivedFn>,
 }
@@ -77,7 +83,11 @@ pub fn create_warmer<D: BonsaiDerived>(ctx: &CoreContext) -> Warmer {
                 .map_err(Error::from)
                 .boxed()
         });
-    Warmer { warmer, is_derived }
+    Warmer {
+        name: D::NAME,
+        warmer,
+        is_derived,
+    }
 }
 
 impl WarmBookmarksCache {
@@ -110,10 +120,13 @@ impl WarmBookmarksCache {
             info!(ctx.logger(), "Starting warm bookmark cache updater");
             let bookmarks = init_bookmarks(&ctx, &repo, &warmers).await?;
             let bookmarks = Arc::new(RwLock::new(bookmarks));
+            let derived_positions = init_derived_positions(&ctx, &repo, &warmers).await?;
+            let derived_positions = Arc::new(RwLock::new(derived_positions));
 
             let loop_sleep = Duration::from_millis(1000);
             spawn_bookmarks_coordinator(
                 bookmarks.clone(),
+                derived_positions.clone(),
                 receiver,
                 ctx.clone(),
                 repo.clone(),
@@ -122,6 +135,7 @@ impl WarmBookmarksCache {
             );
             Ok(Self {
                 bookmarks,
+                derived_positions,
                 terminate: Some(sender),
             })
         }
@@ -136,6 +150,38 @@ impl WarmBookmarksCache {
     pub fn get_all(&self) -> HashMap<BookmarkName, ChangesetId> {
         self.bookmarks.read().unwrap().clone()
     }
+
+    /// Returns the latest commit in the history of the bookmark for which
+    /// the given derived data type is ready. Unlike `get`, this doesn't
+    /// wait for the other derived data types to catch up.
+    pub fn get_for_derived_type(
+        &self,
+        bookmark: &BookmarkName,
+        derived_data_type: &str,
+    ) -> Option<ChangesetId> {
+        self.derived_positions
+            .read()
+            .unwrap()
+            .get(bookmark)
+            .and_then(|positions| positions.get(derived_data_type).cloned())
+    }
+
+    /// Returns warm positions of the bookmark for every derived data type
+    /// that this cache warms. Types that aren't derived for any commit in
+    /// the recent history of the bookmark are omitted.
+    pub fn get_derived_positions(&self, bookmark: &BookmarkName) -> HashMap<String, ChangesetId> {
+        self.derived_positions
+            .read()
+            .unwrap()
+            .get(bookmark)
+            .map(|positions| {
+                positions
+                    .iter()
+                    .map(|(name, cs_id)| (name.to_string(), *cs_id))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
 }
 
 impl Drop for WarmBookmarksCache {
@@ -182,6 +228,54 @@ async fn init_bookmarks(
         .await
 }
 
+/// Finds warm positions of all publishing bookmarks for every derived data
+/// type separately. Types are looked up concurrently, so that a type that
+/// lags far behind doesn't hold up the others.
+async fn init_derived_positions(
+    ctx: &CoreContext,
+    repo: &BlobRepo,
+    warmers: &Arc<Vec<Warmer>>,
+) -> Result<DerivedPositions, Error> {
+    let all_bookmarks = repo
+        .get_bonsai_publishing_bookmarks_maybe_stale(ctx.clone())
+        .compat()
+        .try_collect::<HashMap<_, _>>()
+        .await?;
+
+    all_bookmarks
+        .into_iter()
+        .map(|(book, cs_id)| async move {
+            let book_name = &book.into_name();
+            let positions = warmers
+                .iter()
+                .map(|warmer| async move {
+                    let warmer = std::slice::from_ref(warmer);
+                    let maybe_cs_id = if is_derived(ctx, repo, &cs_id, warmer).await {
+                        Some(cs_id)
+                    } else {
+                        let (latest_derived, _) =
+                            find_all_underived_and_latest_derived(ctx, repo, book_name, warmer)
+                                .await?;
+                        match latest_derived {
+                            LatestDerivedBookmarkEntry::Found(maybe_cs_id_and_ts) => {
+                                maybe_cs_id_and_ts.map(|(cs_id, _)| cs_id)
+                            }
+                            LatestDerivedBookmarkEntry::NotFound => None,
+                        }
+                    };
+                    Ok::<_, Error>(maybe_cs_id.map(|cs_id| (warmer[0].name, cs_id)))
+                })
+                .collect::<FuturesUnordered<_>>()
+                .try_filter_map(|x| async { Ok(x) })
+                .try_collect::<HashMap<_, _>>()
+                .await?;
+            Ok::<_, Error>((book_name.clone(), positions))
+        })
+        .collect::<FuturesUnordered<_>>()
+        .try_collect::<HashMap<_, _>>()
+        .await
+}
+
 async fn is_derived(
     ctx: &CoreContext,
     repo: &BlobRepo,
@@ -198,19 +292,6 @@ async fn is_derived(
         .await
 }
 
-async fn derive_all(
-    ctx: &CoreContext,
-    repo: &BlobRepo,
-    cs_id: &ChangesetId,
-    warmers: &Arc<Vec<Warmer>>,
-) -> Result<(), Error> {
-    stream::iter(warmers.iter().map(Ok))
-        .try_for_each_concurrent(100, |warmer| {
-            (*warmer.warmer)(ctx.clone(), repo.clone(), *cs_id).compat()
-        })
-        .await
-}
-
 async fn move_bookmark_back_in_history_until_derived(
     ctx: &CoreContext,
     repo: &BlobRepo,
@@ -317,6 +398,7 @@ pub async fn find_all_underived_and_latest_derived(
 // Loop that finds bookmarks that were modified and spawns separate bookmark updaters for them
 fn spawn_bookmarks_coordinator(
     bookmarks: Arc<RwLock<HashMap<BookmarkName, ChangesetId>>>,
+    derived_positions: Arc<RwLock<DerivedPositions>>,
     terminate: oneshot::Receiver<()>,
     ctx: CoreContext,
     repo: BlobRepo,
@@ -384,13 +466,22 @@ fn spawn_bookmarks_coordinator(
                     // but that's not a big deal though - we'll do it on the next iteration
                     // of the loop
                     if need_spawning {
-                        cloned!(ctx, repo, book, bookmarks, live_updaters, warmers);
+                        cloned!(
+                            ctx,
+                            repo,
+                            book,
+                            bookmarks,
+                            derived_positions,
+                            live_updaters,
+                            warmers
+                        );
                         let _ = tokio::spawn(async move {
                             let res = single_bookmark_updater(
                                 &ctx,
                                 &repo,
                                 &book,
                                 &bookmarks,
+                                &derived_positions,
                                 &warmers,
                                 |ts: Timestamp| {
                                     live_updaters.with_write(|live_updaters| {
@@ -527,11 +618,61 @@ impl BookmarkUpdaterState {
     }
 }
 
+/// Moves the warm position of the derived data type for the bookmark to
+/// `cs_id`, unless it's already at a commit with a greater generation number.
+async fn advance_derived_position(
+    ctx: &CoreContext,
+    repo: &BlobRepo,
+    derived_positions: &Arc<RwLock<DerivedPositions>>,
+    bookmark: &BookmarkName,
+    derived_data_type: &'static str,
+    cs_id: ChangesetId,
+) -> Result<(), Error> {
+    let current = derived_positions.with_read(|derived_positions| {
+        derived_positions
+            .get(bookmark)
+            .and_then(|positions| positions.get(derived_data_type).cloned())
+    });
+    if let Some(current) = current {
+        if current == cs_id {
+            return Ok(());
+        }
+        let (current_gen, new_gen) = future::try_join(
+            repo.get_generation_number(ctx.clone(), current).compat(),
+            repo.get_generation_number(ctx.clone(), cs_id).compat(),
+        )
+        .await?;
+        if current_gen >= new_gen {
+            return Ok(());
+        }
+    }
+    derived_positions.with_write(|derived_positions| {
+        derived_positions
+            .entry(bookmark.clone())
+            .or_default()
+            .insert(derived_data_type, cs_id);
+    });
+    Ok(())
+}
+
+async fn delay_bookmark_update(ctx: &CoreContext, ts: Timestamp, delay_secs: i64) {
+    let cur_delay = ts.since_seconds();
+    if cur_delay < delay_secs {
+        let to_sleep = (delay_secs - cur_delay) as u64;
+        info!(
+            ctx.logger(),
+            "sleeping for {} secs before updating a bookmark", to_sleep
+        );
+        tokio::time::delay_for(Duration::from_secs(to_sleep)).await;
+    }
+}
+
 async fn single_bookmark_updater(
     ctx: &CoreContext,
     repo: &BlobRepo,
     bookmark: &BookmarkName,
     bookmarks: &Arc<RwLock<HashMap<BookmarkName, ChangesetId>>>,
+    derived_positions: &Arc<RwLock<DerivedPositions>>,
     warmers: &Arc<Vec<Warmer>>,
     mut staleness_reporter: impl FnMut(Timestamp),
 ) -> Result<(), Error> {
@@ -545,26 +686,28 @@ async fn single_bookmark_updater(
             "invalid warm bookmark cache delay value: {}", delay_secs
         );
     }
-    let update_bookmark = |ts: Timestamp, cs_id: ChangesetId| async move {
-        let cur_delay = ts.since_seconds();
-        if cur_delay < delay_secs {
-            let to_sleep = (delay_secs - cur_delay) as u64;
-            info!(
-                ctx.logger(),
-                "sleeping for {} secs before updating a bookmark", to_sleep
-            );
-            tokio::time::delay_for(Duration::from_secs(to_sleep)).await;
-        }
-        bookmarks.with_write(|bookmarks| bookmarks.insert(bookmark.clone(), cs_id));
-    };
 
     match latest_derived {
         // Move bookmark to the latest derived commit or delete the bookmark completely
         LatestDerivedBookmarkEntry::Found(maybe_cs_id_and_ts) => match maybe_cs_id_and_ts {
             Some((cs_id, ts)) => {
-                update_bookmark(ts, cs_id).await;
+                for warmer in warmers.iter() {
+                    advance_derived_position(
+                        ctx,
+                        repo,
+                        derived_positions,
+                        bookmark,
+                        warmer.name,
+                        cs_id,
+                    )
+                    .await?;
+                }
+                delay_bookmark_update(ctx, ts, delay_secs).await;
+                bookmarks.with_write(|bookmarks| bookmarks.insert(bookmark.clone(), cs_id));
             }
             None => {
+                derived_positions
+                    .with_write(|derived_positions| derived_positions.remove(&bookmark));
                 bookmarks.with_write(|bookmarks| bookmarks.remove(&bookmark));
             }
         },
@@ -576,26 +719,69 @@ async fn single_bookmark_updater(
         }
     }
 
-    for (underived_cs_id, ts) in underived_history {
-        staleness_reporter(ts);
+    let underived_history = Vec::from(underived_history);
+    if let Some((_, ts)) = underived_history.first() {
+        staleness_reporter(*ts);
+    }
 
-        let res = derive_all(&ctx, &repo, &underived_cs_id, &warmers).await;
-        match res {
-            Ok(()) => {
-                update_bookmark(ts, underived_cs_id).await;
-            }
-            Err(err) => {
-                warn!(
-                    ctx.logger(),
-                    "failed to derive data for {} while updating {}: {}",
-                    underived_cs_id,
-                    bookmark,
-                    err
-                );
-                break;
+    // Every derived data type walks the underived history on its own, so a
+    // slow type doesn't hold back the warm positions of the others. The
+    // bookmark itself moves to a commit once all types have derived it.
+    let underived_history = &underived_history;
+    let ready_counts = &Mutex::new(vec![0; underived_history.len()]);
+    let bookmark_index = &Mutex::new(None);
+    let staleness_reporter = &Mutex::new(staleness_reporter);
+    stream::iter(warmers.iter())
+        .for_each_concurrent(None, |warmer| async move {
+            for (index, (cs_id, ts)) in underived_history.iter().enumerate() {
+                let res = async {
+                    (*warmer.warmer)(ctx.clone(), repo.clone(), *cs_id)
+                        .compat()
+                        .await?;
+                    advance_derived_position(
+                        ctx,
+                        repo,
+                        derived_positions,
+                        bookmark,
+                        warmer.name,
+                        *cs_id,
+                    )
+                    .await
+                }
+                .await;
+                if let Err(err) = res {
+                    warn!(
+                        ctx.logger(),
+                        "failed to derive {} for {} while updating {}: {}",
+                        warmer.name,
+                        cs_id,
+                        bookmark,
+                        err
+                    );
+                    break;
+                }
+
+                let all_derived = {
+                    let mut ready_counts = ready_counts.lock().expect("lock poisoned");
+                    ready_counts[index] += 1;
+                    ready_counts[index] == warmers.len()
+                };
+                if all_derived {
+                    if let Some((_, next_ts)) = underived_history.get(index + 1) {
+                        (*staleness_reporter.lock().expect("lock poisoned"))(*next_ts);
+                    }
+                    delay_bookmark_update(ctx, *ts, delay_secs).await;
+                    // Updates of later commits might have finished their delay first.
+                    let mut bookmark_index = bookmark_index.lock().expect("lock poisoned");
+                    if bookmark_index.map_or(true, |prev| prev < index) {
+                        *bookmark_index = Some(index);
+                        bookmarks
+                            .with_write(|bookmarks| bookmarks.insert(bookmark.clone(), *cs_id));
+                    }
+                }
             }
-        }
-    }
+        })
+        .await;
 
     Ok(())
 }
@@ -695,6 +881,59 @@ mod tests {
         Ok(())
     }
 
+    #[fbinit::compat_test]
+    async fn test_derived_positions(fb: FacebookInit) -> Result<(), Error> {
+        let repo = linear::getrepo(fb).await;
+        let ctx = CoreContext::test_mock(fb);
+
+        let mut warmers: Vec<Warmer> = Vec::new();
+        warmers.push(create_warmer::<RootUnodeManifestId>(&ctx));
+        warmers.push(create_warmer::<RootFsnodeId>(&ctx));
+        let warmers = Arc::new(warmers);
+
+        let mut master = resolve_cs_id(&ctx, &repo, "master").await?;
+        for _ in 1..5 {
+            let new_master = CreateCommitContext::new(&ctx, &repo, vec![master])
+                .commit()
+                .await?;
+            bookmark(&ctx, &repo, "master").set_to(new_master).await?;
+            master = new_master;
+        }
+        RootFsnodeId::derive(ctx.clone(), repo.clone(), master)
+            .compat()
+            .await?;
+        let fsnodes_master = master;
+
+        for _ in 1..5 {
+            let new_master = CreateCommitContext::new(&ctx, &repo, vec![master])
+                .commit()
+                .await?;
+            bookmark(&ctx, &repo, "master").set_to(new_master).await?;
+            master = new_master;
+        }
+        RootUnodeManifestId::derive(ctx.clone(), repo.clone(), master)
+            .compat()
+            .await?;
+
+        // Fsnodes lag behind unodes, so the bookmark as a whole is only warm
+        // where fsnodes are, but unodes are warm all the way to the top.
+        let bookmarks = init_bookmarks(&ctx, &repo, &warmers).await?;
+        let master_book = BookmarkName::new("master")?;
+        assert_eq!(bookmarks, hashmap! {master_book.clone() => fsnodes_master});
+
+        let positions = init_derived_positions(&ctx, &repo, &warmers).await?;
+        assert_eq!(
+            positions,
+            hashmap! {
+                master_book.clone() => hashmap! {
+                    RootUnodeManifestId::NAME => master,
+                    RootFsnodeId::NAME => fsnodes_master,
+                }
+            }
+        );
+        Ok(())
+    }
+
     #[fbinit::compat_test]
     async fn test_a_lot_of_moves(fb: FacebookInit) -> Result<(), Error> {
         let repo = linear::getrepo(fb).await;
@@ -792,6 +1031,7 @@ mod tests {
         let (cancel, receiver_cancel) = oneshot::channel();
         spawn_bookmarks_coordinator(
             bookmarks.clone(),
+            Arc::new(RwLock::new(HashMap::new())),
             receiver_cancel,
             ctx.clone(),
             repo.clone(),
@@ -840,12 +1080,62 @@ mod tests {
         info!(ctx.logger(), "created the whole stack of commits");
 
         let master_book = BookmarkName::new("master")?;
-        single_bookmark_updater(&ctx, &repo, &master_book, &bookmarks, &warmers, |_| {}).await?;
+        let derived_positions = Arc::new(RwLock::new(HashMap::new()));
+        single_bookmark_updater(
+            &ctx,
+            &repo,
+            &master_book,
+            &bookmarks,
+            &derived_positions,
+            &warmers,
+            |_| {},
+        )
+        .await?;
 
         assert_eq!(
             bookmarks.with_read(|bookmarks| bookmarks.get(&master_book).cloned()),
             Some(master_cs_id)
         );
+        assert_eq!(
+            derived_positions.with_read(|positions| positions.get(&master_book).cloned()),
+            Some(hashmap! {RootUnodeManifestId::NAME => master_cs_id})
+        );
+
+        Ok(())
+    }
+
+    #[fbinit::compat_test]
+    async fn test_derived_positions_only_advance(fb: FacebookInit) -> Result<(), Error> {
+        let repo = linear::getrepo(fb).await;
+        let ctx = CoreContext::test_mock(fb);
+
+        let old_master = resolve_cs_id(&ctx, &repo, "master").await?;
+        let new_master = CreateCommitContext::new(&ctx, &repo, vec![old_master])
+            .commit()
+            .await?;
+
+        let master_book = BookmarkName::new("master")?;
+        let derived_positions = Arc::new(RwLock::new(HashMap::new()));
+        let get_position = || {
+            derived_positions.with_read(|positions| {
+                positions
+                    .get(&master_book)
+                    .and_then(|positions| positions.get(RootUnodeManifestId::NAME).cloned())
+            })
+        };
+
+        for &cs_id in &[new_master, old_master] {
+            advance_derived_position(
+                &ctx,
+                &repo,
+                &derived_positions,
+                &master_book,
+                RootUnodeManifestId::NAME,
+                cs_id,
+            )
+            .await?;
+            assert_eq!(get_position(), Some(new_master));
+        }
 
         Ok(())
     }
@@ -912,6 +1202,7 @@ mod tests {
         bookmark(&ctx, &repo, "master").set_to(master).await?;
 
         let warmer = Warmer {
+            name: RootUnodeManifestId::NAME,
             warmer: Box::new({
                 cloned!(failing_cs_id);
                 move |ctx, repo, cs_id| {
@@ -940,6 +1231,7 @@ mod tests {
         let (cancel, receiver_cancel) = oneshot::channel();
         spawn_bookmarks_coordinator(
             bookmarks.clone(),
+            Arc::new(RwLock::new(HashMap::new())),
             receiver_cancel,
             ctx.clone(),
             repo.clone(),
@@ -965,6 +1257,7 @@ mod tests {
         let (cancel, receiver_cancel) = oneshot::channel();
         spawn_bookmarks_coordinator(
             bookmarks.clone(),
+            Arc::new(RwLock::new(HashMap::new())),
             receiver_cancel,
             ctx,
             repo,
@@ -999,6 +1292,7 @@ mod tests {
         let derive_sleep_time_ms = 100;
         let how_many_derived = Arc::new(RwLock::new(HashMap::new()));
         let warmer = Warmer {
+            name: RootUnodeManifestId::NAME,
             warmer: Box::new({
                 cloned!(how_many_derived);
                 move |ctx, repo, cs_id| {
@@ -1045,6 +1339,7 @@ mod tests {
         let (cancel, receiver_cancel) = oneshot::channel();
         spawn_bookmarks_coordinator(
             bookmarks.clone(),
+            Arc::new(RwLock::new(HashMap::new())),
             receiver_cancel,
             ctx.clone(),
             repo.clone(),
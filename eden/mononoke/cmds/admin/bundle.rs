@@ -0,0 +1,322 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::fs::File;
+use std::io::{Cursor, Write};
+use std::sync::Arc;
+
+use anyhow::{format_err, Error};
+use blobrepo::BlobRepo;
+use bookmarks::{BookmarkName, BookmarkUpdateReason};
+use bytes::Bytes;
+use clap::{App, Arg, ArgMatches, SubCommand};
+use cmdlib::{args, helpers::csid_resolve};
+use context::CoreContext;
+use fbinit::FacebookInit;
+use futures::{
+    compat::{Future01CompatExt, Stream01CompatExt},
+    future::try_join_all,
+    stream::TryStreamExt,
+};
+use futures_ext::StreamExt as OldStreamExt;
+use futures_old::stream::Stream as OldStream;
+use getbundle_response::{create_full_changegroup_parts, SessionLfsParams};
+use mercurial_bundles::{
+    bundle2::{Bundle2Stream, StreamEvent},
+    create_bundle_stream, parts,
+};
+use mercurial_types::HgChangesetId;
+use metaconfig_types::{PushrebaseFlags, RepoReadOnly};
+use mononoke_types::ChangesetId;
+use reachabilityindex::LeastCommonAncestorsHint;
+use skiplist::SkiplistIndex;
+use slog::{info, Logger};
+use unbundle::{PostResolveAction, PostResolvePush};
+
+use crate::error::SubcommandError;
+
+pub const BUNDLE: &str = "bundle";
+const SUBCOMMAND_EXPORT: &str = "export";
+const SUBCOMMAND_IMPORT: &str = "import";
+
+const ARG_HEAD: &str = "head";
+const ARG_COMMON: &str = "common";
+const ARG_BOOKMARK: &str = "bookmark";
+const ARG_LFS_THRESHOLD: &str = "lfs-threshold";
+const ARG_NO_BOOKMARKS: &str = "no-bookmarks";
+const ARG_FILE: &str = "file";
+
+pub fn build_subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name(BUNDLE)
+        .about("export and import commits as self-contained bundle2 files")
+        .subcommand(
+            SubCommand::with_name(SUBCOMMAND_EXPORT)
+                .about(
+                    "write commits, their trees and files, and bookmark positions to a bundle file",
+                )
+                .arg(
+                    Arg::with_name(ARG_HEAD)
+                        .long(ARG_HEAD)
+                        .help(
+                            "(hg|bonsai) commit hash or bookmark to export with all its ancestors",
+                        )
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name(ARG_COMMON)
+                        .long(ARG_COMMON)
+                        .help(
+                            "(hg|bonsai) commit hash or bookmark that the importing repo already \
+                             has. Its ancestors are not exported",
+                        )
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1),
+                )
+                .arg(
+                    Arg::with_name(ARG_BOOKMARK)
+                        .long(ARG_BOOKMARK)
+                        .help(
+                            "bookmark whose current position is recorded in the bundle, \
+                             its commit is exported as one of the heads",
+                        )
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1),
+                )
+                .arg(
+                    Arg::with_name(ARG_LFS_THRESHOLD)
+                        .long(ARG_LFS_THRESHOLD)
+                        .help(
+                            "files larger than this are exported as LFS pointers. Their \
+                             content has to be available to the importing repo separately",
+                        )
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name(ARG_FILE)
+                        .help("bundle file to write")
+                        .takes_value(true)
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name(SUBCOMMAND_IMPORT)
+                .about("load commits from a bundle file and move the bookmarks recorded in it")
+                .arg(
+                    Arg::with_name(ARG_NO_BOOKMARKS)
+                        .long(ARG_NO_BOOKMARKS)
+                        .help("only import commits, leave bookmarks untouched"),
+                )
+                .arg(
+                    Arg::with_name(ARG_FILE)
+                        .help("bundle file to read")
+                        .takes_value(true)
+                        .required(true),
+                ),
+        )
+}
+
+pub async fn subcommand_bundle<'a>(
+    fb: FacebookInit,
+    logger: Logger,
+    matches: &'a ArgMatches<'_>,
+    sub_m: &'a ArgMatches<'_>,
+) -> Result<(), SubcommandError> {
+    args::init_cachelib(fb, &matches, None);
+    let ctx = CoreContext::new_with_logger(fb, logger.clone());
+    let repo = args::open_repo(fb, &logger, &matches).compat().await?;
+
+    match sub_m.subcommand() {
+        (SUBCOMMAND_EXPORT, Some(sub_m)) => {
+            let heads = values(sub_m, ARG_HEAD);
+            let common = values(sub_m, ARG_COMMON);
+            let bookmarks = values(sub_m, ARG_BOOKMARK);
+            let lfs_threshold = sub_m
+                .value_of(ARG_LFS_THRESHOLD)
+                .map(|threshold| threshold.parse::<u64>())
+                .transpose()
+                .map_err(Error::from)?;
+            let file = sub_m
+                .value_of(ARG_FILE)
+                .ok_or(SubcommandError::InvalidArgs)?;
+            bundle_export(&ctx, &repo, heads, common, bookmarks, lfs_threshold, file).await?;
+        }
+        (SUBCOMMAND_IMPORT, Some(sub_m)) => {
+            let file = sub_m
+                .value_of(ARG_FILE)
+                .ok_or(SubcommandError::InvalidArgs)?;
+            let move_bookmarks = !sub_m.is_present(ARG_NO_BOOKMARKS);
+            bundle_import(&ctx, &repo, file, move_bookmarks).await?;
+        }
+        _ => return Err(SubcommandError::InvalidArgs),
+    }
+    Ok(())
+}
+
+fn values(matches: &ArgMatches<'_>, name: &str) -> Vec<String> {
+    matches
+        .values_of(name)
+        .map(|values| values.map(|v| v.to_string()).collect())
+        .unwrap_or_default()
+}
+
+async fn resolve_hg_changesets(
+    ctx: &CoreContext,
+    repo: &BlobRepo,
+    revs: Vec<String>,
+) -> Result<Vec<HgChangesetId>, Error> {
+    try_join_all(revs.into_iter().map(|rev| async move {
+        let cs_id = csid_resolve(ctx.clone(), repo.clone(), rev)
+            .compat()
+            .await?;
+        repo.get_hg_from_bonsai_changeset(ctx.clone(), cs_id)
+            .compat()
+            .await
+    }))
+    .await
+}
+
+async fn bundle_export(
+    ctx: &CoreContext,
+    repo: &BlobRepo,
+    heads: Vec<String>,
+    common: Vec<String>,
+    bookmarks: Vec<String>,
+    lfs_threshold: Option<u64>,
+    file: &str,
+) -> Result<(), Error> {
+    let mut heads = resolve_hg_changesets(ctx, repo, heads).await?;
+    let common = resolve_hg_changesets(ctx, repo, common).await?;
+
+    let bookmarks = try_join_all(bookmarks.into_iter().map(|bookmark| async move {
+        let name = BookmarkName::new(&bookmark)?;
+        let hg_cs_id = repo
+            .get_bookmark(ctx.clone(), &name)
+            .compat()
+            .await?
+            .ok_or_else(|| format_err!("Bookmark does not exist: {}", name))?;
+        Ok::<_, Error>((bookmark, hg_cs_id))
+    }))
+    .await?;
+
+    // The bookmarks are exported as heads too, so that the bundle contains the commits they
+    // point to and import can move them.
+    let mut bookmark_parts = vec![];
+    for (bookmark, hg_cs_id) in bookmarks {
+        info!(ctx.logger(), "Recording {} at {}", bookmark, hg_cs_id);
+        if !heads.contains(&hg_cs_id) {
+            heads.push(hg_cs_id);
+        }
+        bookmark_parts.push(parts::bookmark_pushkey_part(
+            bookmark,
+            String::new(),
+            hg_cs_id.to_hex().to_string(),
+        )?);
+    }
+
+    let lca_hint: Arc<dyn LeastCommonAncestorsHint> = Arc::new(SkiplistIndex::new());
+    let lfs_params = SessionLfsParams {
+        threshold: lfs_threshold,
+    };
+    let (cg_part, tp_part) =
+        create_full_changegroup_parts(ctx, repo, common, heads, &lca_hint, &lfs_params).await?;
+
+    // The order of parts matches what the unbundle resolver expects for a
+    // plain push, which is how `import` loads the bundle back.
+    let mut bundle_parts = vec![parts::replycaps_part(Default::default())?, cg_part];
+    bundle_parts.extend(bookmark_parts);
+    bundle_parts.push(tp_part);
+
+    let mut output = File::create(file)?;
+    let mut size = 0;
+    let mut chunks = create_bundle_stream(bundle_parts, None).compat();
+    while let Some(chunk) = chunks.try_next().await? {
+        output.write_all(&chunk)?;
+        size += chunk.len();
+    }
+    output.flush()?;
+
+    info!(ctx.logger(), "Wrote {} bytes to {}", size, file);
+    Ok(())
+}
+
+async fn bundle_import(
+    ctx: &CoreContext,
+    repo: &BlobRepo,
+    file: &str,
+    move_bookmarks: bool,
+) -> Result<(), Error> {
+    let bundle = Bytes::from(std::fs::read(file)?);
+    let bundle_stream = Bundle2Stream::new(ctx.logger().clone(), Cursor::new(bundle))
+        .filter_map(|e| match e {
+            StreamEvent::Next(item) => Some(item),
+            StreamEvent::Done(..) => None,
+        })
+        .boxify();
+
+    let resolution = unbundle::resolve(
+        ctx,
+        repo,
+        false, // infinitepush_writes_allowed
+        bundle_stream,
+        RepoReadOnly::ReadWrite,
+        None, // maybe_full_content
+        true, // pure_push_allowed
+        PushrebaseFlags::default(),
+    )
+    .await?;
+
+    let PostResolvePush {
+        bookmark_pushes,
+        uploaded_bonsais,
+        ..
+    } = match resolution {
+        PostResolveAction::Push(action) => action,
+        _ => return Err(format_err!("{} is not a bundle created by export", file)),
+    };
+    info!(ctx.logger(), "Imported {} commits", uploaded_bonsais.len());
+
+    if !move_bookmarks || bookmark_pushes.is_empty() {
+        return Ok(());
+    }
+
+    let mut transaction = repo.update_bookmark_transaction(ctx.clone());
+    for bookmark_push in bookmark_pushes {
+        let new_cs_id: ChangesetId = match bookmark_push.new {
+            Some(cs_id) => cs_id,
+            None => continue,
+        };
+        let name = bookmark_push.name;
+        let old_cs_id = repo
+            .get_bonsai_bookmark(ctx.clone(), &name)
+            .compat()
+            .await?;
+        info!(
+            ctx.logger(),
+            "Moving {} from {:?} to {}", name, old_cs_id, new_cs_id
+        );
+        match old_cs_id {
+            Some(old_cs_id) => transaction.update(
+                &name,
+                new_cs_id,
+                old_cs_id,
+                BookmarkUpdateReason::ManualMove,
+            )?,
+            None => transaction.create(&name, new_cs_id, BookmarkUpdateReason::ManualMove)?,
+        }
+    }
+    if !transaction.commit().compat().await? {
+        return Err(format_err!(
+            "Bookmarks were moved concurrently, they were not updated"
+        ));
+    }
+    Ok(())
+}
@@ -33,6 +33,7 @@ use crate::skiplist_subcommand::subcommand_skiplist;
 mod blobstore_fetch;
 mod bonsai_fetch;
 mod bookmarks_manager;
+mod bundle;
 mod common;
 mod content_fetch;
 mod crossrepo;
@@ -76,6 +77,7 @@ fn setup_app<'a, 'b>() -> App<'a, 'b> {
         .subcommand(subcommand_blame::build_subcommand())
         .subcommand(subcommand_deleted_manifest::build_subcommand())
         .subcommand(derived_data::build_subcommand())
+        .subcommand(bundle::build_subcommand())
 }
 
 #[fbinit::main]
@@ -152,6 +154,9 @@ fn main(fb: FacebookInit) -> ExitCode {
             (derived_data::DERIVED_DATA, Some(sub_m)) => {
                 derived_data::subcommand_derived_data(fb, logger, &matches, sub_m).await
             }
+            (bundle::BUNDLE, Some(sub_m)) => {
+                bundle::subcommand_bundle(fb, logger, &matches, sub_m).await
+            }
             _ => Err(SubcommandError::InvalidArgs),
         }
     });
@@ -13,21 +13,31 @@ use clap::{App, Arg, ArgMatches, SubCommand};
 use cloned::cloned;
 use cmdlib::{args, helpers};
 use context::CoreContext;
+use derived_data::BonsaiDerived;
 use failure_ext::FutureFailureErrorExt;
+use fastlog::list_file_history;
 use fbinit::FacebookInit;
-use futures::compat::Future01CompatExt;
+use futures::{
+    compat::Future01CompatExt,
+    future::{ready, try_join},
+    stream::{self as new_stream, StreamExt as NewStreamExt, TryStreamExt},
+};
 use futures_ext::{
     bounded_traversal::bounded_traversal_stream, try_boxfuture, BoxFuture, FutureExt,
 };
 use futures_old::future::{self, join_all, Future};
 use futures_old::stream::Stream;
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
 use itertools::{Either, Itertools};
+use manifest::{Entry, ManifestOps};
 use mercurial_types::{blobs::HgBlobChangeset, HgChangesetId, HgEntryId, HgManifest, MPath};
-use mononoke_types::{typed_hash::MononokeId, ContentId, Timestamp};
+use mononoke_types::{typed_hash::MononokeId, ChangesetId, ContentId, Timestamp};
 use redactedblobstore::SqlRedactedContentStore;
+use revset::RangeNodeStream;
 use slog::{info, Logger};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::sync::Arc;
+use unodes::RootUnodeManifestId;
 
 use crate::error::SubcommandError;
 
@@ -35,6 +45,11 @@ pub const REDACTION: &str = "redaction";
 const REDACTION_ADD: &str = "add";
 const REDACTION_REMOVE: &str = "remove";
 const REDACTION_LIST: &str = "list";
+const REDACTION_ADD_HISTORY: &str = "add-history";
+const ARG_FROM: &str = "from";
+const ARG_TO: &str = "to";
+const ARG_GLOB: &str = "glob";
+const ARG_DRY_RUN: &str = "dry-run";
 
 pub fn build_subcommand<'a, 'b>() -> App<'a, 'b> {
     SubCommand::with_name(REDACTION)
@@ -85,6 +100,52 @@ pub fn build_subcommand<'a, 'b>() -> App<'a, 'b> {
                         .required(true),
                 )
         )
+        .subcommand(
+            SubCommand::with_name(REDACTION_ADD_HISTORY)
+                .about("redact every version that the given files had in a range of commits")
+                .arg(
+                    Arg::with_name("task")
+                        .help("Task tracking the redaction request")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name(ARG_TO)
+                        .long(ARG_TO)
+                        .help("commit hash or bookmark where the history walk starts")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name(ARG_FROM)
+                        .long(ARG_FROM)
+                        .help(
+                            "ancestor commit hash or bookmark where the history walk stops \
+                             (inclusive). If not set, the whole history is walked",
+                        )
+                        .takes_value(true)
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name(ARG_GLOB)
+                        .long(ARG_GLOB)
+                        .help(
+                            "treat FILES_LIST as glob patterns. Patterns are matched against \
+                             files existing in the endpoints of the range, and against files \
+                             changed within the range if --from is set",
+                        ),
+                )
+                .arg(
+                    Arg::with_name(ARG_DRY_RUN)
+                        .long(ARG_DRY_RUN)
+                        .help("only report the content that would be redacted"),
+                )
+                .args_from_usage(
+                    r#"
+                        <FILES_LIST>...                             'list of files (or glob patterns) to be redacted'
+                        "#,
+                )
+        )
 }
 
 fn find_files_with_given_content_id_blobstore_keys(
@@ -189,6 +250,9 @@ pub async fn subcommand_redaction<'a>(
         (REDACTION_ADD, Some(sub_sub_m)) => redaction_add(fb, logger, matches, sub_sub_m),
         (REDACTION_REMOVE, Some(sub_sub_m)) => redaction_remove(fb, logger, matches, sub_sub_m),
         (REDACTION_LIST, Some(sub_sub_m)) => redaction_list(fb, logger, matches, sub_sub_m),
+        (REDACTION_ADD_HISTORY, Some(sub_sub_m)) => {
+            return redaction_add_history(fb, logger, matches, sub_sub_m).await;
+        }
         _ => {
             eprintln!("{}", matches.usage());
             ::std::process::exit(1);
@@ -389,3 +453,292 @@ fn redaction_remove(
         })
         .boxify()
 }
+
+/// Build a matcher out of the glob patterns given on the command line
+fn build_globset(patterns: &[String]) -> Result<GlobSet, Error> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = GlobBuilder::new(pattern)
+            .literal_separator(true)
+            .build()
+            .map_err(|e| format_err!("Invalid glob {}: {}", pattern, e))?;
+        builder.add(glob);
+    }
+    Ok(builder.build()?)
+}
+
+fn mpath_matches(globset: &GlobSet, path: &MPath) -> bool {
+    globset.is_match(String::from_utf8_lossy(&path.to_vec()).as_ref())
+}
+
+/// List all files in the given changeset that match the globset
+async fn matching_files_at(
+    ctx: &CoreContext,
+    repo: &BlobRepo,
+    cs_id: ChangesetId,
+    globset: &GlobSet,
+) -> Result<Vec<MPath>, Error> {
+    let root = RootUnodeManifestId::derive(ctx.clone(), repo.clone(), cs_id)
+        .compat()
+        .await?;
+    let files = root
+        .manifest_unode_id()
+        .list_leaf_entries(ctx.clone(), repo.get_blobstore())
+        .map(|(path, _)| path)
+        .collect()
+        .compat()
+        .await?;
+    Ok(files
+        .into_iter()
+        .filter(|path| mpath_matches(globset, path))
+        .collect())
+}
+
+/// Find every path matching the globset that is visible at either end of the
+/// range, or that was changed (including deleted) by a commit in the range.
+async fn matching_files_in_range(
+    ctx: &CoreContext,
+    repo: &BlobRepo,
+    to: ChangesetId,
+    from: Option<ChangesetId>,
+    range: Option<&HashSet<ChangesetId>>,
+    globset: &GlobSet,
+) -> Result<BTreeSet<MPath>, Error> {
+    let mut paths: BTreeSet<_> = matching_files_at(ctx, repo, to, globset)
+        .await?
+        .into_iter()
+        .collect();
+    if let Some(from) = from {
+        paths.extend(matching_files_at(ctx, repo, from, globset).await?);
+    }
+    if let Some(range) = range {
+        let changed: Vec<_> = new_stream::iter(range.iter().cloned())
+            .map(|cs_id| async move {
+                let bcs = cs_id.load(ctx.clone(), repo.blobstore()).compat().await?;
+                let paths: Vec<_> = bcs
+                    .file_changes()
+                    .map(|(path, _)| path.clone())
+                    .filter(|path| mpath_matches(globset, path))
+                    .collect();
+                Ok::<_, Error>(paths)
+            })
+            .buffer_unordered(100)
+            .try_collect()
+            .await?;
+        paths.extend(changed.into_iter().flatten());
+    }
+    Ok(paths)
+}
+
+/// Content of the file at the given path in the given changeset, if there is
+/// a file there.
+async fn content_id_at(
+    ctx: &CoreContext,
+    repo: &BlobRepo,
+    cs_id: ChangesetId,
+    path: &MPath,
+) -> Result<Option<ContentId>, Error> {
+    let root = RootUnodeManifestId::derive(ctx.clone(), repo.clone(), cs_id)
+        .compat()
+        .await?;
+    let entry = root
+        .manifest_unode_id()
+        .find_entry(ctx.clone(), repo.get_blobstore(), Some(path.clone()))
+        .compat()
+        .await?;
+    match entry {
+        Some(Entry::Leaf(file_unode_id)) => {
+            let unode = file_unode_id
+                .load(ctx.clone(), repo.blobstore())
+                .compat()
+                .await?;
+            Ok(Some(unode.content_id().clone()))
+        }
+        Some(Entry::Tree(_)) | None => Ok(None),
+    }
+}
+
+/// Use fastlog to find every distinct version of `path` in the history of
+/// `to`, restricted to `range` if it is set. For each content the changesets
+/// where it was introduced are returned.
+async fn content_history_for_path(
+    ctx: &CoreContext,
+    repo: &BlobRepo,
+    path: &MPath,
+    to: ChangesetId,
+    from: Option<ChangesetId>,
+    range: Option<Arc<HashSet<ChangesetId>>>,
+) -> Result<BTreeMap<ContentId, BTreeSet<ChangesetId>>, Error> {
+    let terminator = {
+        cloned!(range);
+        move |cs_id| {
+            let out_of_range = range
+                .as_ref()
+                .map_or(false, |range| !range.contains(&cs_id));
+            ready(Ok::<_, Error>(out_of_range))
+        }
+    };
+    let history = list_file_history(
+        ctx.clone(),
+        repo.clone(),
+        Some(path.clone()),
+        to,
+        Some(terminator),
+    )
+    .await
+    .map_err(Error::from)?
+    .try_filter(|cs_id| {
+        let in_range = range.as_ref().map_or(true, |range| range.contains(cs_id));
+        ready(in_range)
+    })
+    .map_ok(|cs_id| async move {
+        let content_id = content_id_at(ctx, repo, cs_id, path).await?;
+        Ok::<_, Error>(content_id.map(|content_id| (content_id, cs_id)))
+    })
+    .try_buffer_unordered(100)
+    .try_collect::<Vec<_>>()
+    .await?;
+
+    let mut versions: BTreeMap<ContentId, BTreeSet<ChangesetId>> = BTreeMap::new();
+    for (content_id, cs_id) in history.into_iter().flatten() {
+        versions.entry(content_id).or_default().insert(cs_id);
+    }
+    // The version visible at the start of the range might have been introduced
+    // before it, so it is not part of the filtered history.
+    if let Some(from) = from {
+        if let Some(content_id) = content_id_at(ctx, repo, from, path).await? {
+            versions.entry(content_id).or_default().insert(from);
+        }
+    }
+    Ok(versions)
+}
+
+async fn redaction_add_history(
+    fb: FacebookInit,
+    logger: Logger,
+    matches: &ArgMatches<'_>,
+    sub_m: &ArgMatches<'_>,
+) -> Result<(), SubcommandError> {
+    let task = sub_m
+        .value_of("task")
+        .ok_or(SubcommandError::InvalidArgs)?
+        .to_string();
+    let to = sub_m
+        .value_of(ARG_TO)
+        .ok_or(SubcommandError::InvalidArgs)?
+        .to_string();
+    let from = sub_m.value_of(ARG_FROM).map(|from| from.to_string());
+    let patterns: Vec<String> = sub_m
+        .values_of("FILES_LIST")
+        .ok_or_else(|| format_err!("File list is needed"))?
+        .map(|p| p.to_string())
+        .collect();
+    let dry_run = sub_m.is_present(ARG_DRY_RUN);
+
+    args::init_cachelib(fb, &matches, None);
+    let ctx = CoreContext::new_with_logger(fb, logger.clone());
+    let (repo, redacted_blobs) = try_join(
+        args::open_repo(fb, &logger, &matches).compat(),
+        args::open_sql::<SqlRedactedContentStore>(fb, &matches)
+            .context("While opening SqlRedactedContentStore")
+            .from_err::<Error>()
+            .compat(),
+    )
+    .await?;
+
+    let to = helpers::csid_resolve(ctx.clone(), repo.clone(), to)
+        .compat()
+        .await?;
+    let from = match from {
+        Some(from) => Some(
+            helpers::csid_resolve(ctx.clone(), repo.clone(), from)
+                .compat()
+                .await?,
+        ),
+        None => None,
+    };
+
+    let range = match from {
+        Some(from) => {
+            let range: HashSet<_> =
+                RangeNodeStream::new(ctx.clone(), repo.get_changeset_fetcher(), from, to)
+                    .collect()
+                    .compat()
+                    .await?
+                    .into_iter()
+                    .collect();
+            if range.is_empty() {
+                return Err(format_err!("{} is not an ancestor of {}", from, to).into());
+            }
+            Some(Arc::new(range))
+        }
+        None => None,
+    };
+
+    let paths: BTreeSet<MPath> = if sub_m.is_present(ARG_GLOB) {
+        let globset = build_globset(&patterns)?;
+        matching_files_in_range(&ctx, &repo, to, from, range.as_deref(), &globset).await?
+    } else {
+        patterns
+            .iter()
+            .map(|path| MPath::new(path))
+            .collect::<Result<_, _>>()?
+    };
+
+    info!(
+        logger,
+        "Walking history of {} paths, please be patient.",
+        paths.len()
+    );
+    let mut versions_per_path = vec![];
+    for path in paths {
+        let versions =
+            content_history_for_path(&ctx, &repo, &path, to, from, range.clone()).await?;
+        versions_per_path.push((path, versions));
+    }
+
+    let already_redacted = redacted_blobs.get_all_redacted_blobs().compat().await?;
+    let mut keys_to_redact = BTreeSet::new();
+    for (path, versions) in &versions_per_path {
+        println!("{}: {} version(s)", path, versions.len());
+        for (content_id, cs_ids) in versions {
+            let key = content_id.blobstore_key();
+            let status = match already_redacted.get(&key) {
+                Some(task) => format!(" (already redacted by {})", task),
+                None => {
+                    keys_to_redact.insert(key);
+                    "".to_string()
+                }
+            };
+            let cs_ids = cs_ids.iter().map(|cs_id| cs_id.to_string()).join(", ");
+            println!("\t{} in {}{}", content_id, cs_ids, status);
+        }
+    }
+
+    if keys_to_redact.is_empty() {
+        info!(logger, "Nothing new to redact");
+        return Ok(());
+    }
+    if dry_run {
+        info!(
+            logger,
+            "Dry run: {} blobs would be redacted under task {}",
+            keys_to_redact.len(),
+            task
+        );
+        return Ok(());
+    }
+
+    let keys_to_redact: Vec<_> = keys_to_redact.into_iter().collect();
+    redacted_blobs
+        .insert_redacted_blobs(&keys_to_redact, &task, &Timestamp::now())
+        .compat()
+        .await?;
+    info!(
+        logger,
+        "Redacted {} blobs under task {}",
+        keys_to_redact.len(),
+        task
+    );
+    Ok(())
+}
@@ -244,14 +244,7 @@ fn diff(
                 contents: xdiff::FileContent::Inline(old),
                 file_type: xdiff::FileType::Regular,
             };
-            let diff = xdiff::diff_unified(
-                Some(old),
-                Some(new),
-                xdiff::DiffOpts {
-                    context: 3,
-                    copy_info: xdiff::CopyInfo::None,
-                },
-            );
+            let diff = xdiff::diff_unified(Some(old), Some(new), xdiff::DiffOpts::default());
             String::from_utf8_lossy(&diff).into_owned()
         })
 }
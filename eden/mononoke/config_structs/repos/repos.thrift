@@ -125,6 +125,9 @@ struct RawDerivedDataConfig {
   2: optional set<string> derived_data_types,
   // Defaults to v1
   3: optional RawUnodeVersion raw_unode_version,
+  // Bonsai changeset ids whose changes blame should skip over, attributing
+  // lines to the previous change instead (e.g. mass reformatting commits)
+  4: optional list<string> blame_ignore_revs,
 }
 
 union RawUnodeVersion {
@@ -239,6 +242,8 @@ struct RawPushrebaseParams {
     7: optional bool emit_obsmarkers,
     8: optional bool assign_globalrevs,
     9: optional bool populate_git_mapping,
+    10: optional i64 textual_merge_size_limit,
+    11: optional i64 landing_queue_window_ms,
 }
 
 struct RawBookmarkConfig {
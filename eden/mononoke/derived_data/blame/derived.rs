@@ -100,7 +100,7 @@ impl BlameRootMapping {
     }
 
     fn format_key(&self, csid: &ChangesetId) -> String {
-        format!("derived_rootblame.v1.{}", csid)
+        format!("derived_rootblame.v2.{}", csid)
     }
 }
 
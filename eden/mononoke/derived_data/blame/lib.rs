@@ -14,6 +14,8 @@ pub use derived::{fetch_file_full_content, BlameRoot, BlameRootMapping};
 #[cfg(test)]
 mod tests;
 
+use std::collections::{HashMap, HashSet};
+
 use anyhow::Error;
 use blobrepo::BlobRepo;
 use blobstore::{Loadable, LoadableError};
@@ -21,15 +23,16 @@ use bytes::Bytes;
 use cloned::cloned;
 use context::CoreContext;
 use derived_data::{BonsaiDerived, DeriveError};
+use futures::{compat::Future01CompatExt, future::try_join_all, try_join, TryFutureExt};
 use futures_ext::FutureExt;
 use futures_old::{future, Future};
 use manifest::ManifestOps;
 use mononoke_types::{
     blame::{Blame, BlameId, BlameMaybeRejected, BlameRejected},
-    ChangesetId, MPath,
+    ChangesetId, FileUnodeId, MPath,
 };
 use thiserror::Error;
-use unodes::RootUnodeManifestId;
+use unodes::{find_unode_renames, RootUnodeManifestId};
 
 #[derive(Debug, Error)]
 pub enum BlameError {
@@ -80,6 +83,121 @@ pub fn fetch_blame(
         })
 }
 
+/// Fetch content and blame for a file, skipping over the changes made by
+/// `ignore_csids` (e.g. mass reformatting commits).
+///
+/// Lines changed by an ignored changeset are attributed to the change that
+/// last touched the lines they replaced, following renames, like git's
+/// `blame --ignore-rev`.
+pub async fn fetch_blame_ignoring(
+    ctx: CoreContext,
+    repo: BlobRepo,
+    csid: ChangesetId,
+    path: MPath,
+    ignore_csids: &HashSet<ChangesetId>,
+) -> Result<(Bytes, Blame), BlameError> {
+    let (content, mut blame) = fetch_blame(ctx.clone(), repo.clone(), csid, path)
+        .compat()
+        .await?;
+
+    // Blames of the files touched by ignored changesets as if they had not
+    // changed them.  Re-attributing lines may uncover more ignored changesets,
+    // so repeat until all of them are known.
+    let mut replacements = HashMap::new();
+    loop {
+        let missing: HashSet<_> = blame
+            .ranges()
+            .iter()
+            .filter(|range| ignore_csids.contains(&range.csid))
+            .map(|range| (range.csid, range.path.clone()))
+            .filter(|key| !replacements.contains_key(key))
+            .collect();
+        if missing.is_empty() {
+            break;
+        }
+        let fetched = try_join_all(missing.into_iter().map(|(csid, path)| {
+            cloned!(ctx, repo);
+            async move {
+                let blame = blame_ignoring_changeset(ctx, repo, csid, path.clone()).await?;
+                Ok::<_, BlameError>(((csid, path), blame))
+            }
+        }))
+        .await?;
+        replacements.extend(fetched);
+        blame = blame.reattribute(&replacements)?;
+    }
+
+    Ok((content, blame))
+}
+
+/// Blame for a file as of `csid`, with lines changed by `csid` attributed
+/// to the lines they replaced in its parents.
+async fn blame_ignoring_changeset(
+    ctx: CoreContext,
+    repo: BlobRepo,
+    csid: ChangesetId,
+    path: MPath,
+) -> Result<Blame, BlameError> {
+    let blobstore = repo.get_blobstore().boxed();
+    let bonsai = csid
+        .load(ctx.clone(), &blobstore)
+        .compat()
+        .await
+        .map_err(Error::from)?;
+    let root = RootUnodeManifestId::derive(ctx.clone(), repo.clone(), csid)
+        .compat()
+        .await?;
+    let renames = find_unode_renames(ctx.clone(), repo.clone(), &bonsai)
+        .compat()
+        .await?;
+    let file_unode_id = root
+        .manifest_unode_id()
+        .clone()
+        .find_entry(ctx.clone(), blobstore.clone(), Some(path.clone()))
+        .compat()
+        .await?
+        .and_then(|entry| entry.into_leaf())
+        .ok_or_else(|| BlameError::NoSuchPath(path.clone()))?;
+    let file_unode = file_unode_id
+        .load(ctx.clone(), &blobstore)
+        .compat()
+        .await
+        .map_err(Error::from)?;
+
+    let parents: Vec<FileUnodeId> = file_unode
+        .parents()
+        .iter()
+        .cloned()
+        .chain(renames.get(&path).cloned())
+        .collect();
+    let parents_content_and_blame = try_join_all(parents.into_iter().map(|parent| {
+        cloned!(ctx, blobstore);
+        async move {
+            let (content, blame) = try_join!(
+                derived::fetch_file_full_content(ctx.clone(), blobstore.clone(), parent).compat(),
+                BlameId::from(parent)
+                    .load(ctx.clone(), &blobstore)
+                    .compat()
+                    .map_err(Error::from),
+            )?;
+            match (content, blame.into_blame()) {
+                (Ok(content), Ok(blame)) => Ok::<_, Error>(Some((content, blame))),
+                _ => Ok(None),
+            }
+        }
+    }))
+    .await?
+    .into_iter()
+    .flatten()
+    .collect();
+
+    let content = derived::fetch_file_full_content(ctx, blobstore, file_unode_id)
+        .compat()
+        .await??;
+    let blame = Blame::from_parents_ignoring(csid, content, path, parents_content_and_blame)?;
+    Ok(blame)
+}
+
 fn fetch_blame_if_derived(
     ctx: CoreContext,
     repo: BlobRepo,
@@ -5,15 +5,15 @@
  * GNU General Public License version 2.
  */
 
-use crate::fetch_blame;
+use crate::{fetch_blame, fetch_blame_ignoring};
 use anyhow::Error;
 use bytes::Bytes;
 use context::CoreContext;
 use fbinit::FacebookInit;
 use futures::compat::Future01CompatExt;
-use maplit::{btreemap, hashmap};
+use maplit::{btreemap, hashmap, hashset};
 use mononoke_types::{Blame, ChangesetId, MPath};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use tests_utils::{create_commit, store_files, store_rename};
 
 // File with multiple changes and a merge
@@ -240,6 +240,113 @@ fn test_blame(fb: FacebookInit) -> Result<(), Error> {
     })
 }
 
+#[fbinit::test]
+fn test_blame_ignoring(fb: FacebookInit) -> Result<(), Error> {
+    async_unit::tokio_unit_test(async move {
+        let ctx = CoreContext::test_mock(fb);
+        let repo = blobrepo_factory::new_memblob_empty(None)?;
+
+        let c0 = create_commit(
+            ctx.clone(),
+            repo.clone(),
+            vec![],
+            store_files(
+                ctx.clone(),
+                btreemap! {"_g" => Some("a\nb\nc\n")},
+                repo.clone(),
+            )
+            .await,
+        )
+        .await;
+
+        // reformatting commit, that also renames the file
+        let (g_path, g_change) = store_rename(
+            ctx.clone(),
+            (MPath::new("_g")?, c0),
+            "g",
+            "a\nB\nc\n",
+            repo.clone(),
+        )
+        .await;
+        let mut c1_changes = btreemap! {g_path => g_change};
+        c1_changes.insert(MPath::new("_g")?, None);
+        let c1 = create_commit(ctx.clone(), repo.clone(), vec![c0], c1_changes).await;
+
+        let c2 = create_commit(
+            ctx.clone(),
+            repo.clone(),
+            vec![c1],
+            store_files(
+                ctx.clone(),
+                btreemap! {"g" => Some("a\nB\nc\nd\n")},
+                repo.clone(),
+            )
+            .await,
+        )
+        .await;
+
+        // a single source copied to two paths
+        let mut c3_changes = BTreeMap::new();
+        for to_path in &["h1", "h2"] {
+            let (path, change) = store_rename(
+                ctx.clone(),
+                (MPath::new("g")?, c2),
+                to_path,
+                "a\nB\nc\nd\ne\n",
+                repo.clone(),
+            )
+            .await;
+            c3_changes.insert(path, change);
+        }
+        let c3 = create_commit(ctx.clone(), repo.clone(), vec![c2], c3_changes).await;
+
+        let names = hashmap! {
+            c0 => "c0",
+            c1 => "c1",
+            c2 => "c2",
+            c3 => "c3",
+        };
+
+        let (content, blame) = fetch_blame(ctx.clone(), repo.clone(), c2, MPath::new("g")?)
+            .compat()
+            .await?;
+        assert_eq!(
+            annotate(content, blame, &names)?,
+            "c0: a\nc1: B\nc0: c\nc2: d\n"
+        );
+
+        let (content, blame) = fetch_blame_ignoring(
+            ctx.clone(),
+            repo.clone(),
+            c2,
+            MPath::new("g")?,
+            &hashset! {c1},
+        )
+        .await?;
+        assert_eq!(
+            annotate(content, blame, &names)?,
+            "c0: a\nc0: B\nc0: c\nc2: d\n"
+        );
+
+        for path in &["h1", "h2"] {
+            let (content, blame) = fetch_blame_ignoring(
+                ctx.clone(),
+                repo.clone(),
+                c3,
+                MPath::new(path)?,
+                &hashset! {c1},
+            )
+            .await?;
+            assert_eq!(
+                annotate(content, blame, &names)?,
+                "c0: a\nc0: B\nc0: c\nc2: d\nc3: e\n"
+            );
+        }
+
+        Ok(())
+    })
+}
+
 fn annotate(
     content: Bytes,
     blame: Blame,
@@ -0,0 +1,328 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use anyhow::{format_err, Error};
+use std::{
+    collections::{BTreeMap, HashMap},
+    iter::FromIterator,
+    sync::Arc,
+};
+
+use blobrepo::BlobRepo;
+use blobstore::Blobstore;
+use bytes::{Bytes, BytesMut};
+use context::CoreContext;
+use derived_data::{BonsaiDerived, BonsaiDerivedMapping};
+use fbthrift::compact_protocol;
+use filestore::{self, FetchKey};
+use fsnodes::RootFsnodeId;
+use futures::{
+    compat::{Future01CompatExt, Stream01CompatExt},
+    future::{self as new_future, FutureExt as _, TryFutureExt},
+    stream::{self as new_stream, StreamExt as _, TryStreamExt},
+};
+use futures_ext::{BoxFuture, FutureExt};
+use futures_old::{stream::FuturesUnordered, Future, Stream};
+use manifest::{Diff, Entry, ManifestOps};
+use mononoke_types::{BlobstoreBytes, BonsaiChangeset, ChangesetId, ContentId};
+use repo_blobstore::RepoBlobstore;
+
+use crate::{ChangesetDiffstat, FileDiffstat};
+
+/// Files larger than this are not diffed, and are counted as binary.
+pub const DIFFSTAT_FILESIZE_LIMIT: u64 = 10 * 1024 * 1024;
+
+impl BonsaiDerived for ChangesetDiffstat {
+    const NAME: &'static str = "diffstat";
+    type Mapping = ChangesetDiffstatMapping;
+
+    fn mapping(_ctx: &CoreContext, repo: &BlobRepo) -> Self::Mapping {
+        ChangesetDiffstatMapping::new(repo.blobstore().boxed())
+    }
+
+    fn derive_from_parents(
+        ctx: CoreContext,
+        repo: BlobRepo,
+        bonsai: BonsaiChangeset,
+        _parents: Vec<Self>,
+    ) -> BoxFuture<Self, Error> {
+        derive_diffstat(ctx, repo, bonsai).boxed().compat().boxify()
+    }
+}
+
+async fn derive_diffstat(
+    ctx: CoreContext,
+    repo: BlobRepo,
+    bonsai: BonsaiChangeset,
+) -> Result<ChangesetDiffstat, Error> {
+    let csid = bonsai.get_changeset_id();
+    let blobstore = repo.get_blobstore();
+    let root = RootFsnodeId::derive(ctx.clone(), repo.clone(), csid)
+        .compat()
+        .await?;
+
+    // Merges are diffed against their first parent only, including the files
+    // they bring in unchanged from other parents
+    let changes = match bonsai.parents().next() {
+        Some(p1) => {
+            RootFsnodeId::derive(ctx.clone(), repo.clone(), p1)
+                .compat()
+                .await?
+                .fsnode_id()
+                .diff(ctx.clone(), blobstore.clone(), *root.fsnode_id())
+                .compat()
+                .try_filter_map(|diff| async move {
+                    let change = match diff {
+                        Diff::Added(Some(path), Entry::Leaf((new, _))) => (path, None, Some(new)),
+                        Diff::Removed(Some(path), Entry::Leaf((old, _))) => (path, Some(old), None),
+                        Diff::Changed(Some(path), Entry::Leaf((old, _)), Entry::Leaf((new, _))) => {
+                            (path, Some(old), Some(new))
+                        }
+                        _ => return Ok(None),
+                    };
+                    Ok(Some(change))
+                })
+                .try_collect::<Vec<_>>()
+                .await?
+        }
+        None => {
+            root.fsnode_id()
+                .list_leaf_entries(ctx.clone(), blobstore.clone())
+                .compat()
+                .map_ok(|(path, (new, _file_type))| (path, None, Some(new)))
+                .try_collect::<Vec<_>>()
+                .await?
+        }
+    };
+
+    let files = new_stream::iter(changes)
+        .map(|(path, old, new)| {
+            let ctx = &ctx;
+            let blobstore = &blobstore;
+            async move {
+                let diffstat = file_diffstat(ctx, blobstore, old, new).await?;
+                Ok::<_, Error>((path, diffstat))
+            }
+        })
+        .buffered(100)
+        .try_collect::<BTreeMap<_, _>>()
+        .await?;
+
+    Ok(ChangesetDiffstat::new(csid, files))
+}
+
+async fn file_diffstat(
+    ctx: &CoreContext,
+    blobstore: &RepoBlobstore,
+    old: Option<ContentId>,
+    new: Option<ContentId>,
+) -> Result<FileDiffstat, Error> {
+    if old == new {
+        // Only the file type has changed
+        return Ok(FileDiffstat::default());
+    }
+    let (old, new) = new_future::try_join(
+        fetch_text(ctx, blobstore, old),
+        fetch_text(ctx, blobstore, new),
+    )
+    .await?;
+    match (old, new) {
+        (Some(old), Some(new)) => Ok(FileDiffstat::from_contents(&old, &new)),
+        _ => Ok(FileDiffstat::binary()),
+    }
+}
+
+/// Fetch the content of a file version, or empty content if the file doesn't
+/// exist.  Returns `None` if the content is binary or too large to diff.
+async fn fetch_text(
+    ctx: &CoreContext,
+    blobstore: &RepoBlobstore,
+    content_id: Option<ContentId>,
+) -> Result<Option<Bytes>, Error> {
+    let content_id = match content_id {
+        Some(content_id) => content_id,
+        None => return Ok(Some(Bytes::new())),
+    };
+    let (stream, size) =
+        filestore::fetch_with_size(blobstore, ctx.clone(), &FetchKey::Canonical(content_id))
+            .compat()
+            .await?
+            .ok_or_else(|| format_err!("missing content {}", content_id))?;
+    if size > DIFFSTAT_FILESIZE_LIMIT {
+        return Ok(None);
+    }
+    let content = stream
+        .compat()
+        .try_fold(
+            BytesMut::with_capacity(size as usize),
+            |mut content, chunk| {
+                content.extend_from_slice(&chunk);
+                new_future::ready(Ok(content))
+            },
+        )
+        .await?
+        .freeze();
+    if content.contains(&0) {
+        return Ok(None);
+    }
+    Ok(Some(content))
+}
+
+#[derive(Clone)]
+pub struct ChangesetDiffstatMapping {
+    blobstore: Arc<dyn Blobstore>,
+}
+
+impl ChangesetDiffstatMapping {
+    pub fn new(blobstore: Arc<dyn Blobstore>) -> Self {
+        Self { blobstore }
+    }
+
+    fn format_key(&self, csid: &ChangesetId) -> String {
+        format!("diffstat.blake2.{}", csid)
+    }
+}
+
+impl BonsaiDerivedMapping for ChangesetDiffstatMapping {
+    type Value = ChangesetDiffstat;
+
+    fn get(
+        &self,
+        ctx: CoreContext,
+        csids: Vec<ChangesetId>,
+    ) -> BoxFuture<HashMap<ChangesetId, Self::Value>, Error> {
+        let futs = csids.into_iter().map(|csid| {
+            self.blobstore
+                .get(ctx.clone(), self.format_key(&csid))
+                .map(move |value| {
+                    value.map(|bytes| {
+                        let diffstat = ChangesetDiffstat::from_bytes(bytes.as_raw_bytes())?;
+                        Ok((csid, diffstat))
+                    })
+                })
+        });
+        FuturesUnordered::from_iter(futs)
+            .filter_map(|maybe_diffstat| maybe_diffstat)
+            .collect()
+            .and_then(move |diffstats| {
+                diffstats
+                    .into_iter()
+                    .collect::<Result<HashMap<_, _>, Error>>()
+            })
+            .boxify()
+    }
+
+    fn put(
+        &self,
+        ctx: CoreContext,
+        csid: ChangesetId,
+        diffstat: Self::Value,
+    ) -> BoxFuture<(), Error> {
+        let data = {
+            let data = compact_protocol::serialize(&diffstat.into_thrift());
+            BlobstoreBytes::from_bytes(data)
+        };
+        self.blobstore.put(ctx, self.format_key(&csid), data)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use fbinit::FacebookInit;
+    use mononoke_types::MPath;
+    use tests_utils::CreateCommitContext;
+    use tokio_compat::runtime::Runtime;
+
+    fn check_file(diffstat: &ChangesetDiffstat, path: &str, expected: (u64, u64, bool)) {
+        let file = diffstat
+            .file(&MPath::new(path).unwrap())
+            .expect("file is in diffstat");
+        assert_eq!(
+            (file.lines_added(), file.lines_removed(), file.is_binary()),
+            expected,
+            "diffstat of {}",
+            path
+        );
+    }
+
+    #[fbinit::test]
+    fn derive_diffstat_test(fb: FacebookInit) {
+        let mut runtime = Runtime::new().unwrap();
+        let ctx = CoreContext::test_mock(fb);
+        let repo = blobrepo_factory::new_memblob_empty(None).unwrap();
+
+        let diffstat = runtime
+            .block_on_std(async {
+                let root = CreateCommitContext::new_root(&ctx, &repo)
+                    .add_file("modified", "a\nb\nc\n")
+                    .add_file("deleted", "a\nb\n")
+                    .add_file("binary", "a\0b")
+                    .commit()
+                    .await?;
+                let csid = CreateCommitContext::new(&ctx, &repo, vec![root])
+                    .add_file("modified", "a\nB\nc\nd\n")
+                    .delete_file("deleted")
+                    .add_file("added", "a\n")
+                    .add_file("binary", "a\0c")
+                    .commit()
+                    .await?;
+                ChangesetDiffstat::derive(ctx.clone(), repo.clone(), csid)
+                    .compat()
+                    .await
+                    .map_err(Error::from)
+            })
+            .unwrap();
+
+        check_file(&diffstat, "modified", (2, 1, false));
+        check_file(&diffstat, "deleted", (0, 2, false));
+        check_file(&diffstat, "added", (1, 0, false));
+        check_file(&diffstat, "binary", (0, 0, true));
+        assert_eq!(diffstat.lines_added(), 3);
+        assert_eq!(diffstat.lines_removed(), 3);
+        assert_eq!(diffstat.binary_files(), 1);
+    }
+
+    #[fbinit::test]
+    fn derive_diffstat_merge_test(fb: FacebookInit) {
+        let mut runtime = Runtime::new().unwrap();
+        let ctx = CoreContext::test_mock(fb);
+        let repo = blobrepo_factory::new_memblob_empty(None).unwrap();
+
+        let diffstat = runtime
+            .block_on_std(async {
+                let root = CreateCommitContext::new_root(&ctx, &repo)
+                    .add_file("file", "a\n")
+                    .commit()
+                    .await?;
+                let p1 = CreateCommitContext::new(&ctx, &repo, vec![root])
+                    .add_file("file", "a\nb\n")
+                    .commit()
+                    .await?;
+                let p2 = CreateCommitContext::new(&ctx, &repo, vec![root])
+                    .add_file("from_p2", "a\nb\nc\n")
+                    .commit()
+                    .await?;
+                let merge = CreateCommitContext::new(&ctx, &repo, vec![p1, p2])
+                    .add_file("merged", "a\n")
+                    .commit()
+                    .await?;
+                ChangesetDiffstat::derive(ctx.clone(), repo.clone(), merge)
+                    .compat()
+                    .await
+                    .map_err(Error::from)
+            })
+            .unwrap();
+
+        // The file the merge brings in from p2 is counted as added
+        check_file(&diffstat, "merged", (1, 0, false));
+        check_file(&diffstat, "from_p2", (3, 0, false));
+        assert!(diffstat.file(&MPath::new("file").unwrap()).is_none());
+        assert_eq!(diffstat.lines_added(), 4);
+    }
+}
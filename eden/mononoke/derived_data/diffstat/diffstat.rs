@@ -0,0 +1,205 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use anyhow::{Context, Result};
+use fbthrift::compact_protocol;
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+
+use diffstat_thrift as thrift;
+use mononoke_types::{errors::ErrorKind, ChangesetId, MPath};
+
+/// Changeset Diffstat is a derived data structure that records how many lines
+/// were added and removed in each file changed by a Bonsai changeset.
+///
+/// Line counts are computed against the changeset's first parent, the same
+/// way `hg diff --stat` does for a commit, so for a merge they include the
+/// files it brings in from its other parents.  Copy and rename sources are
+/// ignored: a renamed file counts as all of its lines added at the new path
+/// and all of them removed at the old one.  Getting them on demand means
+/// fetching and diffing both versions of every changed file, which is too
+/// slow for commits with many changes or for jobs that need the numbers for
+/// lots of commits.
+///
+/// Binary files, and files that are larger than `DIFFSTAT_FILESIZE_LIMIT`, are
+/// not diffed.  They are marked as binary and have no line counts.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct ChangesetDiffstat {
+    /// changeset id of the source Bonsai changeset
+    changeset_id: ChangesetId,
+    files: BTreeMap<MPath, FileDiffstat>,
+}
+
+/// Lines added and removed in a single file.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash)]
+pub struct FileDiffstat {
+    lines_added: u64,
+    lines_removed: u64,
+    is_binary: bool,
+}
+
+impl FileDiffstat {
+    /// Compute the diffstat of a text file.  Missing files are passed as
+    /// empty content.
+    pub fn from_contents(old: &[u8], new: &[u8]) -> Self {
+        let (lines_added, lines_removed) =
+            xdiff::diff_hunks(old, new)
+                .iter()
+                .fold((0, 0), |(added, removed), hunk| {
+                    (
+                        added + hunk.add.len() as u64,
+                        removed + hunk.remove.len() as u64,
+                    )
+                });
+        Self {
+            lines_added,
+            lines_removed,
+            is_binary: false,
+        }
+    }
+
+    /// The diffstat of a binary file.
+    pub fn binary() -> Self {
+        Self {
+            lines_added: 0,
+            lines_removed: 0,
+            is_binary: true,
+        }
+    }
+
+    /// Number of lines added to the file.
+    pub fn lines_added(&self) -> u64 {
+        self.lines_added
+    }
+
+    /// Number of lines removed from the file.
+    pub fn lines_removed(&self) -> u64 {
+        self.lines_removed
+    }
+
+    /// Whether the file was not diffed because it is binary or too large.
+    pub fn is_binary(&self) -> bool {
+        self.is_binary
+    }
+
+    pub(crate) fn from_thrift(tf: thrift::FileDiffstat) -> Result<Self> {
+        Ok(FileDiffstat {
+            lines_added: tf.lines_added.try_into()?,
+            lines_removed: tf.lines_removed.try_into()?,
+            is_binary: tf.is_binary,
+        })
+    }
+
+    pub(crate) fn into_thrift(self) -> thrift::FileDiffstat {
+        thrift::FileDiffstat {
+            lines_added: self.lines_added as i64,
+            lines_removed: self.lines_removed as i64,
+            is_binary: self.is_binary,
+        }
+    }
+}
+
+impl ChangesetDiffstat {
+    pub fn new(changeset_id: ChangesetId, files: BTreeMap<MPath, FileDiffstat>) -> Self {
+        Self {
+            changeset_id,
+            files,
+        }
+    }
+
+    /// Get id of the source Bonsai changeset.
+    pub fn changeset_id(&self) -> &ChangesetId {
+        &self.changeset_id
+    }
+
+    /// Get the diffstat of each file changed in the changeset.
+    pub fn files(&self) -> impl Iterator<Item = (&MPath, &FileDiffstat)> {
+        self.files.iter()
+    }
+
+    /// Get the diffstat of a file.  Returns `None` if the file was not
+    /// changed in the changeset.
+    pub fn file(&self, path: &MPath) -> Option<&FileDiffstat> {
+        self.files.get(path)
+    }
+
+    /// Total number of lines added in the changeset.
+    pub fn lines_added(&self) -> u64 {
+        self.files.values().map(|file| file.lines_added).sum()
+    }
+
+    /// Total number of lines removed in the changeset.
+    pub fn lines_removed(&self) -> u64 {
+        self.files.values().map(|file| file.lines_removed).sum()
+    }
+
+    /// Number of changed files that are binary or too large to diff.
+    pub fn binary_files(&self) -> usize {
+        self.files.values().filter(|file| file.is_binary).count()
+    }
+
+    pub(crate) fn from_thrift(tc: thrift::ChangesetDiffstat) -> Result<Self> {
+        let catch_block = || -> Result<_> {
+            Ok(ChangesetDiffstat {
+                changeset_id: ChangesetId::from_thrift(tc.changeset_id)?,
+                files: tc
+                    .files
+                    .into_iter()
+                    .map(|(path, file)| {
+                        Ok((MPath::from_thrift(path)?, FileDiffstat::from_thrift(file)?))
+                    })
+                    .collect::<Result<_>>()?,
+            })
+        };
+
+        Ok(catch_block().with_context(|| {
+            ErrorKind::InvalidThrift(
+                "ChangesetDiffstat".into(),
+                "Invalid changeset diffstat".into(),
+            )
+        })?)
+    }
+
+    pub(crate) fn into_thrift(self) -> thrift::ChangesetDiffstat {
+        thrift::ChangesetDiffstat {
+            changeset_id: self.changeset_id.into_thrift(),
+            files: self
+                .files
+                .into_iter()
+                .map(|(path, file)| (path.into_thrift(), file.into_thrift()))
+                .collect(),
+        }
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let thrift_tc = compact_protocol::deserialize(bytes)
+            .with_context(|| ErrorKind::BlobDeserializeError("ChangesetDiffstat".into()))?;
+        Self::from_thrift(thrift_tc)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn file_diffstat_test() {
+        let diffstat = FileDiffstat::from_contents(b"a\nb\nc\n", b"a\nB\nc\nd\n");
+        assert_eq!(diffstat.lines_added(), 2);
+        assert_eq!(diffstat.lines_removed(), 1);
+        assert!(!diffstat.is_binary());
+
+        // Added and deleted files are diffed against empty content.
+        let diffstat = FileDiffstat::from_contents(b"", b"a\nb\n");
+        assert_eq!((diffstat.lines_added(), diffstat.lines_removed()), (2, 0));
+        let diffstat = FileDiffstat::from_contents(b"a\nb\n", b"");
+        assert_eq!((diffstat.lines_added(), diffstat.lines_removed()), (0, 2));
+
+        let diffstat = FileDiffstat::from_contents(b"a\n", b"a\n");
+        assert_eq!((diffstat.lines_added(), diffstat.lines_removed()), (0, 0));
+    }
+}
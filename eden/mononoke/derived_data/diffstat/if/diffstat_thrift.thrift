@@ -0,0 +1,27 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+include "eden/mononoke/mononoke_types/if/mononoke_types_thrift.thrift"
+
+// Derived data structure that records the size of a Bonsai changeset's
+// changes: the number of lines added and removed in each changed file,
+// compared to the changeset's first parent.
+//
+// Computing these numbers means fetching and diffing both versions of every
+// changed file, which is too expensive to do on every request.
+struct ChangesetDiffstat {
+  // Changeset id of the source Bonsai changeset
+  1: mononoke_types_thrift.ChangesetId changeset_id,
+  2: map<mononoke_types_thrift.MPath, FileDiffstat> files,
+}
+
+struct FileDiffstat {
+  1: i64 lines_added,
+  2: i64 lines_removed,
+  // Binary files and files that are too large to diff have no line counts.
+  3: bool is_binary,
+}
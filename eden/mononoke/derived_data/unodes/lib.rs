@@ -38,13 +38,16 @@ pub fn find_unode_renames(
     repo: BlobRepo,
     bonsai: &BonsaiChangeset,
 ) -> impl Future<Item = HashMap<MPath, FileUnodeId>, Error = Error> {
-    let mut references: HashMap<ChangesetId, HashMap<MPath, MPath>> = HashMap::new();
+    // A single source may be copied to several paths.
+    let mut references: HashMap<ChangesetId, HashMap<MPath, Vec<MPath>>> = HashMap::new();
     for (to_path, file_change) in bonsai.file_changes() {
         if let Some((from_path, csid)) = file_change.and_then(|fc| fc.copy_from()) {
             references
                 .entry(*csid)
                 .or_default()
-                .insert(from_path.clone(), to_path.clone());
+                .entry(from_path.clone())
+                .or_default()
+                .push(to_path.clone());
         }
     }
 
@@ -68,6 +71,9 @@ pub fn find_unode_renames(
                                 .filter_map(|(from_path, unode_id)| {
                                     Some((paths.remove(&from_path)?, unode_id))
                                 })
+                                .flat_map(|(to_paths, unode_id)| {
+                                    to_paths.into_iter().map(move |to_path| (to_path, unode_id))
+                                })
                                 .collect::<HashMap<_, _>>()
                         })
                 }
@@ -20,6 +20,7 @@ use derived_data::{
     RegenerateMapping,
 };
 use derived_data_filenodes::{FilenodesOnlyPublic, FilenodesOnlyPublicMapping};
+use diffstat::{ChangesetDiffstat, ChangesetDiffstatMapping};
 use fastlog::{RootFastlog, RootFastlogMapping};
 use fsnodes::{RootFsnodeId, RootFsnodeMapping};
 use futures::{compat::Future01CompatExt, stream, StreamExt, TryStreamExt};
@@ -42,6 +43,7 @@ pub const POSSIBLE_DERIVED_TYPES: &[&str] = &[
     ChangesetInfo::NAME,
     RootDeletedManifestId::NAME,
     FilenodesOnlyPublic::NAME,
+    ChangesetDiffstat::NAME,
 ];
 
 #[async_trait]
@@ -350,6 +352,10 @@ fn derived_data_utils_impl(
             let mapping = FilenodesOnlyPublicMapping::new(repo);
             Ok(Arc::new(DerivedUtilsFromMapping::new(mapping, mode)))
         }
+        ChangesetDiffstat::NAME => {
+            let mapping = ChangesetDiffstatMapping::new(repo.get_blobstore().boxed());
+            Ok(Arc::new(DerivedUtilsFromMapping::new(mapping, mode)))
+        }
         name => Err(format_err!("Unsupported derived data type: {}", name)),
     }
 }
@@ -35,7 +35,7 @@ use metaconfig_types::{
     SourceControlServiceParams, StorageConfig, UnodeVersion, WhitelistEntry,
     WireprotoLoggingConfig,
 };
-use mononoke_types::{MPath, RepositoryId};
+use mononoke_types::{ChangesetId, MPath, RepositoryId};
 use regex::Regex;
 use repos::{
     RawCommitSyncConfig, RawCommitSyncSmallRepoConfig, RawCommonConfig, RawHookConfig,
@@ -622,6 +622,11 @@ impl RepoConfigs {
                             .casefolding_check
                             .unwrap_or(default.flags.casefolding_check),
                         not_generated_filenodes_limit: 500,
+                        textual_merge_size_limit: raw
+                            .textual_merge_size_limit
+                            .map(|v| v.try_into())
+                            .transpose()?
+                            .or(default.flags.textual_merge_size_limit),
                     },
                     commit_scribe_category: raw.commit_scribe_category,
                     block_merges: raw.block_merges.unwrap_or(default.block_merges),
@@ -630,6 +635,11 @@ impl RepoConfigs {
                     populate_git_mapping: raw
                         .populate_git_mapping
                         .unwrap_or(default.populate_git_mapping),
+                    landing_queue_window: raw
+                        .landing_queue_window_ms
+                        .map(|ms| -> Result<_, Error> { Ok(Duration::from_millis(ms.try_into()?)) })
+                        .transpose()?
+                        .or(default.landing_queue_window),
                 })
             })
             .transpose()?
@@ -776,12 +786,23 @@ impl RepoConfigs {
                         UnodeVersion::default()
                     };
 
+                let blame_ignore_revs = raw_derived_data_config
+                    .blame_ignore_revs
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|rev| {
+                        ChangesetId::from_str(rev)
+                            .map_err(|e| format_err!("invalid blame ignore rev {}: {}", rev, e))
+                    })
+                    .collect::<Result<BTreeSet<_>>>()?;
+
                 Ok(DerivedDataConfig {
                     scuba_table: raw_derived_data_config.scuba_table,
                     derived_data_types: raw_derived_data_config
                         .derived_data_types
                         .unwrap_or(BTreeSet::new()),
                     unode_version,
+                    blame_ignore_revs,
                 })
             })
             .transpose()?
@@ -1380,6 +1401,7 @@ mod test {
 
             [derived_data_config]
             derived_data_types=["fsnodes"]
+            blame_ignore_revs=["1111111111111111111111111111111111111111111111111111111111111111"]
 
             [derived_data_config.raw_unode_version]
             unode_version_v2 = {}
@@ -1431,6 +1453,8 @@ mod test {
             forbid_p2_root_rebases = false
             casefolding_check = false
             emit_obsmarkers = false
+            textual_merge_size_limit = 65536
+            landing_queue_window_ms = 50
 
             [lfs]
             threshold = 1000
@@ -1597,12 +1621,14 @@ mod test {
                         forbid_p2_root_rebases: false,
                         casefolding_check: false,
                         not_generated_filenodes_limit: 500,
+                        textual_merge_size_limit: Some(65536),
                     },
                     block_merges: false,
                     emit_obsmarkers: false,
                     commit_scribe_category: None,
                     assign_globalrevs: false,
                     populate_git_mapping: false,
+                    landing_queue_window: Some(Duration::from_millis(50)),
                 },
                 lfs: LfsParams {
                     threshold: Some(1000),
@@ -1652,6 +1678,10 @@ mod test {
                     derived_data_types: btreeset![String::from("fsnodes")],
                     scuba_table: None,
                     unode_version: UnodeVersion::V2,
+                    blame_ignore_revs: btreeset![ChangesetId::from_str(
+                        "1111111111111111111111111111111111111111111111111111111111111111"
+                    )
+                    .unwrap()],
                 },
                 hgsql_name: HgsqlName("fbsource".to_string()),
                 hgsql_globalrevs_name: HgsqlGlobalrevsName("fbsource".to_string()),
@@ -27,7 +27,7 @@ use std::{
 
 use ascii::AsciiString;
 use bookmarks_types::BookmarkName;
-use mononoke_types::{MPath, RepositoryId};
+use mononoke_types::{ChangesetId, MPath, RepositoryId};
 use nonzero_ext::nonzero;
 use regex::Regex;
 use repos::{
@@ -152,6 +152,9 @@ pub struct DerivedDataConfig {
     pub derived_data_types: BTreeSet<String>,
     /// What unode version should be used (defaults to V1)
     pub unode_version: UnodeVersion,
+    /// Changesets whose changes are skipped over by blame, so that lines
+    /// are attributed to the previous change instead
+    pub blame_ignore_revs: BTreeSet<ChangesetId>,
 }
 
 /// What type of unode derived data to generate
@@ -448,6 +451,9 @@ pub struct PushrebaseFlags {
     pub casefolding_check: bool,
     /// How many commits are allowed to not have filenodes generated.
     pub not_generated_filenodes_limit: u64,
+    /// Resolve conflicting changes to text files no larger than this many bytes with a
+    /// line-level three-way merge instead of rejecting the push. Disabled if None.
+    pub textual_merge_size_limit: Option<u64>,
 }
 
 impl Default for PushrebaseFlags {
@@ -458,6 +464,7 @@ impl Default for PushrebaseFlags {
             forbid_p2_root_rebases: true,
             casefolding_check: true,
             not_generated_filenodes_limit: 500,
+            textual_merge_size_limit: None,
         }
     }
 }
@@ -477,6 +484,9 @@ pub struct PushrebaseParams {
     pub assign_globalrevs: bool,
     /// Whether Git Mapping should be populated from extras (affects also blobimport)
     pub populate_git_mapping: bool,
+    /// How long to collect concurrent pushrebases to the same bookmark before landing them
+    /// together with a single bookmark move. Pushrebases aren't batched if None.
+    pub landing_queue_window: Option<Duration>,
 }
 
 impl Default for PushrebaseParams {
@@ -488,6 +498,7 @@ impl Default for PushrebaseParams {
             commit_scribe_category: None,
             assign_globalrevs: false,
             populate_git_mapping: false,
+            landing_queue_window: None,
         }
     }
 }
@@ -18,6 +18,7 @@ use chrono::{DateTime, FixedOffset};
 use cloned::cloned;
 use context::CoreContext;
 use derived_data::BonsaiDerived;
+use diffstat::ChangesetDiffstat;
 use fsnodes::RootFsnodeId;
 use futures::compat::{Future01CompatExt, Stream01CompatExt};
 use futures::future::{self, try_join, FutureExt, Shared};
@@ -31,7 +32,9 @@ use reachabilityindex::ReachabilityIndex;
 use unodes::RootUnodeManifestId;
 
 use crate::changeset_path::ChangesetPathContext;
-use crate::changeset_path_diff::ChangesetPathDiffContext;
+use crate::changeset_path_diff::{
+    detect_similar_files, ChangesetPathDiffContext, SimilarityOptions,
+};
 use crate::errors::MononokeError;
 use crate::path::MononokePath;
 use crate::repo::RepoContext;
@@ -299,6 +302,20 @@ impl ChangesetContext {
             .collect())
     }
 
+    /// The number of lines added and removed in each file changed by the
+    /// commit, compared to its first parent.  Returns `None` if diffstat
+    /// derived data is not enabled for the repo.
+    pub async fn diffstat(&self) -> Result<Option<ChangesetDiffstat>, MononokeError> {
+        if !self.repo.derive_diffstat_enabled() {
+            return Ok(None);
+        }
+        let diffstat =
+            ChangesetDiffstat::derive(self.ctx().clone(), self.repo.blob_repo().clone(), self.id)
+                .compat()
+                .await?;
+        Ok(Some(diffstat))
+    }
+
     /// File changes associated with the commit.
     pub async fn file_changes(&self) -> Result<BTreeMap<MPath, Option<FileChange>>, MononokeError> {
         let bonsai = self.bonsai_changeset().await?;
@@ -328,11 +345,14 @@ impl ChangesetContext {
     /// `other` is considered the "old" changeset (so files missing there are "Added")
     /// `include_copies_renames` is only available for diffing commits with its parent
     /// `path_restrictions` if present will narrow down the diff to given paths
+    /// `similarity` if present enables detecting copies and renames that were not
+    /// recorded in the commit by comparing file contents
     pub async fn diff(
         &self,
         other: ChangesetId,
         include_copies_renames: bool,
         path_restrictions: Option<Vec<MononokePath>>,
+        similarity: Option<SimilarityOptions>,
     ) -> Result<Vec<ChangesetPathDiffContext>, MononokeError> {
         // Helper to that checks if a path is within the givien path restrictions
         fn within_restrictions(
@@ -525,7 +545,10 @@ impl ChangesetContext {
             })
             .try_collect::<Vec<_>>()
             .await?;
-        return Ok(change_contexts);
+        match similarity {
+            Some(options) => detect_similar_files(change_contexts, &options).await,
+            None => Ok(change_contexts),
+        }
     }
 
     pub async fn find_files(
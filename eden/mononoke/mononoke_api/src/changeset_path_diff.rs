@@ -5,7 +5,14 @@
  * GNU General Public License version 2.
  */
 
+use std::collections::{HashMap, HashSet};
+
+use bytes::Bytes;
+use futures::future::try_join_all;
+use mononoke_types::ContentId;
+
 use crate::changeset_path::ChangesetPathContext;
+use crate::errors::MononokeError;
 
 /// A path difference between two commits.
 ///
@@ -19,3 +26,278 @@ pub enum ChangesetPathDiffContext {
     Copied(ChangesetPathContext, ChangesetPathContext),
     Moved(ChangesetPathContext, ChangesetPathContext),
 }
+
+/// Options for detecting renames and copies from file contents, for
+/// history where they were not recorded in the commit (e.g. imported from
+/// git).
+#[derive(Clone, Debug)]
+pub struct SimilarityOptions {
+    /// Minimum similarity, as a percentage, for an added file to be
+    /// considered a rename or copy of another file.
+    pub min_similarity: u8,
+    /// Maximum number of added files, and of candidate source files, whose
+    /// contents are compared.  Above this only identical files are matched.
+    pub max_candidates: usize,
+    /// Files larger than this are only matched if they are identical.
+    pub max_file_size: u64,
+    /// Also detect copies, including from files that were modified rather
+    /// than removed.
+    pub detect_copies: bool,
+}
+
+impl Default for SimilarityOptions {
+    fn default() -> Self {
+        Self {
+            min_similarity: 50,
+            max_candidates: 200,
+            max_file_size: 1024 * 1024,
+            detect_copies: false,
+        }
+    }
+}
+
+/// A file that an added file may have been renamed or copied from.
+struct Source<'a> {
+    index: usize,
+    path: &'a ChangesetPathContext,
+    removed: bool,
+}
+
+/// Replace added files that match a removed (or, when detecting copies,
+/// modified) file in the diff with `Moved` or `Copied` entries.
+///
+/// Files with identical content are matched first, then the remaining
+/// files are matched by similarity, most similar pairs first.  A source
+/// file that was removed is reported as moved to the first file matched
+/// with it, and as copied to any others.
+pub(crate) async fn detect_similar_files(
+    diff: Vec<ChangesetPathDiffContext>,
+    options: &SimilarityOptions,
+) -> Result<Vec<ChangesetPathDiffContext>, MononokeError> {
+    let mut added = Vec::new();
+    let mut sources = Vec::new();
+    for (index, entry) in diff.iter().enumerate() {
+        match entry {
+            ChangesetPathDiffContext::Added(to) => added.push((index, to)),
+            ChangesetPathDiffContext::Removed(from) => sources.push(Source {
+                index,
+                path: from,
+                removed: true,
+            }),
+            ChangesetPathDiffContext::Changed(_, from) if options.detect_copies => {
+                sources.push(Source {
+                    index,
+                    path: from,
+                    removed: false,
+                })
+            }
+            _ => {}
+        }
+    }
+    if added.is_empty() || sources.is_empty() {
+        return Ok(diff);
+    }
+
+    let (added_ids, source_ids) = futures::try_join!(
+        try_join_all(added.iter().map(|(_, to)| to.file_content_id())),
+        try_join_all(sources.iter().map(|source| source.path.file_content_id())),
+    )?;
+
+    // Map from position in `added` to position in `sources`.
+    let mut matches: HashMap<usize, usize> = HashMap::new();
+    // Positions in `sources` of removed files that have been moved.
+    let mut moved = HashSet::new();
+    let usable = |source_pos: usize, moved: &HashSet<usize>| {
+        options.detect_copies || !moved.contains(&source_pos)
+    };
+
+    // Identical content.  Removed files are preferred as sources, as they
+    // come first in `sources_by_content`.
+    let mut sources_by_content: HashMap<ContentId, Vec<usize>> = HashMap::new();
+    for removed in &[true, false] {
+        for (source_pos, source) in sources.iter().enumerate() {
+            if source.removed == *removed {
+                if let Some(content_id) = source_ids[source_pos] {
+                    sources_by_content
+                        .entry(content_id)
+                        .or_default()
+                        .push(source_pos);
+                }
+            }
+        }
+    }
+    for (added_pos, content_id) in added_ids.iter().enumerate() {
+        let candidates = match content_id
+            .as_ref()
+            .and_then(|id| sources_by_content.get(id))
+        {
+            Some(candidates) => candidates,
+            None => continue,
+        };
+        let source_pos = candidates
+            .iter()
+            .find(|source_pos| sources[**source_pos].removed && !moved.contains(*source_pos))
+            .or_else(|| {
+                candidates
+                    .iter()
+                    .find(|source_pos| usable(**source_pos, &moved))
+            });
+        if let Some(source_pos) = source_pos {
+            if sources[*source_pos].removed {
+                moved.insert(*source_pos);
+            }
+            matches.insert(added_pos, *source_pos);
+        }
+    }
+
+    // Similar content.
+    let remaining_added: Vec<usize> = (0..added.len())
+        .filter(|added_pos| !matches.contains_key(added_pos))
+        .collect();
+    let remaining_sources: Vec<usize> = (0..sources.len())
+        .filter(|source_pos| usable(*source_pos, &moved))
+        .collect();
+    if !remaining_added.is_empty()
+        && !remaining_sources.is_empty()
+        && remaining_added.len() <= options.max_candidates
+        && remaining_sources.len() <= options.max_candidates
+    {
+        let (added_contents, source_contents) = futures::try_join!(
+            try_join_all(
+                remaining_added
+                    .iter()
+                    .map(|added_pos| fetch_content(added[*added_pos].1, options)),
+            ),
+            try_join_all(
+                remaining_sources
+                    .iter()
+                    .map(|source_pos| fetch_content(sources[*source_pos].path, options)),
+            ),
+        )?;
+
+        let mut scores = Vec::new();
+        for (added_pos, added_content) in remaining_added.iter().zip(added_contents.iter()) {
+            let added_content = match added_content {
+                Some(content) => content,
+                None => continue,
+            };
+            for (source_pos, source_content) in remaining_sources.iter().zip(source_contents.iter())
+            {
+                if let Some(source_content) = source_content {
+                    let score = similarity(source_content, added_content);
+                    if score >= options.min_similarity {
+                        scores.push((score, *added_pos, *source_pos));
+                    }
+                }
+            }
+        }
+        scores.sort_by(|a, b| b.0.cmp(&a.0).then((a.1, a.2).cmp(&(b.1, b.2))));
+
+        for (_score, added_pos, source_pos) in scores {
+            if matches.contains_key(&added_pos) || !usable(source_pos, &moved) {
+                continue;
+            }
+            if sources[source_pos].removed {
+                moved.insert(source_pos);
+            }
+            matches.insert(added_pos, source_pos);
+        }
+    }
+
+    if matches.is_empty() {
+        return Ok(diff);
+    }
+
+    // A removed file is reported as moved to the first file (in diff order)
+    // that was matched with it, and copied to the others.
+    let mut replacements = HashMap::new();
+    let mut moved_indexes = HashSet::new();
+    let mut ordered_matches: Vec<_> = matches.into_iter().collect();
+    ordered_matches.sort();
+    for (added_pos, source_pos) in ordered_matches {
+        let (added_index, to) = added[added_pos];
+        let source = &sources[source_pos];
+        let from = source.path.clone();
+        let replacement = if source.removed && moved_indexes.insert(source.index) {
+            ChangesetPathDiffContext::Moved(to.clone(), from)
+        } else {
+            ChangesetPathDiffContext::Copied(to.clone(), from)
+        };
+        replacements.insert(added_index, replacement);
+    }
+
+    Ok(diff
+        .into_iter()
+        .enumerate()
+        .filter_map(|(index, entry)| {
+            if moved_indexes.contains(&index) {
+                // Covered by the `Moved` entry.
+                None
+            } else if let Some(replacement) = replacements.remove(&index) {
+                Some(replacement)
+            } else {
+                Some(entry)
+            }
+        })
+        .collect())
+}
+
+/// Fetch the content of a file for similarity comparison, or `None` if it
+/// is too large to be compared.
+async fn fetch_content(
+    path: &ChangesetPathContext,
+    options: &SimilarityOptions,
+) -> Result<Option<Bytes>, MononokeError> {
+    let file = match path.file().await? {
+        Some(file) => file,
+        None => return Ok(None),
+    };
+    if file.metadata().await?.total_size > options.max_file_size {
+        return Ok(None);
+    }
+    Ok(Some(file.content_concat().await?))
+}
+
+/// Similarity of two file contents as a percentage: the number of bytes in
+/// lines common to both files, relative to the size of the larger file.
+pub(crate) fn similarity(a: &[u8], b: &[u8]) -> u8 {
+    let larger = a.len().max(b.len());
+    if larger == 0 {
+        return 100;
+    }
+    if a.is_empty() || b.is_empty() {
+        return 0;
+    }
+
+    let mut lines: HashMap<&[u8], usize> = HashMap::new();
+    for line in lines_of(a) {
+        *lines.entry(line).or_insert(0) += 1;
+    }
+    let mut common = 0;
+    for line in lines_of(b) {
+        if let Some(count) = lines.get_mut(line) {
+            if *count > 0 {
+                *count -= 1;
+                common += line.len();
+            }
+        }
+    }
+    (common * 100 / larger) as u8
+}
+
+/// Split content into lines, keeping the line terminators.
+fn lines_of(content: &[u8]) -> impl Iterator<Item = &[u8]> {
+    let mut rest = content;
+    std::iter::from_fn(move || {
+        if rest.is_empty() {
+            return None;
+        }
+        let end = rest
+            .iter()
+            .position(|c| *c == b'\n')
+            .map_or(rest.len(), |pos| pos + 1);
+        let (line, remainder) = rest.split_at(end);
+        rest = remainder;
+        Some(line)
+    })
+}
@@ -49,13 +49,14 @@ pub use crate::legacy::get_content_by_path;
 
 pub use crate::changeset::{ChangesetContext, Generation};
 pub use crate::changeset_path::{
-    unified_diff, ChangesetPathContext, CopyInfo, PathEntry, UnifiedDiff, UnifiedDiffMode,
+    unified_diff, ChangesetPathContext, CopyInfo, DiffAlgorithm, HunkOpts, IgnoreWhitespace,
+    PathEntry, UnifiedDiff, UnifiedDiffMode,
 };
-pub use crate::changeset_path_diff::ChangesetPathDiffContext;
+pub use crate::changeset_path_diff::{ChangesetPathDiffContext, SimilarityOptions};
 pub use crate::errors::MononokeError;
 pub use crate::file::{FileContext, FileId, FileMetadata, FileType};
 pub use crate::path::MononokePath;
-pub use crate::repo::RepoContext;
+pub use crate::repo::{BookmarkDerivedDataStatus, DerivedDataPosition, RepoContext};
 pub use crate::repo_write::{CreateChange, CreateCopyInfo, RepoWriteContext};
 pub use crate::specifiers::{
     ChangesetId, ChangesetIdPrefix, ChangesetPrefixSpecifier, ChangesetSpecifier,
@@ -65,6 +66,7 @@ pub use crate::tree::{TreeContext, TreeEntry, TreeId, TreeSummary};
 
 // Re-export types that are useful for clients.
 pub use context::{CoreContext, LoggingContainer, SessionContainer};
+pub use diffstat::{ChangesetDiffstat, FileDiffstat};
 
 /// An instance of Mononoke, which may manage multiple repositories.
 pub struct Mononoke {
@@ -22,6 +22,7 @@ use changeset_info::ChangesetInfo;
 use context::CoreContext;
 use cross_repo_sync::{CommitSyncRepos, CommitSyncer};
 use derived_data::BonsaiDerived;
+use diffstat::ChangesetDiffstat;
 use fbinit::FacebookInit;
 use filestore::{Alias, FetchKey};
 use futures::compat::{Future01CompatExt, Stream01CompatExt};
@@ -47,7 +48,7 @@ use slog::{debug, error, Logger};
 use sql_construct::SqlConstruct;
 use sql_ext::facebook::MysqlOptions;
 use stats_facebook::service_data::{get_service_data_singleton, ServiceData};
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
 use synced_commit_mapping::{SqlSyncedCommitMapping, SyncedCommitMapping};
 use warm_bookmarks_cache::WarmBookmarksCache;
 
@@ -534,6 +535,25 @@ pub struct Stack {
     pub public: HashSet<ChangesetId>,
 }
 
+/// How far behind the bookmark a particular derived data type is.
+pub struct DerivedDataPosition {
+    /// The latest commit in the history of the bookmark for which the
+    /// derived data type is ready.
+    pub warm: ChangesetContext,
+    /// Number of commits between the warm commit and the bookmark head,
+    /// measured as the difference of their generation numbers.
+    pub lag: u64,
+}
+
+/// Readiness of derived data for a bookmark.
+pub struct BookmarkDerivedDataStatus {
+    /// Where the bookmark really points to, regardless of derived data.
+    pub head: ChangesetContext,
+    /// Warm positions for each derived data type that is being warmed,
+    /// keyed by derived data type name.
+    pub positions: BTreeMap<String, DerivedDataPosition>,
+}
+
 /// A context object representing a query to a particular repo.
 impl RepoContext {
     pub(crate) async fn new(ctx: CoreContext, repo: Arc<Repo>) -> Result<Self, MononokeError> {
@@ -579,6 +599,13 @@ impl RepoContext {
             .contains(ChangesetInfo::NAME)
     }
 
+    pub(crate) fn derive_diffstat_enabled(&self) -> bool {
+        self.blob_repo()
+            .get_derived_data_config()
+            .derived_data_types
+            .contains(ChangesetDiffstat::NAME)
+    }
+
     /// Look up a changeset specifier to find the canonical bonsai changeset
     /// ID for a changeset.
     pub async fn resolve_specifier(
@@ -641,6 +668,47 @@ impl RepoContext {
         Ok(cs_id.map(|cs_id| ChangesetContext::new(self.clone(), cs_id)))
     }
 
+    /// Find out how far behind the bookmark each derived data type is.
+    ///
+    /// Returns `None` if the bookmark doesn't exist. Derived data types for
+    /// which no commit in the recent history of the bookmark is ready are
+    /// omitted from the result.
+    pub async fn bookmark_derived_data_status(
+        &self,
+        bookmark: impl AsRef<str>,
+    ) -> Result<Option<BookmarkDerivedDataStatus>, MononokeError> {
+        let bookmark = BookmarkName::new(bookmark.as_ref())?;
+        let head = match self
+            .blob_repo()
+            .get_bonsai_bookmark(self.ctx.clone(), &bookmark)
+            .compat()
+            .await?
+        {
+            Some(cs_id) => ChangesetContext::new(self.clone(), cs_id),
+            None => return Ok(None),
+        };
+        let head_generation = head.generation().await?.value();
+
+        let positions = self
+            .warm_bookmarks_cache()
+            .get_derived_positions(&bookmark)
+            .into_iter()
+            .map(|(derived_data_type, cs_id)| {
+                let warm = ChangesetContext::new(self.clone(), cs_id);
+                async move {
+                    let warm_generation = warm.generation().await?.value();
+                    let position = DerivedDataPosition {
+                        warm,
+                        lag: head_generation.saturating_sub(warm_generation),
+                    };
+                    Ok::<_, MononokeError>((derived_data_type, position))
+                }
+            });
+        let positions = try_join_all(positions).await?.into_iter().collect();
+
+        Ok(Some(BookmarkDerivedDataStatus { head, positions }))
+    }
+
     /// Resolve a changeset id by its prefix
     pub async fn resolve_changeset_id_prefix(
         &self,
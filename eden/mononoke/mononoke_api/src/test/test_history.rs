@@ -286,6 +286,61 @@ async fn commit_path_history(fb: FacebookInit) -> Result<()> {
     Ok(())
 }
 
+#[fbinit::compat_test]
+async fn commit_path_last_changes(fb: FacebookInit) -> Result<()> {
+    let ctx = CoreContext::test_mock(fb);
+    let (repo, changesets) = init_repo(&ctx).await?;
+
+    let cs = repo
+        .changeset(ChangesetSpecifier::Bonsai(changesets["c2"]))
+        .await?
+        .expect("changeset exists");
+
+    // Each entry of "dir3" was last changed on a different branch.
+    let dir3_last_changes: Vec<_> = cs
+        .path("dir3")?
+        .last_changes()
+        .await?
+        .expect("dir3 is a directory")
+        .into_iter()
+        .map(|(name, cs)| (name, cs.id()))
+        .collect();
+    assert_eq!(
+        dir3_last_changes,
+        vec![
+            (String::from("a"), changesets["a4"]),
+            (String::from("b"), changesets["b3"]),
+            (String::from("c"), changesets["c2"]),
+        ]
+    );
+
+    // Entries of the root directory include directories.
+    let root_last_changes: Vec<_> = cs
+        .path("")?
+        .last_changes()
+        .await?
+        .expect("root is a directory")
+        .into_iter()
+        .map(|(name, cs)| (name, cs.id()))
+        .collect();
+    assert_eq!(
+        root_last_changes,
+        vec![
+            (String::from("a"), changesets["a4"]),
+            (String::from("b"), changesets["b3"]),
+            (String::from("c"), changesets["c2"]),
+            (String::from("dir1"), changesets["a4"]),
+            (String::from("dir2"), changesets["b3"]),
+            (String::from("dir3"), changesets["c2"]),
+        ]
+    );
+
+    // Files have no entries.
+    assert!(cs.path("a")?.last_changes().await?.is_none());
+
+    Ok(())
+}
+
 #[fbinit::compat_test]
 async fn commit_history(fb: FacebookInit) -> Result<()> {
     let ctx = CoreContext::test_mock(fb);
@@ -22,10 +22,10 @@ use futures_old::Future;
 use futures_util::stream::TryStreamExt;
 
 use crate::{
-    changeset_path_diff::ChangesetPathDiffContext, ChangesetId, ChangesetIdPrefix,
-    ChangesetPrefixSpecifier, ChangesetSpecifier, ChangesetSpecifierPrefixResolution, CoreContext,
-    FileId, FileMetadata, FileType, HgChangesetId, HgChangesetIdPrefix, Mononoke, MononokePath,
-    TreeEntry, TreeId,
+    changeset_path_diff::{similarity, ChangesetPathDiffContext, SimilarityOptions},
+    ChangesetId, ChangesetIdPrefix, ChangesetPrefixSpecifier, ChangesetSpecifier,
+    ChangesetSpecifierPrefixResolution, CoreContext, FileId, FileMetadata, FileType, HgChangesetId,
+    HgChangesetIdPrefix, Mononoke, MononokePath, TreeEntry, TreeId,
 };
 use cross_repo_sync_test_utils::init_small_large_repo;
 use mononoke_types::{
@@ -842,6 +842,7 @@ async fn test_diff_with_moves(fb: FacebookInit) -> Result<(), Error> {
         .diff(
             root, true, /* include_copies_renames */
             None, /* path_restrictions */
+            None, /* similarity */
         )
         .await?;
 
@@ -857,3 +858,144 @@ async fn test_diff_with_moves(fb: FacebookInit) -> Result<(), Error> {
     }
     Ok(())
 }
+
+#[fbinit::compat_test]
+async fn test_diff_with_similarity(fb: FacebookInit) -> Result<(), Error> {
+    let ctx = CoreContext::test_mock(fb);
+    let blobrepo = new_memblob_empty(None)?;
+    let root = CreateCommitContext::new_root(&ctx, &blobrepo)
+        .add_file("identical", "unchanged content\n")
+        .add_file("similar", "line 1\nline 2\nline 3\nline 4\n")
+        .add_file("modified", "copied content\n")
+        .add_file("removed", "nothing alike\n")
+        .commit()
+        .await?;
+
+    // No copy information is recorded, as in history imported from git.
+    let commit = CreateCommitContext::new(&ctx, &blobrepo, vec![root])
+        .delete_file("identical")
+        .add_file("identical_moved", "unchanged content\n")
+        .delete_file("similar")
+        .add_file("similar_moved", "line 1\nline 2\nline 3\nline 4 changed\n")
+        .add_file("modified", "modified content\n")
+        .add_file("modified_copy", "copied content\n")
+        .delete_file("removed")
+        .add_file("added", "something else entirely\n")
+        .commit()
+        .await?;
+
+    let mononoke =
+        Mononoke::new_test(ctx.clone(), vec![("test".to_string(), blobrepo.clone())]).await?;
+    let repo = mononoke
+        .repo(ctx.clone(), "test")
+        .await?
+        .expect("repo exists");
+    let commit_ctx = repo
+        .changeset(ChangesetSpecifier::Bonsai(commit))
+        .await?
+        .ok_or(anyhow!("commit not found"))?;
+
+    let summarize = |diff: Vec<ChangesetPathDiffContext>| {
+        let mut summary: Vec<_> = diff
+            .into_iter()
+            .map(|entry| match entry {
+                ChangesetPathDiffContext::Added(to) => format!("A {}", to.path()),
+                ChangesetPathDiffContext::Removed(from) => format!("R {}", from.path()),
+                ChangesetPathDiffContext::Changed(to, _) => format!("M {}", to.path()),
+                ChangesetPathDiffContext::Copied(to, from) => {
+                    format!("C {} -> {}", from.path(), to.path())
+                }
+                ChangesetPathDiffContext::Moved(to, from) => {
+                    format!("V {} -> {}", from.path(), to.path())
+                }
+            })
+            .collect();
+        summary.sort();
+        summary
+    };
+
+    let diff = commit_ctx.diff(root, true, None, None).await?;
+    assert_eq!(
+        summarize(diff),
+        vec![
+            "A added",
+            "A identical_moved",
+            "A modified_copy",
+            "A similar_moved",
+            "M modified",
+            "R identical",
+            "R removed",
+            "R similar",
+        ]
+    );
+
+    let diff = commit_ctx
+        .diff(root, true, None, Some(SimilarityOptions::default()))
+        .await?;
+    assert_eq!(
+        summarize(diff),
+        vec![
+            "A added",
+            "A modified_copy",
+            "M modified",
+            "R removed",
+            "V identical -> identical_moved",
+            "V similar -> similar_moved",
+        ]
+    );
+
+    let diff = commit_ctx
+        .diff(
+            root,
+            true,
+            None,
+            Some(SimilarityOptions {
+                detect_copies: true,
+                ..Default::default()
+            }),
+        )
+        .await?;
+    assert_eq!(
+        summarize(diff),
+        vec![
+            "A added",
+            "C modified -> modified_copy",
+            "M modified",
+            "R removed",
+            "V identical -> identical_moved",
+            "V similar -> similar_moved",
+        ]
+    );
+
+    // Only identical files are matched once there are too many candidates.
+    let diff = commit_ctx
+        .diff(
+            root,
+            true,
+            None,
+            Some(SimilarityOptions {
+                max_candidates: 1,
+                ..Default::default()
+            }),
+        )
+        .await?;
+    assert_eq!(
+        summarize(diff),
+        vec![
+            "A added",
+            "A modified_copy",
+            "A similar_moved",
+            "M modified",
+            "R removed",
+            "R similar",
+            "V identical -> identical_moved",
+        ]
+    );
+
+    assert_eq!(similarity(b"", b""), 100);
+    assert_eq!(similarity(b"a\n", b""), 0);
+    assert_eq!(similarity(b"a\nb\n", b"a\nc\n"), 50);
+    assert_eq!(similarity(b"a\nb\n", b"b\na\n"), 100);
+
+    Ok(())
+}
@@ -311,6 +311,37 @@ impl Blame {
         path: MPath,
         parents: Vec<(C, Blame)>,
     ) -> Result<Blame, Error>
+    where
+        C: AsRef<[u8]>,
+    {
+        Blame::from_parents_impl(csid, content, path, parents, false)
+    }
+
+    /// Construct blame for a file as if `csid` had not changed any lines.
+    ///
+    /// Lines that `csid` modified are attributed to the lines they replaced
+    /// in the parents, matched by their position within the changed hunk.
+    /// Lines that were only added have nothing to be attributed to, so they
+    /// are still attributed to `csid`.
+    pub fn from_parents_ignoring<C>(
+        csid: ChangesetId,
+        content: C,
+        path: MPath,
+        parents: Vec<(C, Blame)>,
+    ) -> Result<Blame, Error>
+    where
+        C: AsRef<[u8]>,
+    {
+        Blame::from_parents_impl(csid, content, path, parents, true)
+    }
+
+    fn from_parents_impl<C>(
+        csid: ChangesetId,
+        content: C,
+        path: MPath,
+        parents: Vec<(C, Blame)>,
+        ignore_csid: bool,
+    ) -> Result<Blame, Error>
     where
         C: AsRef<[u8]>,
     {
@@ -326,6 +357,7 @@ impl Blame {
                     path.clone(),
                     parent_content.as_ref(),
                     parent_blame,
+                    ignore_csid,
                 )
             })
             .collect::<Result<Vec<_>, _>>()?;
@@ -366,6 +398,7 @@ impl Blame {
         path: MPath,
         parent_content: C,
         parent_blame: Blame,
+        ignore_csid: bool,
     ) -> Result<Blame, Error> {
         // Hunks comming from `diff_hunks` have two associated ranges `add` and `remove`
         // they always talk about the same place in a code (you are basically replace
@@ -383,15 +416,34 @@ impl Blame {
                 }
 
                 // skip removed ranges
-                let right = if remove.end > remove.start {
-                    let (_removed_blame, right) = blame_ranges_split_at(mid, remove.end as u32);
-                    right
+                let (removed, right) = if remove.end > remove.start {
+                    blame_ranges_split_at(mid, remove.end as u32)
                 } else {
-                    mid
+                    (Vec::new(), mid)
                 };
 
                 // add new range
-                if add.end > add.start {
+                if add.end > add.start && ignore_csid && !removed.is_empty() {
+                    // attribute each new line to the removed line at the same position
+                    // in the hunk, or to the last removed line if more lines were added.
+                    let removed_lines: Vec<_> = removed
+                        .iter()
+                        .flat_map(|range| (0..range.length).map(move |index| (range, index)))
+                        .collect();
+                    let length = (add.end - add.start) as u32;
+                    for index in 0..length {
+                        let (range, range_index) =
+                            removed_lines[(index as usize).min(removed_lines.len() - 1)];
+                        output.push(BlameRange {
+                            offset: 0,
+                            length: 1,
+                            csid: range.csid,
+                            path: range.path.clone(),
+                            origin_offset: range.origin_offset + range_index,
+                        });
+                    }
+                    origin_offset += length;
+                } else if add.end > add.start {
                     let length = (add.end - add.start) as u32;
                     output.push(BlameRange {
                         // we do not care about offset here since all the ranges at this point
@@ -438,6 +490,61 @@ impl Blame {
         BlameLines::new(&self.ranges)
     }
 
+    /// Re-attribute lines using blames that skip over some changesets.
+    ///
+    /// `replacements` maps a changeset and path to the blame of the file as
+    /// of that changeset, constructed with `from_parents_ignoring`.  Lines
+    /// attributed to one of these changesets and paths are attributed to the
+    /// corresponding line of its replacement instead, repeatedly, until they
+    /// reach a line that is not replaced.
+    pub fn reattribute(
+        &self,
+        replacements: &HashMap<(ChangesetId, MPath), Blame>,
+    ) -> Result<Blame, Error> {
+        let replacement_lines: HashMap<_, Vec<_>> = replacements
+            .iter()
+            .map(|(key, blame)| (key.clone(), blame.lines().collect()))
+            .collect();
+
+        let mut ranges: Vec<BlameRange> = Vec::new();
+        for (offset, (mut csid, mut path, mut origin_offset)) in self.lines().enumerate() {
+            while let Some(lines) = replacement_lines.get(&(csid, path.clone())) {
+                let (next_csid, next_path, next_origin_offset) = *lines
+                    .get(origin_offset as usize)
+                    .ok_or_else(|| Error::msg("replacement blame is too short"))?;
+                if next_csid == csid {
+                    // the line was introduced by this changeset
+                    break;
+                }
+                csid = next_csid;
+                path = next_path;
+                origin_offset = next_origin_offset;
+            }
+            match ranges.last_mut() {
+                Some(ref mut last)
+                    if last.csid == csid
+                        && &last.path == path
+                        && last.origin_offset + last.length == origin_offset =>
+                {
+                    last.length += 1;
+                }
+                _ => {
+                    ranges.push(BlameRange {
+                        offset: offset as u32,
+                        length: 1,
+                        csid,
+                        path: path.clone(),
+                        origin_offset,
+                    });
+                }
+            }
+        }
+        if ranges.is_empty() {
+            return Ok(self.clone());
+        }
+        Blame::new(ranges)
+    }
+
     pub fn annotate(&self, content: &str) -> Result<String, Error> {
         if content.is_empty() {
             return Ok(String::new());
@@ -609,6 +716,7 @@ impl<'a> Iterator for BlameMergeLines<'a> {
 mod test {
     use super::*;
     use crate::hash::Blake2;
+    use maplit::hashmap;
 
     const ONES_CSID: ChangesetId = ChangesetId::new(Blake2::from_byte_array([0x11; 32]));
     const TWOS_CSID: ChangesetId = ChangesetId::new(Blake2::from_byte_array([0x22; 32]));
@@ -900,4 +1008,71 @@ mod test {
         assert_eq!(b3_reference, b3);
         Ok(())
     }
+
+    #[test]
+    fn test_blame_ignoring() -> Result<(), Error> {
+        let path = MPath::new("path")?;
+
+        let c1 = "one\ntwo\nthree\n";
+        // reformatting commit, that also adds a line in the same hunk
+        let c2 = "one\nTWO\nTHREE\nextra\n";
+        let c3 = "zero\none\nTWO\nTHREE\nextra\n";
+
+        let b1 = Blame::from_parents(ONES_CSID, c1, path.clone(), Vec::new())?;
+        let b2 = Blame::from_parents(TWOS_CSID, c2, path.clone(), vec![(c1, b1.clone())])?;
+        let b3 = Blame::from_parents(THREES_CSID, c3, path.clone(), vec![(c2, b2)])?;
+
+        let b2_ignoring =
+            Blame::from_parents_ignoring(TWOS_CSID, c2, path.clone(), vec![(c1, b1)])?;
+        let b2_ignoring_reference = Blame::new(vec![
+            BlameRange {
+                offset: 0,
+                length: 3,
+                csid: ONES_CSID,
+                path: path.clone(),
+                origin_offset: 0,
+            },
+            BlameRange {
+                offset: 3,
+                length: 1,
+                csid: ONES_CSID,
+                path: path.clone(),
+                origin_offset: 2,
+            },
+        ])?;
+        assert_eq!(b2_ignoring_reference, b2_ignoring);
+
+        let replacements = hashmap! { (TWOS_CSID, path.clone()) => b2_ignoring };
+        let b3_reference = Blame::new(vec![
+            BlameRange {
+                offset: 0,
+                length: 1,
+                csid: THREES_CSID,
+                path: path.clone(),
+                origin_offset: 0,
+            },
+            BlameRange {
+                offset: 1,
+                length: 3,
+                csid: ONES_CSID,
+                path: path.clone(),
+                origin_offset: 0,
+            },
+            BlameRange {
+                offset: 4,
+                length: 1,
+                csid: ONES_CSID,
+                path: path.clone(),
+                origin_offset: 2,
+            },
+        ])?;
+        assert_eq!(b3_reference, b3.reattribute(&replacements)?);
+
+        // lines only added by an ignored changeset stay attributed to it
+        let c4 = "zero\none\nTWO\nTHREE\nextra\nfour\n";
+        let b4 =
+            Blame::from_parents_ignoring(FOURS_CSID, c4, path.clone(), vec![(c3, b3.clone())])?;
+        assert_eq!(b4.lines().last(), Some((FOURS_CSID, &path, 5)),);
+        Ok(())
+    }
 }
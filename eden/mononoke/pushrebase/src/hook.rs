@@ -20,6 +20,14 @@ pub trait PushrebaseHook: Send + Sync + 'static {
     /// bookmark for pushrebase. It should return a PushrebaseCommitHook for further processing of
     /// the changeset generated by pushrebase.
     async fn prepushrebase(&self) -> Result<Box<dyn PushrebaseCommitHook>, Error>;
+
+    /// Pushes whose hooks all have the same batch keys can be landed together by a
+    /// `LandingQueue`, in which case the hooks of one of them are called for the changesets of all
+    /// of them. Hooks that keep state about a single push have no batch key, and pushes using them
+    /// are always landed on their own.
+    fn batch_key(&self) -> Option<String> {
+        None
+    }
 }
 
 #[async_trait]
@@ -0,0 +1,733 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+/// Landing queue that lands concurrent pushrebases onto the same bookmark together.
+///
+/// Concurrent pushrebases race on the bookmark transaction, and under load most of them lose the
+/// race, rebase again and eventually fail with `TooManyRebaseAttempts`. With a landing queue the
+/// first request for a bookmark waits for `batch_window` to collect other requests for that
+/// bookmark. Then all of them are rebased one on top of another and the bookmark is moved once for
+/// the whole batch. Every request is checked for conflicts separately, so a request that conflicts
+/// fails on its own and the rest of the batch still lands.
+///
+/// Requests with `HgReplayData` are landed one at a time, since a bookmark move can only be
+/// replayed from a single bundle. Pushrebase hooks are called once per batch using the hooks of
+/// its first request, so requests are only batched together when their hooks have the same batch
+/// keys, and requests using hooks without a batch key are landed one at a time too.
+use crate::{
+    check_rebase_conflicts, create_rebased_changesets, get_onto_bookmark_value, prepare_push,
+    rebased_changesets_into_pairs, try_move_bookmark, ErrorKind, HgReplayData, OntoBookmarkParams,
+    PreparedPush, PushrebaseChangesetPair, PushrebaseError, PushrebaseHook,
+    PushrebaseSuccessResult, RebasedChangesets, MAX_REBASE_ATTEMPTS,
+};
+use anyhow::{format_err, Error};
+use blobrepo::BlobRepo;
+use bookmarks::BookmarkName;
+use context::CoreContext;
+use futures::{
+    channel::oneshot,
+    future::{join_all, try_join, try_join_all, TryFutureExt},
+};
+use metaconfig_types::PushrebaseFlags;
+use mononoke_types::{BonsaiChangeset, ChangesetId};
+use std::collections::{hash_map::Entry, BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+type LandingResult = Result<PushrebaseSuccessResult, PushrebaseError>;
+
+struct LandingRequest {
+    ctx: CoreContext,
+    config: PushrebaseFlags,
+    pushed: HashSet<BonsaiChangeset>,
+    maybe_hg_replay_data: Option<HgReplayData>,
+    prepushrebase_hooks: Vec<Box<dyn PushrebaseHook>>,
+    sender: oneshot::Sender<LandingResult>,
+}
+
+impl LandingRequest {
+    fn respond(self, result: LandingResult) {
+        // The caller might have gone away, in which case nobody is interested in the result
+        let _ = self.sender.send(result);
+    }
+
+    /// Requests with the same batch key can be landed in the same batch, and requests without
+    /// one are landed on their own
+    fn batch_key(&self) -> Option<Vec<String>> {
+        if self.maybe_hg_replay_data.is_some() {
+            return None;
+        }
+        self.prepushrebase_hooks
+            .iter()
+            .map(|hook| hook.batch_key())
+            .collect()
+    }
+}
+
+/// Error that failed a whole batch, shared by all the requests in the batch
+#[derive(Clone)]
+struct SharedError(Arc<Error>);
+
+impl fmt::Debug for SharedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&*self.0, f)
+    }
+}
+
+impl fmt::Display for SharedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&*self.0, f)
+    }
+}
+
+impl std::error::Error for SharedError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.0.source()
+    }
+}
+
+/// Returns a function that makes a copy of `err` for every request in a failed batch. Typed
+/// errors are cloned, and other errors are shared.
+fn share_error(err: PushrebaseError) -> impl Fn() -> PushrebaseError {
+    let err = match err {
+        PushrebaseError::Error(err) => Err(SharedError(Arc::new(err))),
+        err => Ok(err),
+    };
+    move || match &err {
+        Ok(PushrebaseError::Conflicts(conflicts)) => PushrebaseError::Conflicts(conflicts.clone()),
+        Ok(PushrebaseError::TextualMergeConflicts(conflicts)) => {
+            PushrebaseError::TextualMergeConflicts(conflicts.clone())
+        }
+        Ok(PushrebaseError::PotentialCaseConflict(path)) => {
+            PushrebaseError::PotentialCaseConflict(path.clone())
+        }
+        Ok(PushrebaseError::RebaseOverMerge) => PushrebaseError::RebaseOverMerge,
+        Ok(PushrebaseError::RootTooFarBehind) => PushrebaseError::RootTooFarBehind,
+        Ok(PushrebaseError::Error(_)) => unreachable!("untyped errors are shared"),
+        Err(err) => PushrebaseError::Error(err.clone().into()),
+    }
+}
+
+pub struct LandingQueue {
+    repo: BlobRepo,
+    batch_window: Duration,
+    pending: Arc<Mutex<HashMap<BookmarkName, Vec<LandingRequest>>>>,
+}
+
+impl LandingQueue {
+    pub fn new(repo: BlobRepo, batch_window: Duration) -> Self {
+        Self {
+            repo,
+            batch_window,
+            pending: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Same as `do_pushrebase_bonsai`, but lands the pushed set together with other requests
+    /// to the same bookmark that arrive within the batch window.
+    pub async fn land(
+        &self,
+        ctx: &CoreContext,
+        config: &PushrebaseFlags,
+        onto_bookmark: &OntoBookmarkParams,
+        pushed: &HashSet<BonsaiChangeset>,
+        maybe_hg_replay_data: &Option<HgReplayData>,
+        prepushrebase_hooks: Vec<Box<dyn PushrebaseHook>>,
+    ) -> LandingResult {
+        let (sender, receiver) = oneshot::channel();
+        let request = LandingRequest {
+            ctx: ctx.clone(),
+            config: config.clone(),
+            pushed: pushed.clone(),
+            maybe_hg_replay_data: maybe_hg_replay_data.clone(),
+            prepushrebase_hooks,
+            sender,
+        };
+
+        let start_batch = {
+            let mut pending = self.pending.lock().expect("lock poisoned");
+            match pending.entry(onto_bookmark.bookmark.clone()) {
+                Entry::Occupied(mut entry) => {
+                    entry.get_mut().push(request);
+                    false
+                }
+                Entry::Vacant(entry) => {
+                    entry.insert(vec![request]);
+                    true
+                }
+            }
+        };
+
+        // Landing runs in a separate task, so that the batch isn't lost if the caller that
+        // started it goes away.
+        if start_batch {
+            tokio::spawn(process_queue(
+                self.repo.clone(),
+                onto_bookmark.clone(),
+                self.pending.clone(),
+                self.batch_window,
+            ));
+        }
+
+        receiver
+            .await
+            .map_err(|_| format_err!("landing queue dropped the pushrebase request"))?
+    }
+}
+
+/// Removes the queue of a bookmark if the task processing it stops before the queue is empty,
+/// e.g. because it panicked. The senders of the queued requests are dropped, so their callers get
+/// an error instead of waiting for a task that is gone, and later requests start a new queue.
+struct QueueGuard {
+    pending: Arc<Mutex<HashMap<BookmarkName, Vec<LandingRequest>>>>,
+    bookmark: BookmarkName,
+    done: bool,
+}
+
+impl Drop for QueueGuard {
+    fn drop(&mut self) {
+        if !self.done {
+            let mut pending = self
+                .pending
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            pending.remove(&self.bookmark);
+        }
+    }
+}
+
+/// Lands batches of requests to `onto_bookmark` until there are none left
+async fn process_queue(
+    repo: BlobRepo,
+    onto_bookmark: OntoBookmarkParams,
+    pending: Arc<Mutex<HashMap<BookmarkName, Vec<LandingRequest>>>>,
+    batch_window: Duration,
+) {
+    let mut guard = QueueGuard {
+        pending,
+        bookmark: onto_bookmark.bookmark.clone(),
+        done: false,
+    };
+
+    tokio::time::delay_for(batch_window).await;
+
+    loop {
+        let requests = {
+            let mut pending = guard.pending.lock().expect("lock poisoned");
+            let requests = pending
+                .get_mut(&onto_bookmark.bookmark)
+                .map(mem::take)
+                .unwrap_or_default();
+            if requests.is_empty() {
+                pending.remove(&onto_bookmark.bookmark);
+                // The queue is removed while holding the lock, so that a request that arrives
+                // later starts a new one
+                guard.done = true;
+                return;
+            }
+            requests
+        };
+
+        let mut batches: Vec<(Vec<String>, Vec<LandingRequest>)> = vec![];
+        let mut single = vec![];
+        for request in requests {
+            match request.batch_key() {
+                Some(key) => match batches.iter_mut().find(|(batch_key, _)| *batch_key == key) {
+                    Some((_, batch)) => batch.push(request),
+                    None => batches.push((key, vec![request])),
+                },
+                None => single.push(request),
+            }
+        }
+
+        for (_, batch) in batches {
+            land_batch(&repo, &onto_bookmark, batch).await;
+        }
+        for request in single {
+            land_batch(&repo, &onto_bookmark, vec![request]).await;
+        }
+    }
+}
+
+async fn land_batch(
+    repo: &BlobRepo,
+    onto_bookmark: &OntoBookmarkParams,
+    requests: Vec<LandingRequest>,
+) {
+    let ctx = match requests.first() {
+        Some(request) => request.ctx.clone(),
+        None => return,
+    };
+
+    let prepared = join_all(requests.iter().map(|request| {
+        prepare_push(
+            &request.ctx,
+            repo,
+            &request.config,
+            onto_bookmark,
+            &request.pushed,
+        )
+    }))
+    .await;
+
+    let mut batch = vec![];
+    for (request, push) in requests.into_iter().zip(prepared) {
+        match push {
+            Ok(push) => batch.push((request, push)),
+            Err(err) => request.respond(Err(err)),
+        }
+    }
+
+    for retry_num in 0..MAX_REBASE_ATTEMPTS {
+        if batch.is_empty() {
+            return;
+        }
+
+        match rebase_batch(&ctx, repo, onto_bookmark, &mut batch).await {
+            Ok(Some(heads)) => {
+                for ((request, _), (head, rebased_changesets)) in batch.into_iter().zip(heads) {
+                    request.respond(Ok(PushrebaseSuccessResult {
+                        head,
+                        retry_num,
+                        rebased_changesets,
+                    }));
+                }
+                return;
+            }
+            Ok(None) => {}
+            Err(err) => {
+                // Errors that aren't specific to a single request fail the whole batch
+                let err = share_error(err);
+                for (request, _) in batch {
+                    request.respond(Err(err()));
+                }
+                return;
+            }
+        }
+    }
+
+    for (request, _) in batch {
+        request.respond(Err(ErrorKind::TooManyRebaseAttempts.into()));
+    }
+}
+
+/// Rebases every request in `batch` on top of the previous one and moves the bookmark to the last
+/// rebased head. Requests that can't be rebased are removed from `batch` and get their error.
+/// Returns the rebased head and changesets of every request left in `batch`, or None if the
+/// bookmark moved and the batch has to be rebased again.
+///
+/// The hooks of the first request are used for the whole batch, and the bookmark is moved in its
+/// context. Batches only contain requests with the same batch key, so their hooks match, and a
+/// request with replay data is always alone in its batch.
+async fn rebase_batch(
+    ctx: &CoreContext,
+    repo: &BlobRepo,
+    onto_bookmark: &OntoBookmarkParams,
+    batch: &mut Vec<(LandingRequest, PreparedPush)>,
+) -> Result<Option<Vec<(ChangesetId, Vec<PushrebaseChangesetPair>)>>, PushrebaseError> {
+    let batch_key = batch[0].0.batch_key();
+    let same_key = batch_key.is_some()
+        && batch
+            .iter()
+            .all(|(request, _)| request.batch_key() == batch_key);
+    if batch.len() > 1 && !same_key {
+        return Err(format_err!("requests in a landing batch must have the same batch key").into());
+    }
+
+    let hooks = try_join_all(
+        batch[0]
+            .0
+            .prepushrebase_hooks
+            .iter()
+            .map(|h| h.prepushrebase().map_err(PushrebaseError::from)),
+    );
+    let (mut hooks, bookmark_val) =
+        try_join(hooks, get_onto_bookmark_value(ctx, repo, onto_bookmark)).await?;
+
+    let mut onto = bookmark_val;
+    let mut heads = vec![];
+    let mut rebased_changesets = RebasedChangesets::new();
+    let mut idx = 0;
+    while idx < batch.len() {
+        let (request, push) = &batch[idx];
+        let onto_cs_id = onto.unwrap_or(push.root);
+        let rebased = async {
+            let merged_file_changes = check_rebase_conflicts(
+                &request.ctx,
+                repo,
+                &request.config,
+                push,
+                push.root,
+                onto_cs_id,
+                &mut BTreeSet::new(),
+            )
+            .await?;
+
+            create_rebased_changesets(
+                &request.ctx,
+                repo,
+                &request.config,
+                push.root,
+                push.head,
+                onto_cs_id,
+                &merged_file_changes,
+                &mut hooks,
+            )
+            .await
+        }
+        .await;
+
+        match rebased {
+            Ok((head, rebased)) => {
+                onto = Some(head);
+                heads.push((head, rebased_changesets_into_pairs(rebased.clone())));
+                rebased_changesets.extend(rebased);
+                idx += 1;
+            }
+            Err(err) => {
+                let (request, _) = batch.remove(idx);
+                request.respond(Err(err));
+            }
+        }
+    }
+
+    let new_value = match onto {
+        Some(new_value) if !heads.is_empty() => new_value,
+        _ => return Ok(Some(heads)),
+    };
+
+    let hooks = try_join_all(
+        hooks
+            .into_iter()
+            .map(|h| h.into_transaction_hook(ctx, &rebased_changesets)),
+    )
+    .await?;
+
+    // Requests with replay data are always landed on their own
+    let maybe_hg_replay_data = batch[0].0.maybe_hg_replay_data.clone();
+
+    let moved = try_move_bookmark(
+        ctx.clone(),
+        repo,
+        onto_bookmark,
+        bookmark_val,
+        new_value,
+        &maybe_hg_replay_data,
+        rebased_changesets,
+        hooks,
+    )
+    .await?;
+
+    Ok(moved.map(|_| heads))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{PushrebaseCommitHook, PushrebaseTransactionHook, RebasedChangesets};
+    use async_trait::async_trait;
+    use blobstore::Loadable;
+    use bookmarks::BookmarkTransactionError;
+    use fbinit::FacebookInit;
+    use fixtures::linear;
+    use futures::{compat::Future01CompatExt, future::join};
+    use maplit::hashset;
+    use sql::Transaction;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tests_utils::{bookmark, resolve_cs_id, CreateCommitContext};
+
+    #[derive(Clone)]
+    struct CountingHook {
+        key: Option<&'static str>,
+        fail: bool,
+        panic: bool,
+        calls: Arc<AtomicUsize>,
+    }
+
+    fn counting_hook(key: Option<&'static str>, calls: &Arc<AtomicUsize>) -> CountingHook {
+        CountingHook {
+            key,
+            fail: false,
+            panic: false,
+            calls: calls.clone(),
+        }
+    }
+
+    impl CountingHook {
+        fn hooks(self) -> Vec<Box<dyn PushrebaseHook>> {
+            vec![Box::new(self)]
+        }
+    }
+
+    #[async_trait]
+    impl PushrebaseHook for CountingHook {
+        async fn prepushrebase(&self) -> Result<Box<dyn PushrebaseCommitHook>, Error> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            if self.fail {
+                return Err(format_err!("hook failed"));
+            }
+            if self.panic {
+                panic!("hook panicked");
+            }
+            Ok(Box::new(self.clone()) as Box<dyn PushrebaseCommitHook>)
+        }
+
+        fn batch_key(&self) -> Option<String> {
+            self.key.map(String::from)
+        }
+    }
+
+    #[async_trait]
+    impl PushrebaseCommitHook for CountingHook {
+        async fn into_transaction_hook(
+            self: Box<Self>,
+            _ctx: &CoreContext,
+            _changesets: &RebasedChangesets,
+        ) -> Result<Box<dyn PushrebaseTransactionHook>, Error> {
+            Ok(self as Box<dyn PushrebaseTransactionHook>)
+        }
+    }
+
+    #[async_trait]
+    impl PushrebaseTransactionHook for CountingHook {
+        async fn populate_transaction(
+            &self,
+            _ctx: &CoreContext,
+            txn: Transaction,
+        ) -> Result<Transaction, BookmarkTransactionError> {
+            Ok(txn)
+        }
+    }
+
+    async fn init_repo(
+        fb: FacebookInit,
+    ) -> Result<(CoreContext, BlobRepo, ChangesetId, OntoBookmarkParams), Error> {
+        let ctx = CoreContext::test_mock(fb);
+        let repo = linear::getrepo(fb).await;
+        let root = resolve_cs_id(&ctx, &repo, "2d7d4ba9ce0a6ffd222de7785b249ead9c51c536").await?;
+        let book = OntoBookmarkParams::new(BookmarkName::new("master")?);
+        bookmark(&ctx, &repo, book.bookmark.clone())
+            .set_to("a5ffa77602a066db7d5cfb9fb5823a0895717c5a")
+            .await?;
+        Ok((ctx, repo, root, book))
+    }
+
+    async fn create_push(
+        ctx: &CoreContext,
+        repo: &BlobRepo,
+        root: ChangesetId,
+        path: &str,
+        content: &str,
+    ) -> Result<HashSet<BonsaiChangeset>, Error> {
+        let bcs = CreateCommitContext::new(ctx, repo, vec![root])
+            .add_file(path, content)
+            .commit()
+            .await?
+            .load(ctx.clone(), repo.blobstore())
+            .compat()
+            .await?;
+        Ok(hashset![bcs])
+    }
+
+    async fn parents(
+        ctx: &CoreContext,
+        repo: &BlobRepo,
+        cs_id: ChangesetId,
+    ) -> Result<Vec<ChangesetId>, Error> {
+        let bcs = cs_id.load(ctx.clone(), repo.blobstore()).compat().await?;
+        Ok(bcs.parents().collect())
+    }
+
+    #[fbinit::test]
+    fn landing_queue_window_expiry(fb: FacebookInit) -> Result<(), Error> {
+        let mut runtime = tokio_compat::runtime::Runtime::new().unwrap();
+
+        runtime.block_on_std(async move {
+            let (ctx, repo, root, book) = init_repo(fb).await?;
+            let first = create_push(&ctx, &repo, root, "a", "content").await?;
+            let second = create_push(&ctx, &repo, root, "b", "content").await?;
+            let third = create_push(&ctx, &repo, root, "c", "content").await?;
+
+            let queue = LandingQueue::new(repo.clone(), Duration::from_millis(100));
+            let config = PushrebaseFlags::default();
+            let calls = Arc::new(AtomicUsize::new(0));
+            let hooks = || counting_hook(Some("hook"), &calls).hooks();
+
+            // Both requests arrive within the window and land in the same batch
+            let (first, second) = join(
+                queue.land(&ctx, &config, &book, &first, &None, hooks()),
+                queue.land(&ctx, &config, &book, &second, &None, hooks()),
+            )
+            .await;
+            let (first, second) = (first?, second?);
+            assert_eq!(calls.load(Ordering::SeqCst), 1);
+            assert_eq!(parents(&ctx, &repo, second.head).await?, vec![first.head]);
+
+            // The window of the first batch has expired, so the request starts a new batch
+            let third = queue
+                .land(&ctx, &config, &book, &third, &None, hooks())
+                .await?;
+            assert_eq!(calls.load(Ordering::SeqCst), 2);
+            assert_eq!(parents(&ctx, &repo, third.head).await?, vec![second.head]);
+            assert_eq!(resolve_cs_id(&ctx, &repo, "master").await?, third.head);
+
+            Ok(())
+        })
+    }
+
+    #[fbinit::test]
+    fn landing_queue_conflict_in_batch(fb: FacebookInit) -> Result<(), Error> {
+        let mut runtime = tokio_compat::runtime::Runtime::new().unwrap();
+
+        runtime.block_on_std(async move {
+            let (ctx, repo, root, book) = init_repo(fb).await?;
+            // The second push conflicts with the first one
+            let mut pushes = vec![];
+            for (path, content) in &[("a", "first"), ("a", "second"), ("b", "third")] {
+                pushes.push(create_push(&ctx, &repo, root, path, content).await?);
+            }
+
+            let queue = LandingQueue::new(repo.clone(), Duration::from_millis(100));
+            let config = PushrebaseFlags::default();
+            let mut results = join_all(
+                pushes
+                    .iter()
+                    .map(|pushed| queue.land(&ctx, &config, &book, pushed, &None, vec![])),
+            )
+            .await
+            .into_iter();
+
+            let first = results.next().unwrap()?;
+            match results.next().unwrap() {
+                Err(PushrebaseError::Conflicts(_)) => {}
+                _ => panic!("the second push should have failed with conflicts"),
+            }
+            let third = results.next().unwrap()?;
+
+            // The rest of the batch still landed, in a single attempt
+            assert_eq!(first.retry_num, 0);
+            assert_eq!(third.retry_num, 0);
+            assert_eq!(parents(&ctx, &repo, third.head).await?, vec![first.head]);
+            assert_eq!(resolve_cs_id(&ctx, &repo, "master").await?, third.head);
+
+            Ok(())
+        })
+    }
+
+    #[fbinit::test]
+    fn landing_queue_mixed_hooks(fb: FacebookInit) -> Result<(), Error> {
+        let mut runtime = tokio_compat::runtime::Runtime::new().unwrap();
+
+        runtime.block_on_std(async move {
+            let (ctx, repo, root, book) = init_repo(fb).await?;
+            let keys = [Some("x"), Some("x"), None, Some("y")];
+            let mut pushes = vec![];
+            let mut calls = vec![];
+            for (i, key) in keys.iter().enumerate() {
+                let path = format!("file{}", i);
+                let pushed = create_push(&ctx, &repo, root, &path, "content").await?;
+                let count = Arc::new(AtomicUsize::new(0));
+                pushes.push((pushed, counting_hook(*key, &count)));
+                calls.push(count);
+            }
+
+            let queue = LandingQueue::new(repo.clone(), Duration::from_millis(100));
+            let config = PushrebaseFlags::default();
+            let results = join_all(pushes.iter().map(|(pushed, hook)| {
+                queue.land(&ctx, &config, &book, pushed, &None, hook.clone().hooks())
+            }))
+            .await;
+            for result in results {
+                assert_eq!(result?.retry_num, 0);
+            }
+
+            // The first two requests are batched and share the hooks of the first one. The
+            // others have different hooks and land in batches of their own.
+            let calls: Vec<_> = calls
+                .iter()
+                .map(|count| count.load(Ordering::SeqCst))
+                .collect();
+            assert_eq!(calls, vec![1, 0, 1, 1]);
+
+            Ok(())
+        })
+    }
+
+    #[fbinit::test]
+    fn landing_queue_batch_error(fb: FacebookInit) -> Result<(), Error> {
+        let mut runtime = tokio_compat::runtime::Runtime::new().unwrap();
+
+        runtime.block_on_std(async move {
+            let (ctx, repo, root, book) = init_repo(fb).await?;
+            let first = create_push(&ctx, &repo, root, "a", "content").await?;
+            let second = create_push(&ctx, &repo, root, "b", "content").await?;
+
+            let queue = LandingQueue::new(repo.clone(), Duration::from_millis(100));
+            let config = PushrebaseFlags::default();
+            let calls = Arc::new(AtomicUsize::new(0));
+            let hook = CountingHook {
+                fail: true,
+                ..counting_hook(Some("hook"), &calls)
+            };
+            let (first, second) = join(
+                queue.land(&ctx, &config, &book, &first, &None, hook.clone().hooks()),
+                queue.land(&ctx, &config, &book, &second, &None, hook.hooks()),
+            )
+            .await;
+
+            // The hook failed once for the whole batch, and every request got its error
+            assert_eq!(calls.load(Ordering::SeqCst), 1);
+            for result in vec![first, second] {
+                match result {
+                    Err(PushrebaseError::Error(err)) => assert_eq!(err.to_string(), "hook failed"),
+                    _ => panic!("the push should have failed with the hook error"),
+                }
+            }
+
+            Ok(())
+        })
+    }
+
+    #[fbinit::test]
+    fn landing_queue_task_panic(fb: FacebookInit) -> Result<(), Error> {
+        let mut runtime = tokio_compat::runtime::Runtime::new().unwrap();
+
+        runtime.block_on_std(async move {
+            let (ctx, repo, root, book) = init_repo(fb).await?;
+            let first = create_push(&ctx, &repo, root, "a", "content").await?;
+            let second = create_push(&ctx, &repo, root, "b", "content").await?;
+            let third = create_push(&ctx, &repo, root, "c", "content").await?;
+
+            let queue = LandingQueue::new(repo.clone(), Duration::from_millis(100));
+            let config = PushrebaseFlags::default();
+            let calls = Arc::new(AtomicUsize::new(0));
+            let hook = CountingHook {
+                panic: true,
+                ..counting_hook(Some("hook"), &calls)
+            };
+            let (first, second) = join(
+                queue.land(&ctx, &config, &book, &first, &None, hook.clone().hooks()),
+                queue.land(&ctx, &config, &book, &second, &None, hook.hooks()),
+            )
+            .await;
+
+            // The task landing the batch panicked, so both requests fail instead of waiting
+            assert!(first.is_err());
+            assert!(second.is_err());
+
+            // The queue of the bookmark was cleaned up, so later requests still land
+            let third = queue
+                .land(&ctx, &config, &book, &third, &None, vec![])
+                .await?;
+            assert_eq!(resolve_cs_id(&ctx, &repo, "master").await?, third.head);
+
+            Ok(())
+        })
+    }
+}
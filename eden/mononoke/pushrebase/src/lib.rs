@@ -48,6 +48,12 @@
 /// Pushrebase supports hooks, which can be used to modify rebased Bonsai commits as well as
 /// sideload database updates in the transaction that moves forward the bookmark. See hooks.rs for
 /// more information on those;
+///
+/// If `textual_merge_size_limit` is set, files changed both in the rebased set and in
+/// root::onto are merged line by line instead of failing the push. See textual_merge.rs.
+///
+/// Concurrent pushrebases onto the same bookmark can be batched with a `LandingQueue`, which
+/// moves the bookmark once for the whole batch. See landing_queue.rs.
 use anyhow::{format_err, Error, Result};
 use blobrepo::{save_bonsai_changesets, BlobRepo};
 use blobrepo_utils::convert_diff_result_into_file_change_for_diamond_merge;
@@ -75,14 +81,19 @@ use mononoke_types::{
 use revset::RangeNodeStream;
 use slog::info;
 use std::cmp::{max, Ordering};
-use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
 use std::iter::FromIterator;
 use std::sync::Arc;
+use textual_merge::{merge_conflicting_files, MergedFileChanges};
 use thiserror::Error;
 
 pub use hook::{PushrebaseCommitHook, PushrebaseHook, PushrebaseTransactionHook};
+pub use landing_queue::LandingQueue;
+pub use textual_merge::TextualMergeConflict;
 
 mod hook;
+mod landing_queue;
+mod textual_merge;
 
 const MAX_REBASE_ATTEMPTS: usize = 100;
 
@@ -114,6 +125,8 @@ pub enum ErrorKind {
 pub enum PushrebaseError {
     #[error("Conflicts while pushrebasing: {0:?}")]
     Conflicts(Vec<PushrebaseConflict>),
+    #[error("Conflicting lines while merging files during pushrebase: {0:?}")]
+    TextualMergeConflicts(Vec<TextualMergeConflict>),
     #[error("Potential case conflict while pushrebasing: {0:?}")]
     PotentialCaseConflict(MPath),
     #[error("Pushrebase over merge")]
@@ -238,6 +251,37 @@ pub async fn do_pushrebase_bonsai(
     maybe_hg_replay_data: &Option<HgReplayData>,
     prepushrebase_hooks: &[Box<dyn PushrebaseHook>],
 ) -> Result<PushrebaseSuccessResult, PushrebaseError> {
+    let push = prepare_push(ctx, repo, config, onto_bookmark, pushed).await?;
+
+    let res = rebase_in_loop(
+        ctx,
+        repo,
+        config,
+        onto_bookmark,
+        &push,
+        maybe_hg_replay_data,
+        prepushrebase_hooks,
+    )
+    .await?;
+
+    Ok(res)
+}
+
+/// Pushed set together with what's needed to rebase it
+struct PreparedPush {
+    head: ChangesetId,
+    root: ChangesetId,
+    client_cf: Vec<MPath>,
+    client_bcs: Vec<BonsaiChangeset>,
+}
+
+async fn prepare_push(
+    ctx: &CoreContext,
+    repo: &BlobRepo,
+    config: &PushrebaseFlags,
+    onto_bookmark: &OntoBookmarkParams,
+    pushed: &HashSet<BonsaiChangeset>,
+) -> Result<PreparedPush, PushrebaseError> {
     let head = find_only_head_or_fail(&pushed)?;
     let roots = find_roots(&pushed);
 
@@ -255,21 +299,12 @@ pub async fn do_pushrebase_bonsai(
     // many commits are missing filenodes.
     check_filenodes_backfilled(&ctx, &repo, &head, config.not_generated_filenodes_limit).await?;
 
-    let res = rebase_in_loop(
-        ctx,
-        repo,
-        config,
-        onto_bookmark,
+    Ok(PreparedPush {
         head,
         root,
         client_cf,
-        &client_bcs,
-        maybe_hg_replay_data,
-        prepushrebase_hooks,
-    )
-    .await?;
-
-    Ok(res)
+        client_bcs,
+    })
 }
 
 async fn check_filenodes_backfilled<'a>(
@@ -295,14 +330,14 @@ async fn rebase_in_loop(
     repo: &BlobRepo,
     config: &PushrebaseFlags,
     onto_bookmark: &OntoBookmarkParams,
-    head: ChangesetId,
-    root: ChangesetId,
-    client_cf: Vec<MPath>,
-    client_bcs: &Vec<BonsaiChangeset>,
+    push: &PreparedPush,
     maybe_hg_replay_data: &Option<HgReplayData>,
     prepushrebase_hooks: &[Box<dyn PushrebaseHook>],
 ) -> Result<PushrebaseSuccessResult, PushrebaseError> {
+    let root = push.root;
     let mut latest_rebase_attempt = root;
+    // Paths that were changed on both sides and have to be merged on every attempt
+    let mut textual_merge_paths = BTreeSet::new();
 
     for retry_num in 0..MAX_REBASE_ATTEMPTS {
         let hooks = try_join_all(
@@ -314,42 +349,27 @@ async fn rebase_in_loop(
         let (hooks, bookmark_val) =
             try_join(hooks, get_onto_bookmark_value(&ctx, &repo, &onto_bookmark)).await?;
 
-        let server_bcs = fetch_bonsai_range(
-            &ctx,
-            &repo,
-            latest_rebase_attempt,
-            bookmark_val.unwrap_or(root),
-        )
-        .await?;
-
-        if config.casefolding_check {
-            let conflict =
-                check_case_conflicts(server_bcs.iter().rev().chain(client_bcs.iter().rev()));
-            if let Some(conflict) = conflict {
-                return Err(PushrebaseError::PotentialCaseConflict(conflict));
-            }
-        }
-
-        let server_cf = find_changed_files(
-            &ctx,
-            &repo,
+        let merged_file_changes = check_rebase_conflicts(
+            ctx,
+            repo,
+            config,
+            push,
             latest_rebase_attempt,
             bookmark_val.unwrap_or(root),
+            &mut textual_merge_paths,
         )
         .await?;
 
-        // TODO: Avoid this clone
-        intersect_changed_files(server_cf, client_cf.clone())?;
-
         let rebase_outcome = do_rebase(
             &ctx,
             &repo,
             &config,
             root,
-            head,
+            push.head,
             bookmark_val,
             &onto_bookmark,
             maybe_hg_replay_data,
+            &merged_file_changes,
             hooks,
         )
         .await?;
@@ -369,6 +389,57 @@ async fn rebase_in_loop(
     Err(ErrorKind::TooManyRebaseAttempts.into())
 }
 
+/// Checks that commits in `latest_rebase_attempt::onto` don't conflict with the pushed set.
+/// Returns file changes that resolve the conflicts which can be merged line by line.
+async fn check_rebase_conflicts(
+    ctx: &CoreContext,
+    repo: &BlobRepo,
+    config: &PushrebaseFlags,
+    push: &PreparedPush,
+    latest_rebase_attempt: ChangesetId,
+    onto: ChangesetId,
+    textual_merge_paths: &mut BTreeSet<MPath>,
+) -> Result<MergedFileChanges, PushrebaseError> {
+    let server_bcs = fetch_bonsai_range(&ctx, &repo, latest_rebase_attempt, onto).await?;
+
+    if config.casefolding_check {
+        let conflict =
+            check_case_conflicts(server_bcs.iter().rev().chain(push.client_bcs.iter().rev()));
+        if let Some(conflict) = conflict {
+            return Err(PushrebaseError::PotentialCaseConflict(conflict));
+        }
+    }
+
+    let server_cf = find_changed_files(&ctx, &repo, latest_rebase_attempt, onto).await?;
+
+    // TODO: Avoid this clone
+    match intersect_changed_files(server_cf, push.client_cf.clone()) {
+        Err(PushrebaseError::Conflicts(conflicts))
+            if config.textual_merge_size_limit.is_some()
+                && conflicts.iter().all(|c| c.left == c.right) =>
+        {
+            textual_merge_paths.extend(conflicts.into_iter().map(|c| c.left));
+        }
+        res => res?,
+    }
+
+    match config.textual_merge_size_limit {
+        Some(size_limit) if !textual_merge_paths.is_empty() => {
+            merge_conflicting_files(
+                &ctx,
+                &repo,
+                size_limit,
+                push.root,
+                onto,
+                &textual_merge_paths,
+                &push.client_bcs,
+            )
+            .await
+        }
+        _ => Ok(MergedFileChanges::new()),
+    }
+}
+
 async fn do_rebase(
     ctx: &CoreContext,
     repo: &BlobRepo,
@@ -378,6 +449,7 @@ async fn do_rebase(
     bookmark_val: Option<ChangesetId>,
     onto_bookmark: &OntoBookmarkParams,
     maybe_hg_replay_data: &Option<HgReplayData>,
+    merged_file_changes: &MergedFileChanges,
     mut hooks: Vec<Box<dyn PushrebaseCommitHook>>,
 ) -> Result<Option<(ChangesetId, Vec<PushrebaseChangesetPair>)>, PushrebaseError> {
     let (new_head, rebased_changesets) = create_rebased_changesets(
@@ -387,6 +459,7 @@ async fn do_rebase(
         root,
         head,
         bookmark_val.unwrap_or(root),
+        merged_file_changes,
         &mut hooks,
     )
     .await?;
@@ -814,6 +887,7 @@ async fn create_rebased_changesets(
     root: ChangesetId,
     head: ChangesetId,
     onto: ChangesetId,
+    merged_file_changes: &MergedFileChanges,
     hooks: &mut [Box<dyn PushrebaseCommitHook>],
 ) -> Result<(ChangesetId, RebasedChangesets), PushrebaseError> {
     let rebased_set = find_rebased_set(&ctx, &repo, root, head).await?;
@@ -848,6 +922,7 @@ async fn create_rebased_changesets(
             &onto,
             &repo,
             &rebased_set_ids,
+            merged_file_changes,
             hooks,
         )
         .await?;
@@ -885,6 +960,7 @@ async fn rebase_changeset(
     onto: &ChangesetId,
     repo: &BlobRepo,
     rebased_set: &HashSet<ChangesetId>,
+    merged_file_changes: &MergedFileChanges,
     hooks: &mut [Box<dyn PushrebaseCommitHook>],
 ) -> Result<BonsaiChangeset> {
     let orig_cs_id = bcs.get_changeset_id();
@@ -937,6 +1013,12 @@ async fn rebase_changeset(
         })
         .collect();
 
+    if let Some(merged) = merged_file_changes.get(&orig_cs_id) {
+        for (path, file_change) in merged {
+            file_changes.insert(path.clone(), Some(file_change.clone()));
+        }
+    }
+
     let new_file_paths: HashSet<_> =
         HashSet::from_iter(new_file_changes.iter().map(|(path, _)| path));
     for (path, _) in &file_changes {
@@ -1200,7 +1282,7 @@ mod tests {
     use fixtures::{linear, many_files_dirs, merge_even};
     use futures::{
         compat::Future01CompatExt,
-        future::{try_join_all, TryFutureExt},
+        future::{join_all, try_join_all, TryFutureExt},
         stream::{self, TryStreamExt},
     };
     use manifest::{Entry, ManifestOps};
@@ -1866,6 +1948,168 @@ mod tests {
         })
     }
 
+    #[fbinit::test]
+    fn pushrebase_textual_merge(fb: FacebookInit) -> Result<(), Error> {
+        let mut runtime = tokio_compat::runtime::Runtime::new().unwrap();
+
+        runtime.block_on_std(async move {
+            let ctx = CoreContext::test_mock(fb);
+            let repo = linear::getrepo(fb).await;
+            let root = CreateCommitContext::new(
+                &ctx,
+                &repo,
+                vec!["a5ffa77602a066db7d5cfb9fb5823a0895717c5a"],
+            )
+            .add_file("list", "a\nb\nc\nd\ne\n")
+            .commit()
+            .await?;
+            let onto = CreateCommitContext::new(&ctx, &repo, vec![root])
+                .add_file("list", "A\nb\nc\nd\ne\n")
+                .commit()
+                .await?;
+
+            let book = master_bookmark();
+            bookmark(&ctx, &repo, book.bookmark.clone())
+                .set_to(onto)
+                .await?;
+
+            let config = PushrebaseFlags {
+                textual_merge_size_limit: Some(1000),
+                ..Default::default()
+            };
+
+            let bcs_id_1 = CreateCommitContext::new(&ctx, &repo, vec![root])
+                .add_file("list", "a\nb\nc\nd\nE\n")
+                .commit()
+                .await?;
+            let bcs_id_2 = CreateCommitContext::new(&ctx, &repo, vec![bcs_id_1])
+                .add_file("list", "a\nb\nc\nd\nE\nf\n")
+                .add_file("other", "other")
+                .commit()
+                .await?;
+            let hgcss = hashset![
+                repo.get_hg_from_bonsai_changeset(ctx.clone(), bcs_id_1)
+                    .compat()
+                    .await?,
+                repo.get_hg_from_bonsai_changeset(ctx.clone(), bcs_id_2)
+                    .compat()
+                    .await?,
+            ];
+
+            should_have_conflicts(
+                do_pushrebase(&ctx, &repo, &Default::default(), &book, &hgcss, &None).await,
+            );
+
+            let result = do_pushrebase(&ctx, &repo, &config, &book, &hgcss, &None)
+                .map_err(|err| format_err!("{:?}", err))
+                .await?;
+
+            let head = result
+                .head
+                .load(ctx.clone(), repo.blobstore())
+                .compat()
+                .await?;
+            let file_change = head
+                .file_changes()
+                .find(|(path, _)| *path == &MPath::new("list").unwrap())
+                .and_then(|(_, file_change)| file_change.cloned())
+                .ok_or(Error::msg("list is missing"))?;
+            let content =
+                filestore::fetch_concat(repo.blobstore(), ctx.clone(), file_change.content_id())
+                    .compat()
+                    .await?;
+            assert_eq!(content.as_ref(), b"A\nb\nc\nd\nE\nf\n");
+
+            let bcs_id_3 = CreateCommitContext::new(&ctx, &repo, vec![root])
+                .add_file("list", "X\nb\nc\nd\ne\n")
+                .commit()
+                .await?;
+            let hgcss = hashset![
+                repo.get_hg_from_bonsai_changeset(ctx.clone(), bcs_id_3)
+                    .compat()
+                    .await?,
+            ];
+            match do_pushrebase(&ctx, &repo, &config, &book, &hgcss, &None).await {
+                Err(PushrebaseError::TextualMergeConflicts(conflicts)) => {
+                    assert_eq!(
+                        conflicts,
+                        vec![TextualMergeConflict {
+                            path: MPath::new("list")?,
+                            onto_lines: 0..1,
+                            pushed_lines: 0..1,
+                        }],
+                    );
+                }
+                _ => panic!("push-rebase should have failed with textual merge conflicts"),
+            }
+
+            Ok(())
+        })
+    }
+
+    #[fbinit::test]
+    fn pushrebase_landing_queue(fb: FacebookInit) -> Result<(), Error> {
+        let mut runtime = tokio_compat::runtime::Runtime::new().unwrap();
+
+        runtime.block_on_std(async move {
+            let ctx = CoreContext::test_mock(fb);
+            let repo = linear::getrepo(fb).await;
+            let root =
+                resolve_cs_id(&ctx, &repo, "2d7d4ba9ce0a6ffd222de7785b249ead9c51c536").await?;
+
+            let book = master_bookmark();
+            bookmark(&ctx, &repo, book.bookmark.clone())
+                .set_to("a5ffa77602a066db7d5cfb9fb5823a0895717c5a")
+                .await?;
+
+            // The last push conflicts with commits on master
+            let mut pushes = vec![];
+            for path in &["a", "b", "c", "9"] {
+                let bcs = CreateCommitContext::new(&ctx, &repo, vec![root])
+                    .add_file(*path, "content")
+                    .commit()
+                    .await?
+                    .load(ctx.clone(), repo.blobstore())
+                    .compat()
+                    .await?;
+                pushes.push(hashset![bcs]);
+            }
+
+            let queue = LandingQueue::new(repo.clone(), Duration::from_millis(100));
+            let config = PushrebaseFlags::default();
+            let mut results = join_all(
+                pushes
+                    .iter()
+                    .map(|pushed| queue.land(&ctx, &config, &book, pushed, &None, vec![])),
+            )
+            .await;
+
+            should_have_conflicts(results.pop().unwrap());
+            let results = results
+                .into_iter()
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|err| format_err!("{:?}", err))?;
+
+            // All pushes landed in the same batch, one on top of another
+            for result in &results {
+                assert_eq!(result.retry_num, 0);
+                assert_eq!(result.rebased_changesets.len(), 1);
+            }
+            let master = resolve_cs_id(&ctx, &repo, "master").await?;
+            assert_eq!(master, results[2].head);
+            let parents: Vec<_> = results[2]
+                .head
+                .load(ctx.clone(), repo.blobstore())
+                .compat()
+                .await?
+                .parents()
+                .collect();
+            assert_eq!(parents, vec![results[1].head]);
+
+            Ok(())
+        })
+    }
+
     #[fbinit::test]
     fn pushrebase_caseconflicting_rename(fb: FacebookInit) -> Result<(), Error> {
         let mut runtime = tokio_compat::runtime::Runtime::new().unwrap();
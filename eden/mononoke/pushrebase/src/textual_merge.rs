@@ -0,0 +1,429 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+/// Line-level merge of files that were modified both by the pushed set and by the commits
+/// that landed on the onto bookmark since the root.
+///
+/// Normally any such file is a pushrebase conflict. When `textual_merge_size_limit` is set in
+/// `PushrebaseFlags`, small text files are instead merged with a three-way merge that uses the
+/// version from the root as a base. Every commit from the rebased set that modifies the file
+/// gets a new file change with onto's edits merged into its own version of the file, so the
+/// whole rebased stack stays consistent. The push still fails if the two sides changed
+/// overlapping (or adjacent) lines.
+///
+/// Files that can't be merged (binary, too large, symlinks, deleted, copied, or missing from
+/// either the root or onto) are reported as regular conflicts.
+use crate::{PushrebaseConflict, PushrebaseError};
+use anyhow::Error;
+use blobrepo::BlobRepo;
+use blobstore::Loadable;
+use bytes::Bytes;
+use context::CoreContext;
+use filestore::{self, StoreRequest};
+use futures::{
+    compat::{Future01CompatExt, Stream01CompatExt},
+    future::try_join_all,
+    stream::TryStreamExt,
+};
+use futures_old::stream as old_stream;
+use manifest::{Entry, ManifestOps};
+use mercurial_types::MPath;
+use mononoke_types::{BonsaiChangeset, ChangesetId, ContentId, FileChange, FileType};
+use std::collections::{BTreeSet, HashMap};
+use std::ops::Range;
+use xdiff::{diff_hunks, Hunk};
+
+/// File changes that replace the original ones in the rebased commits, keyed by the id of the
+/// commit before the rebase.
+pub type MergedFileChanges = HashMap<ChangesetId, Vec<(MPath, FileChange)>>;
+
+/// Lines that were changed differently by onto and by the pushed commit. Ranges are zero-based
+/// and exclusive at the end; an empty range marks the position of an insertion.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct TextualMergeConflict {
+    pub path: MPath,
+    pub onto_lines: Range<usize>,
+    pub pushed_lines: Range<usize>,
+}
+
+/// Merges every path in `paths` for each commit in `rebased_set` that modifies it.
+pub async fn merge_conflicting_files(
+    ctx: &CoreContext,
+    repo: &BlobRepo,
+    size_limit: u64,
+    root: ChangesetId,
+    onto: ChangesetId,
+    paths: &BTreeSet<MPath>,
+    rebased_set: &[BonsaiChangeset],
+) -> Result<MergedFileChanges, PushrebaseError> {
+    let rebased_set: Vec<_> = rebased_set
+        .iter()
+        .filter(|bcs| bcs.get_changeset_id() != root)
+        .collect();
+
+    // Merge commits get extra file changes from `generate_additional_bonsai_file_changes`,
+    // which would clash with the merged ones.
+    let unmergeable = |paths: &BTreeSet<MPath>| {
+        PushrebaseError::Conflicts(
+            paths
+                .iter()
+                .map(|path| PushrebaseConflict::new(path.clone(), path.clone()))
+                .collect(),
+        )
+    };
+    if rebased_set.iter().any(|bcs| bcs.parents().count() > 1) {
+        return Err(unmergeable(paths));
+    }
+
+    let (base_files, onto_files) = futures::try_join!(
+        fetch_files(ctx, repo, root, paths, size_limit),
+        fetch_files(ctx, repo, onto, paths, size_limit),
+    )?;
+
+    let mut unmergeable_paths = BTreeSet::new();
+    let mut conflicts = vec![];
+    let mut merged_file_changes = MergedFileChanges::new();
+    for path in paths {
+        let (base, onto) = match (base_files.get(path), onto_files.get(path)) {
+            (Some((base_type, base)), Some((onto_type, onto))) if base_type == onto_type => {
+                (base, onto)
+            }
+            _ => {
+                unmergeable_paths.insert(path.clone());
+                continue;
+            }
+        };
+
+        let mut changed_in_rebased_set = false;
+        for bcs in &rebased_set {
+            for (changed_path, maybe_file_change) in bcs.file_changes() {
+                let copied_from_path = maybe_file_change
+                    .and_then(|file_change| file_change.copy_from())
+                    .map_or(false, |(copy_from_path, _)| copy_from_path == path);
+                if copied_from_path {
+                    unmergeable_paths.insert(path.clone());
+                }
+                if changed_path != path {
+                    continue;
+                }
+                changed_in_rebased_set = true;
+
+                let file_change = match maybe_file_change {
+                    Some(file_change)
+                        if file_change.copy_from().is_none()
+                            && file_change.file_type() != FileType::Symlink
+                            && file_change.size() <= size_limit =>
+                    {
+                        file_change
+                    }
+                    _ => {
+                        unmergeable_paths.insert(path.clone());
+                        continue;
+                    }
+                };
+
+                let pushed = fetch_content(ctx, repo, file_change.content_id()).await?;
+                if is_binary(&pushed) {
+                    unmergeable_paths.insert(path.clone());
+                    continue;
+                }
+
+                match merge3(base, onto, &pushed) {
+                    Ok(merged) => {
+                        let file_change =
+                            store_content(ctx, repo, merged, file_change.file_type()).await?;
+                        merged_file_changes
+                            .entry(bcs.get_changeset_id())
+                            .or_insert_with(Vec::new)
+                            .push((path.clone(), file_change));
+                    }
+                    Err(ranges) => {
+                        conflicts.extend(ranges.into_iter().map(|(onto_lines, pushed_lines)| {
+                            TextualMergeConflict {
+                                path: path.clone(),
+                                onto_lines,
+                                pushed_lines,
+                            }
+                        }));
+                    }
+                }
+            }
+        }
+
+        if !changed_in_rebased_set {
+            unmergeable_paths.insert(path.clone());
+        }
+    }
+
+    if !unmergeable_paths.is_empty() {
+        Err(unmergeable(&unmergeable_paths))
+    } else if !conflicts.is_empty() {
+        Err(PushrebaseError::TextualMergeConflicts(conflicts))
+    } else {
+        Ok(merged_file_changes)
+    }
+}
+
+/// Fetches the content of those `paths` in `cs_id` that are mergeable text files.
+async fn fetch_files(
+    ctx: &CoreContext,
+    repo: &BlobRepo,
+    cs_id: ChangesetId,
+    paths: &BTreeSet<MPath>,
+    size_limit: u64,
+) -> Result<HashMap<MPath, (FileType, Bytes)>, Error> {
+    let mf_id = crate::id_to_manifestid(ctx, repo, cs_id).await?;
+    let entries = mf_id
+        .find_entries(ctx.clone(), repo.get_blobstore(), paths.iter().cloned())
+        .compat()
+        .try_collect::<Vec<_>>()
+        .await?;
+
+    let files = try_join_all(entries.into_iter().map(|(path, entry)| async move {
+        let (path, file_type, filenode_id) = match (path, entry) {
+            (Some(path), Entry::Leaf((file_type, filenode_id)))
+                if file_type != FileType::Symlink =>
+            {
+                (path, file_type, filenode_id)
+            }
+            _ => return Ok(None),
+        };
+
+        let envelope = filenode_id
+            .load(ctx.clone(), repo.blobstore())
+            .compat()
+            .await?;
+        if envelope.content_size() > size_limit {
+            return Ok(None);
+        }
+
+        let content = fetch_content(ctx, repo, envelope.content_id()).await?;
+        if is_binary(&content) {
+            return Ok(None);
+        }
+        Result::<_, Error>::Ok(Some((path, (file_type, content))))
+    }))
+    .await?;
+
+    Ok(files.into_iter().flatten().collect())
+}
+
+async fn fetch_content(
+    ctx: &CoreContext,
+    repo: &BlobRepo,
+    content_id: ContentId,
+) -> Result<Bytes, Error> {
+    filestore::fetch_concat(repo.blobstore(), ctx.clone(), content_id)
+        .compat()
+        .await
+}
+
+async fn store_content(
+    ctx: &CoreContext,
+    repo: &BlobRepo,
+    content: Vec<u8>,
+    file_type: FileType,
+) -> Result<FileChange, Error> {
+    let content = Bytes::from(content);
+    let meta = filestore::store(
+        repo.get_blobstore(),
+        repo.filestore_config(),
+        ctx.clone(),
+        &StoreRequest::new(content.len() as u64),
+        old_stream::once(Ok(content)),
+    )
+    .compat()
+    .await?;
+
+    Ok(FileChange::new(
+        meta.content_id,
+        file_type,
+        meta.total_size,
+        None,
+    ))
+}
+
+fn is_binary(content: &[u8]) -> bool {
+    content.contains(&0)
+}
+
+/// Splits `text` into lines the same way xdiff does: every line keeps its trailing newline, and
+/// trailing bytes without a newline form the last line.
+fn split_lines(text: &[u8]) -> Vec<&[u8]> {
+    let mut lines = vec![];
+    let mut start = 0;
+    for (idx, byte) in text.iter().enumerate() {
+        if *byte == b'\n' {
+            lines.push(&text[start..idx + 1]);
+            start = idx + 1;
+        }
+    }
+    if start < text.len() {
+        lines.push(&text[start..]);
+    }
+    lines
+}
+
+/// Three-way merge of `onto` and `pushed`, which were both derived from `base`.
+///
+/// Hunks from both sides whose base ranges overlap or touch are grouped together. A group with
+/// hunks from just one side takes that side's lines, and a group with hunks from both sides is
+/// only accepted if both sides ended up with the same lines. Otherwise the lines of the group
+/// in `onto` and in `pushed` are returned as a conflict.
+fn merge3(
+    base: &[u8],
+    onto: &[u8],
+    pushed: &[u8],
+) -> Result<Vec<u8>, Vec<(Range<usize>, Range<usize>)>> {
+    let base_lines = split_lines(base);
+    let onto_lines = split_lines(onto);
+    let pushed_lines = split_lines(pushed);
+    let mut onto_hunks = diff_hunks(base, onto).into_iter().peekable();
+    let mut pushed_hunks = diff_hunks(base, pushed).into_iter().peekable();
+
+    let mut merged = vec![];
+    let mut conflicts = vec![];
+    // Lines added minus lines removed by the hunks that were already merged. Converts a line
+    // number in base into a line number in onto or pushed outside of the hunks.
+    let mut onto_offset: isize = 0;
+    let mut pushed_offset: isize = 0;
+    let mut base_pos = 0;
+
+    loop {
+        let group_start = match (onto_hunks.peek(), pushed_hunks.peek()) {
+            (None, None) => break,
+            (Some(hunk), None) | (None, Some(hunk)) => hunk.remove.start,
+            (Some(onto_hunk), Some(pushed_hunk)) => {
+                onto_hunk.remove.start.min(pushed_hunk.remove.start)
+            }
+        };
+
+        let mut group_end = group_start;
+        let mut onto_delta: isize = 0;
+        let mut pushed_delta: isize = 0;
+        let mut changed_in_onto = false;
+        let mut changed_in_pushed = false;
+        loop {
+            let overlaps =
+                |hunk: Option<&Hunk>| hunk.map_or(false, |h| h.remove.start <= group_end);
+            let (hunk, delta) = if overlaps(onto_hunks.peek()) {
+                changed_in_onto = true;
+                (onto_hunks.next(), &mut onto_delta)
+            } else if overlaps(pushed_hunks.peek()) {
+                changed_in_pushed = true;
+                (pushed_hunks.next(), &mut pushed_delta)
+            } else {
+                break;
+            };
+
+            if let Some(hunk) = hunk {
+                group_end = group_end.max(hunk.remove.end);
+                *delta += hunk.add.len() as isize - hunk.remove.len() as isize;
+            }
+        }
+
+        let onto_range = (group_start as isize + onto_offset) as usize
+            ..(group_end as isize + onto_offset + onto_delta) as usize;
+        let pushed_range = (group_start as isize + pushed_offset) as usize
+            ..(group_end as isize + pushed_offset + pushed_delta) as usize;
+        onto_offset += onto_delta;
+        pushed_offset += pushed_delta;
+
+        merged.extend(base_lines[base_pos..group_start].iter().copied().flatten());
+        base_pos = group_end;
+
+        let onto_group = &onto_lines[onto_range.clone()];
+        let pushed_group = &pushed_lines[pushed_range.clone()];
+        if !changed_in_pushed || onto_group == pushed_group {
+            merged.extend(onto_group.iter().copied().flatten());
+        } else if !changed_in_onto {
+            merged.extend(pushed_group.iter().copied().flatten());
+        } else {
+            conflicts.push((onto_range, pushed_range));
+        }
+    }
+    merged.extend(base_lines[base_pos..].iter().copied().flatten());
+
+    if conflicts.is_empty() {
+        Ok(merged)
+    } else {
+        Err(conflicts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merge3_distant_changes() {
+        let base = b"a\nb\nc\nd\ne\nf\n";
+        let onto = b"A\nb\nc\nd\ne\nf\n";
+        let pushed = b"a\nb\nc\nd\ne\nF\ng\n";
+        assert_eq!(
+            merge3(base, onto, pushed),
+            Ok(b"A\nb\nc\nd\ne\nF\ng\n".to_vec())
+        );
+        assert_eq!(
+            merge3(base, pushed, onto),
+            Ok(b"A\nb\nc\nd\ne\nF\ng\n".to_vec())
+        );
+    }
+
+    #[test]
+    fn merge3_insertions_and_removals() {
+        let base = b"1\n2\n3\n4\n5\n6\n7\n";
+        let onto = b"0\n1\n2\n4\n5\n6\n7\n";
+        let pushed = b"1\n2\n3\n4\n5\n6\n6.5\n7\n";
+        assert_eq!(
+            merge3(base, onto, pushed),
+            Ok(b"0\n1\n2\n4\n5\n6\n6.5\n7\n".to_vec())
+        );
+    }
+
+    #[test]
+    fn merge3_same_change_on_both_sides() {
+        let base = b"a\nb\nc\n";
+        let both = b"a\nB\nc\n";
+        assert_eq!(merge3(base, both, both), Ok(both.to_vec()));
+    }
+
+    #[test]
+    fn merge3_one_side_unchanged() {
+        let base = b"a\nb\n";
+        let changed = b"a\nb\nc";
+        assert_eq!(merge3(base, base, changed), Ok(changed.to_vec()));
+        assert_eq!(merge3(base, changed, base), Ok(changed.to_vec()));
+    }
+
+    #[test]
+    fn merge3_overlapping_changes() {
+        let base = b"a\nb\nc\nd\ne\nf\n";
+        let onto = b"a\nB\nC\nd\ne\nf\n";
+        let pushed = b"a\nb\nX\nd\ne\nF\n";
+        assert_eq!(merge3(base, onto, pushed), Err(vec![(1..3, 1..3)]));
+    }
+
+    #[test]
+    fn merge3_adjacent_changes() {
+        let base = b"a\nb\nc\n";
+        let onto = b"A\nb\nc\n";
+        let pushed = b"a\nB\nc\n";
+        assert_eq!(merge3(base, onto, pushed), Err(vec![(0..2, 0..2)]));
+
+        // Different lines inserted at the same position.
+        let onto = b"a\nb\nc\nd\n";
+        let pushed = b"a\nb\nc\ne\n";
+        assert_eq!(merge3(base, onto, pushed), Err(vec![(3..4, 3..4)]));
+    }
+
+    #[test]
+    fn split_lines_without_trailing_newline() {
+        assert_eq!(split_lines(b"a\n\nb"), vec![&b"a\n"[..], b"\n", b"b"]);
+        assert_eq!(split_lines(b""), Vec::<&[u8]>::new());
+    }
+}
@@ -165,6 +165,59 @@ pub async fn create_getbundle_response(
     Ok(parts)
 }
 
+/// Create changegroup and treepack parts for all commits that are ancestors
+/// of `heads` but not of `common`. Unlike `create_getbundle_response`, trees
+/// and files are sent for every commit, public or not, so the parts are
+/// enough to recreate these commits in a repository that has only `common`.
+/// An empty `common` means that the whole history of `heads` is sent.
+pub async fn create_full_changegroup_parts(
+    ctx: &CoreContext,
+    blobrepo: &BlobRepo,
+    common: Vec<HgChangesetId>,
+    heads: Vec<HgChangesetId>,
+    lca_hint: &Arc<dyn LeastCommonAncestorsHint>,
+    lfs_params: &SessionLfsParams,
+) -> Result<(PartEncodeBuilder, PartEncodeBuilder), Error> {
+    let mut common: HashSet<_> = common.into_iter().collect();
+    if common.is_empty() {
+        common.insert(NULL_CSID);
+    }
+
+    let commits_to_send = find_commits_to_send(ctx, blobrepo, &common, &heads, lca_hint).await?;
+
+    // Files and trees have to be sent in the same order as commits, so that
+    // parents are always sent before their children.
+    let mapping: HashMap<_, _> = blobrepo
+        .get_hg_bonsai_mapping(ctx.clone(), commits_to_send.clone())
+        .compat()
+        .await?
+        .into_iter()
+        .map(|(hg_cs_id, bcs_id)| (bcs_id, hg_cs_id))
+        .collect();
+    let hg_commits = commits_to_send
+        .iter()
+        .map(|bcs_id| {
+            mapping
+                .get(bcs_id)
+                .cloned()
+                .ok_or_else(|| anyhow::format_err!("cs_id was missing from mapping: {:?}", bcs_id))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    let (manifests, filenodes) =
+        get_manifests_and_filenodes(ctx, blobrepo, hg_commits, lfs_params).await?;
+
+    let cg_part =
+        create_hg_changeset_part(ctx, blobrepo, commits_to_send, Some(filenodes), lfs_params)
+            .await?;
+
+    let manifests_stream =
+        create_manifest_entries_stream(ctx.clone(), blobrepo.get_blobstore(), manifests);
+    let tp_part = parts::treepack_part(manifests_stream)?;
+
+    Ok((cg_part, tp_part))
+}
+
 fn report_draft_commits(ctx: &CoreContext, draft_commits: &HashSet<HgChangesetId>) {
     debug!(
         ctx.logger(),
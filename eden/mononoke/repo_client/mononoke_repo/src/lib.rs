@@ -19,6 +19,7 @@ use metaconfig_types::{
 };
 use mononoke_types::RepositoryId;
 use mutable_counters::MutableCounters;
+use pushrebase::LandingQueue;
 use rand::Rng;
 use reachabilityindex::LeastCommonAncestorsHint;
 use repo_blobstore::RepoBlobstore;
@@ -66,6 +67,9 @@ pub struct MononokeRepo {
     // Reverse filler queue for recording accepted infinitepush bundles
     // This field is `None` if we don't want recording to happen
     maybe_reverse_filler_queue: Option<Arc<dyn ReverseFillerQueue>>,
+    // Queue that batches concurrent pushrebases to the same bookmark
+    // This field is `None` if pushrebases shouldn't be batched
+    maybe_landing_queue: Option<Arc<LandingQueue>>,
 }
 
 impl MononokeRepo {
@@ -104,6 +108,10 @@ impl MononokeRepo {
             }
         }
 
+        let maybe_landing_queue = pushrebase_params
+            .landing_queue_window
+            .map(|window| Arc::new(LandingQueue::new(blobrepo.clone(), window)));
+
         Ok(MononokeRepo {
             blobrepo,
             pushrebase_params: pushrebase_params.clone(),
@@ -118,6 +126,7 @@ impl MononokeRepo {
             mutable_counters,
             lfs_rolled_out_hostnames,
             maybe_reverse_filler_queue,
+            maybe_landing_queue,
         })
     }
 
@@ -146,6 +155,10 @@ impl MononokeRepo {
         self.maybe_reverse_filler_queue.as_deref()
     }
 
+    pub fn maybe_landing_queue(&self) -> Option<&LandingQueue> {
+        self.maybe_landing_queue.as_deref()
+    }
+
     pub fn lfs_params(&self, client_hostname: Option<&str>) -> SessionLfsParams {
         let percentage = self.lfs_params.rollout_percentage;
         let allowed = match client_hostname {
@@ -1457,6 +1457,7 @@ impl HgCommands for RepoClient {
                             None => {
                                 async move {
                                     let maybe_reverse_filler_queue = client.repo.maybe_reverse_filler_queue();
+                                    let maybe_landing_queue = client.repo.maybe_landing_queue();
                                     run_post_resolve_action(
                                         &ctx,
                                         &blobrepo,
@@ -1465,6 +1466,7 @@ impl HgCommands for RepoClient {
                                         &infinitepush_params,
                                         &pushrebase_params,
                                         maybe_reverse_filler_queue,
+                                        maybe_landing_queue,
                                         action,
                                     )
                                     .await
@@ -24,7 +24,7 @@ use git_mapping_pushrebase_hook::GitMappingPushrebaseHook;
 use globalrev_pushrebase_hook::GlobalrevPushrebaseHook;
 use metaconfig_types::{BookmarkAttrs, InfinitepushParams, PushrebaseParams};
 use mononoke_types::{BonsaiChangeset, ChangesetId, RawBundle2Id};
-use pushrebase::{self, PushrebaseHook};
+use pushrebase::{self, LandingQueue, PushrebaseHook};
 use reachabilityindex::LeastCommonAncestorsHint;
 use reverse_filler_queue::ReverseFillerQueue;
 use scribe_commit_queue::{self, ScribeCommitQueue};
@@ -60,6 +60,7 @@ pub async fn run_post_resolve_action(
     infinitepush_params: &InfinitepushParams,
     pushrebase_params: &PushrebaseParams,
     maybe_reverse_filler_queue: Option<&dyn ReverseFillerQueue>,
+    maybe_landing_queue: Option<&LandingQueue>,
     action: PostResolveAction,
 ) -> Result<UnbundleResponse, BundleResolverError> {
     enforce_commit_rate_limits(ctx.clone(), &action)
@@ -95,6 +96,7 @@ pub async fn run_post_resolve_action(
             lca_hint,
             infinitepush_params,
             pushrebase_params,
+            maybe_landing_queue,
             action,
         )
         .await
@@ -267,6 +269,7 @@ async fn run_pushrebase(
     lca_hint: &dyn LeastCommonAncestorsHint,
     infinitepush_params: &InfinitepushParams,
     pushrebase_params: &PushrebaseParams,
+    maybe_landing_queue: Option<&LandingQueue>,
     action: PostResolvePushRebase,
 ) -> Result<UnbundlePushRebaseResponse, BundleResolverError> {
     debug!(ctx.logger(), "unbundle processing: running pushrebase.");
@@ -300,6 +303,7 @@ async fn run_pushrebase(
                 &maybe_hg_replay_data,
                 bookmark_attrs,
                 infinitepush_params,
+                maybe_landing_queue,
             )
             .await?
         }
@@ -395,6 +399,7 @@ async fn normal_pushrebase(
     maybe_hg_replay_data: &Option<pushrebase::HgReplayData>,
     bookmark_attrs: &BookmarkAttrs,
     infinitepush_params: &InfinitepushParams,
+    maybe_landing_queue: Option<&LandingQueue>,
 ) -> Result<(ChangesetId, Vec<pushrebase::PushrebaseChangesetPair>), BundleResolverError> {
     let bookmark = &onto_bookmark.bookmark;
 
@@ -425,15 +430,34 @@ async fn normal_pushrebase(
     }
 
     ctx.scuba().clone().log_with_msg("Pushrebase started", None);
-    let (stats, result) = pushrebase::do_pushrebase_bonsai(
-        &ctx,
-        &repo,
-        &flags,
-        &onto_bookmark,
-        &changesets,
-        maybe_hg_replay_data,
-        &hooks[..],
-    )
+    let (stats, result) = async {
+        match maybe_landing_queue {
+            Some(landing_queue) => {
+                landing_queue
+                    .land(
+                        &ctx,
+                        &flags,
+                        &onto_bookmark,
+                        &changesets,
+                        maybe_hg_replay_data,
+                        hooks,
+                    )
+                    .await
+            }
+            None => {
+                pushrebase::do_pushrebase_bonsai(
+                    &ctx,
+                    &repo,
+                    &flags,
+                    &onto_bookmark,
+                    &changesets,
+                    maybe_hg_replay_data,
+                    &hooks[..],
+                )
+                .await
+            }
+        }
+    }
     .timed()
     .await;
 
@@ -109,6 +109,7 @@ impl PushRedirector {
             &infinitepush_params,
             &puhsrebase_params,
             self.repo.maybe_reverse_filler_queue(),
+            self.repo.maybe_landing_queue(),
             large_repo_action,
         )
         .await?;
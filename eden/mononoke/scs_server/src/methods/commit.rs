@@ -9,7 +9,9 @@ use std::convert::{TryFrom, TryInto};
 
 use context::CoreContext;
 use futures_util::{future, stream, try_join, StreamExt, TryStreamExt};
-use mononoke_api::{unified_diff, ChangesetSpecifier, CopyInfo, MononokePath, UnifiedDiffMode};
+use mononoke_api::{
+    unified_diff, ChangesetSpecifier, CopyInfo, HunkOpts, MononokePath, UnifiedDiffMode,
+};
 use source_control as thrift;
 
 use crate::commit_id::{map_commit_identity, CommitIdExt};
@@ -126,8 +128,16 @@ impl SourceControlServiceImpl {
 
         let path_diffs = future::try_join_all(paths.into_iter().map(
             |(base_path, other_path, copy_info, mode)| async move {
-                let diff =
-                    unified_diff(&other_path, &base_path, copy_info, context_lines, mode).await?;
+                let diff = unified_diff(
+                    &other_path,
+                    &base_path,
+                    copy_info,
+                    context_lines,
+                    HunkOpts::default(),
+                    false,
+                    mode,
+                )
+                .await?;
                 let r: Result<_, errors::ServiceError> =
                     Ok(thrift::CommitFileDiffsResponseElement {
                         base_path: base_path.map(|p| p.path().to_string()),
@@ -221,7 +231,7 @@ impl SourceControlServiceImpl {
             ),
         };
         let diff = base_changeset
-            .diff(other_changeset_id, !params.skip_copies_renames, paths)
+            .diff(other_changeset_id, !params.skip_copies_renames, paths, None)
             .await?;
         let diff_files = stream::iter(diff)
             .map(|d| d.into_response())
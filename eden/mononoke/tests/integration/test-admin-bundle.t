@@ -0,0 +1,69 @@
+# Copyright (c) Facebook, Inc. and its affiliates.
+#
+# This software may be used and distributed according to the terms of the
+# GNU General Public License found in the LICENSE file in the root
+# directory of this source tree.
+
+  $ . "${TEST_FIXTURES}/library.sh"
+
+setup configuration for a source and a target repo
+
+  $ REPOTYPE="blob_files"
+  $ REPOID=0 REPONAME=source setup_common_config $REPOTYPE
+  $ REPOID=1 REPONAME=target setup_common_config $REPOTYPE
+  $ REPOID=2 REPONAME=target2 setup_common_config $REPOTYPE
+
+  $ cd $TESTTMP
+
+setup hg server repo
+
+  $ hginit_treemanifest repo-hg
+  $ cd repo-hg
+  $ echo a > a && hg ci -A -q -m 'add a'
+  $ mkdir dir && echo b > dir/b && hg ci -A -q -m 'add dir/b'
+  $ echo c > dir/c && hg ci -A -q -m 'add dir/c'
+  $ hg bookmark master_bookmark -r tip
+  $ hg bookmark other -r 1
+  $ hg log -T '{node} {desc}\n'
+  * add dir/c (glob)
+  * add dir/b (glob)
+  * add a (glob)
+
+  $ cd $TESTTMP
+  $ REPOID=0 blobimport repo-hg/.hg source
+
+Export everything reachable from master_bookmark along with both bookmarks
+  $ REPOID=0 mononoke_admin bundle export --head master_bookmark \
+  >   --bookmark master_bookmark --bookmark other "$TESTTMP/full.bundle" 2>&1 | grep -v "using repo"
+  * Recording master_bookmark at * (glob)
+  * Recording other at * (glob)
+  * Wrote * bytes to $TESTTMP/full.bundle (glob)
+
+Import it into the empty target repo
+  $ REPOID=1 mononoke_admin bundle import "$TESTTMP/full.bundle" 2>&1 | grep -v "using repo"
+  * Imported 3 commits (glob)
+  * Moving master_bookmark from None to * (glob)
+  * Moving other from None to * (glob)
+
+  $ [ "$(REPOID=1 mononoke_admin bookmarks get master_bookmark 2>/dev/null)" = "(HG) $(hg log -R repo-hg -r 2 -T '{node}')" ]
+  $ [ "$(REPOID=1 mononoke_admin bookmarks get other 2>/dev/null)" = "(HG) $(hg log -R repo-hg -r 1 -T '{node}')" ]
+
+Incremental export only contains commits the target does not have yet
+  $ cd repo-hg
+  $ echo d > d && hg ci -A -q -m 'add d'
+  $ hg bookmark -f master_bookmark -r tip
+  $ cd $TESTTMP
+  $ REPOID=0 blobimport repo-hg/.hg source
+  $ REPOID=0 mononoke_admin bundle export --head master_bookmark --common other \
+  >   --bookmark master_bookmark "$TESTTMP/incr.bundle" 2>/dev/null
+  $ REPOID=1 mononoke_admin bundle import --no-bookmarks "$TESTTMP/incr.bundle" 2>&1 | grep -v "using repo"
+  * Imported 2 commits (glob)
+  $ [ "$(REPOID=1 mononoke_admin bookmarks get master_bookmark 2>/dev/null)" = "(HG) $(hg log -R repo-hg -r 2 -T '{node}')" ]
+
+Bookmarks that are not below the heads are exported as heads too
+  $ REPOID=0 mononoke_admin bundle export --head other \
+  >   --bookmark master_bookmark "$TESTTMP/book.bundle" 2>/dev/null
+  $ REPOID=2 mononoke_admin bundle import "$TESTTMP/book.bundle" 2>&1 | grep -v "using repo"
+  * Imported 4 commits (glob)
+  * Moving master_bookmark from None to * (glob)
+  $ [ "$(REPOID=2 mononoke_admin bookmarks get master_bookmark 2>/dev/null)" = "(HG) $(hg log -R repo-hg -r 3 -T '{node}')" ]
@@ -0,0 +1,69 @@
+# Copyright (c) Facebook, Inc. and its affiliates.
+#
+# This software may be used and distributed according to the terms of the
+# GNU General Public License found in the LICENSE file in the root
+# directory of this source tree.
+
+  $ . "${TEST_FIXTURES}/library.sh"
+
+setup configuration
+
+  $ REPOTYPE="blob_files"
+  $ setup_common_config $REPOTYPE
+
+  $ cd $TESTTMP
+
+setup hg server repo with several versions of the same files
+
+  $ hginit_treemanifest repo-hg
+  $ cd repo-hg
+  $ echo a > a && hg ci -A -q -m 'add a'
+  $ echo secret1 > creds && mkdir dir && echo key1 > dir/key.pem && hg ci -A -q -m 'add creds'
+  $ echo secret2 > creds && echo key2 > dir/key.pem && hg ci -q -m 'update creds'
+  $ echo secret3 > creds && hg ci -q -m 'update creds again'
+  $ hg rm -q dir/key.pem && hg ci -q -m 'remove key'
+  $ hg bookmark master_bookmark -r tip
+
+  $ cd $TESTTMP
+
+blobimport
+  $ blobimport repo-hg/.hg repo
+
+Dry run does not redact anything
+  $ mononoke_admin redaction add-history "[TASK]Censor creds" --to master_bookmark --dry-run creds 2>/dev/null
+  creds: 3 version(s)
+  	content.blake2.* in * (glob)
+  	content.blake2.* in * (glob)
+  	content.blake2.* in * (glob)
+
+  $ sqlite3 "$TESTTMP/monsql/sqlite_dbs" 'SELECT count(*) FROM censored_contents;'
+  0
+
+Only the versions introduced in the range are redacted
+  $ SECOND=$(hg log -R repo-hg -r 2 -T '{node}')
+  $ mononoke_admin redaction add-history "[TASK]Censor creds" --from "$SECOND" --to master_bookmark creds 2>/dev/null
+  creds: 2 version(s)
+  	content.blake2.* in * (glob)
+  	content.blake2.* in * (glob)
+
+  $ sqlite3 "$TESTTMP/monsql/sqlite_dbs" 'SELECT task FROM censored_contents;'
+  [TASK]Censor creds
+  [TASK]Censor creds
+
+Globs also find files deleted within the range, and already redacted content is skipped
+  $ FIRST=$(hg log -R repo-hg -r 0 -T '{node}')
+  $ mononoke_admin redaction add-history "[TASK]Censor all" --from "$FIRST" --to master_bookmark --glob 'creds' '**/*.pem' 2>/dev/null
+  creds: 3 version(s)
+  	content.blake2.* in * (glob)
+  	content.blake2.* in * (glob)
+  	content.blake2.* in * (glob)
+  dir/key.pem: 2 version(s)
+  	content.blake2.* in * (glob)
+  	content.blake2.* in * (glob)
+
+  $ sqlite3 "$TESTTMP/monsql/sqlite_dbs" 'SELECT task FROM censored_contents ORDER BY task;'
+  [TASK]Censor all
+  [TASK]Censor all
+  [TASK]Censor all
+  [TASK]Censor creds
+  [TASK]Censor creds
@@ -0,0 +1,106 @@
+# Copyright (c) Facebook, Inc. and its affiliates.
+#
+# This software may be used and distributed according to the terms of the
+# GNU General Public License version 2.
+
+"""keep track of the file change journal for faster status (EXPERIMENTAL)
+
+The file change journal is recorded by ``scm_daemon`` on Linux, by watching
+the working copy with inotify. The repo must be listed in its TOML
+configuration::
+
+    [fsjournal]
+    repos = ["/path/to/repo"]
+
+With this extension enabled, every status of the whole working copy stores the
+journal's clock in the treestate, and marks the files it found changed as
+NEED_CHECK. The next native status then only checks the files that changed
+since that clock, and the NEED_CHECK files, instead of walking the working
+copy. It walks the working copy again when the journal overflowed, or when
+``scm_daemon`` was restarted.
+
+The following configuration options exist:
+
+::
+
+    [fsjournal]
+    # Seconds to wait for an answer from the journal
+    timeout = 1.0
+"""
+
+from bindings import workingcopy
+from edenscm.mercurial import registrar, scmutil
+
+
+configtable = {}
+configitem = registrar.configitem(configtable)
+
+configitem("fsjournal", "timeout", default=1.0)
+
+# Must match fsjournal::CLOCK_METADATA_KEY.
+_clockkey = "journalclock"
+
+
+def reposetup(ui, repo):
+    # The journal only helps the treestate status of a local, non-Eden repo.
+    if not repo.local() or "eden" in repo.requirements:
+        return
+    if "treestate" not in repo.requirements:
+        return
+
+    class fsjournalrepo(repo.__class__):
+        def status(
+            self,
+            node1=".",
+            node2=None,
+            match=None,
+            ignored=False,
+            clean=False,
+            unknown=False,
+        ):
+            orig = super(fsjournalrepo, self).status
+            # Only a status of the whole working copy checks every changed file.
+            if node1 != "." or node2 is not None or (match and not match.always()):
+                return orig(node1, node2, match, ignored, clean, unknown)
+
+            # The clock has to be taken before the working copy is checked, so the
+            # files changed while it is are in the journal after that clock.
+            try:
+                clock, _changed = workingcopy.journalchanges(
+                    self.root,
+                    self.dirstate.getmeta(_clockkey),
+                    self.ui.configwith(float, "fsjournal", "timeout"),
+                )
+            except Exception as ex:
+                self.ui.debug("fsjournal: journal unavailable: %s\n" % ex)
+                clock = None
+
+            self.addpostdsstatus(_poststatus(clock), afterdirstatewrite=False)
+            # Unknown files have to be marked too.
+            status = orig(node1, node2, match, ignored, clean, True)
+            if not unknown:
+                status = scmutil.status(
+                    status.modified,
+                    status.added,
+                    status.removed,
+                    status.deleted,
+                    [],
+                    status.ignored,
+                    status.clean,
+                )
+            return status
+
+    repo.__class__ = fsjournalrepo
+
+
+def _poststatus(clock):
+    def poststatus(wctx, status):
+        dirstate = wctx.repo().dirstate
+        if clock:
+            changed = (status.modified, status.added, status.deleted, status.unknown)
+            for files in changed:
+                for path in files:
+                    dirstate.needcheck(path)
+        dirstate.setmeta(_clockkey, clock)
+
+    return poststatus
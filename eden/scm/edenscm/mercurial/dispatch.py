@@ -274,6 +274,7 @@ def _preimportmodules():
         "fbscmquery",
         "fbhistedit",
         "fixcorrupt",
+        "fsjournal",
         "fsmonitor",
         "githelp",
         "gitlookup",
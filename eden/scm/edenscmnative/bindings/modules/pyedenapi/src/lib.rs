@@ -27,11 +27,13 @@ mod exceptions {
     use super::*;
 
     py_exception!(edenapi, CertificateError);
+    py_exception!(edenapi, CircuitOpenError);
     py_exception!(edenapi, ConfigError);
     py_exception!(edenapi, ResponseError);
     py_exception!(edenapi, CurlError);
     py_exception!(edenapi, HttpError);
     py_exception!(edenapi, ProxyError);
+    py_exception!(edenapi, RetriesExhaustedError);
     py_exception!(edenapi, SerializationError);
     py_exception!(edenapi, StoreError);
     py_exception!(edenapi, TlsError);
@@ -46,11 +48,17 @@ pub fn init_module(py: Python, package: &str) -> PyResult<PyModule> {
     {
         use exceptions::*;
         m.add(py, "CertificateError", CertificateError::type_object(py))?;
+        m.add(py, "CircuitOpenError", CircuitOpenError::type_object(py))?;
         m.add(py, "ConfigError", ConfigError::type_object(py))?;
         m.add(py, "ResponseError", ResponseError::type_object(py))?;
         m.add(py, "CurlError", CurlError::type_object(py))?;
         m.add(py, "HttpError", HttpError::type_object(py))?;
         m.add(py, "ProxyError", ProxyError::type_object(py))?;
+        m.add(
+            py,
+            "RetriesExhaustedError",
+            RetriesExhaustedError::type_object(py),
+        )?;
         m.add(
             py,
             "SerializationError",
@@ -275,6 +283,10 @@ py_class!(class downloadstats |py| {
         Ok(self.stats(py).requests)
     }
 
+    def retries(&self) -> PyResult<usize> {
+        Ok(self.stats(py).retries)
+    }
+
     def time_in_seconds(&self) -> PyResult<f64> {
         Ok(self.stats(py).time_in_seconds())
     }
@@ -333,9 +345,11 @@ fn into_exception(py: Python, error: ApiError) -> PyErr {
         &BadCertificate(..) => PyErr::new::<CertificateError, _>(py, msg),
         &BadConfig(..) => PyErr::new::<ConfigError, _>(py, msg),
         &BadResponse => PyErr::new::<ResponseError, _>(py, msg),
+        &CircuitOpen(..) => PyErr::new::<CircuitOpenError, _>(py, msg),
         &Curl => PyErr::new::<CurlError, _>(py, msg),
         &Http { .. } => PyErr::new::<HttpError, _>(py, msg),
         &Proxy(..) => PyErr::new::<ProxyError, _>(py, msg),
+        &RetriesExhausted(..) => PyErr::new::<RetriesExhaustedError, _>(py, msg),
         &Serialization => PyErr::new::<SerializationError, _>(py, msg),
         &Store => PyErr::new::<StoreError, _>(py, msg),
         &Url => PyErr::new::<UrlError, _>(py, msg),
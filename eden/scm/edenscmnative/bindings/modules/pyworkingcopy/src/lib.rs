@@ -8,6 +8,7 @@
 #![allow(non_camel_case_types)]
 
 use std::cell::RefCell;
+use std::time::Duration;
 
 use anyhow::Error;
 use cpython::*;
@@ -27,9 +28,39 @@ pub fn init_module(py: Python, package: &str) -> PyResult<PyModule> {
     m.add_class::<walker>(py)?;
     m.add_class::<pendingchanges>(py)?;
     m.add_class::<physicalfilesystem>(py)?;
+    m.add(
+        py,
+        "journalchanges",
+        py_fn!(
+            py,
+            journal_changes(root: PyPathBuf, clock: Option<String>, timeout: f64)
+        ),
+    )?;
     Ok(m)
 }
 
+/// Ask the file change journal of the working copy at `root` what changed since `clock`.
+///
+/// Return the clock for the next query, and the changed paths, or None if every file has to be
+/// checked.
+fn journal_changes(
+    py: Python,
+    root: PyPathBuf,
+    clock: Option<String>,
+    timeout: f64,
+) -> PyResult<(Option<String>, Option<Vec<PyPathBuf>>)> {
+    let changes = fsjournal::query(
+        root.as_path(),
+        clock.as_ref().map(|clock| clock.as_str()),
+        Duration::from_secs_f64(timeout),
+    )
+    .map_pyerr(py)?;
+    let paths = changes
+        .paths
+        .map(|paths| paths.into_iter().map(PyPathBuf::from).collect());
+    Ok((changes.clock, paths))
+}
+
 py_class!(class physicalfilesystem |py| {
     data filesystem: RefCell<PhysicalFileSystem>;
 
@@ -12,6 +12,11 @@ use clap::{App, Arg};
 use commitcloudsubscriber::{
     CommitCloudConfig, CommitCloudTcpReceiverService, CommitCloudWorkspaceSubscriberService,
 };
+use fsjournal::FsJournalConfig;
+#[cfg(target_os = "linux")]
+use fsjournal::JournalService;
+#[cfg(target_os = "linux")]
+use log::error;
 use log::info;
 use serde::Deserialize;
 use std::fs::File;
@@ -27,6 +32,8 @@ pub struct Config {
     pub title: Option<String>,
     /// [commitcloud] section: commitcloudlib provides description of it
     pub commitcloud: Option<CommitCloudConfig>,
+    /// [fsjournal] section: fsjournal provides description of it
+    pub fsjournal: Option<FsJournalConfig>,
 }
 
 // To support older than Rust 1.26 on dev servers
@@ -93,6 +100,21 @@ fn run() -> Result<()> {
     let commitcloud_tcpreceiver_handler = commitcloud_tcpreceiver.serve()?;
     let commitcloud_workspacesubscriber_handler = commitcloud_workspacesubscriber.serve()?;
 
+    // start file change journals, skipping the repos that can't be watched
+    #[cfg(target_os = "linux")]
+    let fsjournal_handlers = {
+        let mut handlers = Vec::new();
+        if let Some(fsjournalconf) = &config.fsjournal {
+            for root in &fsjournalconf.repos {
+                match JournalService::new(root.clone(), fsjournalconf.capacity).serve() {
+                    Ok(handler) => handlers.push(handler),
+                    Err(e) => error!("Failed to record changes of {}: {}", root.display(), e),
+                }
+            }
+        }
+        handlers
+    };
+
     // join running services, this will block
     match commitcloud_tcpreceiver_handler.join() {
         Ok(result) => result?,
@@ -104,6 +126,14 @@ fn run() -> Result<()> {
         Err(_) => bail!("commitcloud workspace subscriber panicked"),
     };
 
+    #[cfg(target_os = "linux")]
+    for handler in fsjournal_handlers {
+        match handler.join() {
+            Ok(result) => result?,
+            Err(_) => bail!("fsjournal service panicked"),
+        };
+    }
+
     Ok(())
 }
 
@@ -72,6 +72,49 @@ std::unique_ptr<folly::IOBuf> HgNativeBackingStore::getBlob(
   return bytesToIOBuf(result.unwrap().release());
 }
 
+void HgNativeBackingStore::getBlobBatch(
+    const std::vector<std::pair<folly::ByteRange, folly::ByteRange>>& requests,
+    bool local,
+    std::function<void(size_t, std::unique_ptr<folly::IOBuf>)>&& resolve) {
+  XLOG(DBG7) << "Importing " << requests.size() << " blobs from hgcache";
+
+  std::vector<RustRequest> raw;
+  raw.reserve(requests.size());
+  for (const auto& request : requests) {
+    raw.push_back(RustRequest{
+        request.first.data(),
+        request.first.size(),
+        request.second.data(),
+        request.second.size()});
+  }
+
+  RustCFallible<uint8_t> result(
+      rust_backingstore_get_blob_batch(
+          store_.get(),
+          raw.data(),
+          raw.size(),
+          local,
+          &resolve,
+          [](void* data, size_t index, RustCFallibleBase base) {
+            auto resolver = reinterpret_cast<
+                std::function<void(size_t, std::unique_ptr<folly::IOBuf>)>*>(
+                data);
+            RustCFallible<RustCBytes> blob(std::move(base), rust_cbytes_free);
+            if (blob.isError()) {
+              XLOG(DBG5) << "Error while getting blob from backingstore: "
+                         << blob.getError();
+              (*resolver)(index, nullptr);
+            } else {
+              (*resolver)(index, bytesToIOBuf(blob.unwrap().release()));
+            }
+          }),
+      [](uint8_t*) {});
+
+  if (result.isError()) {
+    throw std::runtime_error(result.getError());
+  }
+}
+
 std::shared_ptr<RustTree> HgNativeBackingStore::getTree(folly::ByteRange node) {
   XLOG(DBG7) << "Importing tree node=" << folly::hexlify(node)
              << " from hgcache";
@@ -89,6 +132,43 @@ std::shared_ptr<RustTree> HgNativeBackingStore::getTree(folly::ByteRange node) {
   return manifest.unwrap();
 }
 
+void HgNativeBackingStore::getTreeBatch(
+    const std::vector<folly::ByteRange>& nodes,
+    std::function<void(size_t, std::shared_ptr<RustTree>)>&& resolve) {
+  XLOG(DBG7) << "Importing " << nodes.size() << " trees from hgcache";
+
+  std::vector<RustRequest> raw;
+  raw.reserve(nodes.size());
+  for (const auto& node : nodes) {
+    raw.push_back(RustRequest{nullptr, 0, node.data(), node.size()});
+  }
+
+  RustCFallible<uint8_t> result(
+      rust_backingstore_get_tree_batch(
+          store_.get(),
+          raw.data(),
+          raw.size(),
+          &resolve,
+          [](void* data, size_t index, RustCFallibleBase base) {
+            auto resolver = reinterpret_cast<
+                std::function<void(size_t, std::shared_ptr<RustTree>)>*>(
+                data);
+            RustCFallible<RustTree> tree(std::move(base), rust_tree_free);
+            if (tree.isError()) {
+              XLOG(DBG5) << "Error while getting tree from backingstore: "
+                         << tree.getError();
+              (*resolver)(index, nullptr);
+            } else {
+              (*resolver)(index, tree.unwrap());
+            }
+          }),
+      [](uint8_t*) {});
+
+  if (result.isError()) {
+    throw std::runtime_error(result.getError());
+  }
+}
+
 void HgNativeBackingStore::refresh() {
   XLOG(DBG7) << "Refreshing backing store";
 
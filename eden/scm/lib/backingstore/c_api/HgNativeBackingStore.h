@@ -7,7 +7,10 @@
 #pragma once
 
 #include <folly/Range.h>
+#include <functional>
 #include <memory>
+#include <utility>
+#include <vector>
 
 #include "eden/scm/lib/backingstore/c_api/RustBackingStore.h"
 
@@ -25,8 +28,27 @@ class HgNativeBackingStore {
   std::unique_ptr<folly::IOBuf>
   getBlob(folly::ByteRange name, folly::ByteRange node, bool local);
 
+  /**
+   * Fetch blobs of `requests` (pairs of name and node) in one batch.
+   * `resolve` is called with the index of each request and its blob, or
+   * nullptr if the blob cannot be read.
+   */
+  void getBlobBatch(
+      const std::vector<std::pair<folly::ByteRange, folly::ByteRange>>&
+          requests,
+      bool local,
+      std::function<void(size_t, std::unique_ptr<folly::IOBuf>)>&& resolve);
+
   std::shared_ptr<RustTree> getTree(folly::ByteRange node);
 
+  /**
+   * Fetch root trees of `nodes` in one batch. `resolve` is called with the
+   * index of each node and its tree, or nullptr if the tree cannot be read.
+   */
+  void getTreeBatch(
+      const std::vector<folly::ByteRange>& nodes,
+      std::function<void(size_t, std::shared_ptr<RustTree>)>&& resolve);
+
   void refresh();
 
  private:
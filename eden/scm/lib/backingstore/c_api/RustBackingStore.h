@@ -101,6 +101,14 @@ operator folly::ByteRange() const {
 }
 };
 
+/// A key of a blob or tree. `path` is ignored for trees.
+struct RustRequest {
+  const uint8_t *path;
+  uintptr_t length;
+  const uint8_t *node;
+  uintptr_t node_length;
+};
+
 struct RustTreeEntry {
   RustCBytes hash;
   RustCBytes name;
@@ -128,10 +136,29 @@ RustCFallibleBase rust_backingstore_get_blob(RustBackingStore *store,
                                                          uintptr_t node_len,
                                                          bool local);
 
+/// Reads the blobs of `size` requests. `resolve` is called once for every request with `data`,
+/// the index of the request and the result. The caller owns the `CBytes` and the error string in
+/// the result.
+RustCFallibleBase rust_backingstore_get_blob_batch(RustBackingStore *store,
+                                                   const RustRequest *requests,
+                                                   uintptr_t size,
+                                                   bool local,
+                                                   void *data,
+                                                   void (*resolve)(void*, uintptr_t, RustCFallibleBase));
+
 RustCFallibleBase rust_backingstore_get_tree(RustBackingStore *store,
                                                        const uint8_t *node,
                                                        uintptr_t node_len);
 
+/// Reads the root trees of `size` requests. Paths of the requests are ignored. `resolve` is
+/// called once for every request with `data`, the index of the request and the result. The
+/// caller owns the `Tree` and the error string in the result.
+RustCFallibleBase rust_backingstore_get_tree_batch(RustBackingStore *store,
+                                                   const RustRequest *requests,
+                                                   uintptr_t size,
+                                                   void *data,
+                                                   void (*resolve)(void*, uintptr_t, RustCFallibleBase));
+
 RustCFallibleBase rust_backingstore_new(const char *repository,
                                                           size_t repository_len,
                                                           bool use_edenapi);
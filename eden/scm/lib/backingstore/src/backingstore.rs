@@ -15,11 +15,11 @@ use manifest::{List, Manifest};
 use manifest_tree::TreeManifest;
 use revisionstore::{
     ContentStore, ContentStoreBuilder, EdenApiHgIdRemoteStore, HgIdDataStore, LocalStore,
-    MemcacheStore, StoreKey,
+    MemcacheStore, RemoteDataStore, StoreKey,
 };
 use std::path::Path;
 use std::sync::Arc;
-use types::{Key, Node, RepoPath};
+use types::{Key, Node, RepoPath, RepoPathBuf};
 
 pub struct BackingStore {
     blobstore: ContentStore,
@@ -72,18 +72,19 @@ impl BackingStore {
     /// Reads file from blobstores. When `local_only` is true, this function will only read blobs
     /// from on disk stores.
     pub fn get_blob(&self, path: &[u8], node: &[u8], local_only: bool) -> Result<Option<Vec<u8>>> {
-        let path = RepoPath::from_utf8(path)?.to_owned();
-        let node = Node::from_slice(node)?;
-        let key = Key::new(path, node);
+        let key = to_key(path, node)?;
+        self.get_blob_by_key(&key, local_only)
+    }
 
+    fn get_blob_by_key(&self, key: &Key, local_only: bool) -> Result<Option<Vec<u8>>> {
         // check if the blob present on disk
-        if local_only && !self.blobstore.contains(&StoreKey::from(&key))? {
+        if local_only && !self.blobstore.contains(&StoreKey::from(key))? {
             return Ok(None);
         }
 
         // Return None for LFS blobs
         // TODO: LFS support
-        if let Ok(Some(metadata)) = self.blobstore.get_meta(&key) {
+        if let Ok(Some(metadata)) = self.blobstore.get_meta(key) {
             if metadata.is_lfs() {
                 return Ok(None);
             }
@@ -91,10 +92,41 @@ impl BackingStore {
 
         Ok(self
             .blobstore
-            .get_file_content(&key)?
+            .get_file_content(key)?
             .map(|blob| blob.as_ref().to_vec()))
     }
 
+    /// Reads blobs of `keys` (pairs of path and node). Unless `local_only` is true, the blobs
+    /// missing on disk are fetched from the remote store in one batch first.
+    ///
+    /// `resolve` is called once for every key, in order, with the index of the key and the result
+    /// of reading it. A failure to read one key does not affect the other keys.
+    pub fn get_blob_batch<F>(&self, keys: &[(&[u8], &[u8])], local_only: bool, resolve: F)
+    where
+        F: Fn(usize, Result<Option<Vec<u8>>>),
+    {
+        let keys: Vec<Result<Key>> = keys.iter().map(|(path, node)| to_key(path, node)).collect();
+
+        if !local_only {
+            let store_keys: Vec<StoreKey> = keys
+                .iter()
+                .filter_map(|key| key.as_ref().ok())
+                .map(StoreKey::from)
+                .collect();
+            // Errors are not fatal here. Keys that are still missing are fetched one by one below.
+            if let Err(e) = self.blobstore.prefetch(&store_keys) {
+                warn!("couldn't prefetch {} blobs: {}", store_keys.len(), e);
+            }
+        }
+
+        for (index, key) in keys.into_iter().enumerate() {
+            resolve(
+                index,
+                key.and_then(|key| self.get_blob_by_key(&key, local_only)),
+            );
+        }
+    }
+
     pub fn get_tree(&self, node: &[u8]) -> Result<List> {
         let node = Node::from_slice(node)?;
         let manifest = TreeManifest::durable(self.treestore.clone(), node);
@@ -102,9 +134,135 @@ impl BackingStore {
         manifest.list(RepoPath::empty())
     }
 
+    /// Reads the root trees of `nodes`. The trees missing on disk are fetched from the remote
+    /// store in one batch first.
+    ///
+    /// `resolve` is called once for every node, in order, with the index of the node and the
+    /// result of reading it.
+    pub fn get_tree_batch<F>(&self, nodes: &[&[u8]], resolve: F)
+    where
+        F: Fn(usize, Result<List>),
+    {
+        let nodes: Vec<Result<Node>> = nodes.iter().map(|node| Node::from_slice(node)).collect();
+
+        let store_keys: Vec<StoreKey> = nodes
+            .iter()
+            .filter_map(|node| node.as_ref().ok())
+            .map(|node| StoreKey::from(Key::new(RepoPathBuf::new(), *node)))
+            .collect();
+        if let Err(e) = self.treestore.as_content_store().prefetch(&store_keys) {
+            warn!("couldn't prefetch {} trees: {}", store_keys.len(), e);
+        }
+
+        for (index, node) in nodes.into_iter().enumerate() {
+            resolve(
+                index,
+                node.and_then(|node| {
+                    TreeManifest::durable(self.treestore.clone(), node).list(RepoPath::empty())
+                }),
+            );
+        }
+    }
+
     /// forces backing store to rescan pack files
     pub fn refresh(&self) {
         self.blobstore.get_missing(&[]).ok();
         self.treestore.as_content_store().get_missing(&[]).ok();
     }
 }
+
+fn to_key(path: &[u8], node: &[u8]) -> Result<Key> {
+    let path = RepoPath::from_utf8(path)?.to_owned();
+    let node = Node::from_slice(node)?;
+    Ok(Key::new(path, node))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    use std::cell::RefCell;
+    use std::fs;
+
+    use bytes::Bytes;
+    use revisionstore::{Delta, HgIdMutableDeltaStore, Metadata};
+    use tempfile::TempDir;
+
+    pub(crate) fn backingstore(dir: &TempDir) -> Result<BackingStore> {
+        let hg = dir.path().join(".hg");
+        fs::create_dir_all(hg.join("store"))?;
+        fs::write(
+            hg.join("hgrc"),
+            format!(
+                "[remotefilelog]\nreponame = test\ncachepath = {}\n",
+                dir.path().join("cache").display()
+            ),
+        )?;
+        BackingStore::new(dir.path(), false)
+    }
+
+    pub(crate) fn add_blob(
+        store: &BackingStore,
+        path: &str,
+        node: &[u8],
+        data: &str,
+    ) -> Result<()> {
+        let key = to_key(path.as_bytes(), node)?;
+        let delta = Delta {
+            data: Bytes::copy_from_slice(data.as_bytes()),
+            base: None,
+            key,
+        };
+        store.blobstore.add(&delta, &Metadata::default())?;
+        store.blobstore.flush()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_blob_batch() -> Result<()> {
+        let dir = TempDir::new()?;
+        let store = backingstore(&dir)?;
+        add_blob(&store, "a", &[1; 20], "hello")?;
+
+        let (hit, miss, short) = ([1; 20], [2; 20], [3; 5]);
+        let keys = vec![
+            (&b"a"[..], &hit[..]),
+            (&b"b"[..], &miss[..]),
+            // Nodes are 20 bytes long.
+            (&b"c"[..], &short[..]),
+        ];
+        let results = RefCell::new(Vec::new());
+        store.get_blob_batch(&keys, true, |index, result| {
+            results.borrow_mut().push((index, result))
+        });
+        let results = results.into_inner();
+
+        assert_eq!(
+            results.iter().map(|(index, _)| *index).collect::<Vec<_>>(),
+            vec![0, 1, 2]
+        );
+        assert_eq!(
+            results[0].1.as_ref().unwrap().as_deref(),
+            Some(&b"hello"[..])
+        );
+        assert_eq!(results[1].1.as_ref().unwrap(), &None);
+        assert!(results[2].1.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_tree_batch() -> Result<()> {
+        let dir = TempDir::new()?;
+        let store = backingstore(&dir)?;
+
+        let (miss, short) = ([1; 20], [2; 5]);
+        let nodes = vec![&miss[..], &short[..]];
+        let results = RefCell::new(Vec::new());
+        store.get_tree_batch(&nodes, |index, result| {
+            results.borrow_mut().push((index, result.is_ok()))
+        });
+
+        assert_eq!(results.into_inner(), vec![(0, false), (1, false)]);
+        Ok(())
+    }
+}
@@ -8,12 +8,12 @@
 //! Provides the c-bindings for `crate::backingstore`.
 
 use anyhow::{ensure, Error, Result};
-use libc::{c_char, size_t};
+use libc::{c_char, c_void, size_t};
 use std::convert::TryInto;
 use std::{slice, str};
 
 use crate::backingstore::BackingStore;
-use crate::raw::{CBytes, CFallible, Tree};
+use crate::raw::{CBytes, CFallible, Request, Tree};
 
 fn stringpiece_to_slice<'a, T, U>(ptr: *const T, length: size_t) -> Result<&'a [U]> {
     ensure!(!ptr.is_null(), "string ptr is null");
@@ -106,6 +106,80 @@ pub extern "C" fn rust_backingstore_get_tree(
     backingstore_get_tree(store, node, node_len).into()
 }
 
+fn requests_to_slices<'a>(
+    requests: *const Request,
+    size: usize,
+) -> Result<Vec<(&'a [u8], &'a [u8])>> {
+    if size == 0 {
+        return Ok(Vec::new());
+    }
+    let requests: &[Request] = stringpiece_to_slice(requests, size)?;
+    Ok(requests
+        .iter()
+        .map(|request| unsafe { request.as_slices() })
+        .collect())
+}
+
+/// Reads the blobs of `size` requests. `resolve` is called once for every request with `data`,
+/// the index of the request and the result. The caller owns the `CBytes` and the error string in
+/// the result.
+#[no_mangle]
+pub extern "C" fn rust_backingstore_get_blob_batch(
+    store: *mut BackingStore,
+    requests: *const Request,
+    size: usize,
+    local: bool,
+    data: *mut c_void,
+    resolve: unsafe extern "C" fn(*mut c_void, usize, CFallible<CBytes>),
+) -> CFallible<u8> {
+    assert!(!store.is_null());
+    let store = unsafe { &*store };
+    let keys = match requests_to_slices(requests, size) {
+        Ok(keys) => keys,
+        Err(e) => return CFallible::err(e),
+    };
+
+    store.get_blob_batch(&keys, local, |index, result| {
+        let result: CFallible<CBytes> = result
+            .and_then(|opt| opt.ok_or_else(|| Error::msg("no blob found")))
+            .map(CBytes::from_vec)
+            .map(|result| Box::into_raw(Box::new(result)))
+            .into();
+        unsafe { resolve(data, index, result) };
+    });
+
+    CFallible::ok(std::ptr::null_mut())
+}
+
+/// Reads the root trees of `size` requests. Paths of the requests are ignored. `resolve` is
+/// called once for every request with `data`, the index of the request and the result. The
+/// caller owns the `Tree` and the error string in the result.
+#[no_mangle]
+pub extern "C" fn rust_backingstore_get_tree_batch(
+    store: *mut BackingStore,
+    requests: *const Request,
+    size: usize,
+    data: *mut c_void,
+    resolve: unsafe extern "C" fn(*mut c_void, usize, CFallible<Tree>),
+) -> CFallible<u8> {
+    assert!(!store.is_null());
+    let store = unsafe { &*store };
+    let nodes: Vec<&[u8]> = match requests_to_slices(requests, size) {
+        Ok(keys) => keys.into_iter().map(|(_path, node)| node).collect(),
+        Err(e) => return CFallible::err(e),
+    };
+
+    store.get_tree_batch(&nodes, |index, result| {
+        let result: CFallible<Tree> = result
+            .and_then(|list| list.try_into())
+            .map(|result| Box::into_raw(Box::new(result)))
+            .into();
+        unsafe { resolve(data, index, result) };
+    });
+
+    CFallible::ok(std::ptr::null_mut())
+}
+
 #[no_mangle]
 pub extern "C" fn rust_tree_free(tree: *mut Tree) {
     assert!(!tree.is_null());
@@ -120,3 +194,54 @@ pub extern "C" fn rust_backingstore_refresh(store: *mut BackingStore) {
 
     store.refresh();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::TempDir;
+
+    use crate::backingstore::tests::{add_blob, backingstore};
+
+    unsafe extern "C" fn collect(data: *mut c_void, index: usize, result: CFallible<CBytes>) {
+        let results = &mut *(data as *mut Vec<(usize, std::result::Result<Vec<u8>, String>)>);
+        let result = result
+            .into_result()
+            .map(|bytes| Box::from_raw(bytes).as_ref().to_vec());
+        results.push((index, result));
+    }
+
+    #[test]
+    fn test_get_blob_batch() -> Result<()> {
+        let dir = TempDir::new()?;
+        let mut store = backingstore(&dir)?;
+        add_blob(&store, "a", &[1; 20], "hello")?;
+
+        let hit = [1; 20];
+        let miss = [2; 20];
+        let short = [3; 5];
+        let requests = vec![
+            Request::new(b"a", &hit),
+            Request::new(b"b", &miss),
+            Request::new(b"c", &short),
+            Request::new(b"d", &[]),
+        ];
+        let mut results = Vec::new();
+        let result = rust_backingstore_get_blob_batch(
+            &mut store,
+            requests.as_ptr(),
+            requests.len(),
+            true,
+            &mut results as *mut _ as *mut c_void,
+            collect,
+        );
+        assert!(result.into_result().is_ok());
+
+        assert_eq!(results.len(), 4);
+        assert_eq!(results[0], (0, Ok(b"hello".to_vec())));
+        for (index, result) in &results[1..] {
+            assert!(result.is_err(), "request {} should fail", index);
+        }
+        Ok(())
+    }
+}
@@ -31,6 +31,13 @@ impl CBytes {
     }
 }
 
+#[cfg(test)]
+impl AsRef<[u8]> for CBytes {
+    fn as_ref(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
 impl From<Vec<u8>> for CBytes {
     fn from(vec: Vec<u8>) -> Self {
         CBytes::from_vec(vec)
@@ -58,6 +58,19 @@ impl<T> CFallible<T> {
     }
 }
 
+#[cfg(test)]
+impl<T> CFallible<T> {
+    /// Returns the value pointer, or takes back ownership of the error message.
+    pub(crate) fn into_result(self) -> std::result::Result<*mut T, String> {
+        if self.error.is_null() {
+            Ok(self.value)
+        } else {
+            let error = unsafe { CString::from_raw(self.error) };
+            Err(error.to_string_lossy().into_owned())
+        }
+    }
+}
+
 impl<T> From<Result<*mut T>> for CFallible<T> {
     fn from(value: Result<*mut T>) -> Self {
         match value {
@@ -0,0 +1,51 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Representation of a single key in batched requests from C++.
+
+/// A key of a blob or tree. `path` is ignored for trees.
+#[repr(C)]
+pub struct Request {
+    path: *const u8,
+    length: usize,
+    node: *const u8,
+    node_length: usize,
+}
+
+impl Request {
+    /// Returns the path and the node of this request. The node isn't validated here, so callers
+    /// should parse it with `Node::from_slice` and report bad nodes for this request only.
+    ///
+    /// The caller must make sure both pointers are valid for the duration of the returned
+    /// lifetime.
+    pub(crate) unsafe fn as_slices<'a>(&self) -> (&'a [u8], &'a [u8]) {
+        (
+            raw_slice(self.path, self.length),
+            raw_slice(self.node, self.node_length),
+        )
+    }
+}
+
+unsafe fn raw_slice<'a>(ptr: *const u8, length: usize) -> &'a [u8] {
+    if ptr.is_null() || length == 0 {
+        &[][..]
+    } else {
+        std::slice::from_raw_parts(ptr, length)
+    }
+}
+
+#[cfg(test)]
+impl Request {
+    pub(crate) fn new(path: &[u8], node: &[u8]) -> Self {
+        Self {
+            path: path.as_ptr(),
+            length: path.len(),
+            node: node.as_ptr(),
+            node_length: node.len(),
+        }
+    }
+}
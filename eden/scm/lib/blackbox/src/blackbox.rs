@@ -463,7 +463,7 @@ impl Entry {
         }
     }
 
-    fn from_slice(bytes: &[u8]) -> Option<Self> {
+    pub(crate) fn from_slice(bytes: &[u8]) -> Option<Self> {
         if bytes.len() >= HEADER_BYTES {
             let mut cur = Cursor::new(bytes);
             let timestamp = cur.read_u64::<BigEndian>().unwrap();
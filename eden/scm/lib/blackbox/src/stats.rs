@@ -0,0 +1,495 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Aggregated statistics of sessions (command invocations).
+//!
+//! Like the `event` module, this assumes the source control application.
+//! Sessions are summarized from their [`Event::Start`], [`Event::Finish`]
+//! and [`Event::Config`] events.
+
+use crate::event::Event;
+use crate::{Blackbox, Entry};
+use serde_derive::Serialize;
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::Range;
+
+/// Summary of a single session.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SessionSummary {
+    pub session_id: u64,
+
+    /// Timestamp of the `Start` event of the session, in milliseconds.
+    pub timestamp_ms: u64,
+
+    pub command: String,
+
+    /// Arguments, including the program name.
+    pub args: Vec<String>,
+
+    /// `None` if the session has not finished (ex. still running or
+    /// crashed), or its `Finish` event was rotated out.
+    pub exit_code: Option<u8>,
+    pub duration_ms: Option<u64>,
+
+    /// Config items logged by the session.
+    pub config: BTreeMap<String, String>,
+}
+
+/// How sessions are grouped in [`Stats`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum GroupBy {
+    Command,
+    ExitCode,
+    /// A config item (`section.name`).
+    Config(String),
+}
+
+/// Statistics of a group of sessions.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct GroupStats {
+    pub key: String,
+    pub count: usize,
+
+    /// Sessions without a known exit code.
+    pub unfinished: usize,
+
+    /// Finished sessions with a non-zero exit code.
+    pub errors: usize,
+
+    /// `errors` divided by the number of finished sessions.
+    pub error_rate: f64,
+
+    pub p50_ms: Option<u64>,
+    pub p90_ms: Option<u64>,
+    pub p99_ms: Option<u64>,
+}
+
+/// Aggregated statistics of sessions.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Stats {
+    /// Groups sorted by session count, descending.
+    pub groups: Vec<GroupStats>,
+
+    /// Slowest finished sessions, slowest first.
+    pub slowest: Vec<SessionSummary>,
+}
+
+impl Blackbox {
+    /// Summarize sessions that started within `range` (milliseconds since
+    /// epoch), using all of their entries, including the ones logged after
+    /// `range`.
+    ///
+    /// This scans all entries without using indexes. Entries that cannot be
+    /// read or deserialized are ignored silently. Sessions whose `Start`
+    /// event was rotated out are skipped. Sessions are ordered by their
+    /// start.
+    pub fn session_summaries(&self, range: Range<u64>) -> Vec<SessionSummary> {
+        summarize(
+            self.log
+                .iter()
+                .filter_map(|bytes| match bytes {
+                    Ok(bytes) => Entry::from_slice(bytes),
+                    Err(_) => None,
+                })
+                .map(|entry| (entry.timestamp, entry.session_id, entry.data)),
+            range,
+        )
+    }
+}
+
+/// Summarize sessions from `(timestamp, session_id, event)` entries. See
+/// [`Blackbox::session_summaries`].
+fn summarize(
+    entries: impl IntoIterator<Item = (u64, u64, Event)>,
+    range: Range<u64>,
+) -> Vec<SessionSummary> {
+    // Sessions by id, and whether their start is known to be within range.
+    let mut sessions = BTreeMap::<u64, (bool, SessionSummary)>::new();
+    for (timestamp, session_id, event) in entries {
+        let (started, summary) = sessions.entry(session_id).or_insert_with(|| {
+            (
+                false,
+                SessionSummary {
+                    session_id,
+                    timestamp_ms: timestamp,
+                    command: String::new(),
+                    args: Vec::new(),
+                    exit_code: None,
+                    duration_ms: None,
+                    config: BTreeMap::new(),
+                },
+            )
+        });
+        match event {
+            Event::Start { args, .. } => {
+                *started = range.contains(&timestamp);
+                summary.timestamp_ms = timestamp;
+                summary.command = command_name(&args).to_string();
+                summary.args = args;
+            }
+            Event::Finish {
+                exit_code,
+                duration_ms,
+                ..
+            } => {
+                summary.exit_code = Some(exit_code);
+                summary.duration_ms = Some(duration_ms);
+            }
+            Event::Config { items, .. } => summary.config.extend(items),
+            _ => {}
+        }
+    }
+
+    let mut sessions: Vec<SessionSummary> = sessions
+        .into_iter()
+        .filter_map(|(_, (started, summary))| if started { Some(summary) } else { None })
+        .collect();
+    sessions.sort_by_key(|s| s.timestamp_ms);
+    sessions
+}
+
+/// Global flags that take a value. Used to skip the value when looking for
+/// the command name.
+const GLOBAL_FLAGS_WITH_VALUE: &[&str] = &[
+    "-R",
+    "--repository",
+    "--cwd",
+    "--config",
+    "--configfile",
+    "--color",
+    "--encoding",
+    "--encodingmode",
+    "--pager",
+];
+
+/// Find the command name from `args` (including the program name).
+fn command_name(args: &[String]) -> &str {
+    let mut iter = args.iter().skip(1);
+    while let Some(arg) = iter.next() {
+        if GLOBAL_FLAGS_WITH_VALUE.contains(&arg.as_str()) {
+            iter.next();
+        } else if !arg.starts_with('-') {
+            return arg;
+        }
+    }
+    "(none)"
+}
+
+/// Aggregate sessions by `group_by`. Include `top` slowest sessions.
+pub fn aggregate(sessions: &[SessionSummary], group_by: &GroupBy, top: usize) -> Stats {
+    let mut groups = BTreeMap::<String, Vec<&SessionSummary>>::new();
+    for session in sessions {
+        let key = match group_by {
+            GroupBy::Command => session.command.clone(),
+            GroupBy::ExitCode => match session.exit_code {
+                Some(code) => code.to_string(),
+                None => "(unfinished)".to_string(),
+            },
+            GroupBy::Config(name) => match session.config.get(name) {
+                Some(value) => value.clone(),
+                None => "(unset)".to_string(),
+            },
+        };
+        groups.entry(key).or_default().push(session);
+    }
+
+    let mut groups: Vec<GroupStats> = groups
+        .into_iter()
+        .map(|(key, sessions)| {
+            let mut durations: Vec<u64> = sessions.iter().filter_map(|s| s.duration_ms).collect();
+            durations.sort_unstable();
+            let finished = sessions.iter().filter(|s| s.exit_code.is_some()).count();
+            let errors = sessions
+                .iter()
+                .filter(|s| s.exit_code.unwrap_or(0) != 0)
+                .count();
+            GroupStats {
+                key,
+                count: sessions.len(),
+                unfinished: sessions.len() - finished,
+                errors,
+                error_rate: if finished == 0 {
+                    0.0
+                } else {
+                    errors as f64 / finished as f64
+                },
+                p50_ms: percentile(&durations, 50),
+                p90_ms: percentile(&durations, 90),
+                p99_ms: percentile(&durations, 99),
+            }
+        })
+        .collect();
+    // Stable sort keeps groups of the same count sorted by key.
+    groups.sort_by_key(|g| Reverse(g.count));
+
+    let mut slowest: Vec<SessionSummary> = sessions
+        .iter()
+        .filter(|s| s.duration_ms.is_some())
+        .cloned()
+        .collect();
+    slowest.sort_by_key(|s| Reverse(s.duration_ms));
+    slowest.truncate(top);
+
+    Stats { groups, slowest }
+}
+
+/// Nearest-rank percentile of sorted values.
+fn percentile(sorted: &[u64], percent: usize) -> Option<u64> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let rank = ((sorted.len() * percent) as f64 / 100.0).ceil() as usize;
+    Some(sorted[rank.max(1) - 1])
+}
+
+impl fmt::Display for Stats {
+    /// Render as plain text tables.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ms = |v: Option<u64>| v.map(|v| v.to_string()).unwrap_or_else(|| "-".to_string());
+        let key_width = self
+            .groups
+            .iter()
+            .map(|g| g.key.len())
+            .chain(Some(5))
+            .max()
+            .unwrap_or(0);
+        writeln!(
+            f,
+            "{:<w$} {:>6} {:>6} {:>8} {:>8} {:>8} {:>8}",
+            "Group",
+            "Count",
+            "Errors",
+            "Error%",
+            "P50(ms)",
+            "P90(ms)",
+            "P99(ms)",
+            w = key_width
+        )?;
+        for group in &self.groups {
+            writeln!(
+                f,
+                "{:<w$} {:>6} {:>6} {:>8.1} {:>8} {:>8} {:>8}",
+                group.key,
+                group.count,
+                group.errors,
+                group.error_rate * 100.0,
+                ms(group.p50_ms),
+                ms(group.p90_ms),
+                ms(group.p99_ms),
+                w = key_width
+            )?;
+        }
+
+        if !self.slowest.is_empty() {
+            writeln!(f)?;
+            writeln!(f, "Slowest sessions:")?;
+            for session in &self.slowest {
+                writeln!(
+                    f,
+                    "{:>8}ms  exit {:>3}  session {}  {}",
+                    ms(session.duration_ms),
+                    session
+                        .exit_code
+                        .map(|c| c.to_string())
+                        .unwrap_or_else(|| "-".to_string()),
+                    session.session_id,
+                    session.args.join(" ")
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BlackboxOptions;
+    use tempfile::tempdir;
+
+    fn args(s: &str) -> Vec<String> {
+        s.split_whitespace().map(|s| s.to_string()).collect()
+    }
+
+    fn log_session(blackbox: &mut Blackbox, cmd: &str, result: Option<(u8, u64)>) -> u64 {
+        blackbox.refresh_session_id();
+        blackbox.log(&Event::Start {
+            pid: 0,
+            uid: 0,
+            nice: 0,
+            args: args(cmd),
+            timestamp_ms: 0,
+        });
+        blackbox.log(&Event::Config {
+            interactive: false,
+            items: vec![("ui.ssh".to_string(), cmd.len().to_string())]
+                .into_iter()
+                .collect(),
+        });
+        if let Some((exit_code, duration_ms)) = result {
+            blackbox.log(&Event::Finish {
+                exit_code,
+                max_rss: 0,
+                duration_ms,
+                timestamp_ms: 0,
+            });
+        }
+        blackbox.session_id().0
+    }
+
+    #[test]
+    fn test_command_name() {
+        assert_eq!(command_name(&args("hg")), "(none)");
+        assert_eq!(command_name(&args("hg status -A")), "status");
+        assert_eq!(
+            command_name(&args("hg -R repo --config a.b=c -q log -r .")),
+            "log"
+        );
+        assert_eq!(command_name(&args("hg --config=a.b=c st")), "st");
+    }
+
+    #[test]
+    fn test_percentile() {
+        assert_eq!(percentile(&[], 50), None);
+        assert_eq!(percentile(&[7], 99), Some(7));
+        let values: Vec<u64> = (1..=10).collect();
+        assert_eq!(percentile(&values, 50), Some(5));
+        assert_eq!(percentile(&values, 90), Some(9));
+        assert_eq!(percentile(&values, 99), Some(10));
+    }
+
+    #[test]
+    fn test_summarize_range() {
+        let start = |cmd: &str| Event::Start {
+            pid: 0,
+            uid: 0,
+            nice: 0,
+            args: args(cmd),
+            timestamp_ms: 0,
+        };
+        let finish = |duration_ms| Event::Finish {
+            exit_code: 0,
+            max_rss: 0,
+            duration_ms,
+            timestamp_ms: 0,
+        };
+        let entries = vec![
+            (10, 1, start("hg log")),
+            (20, 2, start("hg status")),
+            (25, 1, finish(15)),
+            (30, 3, start("hg diff")),
+            (40, 2, finish(20)),
+            (50, 4, finish(5)),
+        ];
+
+        // Session 1 started before the range and session 3 after it. Session
+        // 2 finished after the range but started within it. The start of
+        // session 4 is unknown.
+        let sessions = summarize(entries, 15..30);
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].session_id, 2);
+        assert_eq!(sessions[0].timestamp_ms, 20);
+        assert_eq!(sessions[0].command, "status");
+        assert_eq!(sessions[0].duration_ms, Some(20));
+    }
+
+    #[test]
+    fn test_aggregate() {
+        let dir = tempdir().unwrap();
+        let mut blackbox = BlackboxOptions::new().open(&dir.path()).unwrap();
+        log_session(&mut blackbox, "hg status", Some((0, 100)));
+        log_session(&mut blackbox, "hg status", Some((0, 300)));
+        let slow_log = log_session(&mut blackbox, "hg log -r .", Some((255, 900)));
+        log_session(&mut blackbox, "hg status", Some((1, 200)));
+        log_session(&mut blackbox, "hg log", None);
+
+        let sessions = blackbox.session_summaries(0..u64::max_value());
+        assert_eq!(sessions.len(), 5);
+        assert!(blackbox.session_summaries(0..1).is_empty());
+
+        let stats = aggregate(&sessions, &GroupBy::Command, 2);
+        assert_eq!(
+            stats.groups,
+            vec![
+                GroupStats {
+                    key: "status".to_string(),
+                    count: 3,
+                    unfinished: 0,
+                    errors: 1,
+                    error_rate: 1.0 / 3.0,
+                    p50_ms: Some(200),
+                    p90_ms: Some(300),
+                    p99_ms: Some(300),
+                },
+                GroupStats {
+                    key: "log".to_string(),
+                    count: 2,
+                    unfinished: 1,
+                    errors: 1,
+                    error_rate: 1.0,
+                    p50_ms: Some(900),
+                    p90_ms: Some(900),
+                    p99_ms: Some(900),
+                },
+            ]
+        );
+        let slowest: Vec<(u64, Option<u64>)> = stats
+            .slowest
+            .iter()
+            .map(|s| (s.session_id, s.duration_ms))
+            .collect();
+        assert_eq!(slowest[0], (slow_log, Some(900)));
+        assert_eq!(slowest[1].1, Some(300));
+
+        let stats = aggregate(&sessions, &GroupBy::ExitCode, 0);
+        let keys: Vec<(&str, usize)> = stats
+            .groups
+            .iter()
+            .map(|g| (g.key.as_str(), g.count))
+            .collect();
+        assert_eq!(keys, [("0", 2), ("(unfinished)", 1), ("1", 1), ("255", 1)]);
+
+        let stats = aggregate(&sessions, &GroupBy::Config("ui.ssh".to_string()), 0);
+        let keys: Vec<(&str, usize)> = stats
+            .groups
+            .iter()
+            .map(|g| (g.key.as_str(), g.count))
+            .collect();
+        assert_eq!(keys, [("9", 3), ("11", 1), ("6", 1)]);
+    }
+
+    #[test]
+    fn test_display() {
+        let session = |session_id, cmd: &str, exit_code, duration_ms| SessionSummary {
+            session_id,
+            timestamp_ms: 0,
+            command: command_name(&args(cmd)).to_string(),
+            args: args(cmd),
+            exit_code,
+            duration_ms,
+            config: Default::default(),
+        };
+        let sessions = vec![
+            session(1, "hg status", Some(0), Some(120)),
+            session(2, "hg status", Some(1), Some(80)),
+            session(3, "hg pull", None, None),
+        ];
+        let stats = aggregate(&sessions, &GroupBy::Command, 1);
+        assert_eq!(
+            stats.to_string(),
+            r#"Group   Count Errors   Error%  P50(ms)  P90(ms)  P99(ms)
+status      2      1     50.0       80      120      120
+pull        1      0      0.0        -        -        -
+
+Slowest sessions:
+     120ms  exit   0  session 1  hg status
+"#
+        );
+    }
+}
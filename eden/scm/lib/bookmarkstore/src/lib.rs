@@ -12,6 +12,7 @@
 //!
 //! Bookmarks can be loaded from an existing hg bookmarks file.
 
+use std::collections::BTreeSet;
 use std::io::Write;
 use std::path::Path;
 use std::str;
@@ -119,6 +120,22 @@ impl BookmarkStore {
         }
     }
 
+    /// Return the names of all bookmarks that currently exist, sorted.
+    pub fn list_bookmarks(&self) -> Result<Vec<String>> {
+        let mut bookmarks = BTreeSet::new();
+        for data in self.log.iter() {
+            match BookmarkEntry::unpack(data?) {
+                BookmarkEntry::Remove { bookmark } => {
+                    bookmarks.remove(bookmark);
+                }
+                BookmarkEntry::Update { bookmark, hgid: _ } => {
+                    bookmarks.insert(bookmark);
+                }
+            }
+        }
+        Ok(bookmarks.into_iter().map(String::from).collect())
+    }
+
     pub fn update(&mut self, bookmark: &str, hgid: HgId) -> Result<()> {
         Ok(self
             .log
@@ -276,6 +293,26 @@ mod tests {
         assert_eq!(bm_store.lookup_bookmark(bookmark), Some(node2));
     }
 
+    #[test]
+    fn test_list_bookmarks() {
+        let hgid = HgId::from_str("0123456789012345678901234567890123456789").unwrap();
+        let node2 = HgId::from(&[1u8; 20]);
+
+        let (mut bm_store, _) = new_indexed_log_bookmark_store();
+        assert!(bm_store.list_bookmarks().unwrap().is_empty());
+
+        bm_store.update("stable", hgid).unwrap();
+        bm_store.update("master", hgid).unwrap();
+        bm_store.update("feature", node2).unwrap();
+        bm_store.update("master", node2).unwrap();
+        bm_store.remove("stable").unwrap();
+
+        assert_eq!(
+            bm_store.list_bookmarks().unwrap(),
+            vec!["feature".to_string(), "master".to_string()]
+        );
+    }
+
     #[test]
     fn test_write_bookmarks_to_file() {
         let bookmark = "testbookmark";
@@ -0,0 +1,826 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Shell completion scripts generated from a [`CommandTable`].
+//!
+//! Command names, aliases and flags are written into the scripts. Values that
+//! depend on the repository, like bookmark names or file paths, are completed
+//! at runtime by calling `hg debugcompletion --values KIND -- PREFIX`.
+
+use crate::command::CommandTable;
+use crate::errors;
+use crate::global_flags::HgGlobalOpts;
+use anyhow::Result;
+use cliparser::parser::{Flag, StructFlags, Value};
+use std::fmt::{self, Write};
+use std::str::FromStr;
+
+/// Shells that completion scripts can be generated for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+impl FromStr for Shell {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "bash" => Ok(Shell::Bash),
+            "zsh" => Ok(Shell::Zsh),
+            "fish" => Ok(Shell::Fish),
+            _ => Err(errors::Abort(
+                format!("unsupported shell '{}' (use bash, zsh or fish)", s).into(),
+            )
+            .into()),
+        }
+    }
+}
+
+/// Values that are only known at completion time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ValueKind {
+    /// Bookmark names.
+    Bookmark,
+    /// Paths in the working copy.
+    File,
+}
+
+impl ValueKind {
+    /// Name used by `debugcompletion --values`.
+    pub fn name(self) -> &'static str {
+        match self {
+            ValueKind::Bookmark => "bookmarks",
+            ValueKind::File => "files",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "bookmarks" => Some(ValueKind::Bookmark),
+            "files" => Some(ValueKind::File),
+            _ => None,
+        }
+    }
+}
+
+/// What the argument of a flag completes to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum FlagValue {
+    /// The flag does not take an argument.
+    Switch,
+    /// Free-form argument. Nothing to complete.
+    Any,
+    Dynamic(ValueKind),
+}
+
+impl FlagValue {
+    fn of(flag: &Flag) -> Self {
+        if !flag.takes_argument() {
+            return FlagValue::Switch;
+        }
+        match flag.long_name() {
+            "bookmark" | "rev" | "dest" | "to" => FlagValue::Dynamic(ValueKind::Bookmark),
+            "include" | "exclude" | "cwd" | "repository" | "configfile" | "output-path" => {
+                FlagValue::Dynamic(ValueKind::File)
+            }
+            _ => FlagValue::Any,
+        }
+    }
+
+    /// Name used by the generated bash script.
+    fn name(self) -> &'static str {
+        match self {
+            FlagValue::Switch => "",
+            FlagValue::Any => "any",
+            FlagValue::Dynamic(kind) => kind.name(),
+        }
+    }
+}
+
+/// A command in the table, prepared for script generation.
+struct Command {
+    /// All names, including aliases. The first one is the canonical name.
+    names: Vec<String>,
+    /// First line of the documentation.
+    summary: String,
+    flags: Vec<Flag>,
+}
+
+impl Command {
+    /// Names offered when completing command names.
+    ///
+    /// Aliases like `stat` for `status` only exist so abbreviations keep
+    /// working. They are not offered as separate candidates.
+    fn visible_names(&self) -> impl Iterator<Item = &str> {
+        let canonical = &self.names[0];
+        self.names
+            .iter()
+            .enumerate()
+            .filter(move |(i, name)| *i == 0 || !canonical.starts_with(name.as_str()))
+            .map(|(_, name)| name.as_str())
+    }
+}
+
+/// Generate a completion script of `shell` for the program `prog`.
+pub fn generate(table: &CommandTable, shell: Shell, prog: &str) -> String {
+    generate_with_global_flags(table, &HgGlobalOpts::flags(), shell, prog)
+}
+
+fn generate_with_global_flags(
+    table: &CommandTable,
+    global_flags: &[Flag],
+    shell: Shell,
+    prog: &str,
+) -> String {
+    let commands: Vec<Command> = table
+        .values()
+        .map(|def| Command {
+            names: def.name().split('|').map(|s| s.to_string()).collect(),
+            summary: def.doc().lines().next().unwrap_or("").trim().to_string(),
+            flags: def.flags(),
+        })
+        .collect();
+    let script = Script {
+        prog,
+        func: identifier(prog),
+        global_flags,
+        commands: &commands,
+    };
+    let mut out = String::new();
+    match shell {
+        Shell::Bash => script.bash(&mut out),
+        Shell::Zsh => script.zsh(&mut out),
+        Shell::Fish => script.fish(&mut out),
+    }
+    .expect("writing to a String cannot fail");
+    out
+}
+
+struct Script<'a> {
+    prog: &'a str,
+    /// `prog` made suitable for shell function names.
+    func: String,
+    global_flags: &'a [Flag],
+    commands: &'a [Command],
+}
+
+impl<'a> Script<'a> {
+    fn bash(&self, out: &mut String) -> fmt::Result {
+        let (prog, func) = (self.prog, &self.func);
+        writeln!(
+            out,
+            "# bash completion for {0}. Generated by `{0} debugcompletion bash`.",
+            prog
+        )?;
+        writeln!(out)?;
+
+        let names: Vec<&str> = self
+            .commands
+            .iter()
+            .flat_map(|c| c.visible_names())
+            .collect();
+        writeln!(out, "_{}_commands=\"{}\"", func, names.join(" "))?;
+        writeln!(
+            out,
+            "_{}_global_flags=\"{}\"",
+            func,
+            bash_flag_words(self.global_flags).join(" ")
+        )?;
+        writeln!(out)?;
+
+        writeln!(out, "_{}_command_flags() {{", func)?;
+        writeln!(out, "    case \"$1\" in")?;
+        for command in self.commands.iter().filter(|c| !c.flags.is_empty()) {
+            writeln!(
+                out,
+                "        {}) echo \"{}\" ;;",
+                command.names.join("|"),
+                bash_flag_words(&command.flags).join(" ")
+            )?;
+        }
+        writeln!(out, "    esac")?;
+        writeln!(out, "}}")?;
+        writeln!(out)?;
+
+        writeln!(
+            out,
+            "# Print what the argument of flag $2 of command $1 completes to: a value"
+        )?;
+        writeln!(
+            out,
+            "# kind for '{} debugcompletion --values', \"any\", or nothing for switches.",
+            prog
+        )?;
+        writeln!(out, "_{}_flag_kind() {{", func)?;
+        writeln!(out, "    case \"$2\" in")?;
+        for (patterns, value) in bash_flag_kinds(self.global_flags) {
+            writeln!(out, "        {}) echo {}; return ;;", patterns, value)?;
+        }
+        writeln!(out, "    esac")?;
+        writeln!(out, "    case \"$1\" in")?;
+        for command in self.commands.iter() {
+            let kinds = bash_flag_kinds(&command.flags);
+            if kinds.is_empty() {
+                continue;
+            }
+            writeln!(out, "        {})", command.names.join("|"))?;
+            writeln!(out, "            case \"$2\" in")?;
+            for (patterns, value) in kinds {
+                writeln!(out, "                {}) echo {} ;;", patterns, value)?;
+            }
+            writeln!(out, "            esac")?;
+            writeln!(out, "            ;;")?;
+        }
+        writeln!(out, "    esac")?;
+        writeln!(out, "}}")?;
+        writeln!(out)?;
+
+        writeln!(out, "_{}_values() {{", func)?;
+        writeln!(
+            out,
+            "    COMPREPLY=($(compgen -W \"$({} debugcompletion --values \"$1\" -- \"$2\" 2>/dev/null)\" -- \"$2\"))",
+            prog
+        )?;
+        writeln!(
+            out,
+            "    if [[ ${{#COMPREPLY[@]}} -eq 1 && \"${{COMPREPLY[0]}}\" == */ ]]; then"
+        )?;
+        writeln!(out, "        compopt -o nospace")?;
+        writeln!(out, "    fi")?;
+        writeln!(out, "}}")?;
+        writeln!(out)?;
+
+        writeln!(out, "_{}() {{", func)?;
+        writeln!(out, "    local cur prev cmd kind i")?;
+        writeln!(out, "    COMPREPLY=()")?;
+        writeln!(out, "    cur=\"${{COMP_WORDS[COMP_CWORD]}}\"")?;
+        writeln!(out, "    prev=\"${{COMP_WORDS[COMP_CWORD-1]}}\"")?;
+        writeln!(out, "    cmd=\"\"")?;
+        writeln!(out, "    for ((i = 1; i < COMP_CWORD; i++)); do")?;
+        writeln!(out, "        case \"${{COMP_WORDS[i]}}\" in")?;
+        writeln!(out, "            -*)")?;
+        writeln!(
+            out,
+            "                if [[ -n \"$(_{}_flag_kind \"\" \"${{COMP_WORDS[i]}}\")\" ]]; then",
+            func
+        )?;
+        writeln!(out, "                    i=$((i + 1))")?;
+        writeln!(out, "                fi")?;
+        writeln!(out, "                ;;")?;
+        writeln!(out, "            *)")?;
+        writeln!(out, "                cmd=\"${{COMP_WORDS[i]}}\"")?;
+        writeln!(out, "                break")?;
+        writeln!(out, "                ;;")?;
+        writeln!(out, "        esac")?;
+        writeln!(out, "    done")?;
+        writeln!(
+            out,
+            "    kind=\"$(_{}_flag_kind \"$cmd\" \"$prev\")\"",
+            func
+        )?;
+        writeln!(out, "    case \"$kind\" in")?;
+        writeln!(out, "        \"\") ;;")?;
+        writeln!(out, "        any) return ;;")?;
+        writeln!(
+            out,
+            "        *) _{}_values \"$kind\" \"$cur\"; return ;;",
+            func
+        )?;
+        writeln!(out, "    esac")?;
+        writeln!(out, "    if [[ \"$cur\" == -* ]]; then")?;
+        writeln!(
+            out,
+            "        COMPREPLY=($(compgen -W \"$(_{0}_command_flags \"$cmd\") $_{0}_global_flags\" -- \"$cur\"))",
+            func
+        )?;
+        writeln!(out, "    elif [[ -z \"$cmd\" ]]; then")?;
+        writeln!(
+            out,
+            "        COMPREPLY=($(compgen -W \"$_{}_commands\" -- \"$cur\"))",
+            func
+        )?;
+        writeln!(out, "    else")?;
+        writeln!(out, "        _{}_values files \"$cur\"", func)?;
+        writeln!(out, "    fi")?;
+        writeln!(out, "}}")?;
+        writeln!(out)?;
+        writeln!(out, "complete -F _{} {}", func, prog)
+    }
+
+    fn zsh(&self, out: &mut String) -> fmt::Result {
+        let (prog, func) = (self.prog, &self.func);
+        writeln!(out, "#compdef {}", prog)?;
+        writeln!(
+            out,
+            "# zsh completion for {0}. Generated by `{0} debugcompletion zsh`.",
+            prog
+        )?;
+        writeln!(out)?;
+
+        writeln!(out, "_{}_values() {{", func)?;
+        writeln!(out, "    local -a values dirs")?;
+        writeln!(
+            out,
+            "    values=(${{(f)\"$(_call_program values {} debugcompletion --values $1 -- ${{(q)PREFIX}} 2>/dev/null)\"}})",
+            prog
+        )?;
+        writeln!(out, "    dirs=(${{(M)values:#*/}})")?;
+        writeln!(out, "    values=(${{values:#*/}})")?;
+        writeln!(out, "    compadd -S '' -a dirs")?;
+        writeln!(out, "    compadd -a values")?;
+        writeln!(out, "}}")?;
+        writeln!(out)?;
+
+        writeln!(out, "_{}_command_flags() {{", func)?;
+        writeln!(out, "    case $1 in")?;
+        for command in self.commands.iter().filter(|c| !c.flags.is_empty()) {
+            writeln!(out, "        {})", command.names.join("|"))?;
+            writeln!(out, "            reply=(")?;
+            for flag in &command.flags {
+                writeln!(out, "                {}", zsh_flag_spec(flag, func))?;
+            }
+            writeln!(out, "            )")?;
+            writeln!(out, "            ;;")?;
+        }
+        writeln!(out, "        *)")?;
+        writeln!(out, "            reply=()")?;
+        writeln!(out, "            ;;")?;
+        writeln!(out, "    esac")?;
+        writeln!(out, "}}")?;
+        writeln!(out)?;
+
+        writeln!(out, "_{}() {{", func)?;
+        writeln!(out, "    local curcontext=\"$curcontext\" state line")?;
+        writeln!(out, "    local -a global_flags commands reply")?;
+        writeln!(out, "    global_flags=(")?;
+        for flag in self.global_flags {
+            writeln!(out, "        {}", zsh_flag_spec(flag, func))?;
+        }
+        writeln!(out, "    )")?;
+        writeln!(out, "    commands=(")?;
+        for command in self.commands {
+            for name in command.visible_names() {
+                writeln!(
+                    out,
+                    "        {}",
+                    single_quote(&format!("{}:{}", name, command.summary))
+                )?;
+            }
+        }
+        writeln!(out, "    )")?;
+        writeln!(
+            out,
+            "    _arguments -C $global_flags '1:command:->command' '*::arg:->args'"
+        )?;
+        writeln!(out, "    case $state in")?;
+        writeln!(out, "        command)")?;
+        writeln!(out, "            _describe -t commands command commands")?;
+        writeln!(out, "            ;;")?;
+        writeln!(out, "        args)")?;
+        writeln!(out, "            _{}_command_flags $words[1]", func)?;
+        writeln!(
+            out,
+            "            _arguments $global_flags $reply '*:file:_{}_values files'",
+            func
+        )?;
+        writeln!(out, "            ;;")?;
+        writeln!(out, "    esac")?;
+        writeln!(out, "}}")?;
+        writeln!(out)?;
+        writeln!(out, "_{} \"$@\"", func)
+    }
+
+    fn fish(&self, out: &mut String) -> fmt::Result {
+        let (prog, func) = (self.prog, &self.func);
+        writeln!(
+            out,
+            "# fish completion for {0}. Generated by `{0} debugcompletion fish`.",
+            prog
+        )?;
+        writeln!(out)?;
+        writeln!(out, "function __{}_values", func)?;
+        writeln!(
+            out,
+            "    {} debugcompletion --values $argv[1] -- (commandline -ct) 2>/dev/null",
+            prog
+        )?;
+        writeln!(out, "end")?;
+        writeln!(out)?;
+
+        writeln!(out, "complete -c {} -f", prog)?;
+        for command in self.commands {
+            for name in command.visible_names() {
+                writeln!(
+                    out,
+                    "complete -c {} -n __fish_use_subcommand -a {} -d {}",
+                    prog,
+                    name,
+                    single_quote(&command.summary)
+                )?;
+            }
+        }
+        for flag in self.global_flags {
+            writeln!(out, "complete -c {}{}", prog, fish_flag_args(flag, func))?;
+        }
+        for command in self.commands {
+            let condition = single_quote(&format!(
+                "__fish_seen_subcommand_from {}",
+                command.names.join(" ")
+            ));
+            writeln!(
+                out,
+                "complete -c {} -n {} -a '(__{}_values files)'",
+                prog, condition, func
+            )?;
+            for flag in &command.flags {
+                writeln!(
+                    out,
+                    "complete -c {} -n {}{}",
+                    prog,
+                    condition,
+                    fish_flag_args(flag, func)
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Words a flag can be spelled as. For example, `["-r", "--rev"]`.
+fn flag_words(flag: &Flag) -> Vec<String> {
+    let mut words = Vec::new();
+    if let Some(short) = flag.short_name() {
+        words.push(format!("-{}", short));
+    }
+    words.push(format!("--{}", flag.long_name()));
+    words
+}
+
+fn bash_flag_words(flags: &[Flag]) -> Vec<String> {
+    flags.iter().flat_map(flag_words).collect()
+}
+
+/// Group flags taking arguments by what they complete to, as bash `case`
+/// patterns. For example, `[("-r|--rev", "bookmarks")]`.
+fn bash_flag_kinds(flags: &[Flag]) -> Vec<(String, &'static str)> {
+    let mut kinds: Vec<(Vec<String>, FlagValue)> = Vec::new();
+    for flag in flags {
+        let value = FlagValue::of(flag);
+        if value == FlagValue::Switch {
+            continue;
+        }
+        match kinds.iter_mut().find(|(_, v)| *v == value) {
+            Some((words, _)) => words.extend(flag_words(flag)),
+            None => kinds.push((flag_words(flag), value)),
+        }
+    }
+    kinds
+        .into_iter()
+        .map(|(words, value)| (words.join("|"), value.name()))
+        .collect()
+}
+
+/// Flag specification for zsh `_arguments`. For example,
+/// `'(-r --rev)'{-r+,--rev=}'[revision]:rev:_hg_values bookmarks'`.
+fn zsh_flag_spec(flag: &Flag, func: &str) -> String {
+    let value = FlagValue::of(flag);
+    let repeated = matches!(flag.default_value(), Value::List(_));
+    let (short_suffix, long_suffix) = match value {
+        FlagValue::Switch => ("", ""),
+        _ => ("+", "="),
+    };
+    let description = flag
+        .description()
+        .replace('[', "\\[")
+        .replace(']', "\\]")
+        .replace(':', "\\:");
+    let argument = match value {
+        FlagValue::Switch => String::new(),
+        FlagValue::Any => format!(":{}: ", flag.long_name()),
+        FlagValue::Dynamic(kind) => {
+            format!(":{}:_{}_values {}", flag.long_name(), func, kind.name())
+        }
+    };
+    let rest = single_quote(&format!("[{}]{}", description, argument));
+    let long = flag.long_name();
+    match (flag.short_name(), repeated) {
+        (Some(short), true) => format!(
+            "'*'{{-{}{},--{}{}}}{}",
+            short, short_suffix, long, long_suffix, rest
+        ),
+        (Some(short), false) => format!(
+            "'(-{} --{})'{{-{}{},--{}{}}}{}",
+            short, long, short, short_suffix, long, long_suffix, rest
+        ),
+        (None, true) => format!("'*'--{}{}{}", long, long_suffix, rest),
+        (None, false) => format!("--{}{}{}", long, long_suffix, rest),
+    }
+}
+
+/// Arguments of fish `complete` describing a flag, with a leading space.
+fn fish_flag_args(flag: &Flag, func: &str) -> String {
+    let mut args = String::new();
+    if let Some(short) = flag.short_name() {
+        args += &format!(" -s {}", short);
+    }
+    args += &format!(" -l {}", flag.long_name());
+    match FlagValue::of(flag) {
+        FlagValue::Switch => {}
+        FlagValue::Any => args += " -x",
+        FlagValue::Dynamic(kind) => {
+            args += &format!(" -x -a '(__{}_values {})'", func, kind.name())
+        }
+    }
+    if !flag.description().is_empty() {
+        args += &format!(" -d {}", single_quote(flag.description()));
+    }
+    args
+}
+
+/// Quote `s` in single quotes. Works for bash, zsh and fish as long as `s`
+/// does not contain backslashes, which fish would interpret.
+fn single_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+/// Turn a program name into something usable in shell function names.
+fn identifier(prog: &str) -> String {
+    prog.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::Register;
+    use crate::io::IO;
+    use crate::repo::Repo;
+    use cliparser::define_flags;
+
+    define_flags! {
+        pub struct TestGlobalOpts {
+            /// repository root directory
+            #[short('R')]
+            repository: String,
+
+            /// suppress output
+            #[short('q')]
+            quiet: bool,
+        }
+
+        pub struct TestStatusOpts {
+            /// show status of all files
+            #[short('A')]
+            all: bool,
+
+            /// show difference from revision
+            rev: Vec<String>,
+
+            /// include names matching the given patterns
+            #[short('I')]
+            include: Vec<String>,
+
+            /// display with template
+            #[short('T')]
+            template: String,
+
+            #[args]
+            args: Vec<String>,
+        }
+
+        pub struct TestRootOpts {
+            /// show root of the shared repo
+            shared: bool,
+        }
+
+        pub struct TestNoOpts {}
+    }
+
+    fn status(_: TestStatusOpts, _: &mut IO, _: Repo) -> Result<u8> {
+        Ok(0)
+    }
+
+    fn root(_: TestRootOpts, _: &mut IO, _: Repo) -> Result<u8> {
+        Ok(0)
+    }
+
+    fn debugpython(_: TestNoOpts, _: &mut IO) -> Result<u8> {
+        Ok(0)
+    }
+
+    fn test_table() -> CommandTable {
+        let mut table = CommandTable::new();
+        table.register(
+            status,
+            "status|st|sta|stat|statu",
+            "list files with pending changes\n\n    More details.",
+        );
+        table.register(
+            root,
+            "root",
+            "print the root (top) of the current working directory",
+        );
+        table.register(
+            debugpython,
+            "debugpython|debugpy|dpy",
+            "run python interpreter",
+        );
+        table
+    }
+
+    fn generate_for_test(shell: Shell) -> String {
+        generate_with_global_flags(&test_table(), &TestGlobalOpts::flags(), shell, "hg")
+    }
+
+    #[test]
+    fn test_shell_from_str() {
+        assert_eq!("zsh".parse::<Shell>().unwrap(), Shell::Zsh);
+        assert_eq!(
+            "csh".parse::<Shell>().unwrap_err().to_string(),
+            "unsupported shell 'csh' (use bash, zsh or fish)"
+        );
+    }
+
+    #[test]
+    fn test_bash() {
+        assert_eq!(
+            generate_for_test(Shell::Bash),
+            r#"# bash completion for hg. Generated by `hg debugcompletion bash`.
+
+_hg_commands="debugpython dpy root status"
+_hg_global_flags="-R --repository -q --quiet"
+
+_hg_command_flags() {
+    case "$1" in
+        root) echo "--shared" ;;
+        status|st|sta|stat|statu) echo "-A --all --rev -I --include -T --template" ;;
+    esac
+}
+
+# Print what the argument of flag $2 of command $1 completes to: a value
+# kind for 'hg debugcompletion --values', "any", or nothing for switches.
+_hg_flag_kind() {
+    case "$2" in
+        -R|--repository) echo files; return ;;
+    esac
+    case "$1" in
+        status|st|sta|stat|statu)
+            case "$2" in
+                --rev) echo bookmarks ;;
+                -I|--include) echo files ;;
+                -T|--template) echo any ;;
+            esac
+            ;;
+    esac
+}
+
+_hg_values() {
+    COMPREPLY=($(compgen -W "$(hg debugcompletion --values "$1" -- "$2" 2>/dev/null)" -- "$2"))
+    if [[ ${#COMPREPLY[@]} -eq 1 && "${COMPREPLY[0]}" == */ ]]; then
+        compopt -o nospace
+    fi
+}
+
+_hg() {
+    local cur prev cmd kind i
+    COMPREPLY=()
+    cur="${COMP_WORDS[COMP_CWORD]}"
+    prev="${COMP_WORDS[COMP_CWORD-1]}"
+    cmd=""
+    for ((i = 1; i < COMP_CWORD; i++)); do
+        case "${COMP_WORDS[i]}" in
+            -*)
+                if [[ -n "$(_hg_flag_kind "" "${COMP_WORDS[i]}")" ]]; then
+                    i=$((i + 1))
+                fi
+                ;;
+            *)
+                cmd="${COMP_WORDS[i]}"
+                break
+                ;;
+        esac
+    done
+    kind="$(_hg_flag_kind "$cmd" "$prev")"
+    case "$kind" in
+        "") ;;
+        any) return ;;
+        *) _hg_values "$kind" "$cur"; return ;;
+    esac
+    if [[ "$cur" == -* ]]; then
+        COMPREPLY=($(compgen -W "$(_hg_command_flags "$cmd") $_hg_global_flags" -- "$cur"))
+    elif [[ -z "$cmd" ]]; then
+        COMPREPLY=($(compgen -W "$_hg_commands" -- "$cur"))
+    else
+        _hg_values files "$cur"
+    fi
+}
+
+complete -F _hg hg
+"#
+        );
+    }
+
+    #[test]
+    fn test_zsh() {
+        assert_eq!(
+            generate_for_test(Shell::Zsh),
+            r#"#compdef hg
+# zsh completion for hg. Generated by `hg debugcompletion zsh`.
+
+_hg_values() {
+    local -a values dirs
+    values=(${(f)"$(_call_program values hg debugcompletion --values $1 -- ${(q)PREFIX} 2>/dev/null)"})
+    dirs=(${(M)values:#*/})
+    values=(${values:#*/})
+    compadd -S '' -a dirs
+    compadd -a values
+}
+
+_hg_command_flags() {
+    case $1 in
+        root)
+            reply=(
+                --shared'[show root of the shared repo]'
+            )
+            ;;
+        status|st|sta|stat|statu)
+            reply=(
+                '(-A --all)'{-A,--all}'[show status of all files]'
+                '*'--rev='[show difference from revision]:rev:_hg_values bookmarks'
+                '*'{-I+,--include=}'[include names matching the given patterns]:include:_hg_values files'
+                '(-T --template)'{-T+,--template=}'[display with template]:template: '
+            )
+            ;;
+        *)
+            reply=()
+            ;;
+    esac
+}
+
+_hg() {
+    local curcontext="$curcontext" state line
+    local -a global_flags commands reply
+    global_flags=(
+        '(-R --repository)'{-R+,--repository=}'[repository root directory]:repository:_hg_values files'
+        '(-q --quiet)'{-q,--quiet}'[suppress output]'
+    )
+    commands=(
+        'debugpython:run python interpreter'
+        'dpy:run python interpreter'
+        'root:print the root (top) of the current working directory'
+        'status:list files with pending changes'
+    )
+    _arguments -C $global_flags '1:command:->command' '*::arg:->args'
+    case $state in
+        command)
+            _describe -t commands command commands
+            ;;
+        args)
+            _hg_command_flags $words[1]
+            _arguments $global_flags $reply '*:file:_hg_values files'
+            ;;
+    esac
+}
+
+_hg "$@"
+"#
+        );
+    }
+
+    #[test]
+    fn test_fish() {
+        assert_eq!(
+            generate_for_test(Shell::Fish),
+            r#"# fish completion for hg. Generated by `hg debugcompletion fish`.
+
+function __hg_values
+    hg debugcompletion --values $argv[1] -- (commandline -ct) 2>/dev/null
+end
+
+complete -c hg -f
+complete -c hg -n __fish_use_subcommand -a debugpython -d 'run python interpreter'
+complete -c hg -n __fish_use_subcommand -a dpy -d 'run python interpreter'
+complete -c hg -n __fish_use_subcommand -a root -d 'print the root (top) of the current working directory'
+complete -c hg -n __fish_use_subcommand -a status -d 'list files with pending changes'
+complete -c hg -s R -l repository -x -a '(__hg_values files)' -d 'repository root directory'
+complete -c hg -s q -l quiet -d 'suppress output'
+complete -c hg -n '__fish_seen_subcommand_from debugpython debugpy dpy' -a '(__hg_values files)'
+complete -c hg -n '__fish_seen_subcommand_from root' -a '(__hg_values files)'
+complete -c hg -n '__fish_seen_subcommand_from root' -l shared -d 'show root of the shared repo'
+complete -c hg -n '__fish_seen_subcommand_from status st sta stat statu' -a '(__hg_values files)'
+complete -c hg -n '__fish_seen_subcommand_from status st sta stat statu' -s A -l all -d 'show status of all files'
+complete -c hg -n '__fish_seen_subcommand_from status st sta stat statu' -l rev -x -a '(__hg_values bookmarks)' -d 'show difference from revision'
+complete -c hg -n '__fish_seen_subcommand_from status st sta stat statu' -s I -l include -x -a '(__hg_values files)' -d 'include names matching the given patterns'
+complete -c hg -n '__fish_seen_subcommand_from status st sta stat statu' -s T -l template -x -d 'display with template'
+"#
+        );
+    }
+}
@@ -274,6 +274,37 @@ where
     }
 }
 
+impl Flag {
+    /// Short name of the flag, if any. For example, `q`.
+    pub fn short_name(&self) -> Option<char> {
+        self.short_name
+    }
+
+    /// Long name of the flag. For example, `quiet`.
+    pub fn long_name(&self) -> &str {
+        &self.long_name
+    }
+
+    /// Description of the flag, used by help text.
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+
+    /// Default value of the flag. Also decides the type of the flag.
+    pub fn default_value(&self) -> &Value {
+        &self.default_value
+    }
+
+    /// Whether the flag takes an argument (ex. `--rev REV`), or is a switch
+    /// (ex. `--quiet`).
+    pub fn takes_argument(&self) -> bool {
+        match self.default_value {
+            Value::Bool(_) | Value::OptBool() => false,
+            _ => true,
+        }
+    }
+}
+
 /// Convert [`Flag`] to Python tuple `(short, long, val, desc)`.
 #[cfg(feature = "python")]
 impl ToPyObject for Flag {
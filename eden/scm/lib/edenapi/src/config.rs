@@ -6,6 +6,7 @@
  */
 
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use url::Url;
 
@@ -25,6 +26,11 @@ pub struct Config {
     pub(crate) stream_data: bool,
     pub(crate) stream_history: bool,
     pub(crate) stream_trees: bool,
+    pub(crate) max_retries: Option<usize>,
+    pub(crate) retry_backoff: Option<Duration>,
+    pub(crate) max_retry_backoff: Option<Duration>,
+    pub(crate) circuit_breaker_threshold: Option<usize>,
+    pub(crate) circuit_breaker_cooldown: Option<Duration>,
 }
 
 impl Config {
@@ -68,6 +74,28 @@ impl Config {
         let stream_trees = config
             .get_or_default("edenapi", "streamtrees")
             .context(ApiErrorKind::BadConfig("edenapi.streamtrees".into()))?;
+        let max_retries = config
+            .get_opt("edenapi", "maxretries")
+            .context(ApiErrorKind::BadConfig("edenapi.maxretries".into()))?;
+        let retry_backoff = config
+            .get_opt("edenapi", "retrybackoffms")
+            .context(ApiErrorKind::BadConfig("edenapi.retrybackoffms".into()))?
+            .map(Duration::from_millis);
+        let max_retry_backoff = config
+            .get_opt("edenapi", "maxretrybackoffms")
+            .context(ApiErrorKind::BadConfig("edenapi.maxretrybackoffms".into()))?
+            .map(Duration::from_millis);
+        let circuit_breaker_threshold = config
+            .get_opt("edenapi", "circuitbreakerthreshold")
+            .context(ApiErrorKind::BadConfig(
+                "edenapi.circuitbreakerthreshold".into(),
+            ))?;
+        let circuit_breaker_cooldown = config
+            .get_opt("edenapi", "circuitbreakercooldownms")
+            .context(ApiErrorKind::BadConfig(
+                "edenapi.circuitbreakercooldownms".into(),
+            ))?
+            .map(Duration::from_millis);
 
         Ok(Self {
             base_url,
@@ -79,6 +107,11 @@ impl Config {
             stream_data,
             stream_history,
             stream_trees,
+            max_retries,
+            retry_backoff,
+            max_retry_backoff,
+            circuit_breaker_threshold,
+            circuit_breaker_cooldown,
         })
     }
 
@@ -155,6 +188,33 @@ impl Config {
         self.stream_trees = stream_trees;
         self
     }
+
+    /// Number of times a batch that failed with a server error or a
+    /// network error should be sent again. Setting this to 0 disables
+    /// retries.
+    pub fn max_retries(mut self, max_retries: usize) -> Self {
+        self.max_retries = Some(max_retries);
+        self
+    }
+
+    /// Initial delay before retrying a failed batch. The delay doubles
+    /// with each retry, up to `max_backoff`, and is randomized to avoid
+    /// many clients retrying at the same time.
+    pub fn retry_backoff(mut self, backoff: Duration, max_backoff: Duration) -> Self {
+        self.retry_backoff = Some(backoff);
+        self.max_retry_backoff = Some(max_backoff);
+        self
+    }
+
+    /// Stop sending requests for `cooldown` after `threshold` attempts in a
+    /// row had requests fail with retryable errors. The concurrent batches
+    /// of one attempt count once. Setting the threshold to 0 disables the
+    /// circuit breaker.
+    pub fn circuit_breaker(mut self, threshold: usize, cooldown: Duration) -> Self {
+        self.circuit_breaker_threshold = Some(threshold);
+        self.circuit_breaker_cooldown = Some(cooldown);
+        self
+    }
 }
 
 /// Client credentials for TLS mutual authentication, including an X.509 client
@@ -214,6 +274,9 @@ mod tests {
                  historybatchsize = 5678\n\
                  validate = true\n\
                  streamdata = true\n\
+                 maxretries = 5\n\
+                 retrybackoffms = 50\n\
+                 circuitbreakerthreshold = 0\n\
                  [auth]\n\
                  edenapi.prefix = example.com\n\
                  edenapi.cert = {}\n\
@@ -236,6 +299,11 @@ mod tests {
         assert_eq!(config.stream_data, true);
         assert_eq!(config.stream_history, false);
         assert_eq!(config.stream_trees, false);
+        assert_eq!(config.max_retries, Some(5));
+        assert_eq!(config.retry_backoff, Some(Duration::from_millis(50)));
+        assert_eq!(config.max_retry_backoff, None);
+        assert_eq!(config.circuit_breaker_threshold, Some(0));
+        assert_eq!(config.circuit_breaker_cooldown, None);
 
         Ok(())
     }
@@ -31,6 +31,11 @@ use crate::api::EdenApi;
 use crate::config::{ClientCreds, Config};
 use crate::errors::{ApiError, ApiErrorContext, ApiErrorKind, ApiResult};
 use crate::progress::{ProgressFn, ProgressReporter};
+use crate::retry::{
+    CircuitBreaker, RetryPolicy, DEFAULT_CIRCUIT_BREAKER_COOLDOWN,
+    DEFAULT_CIRCUIT_BREAKER_THRESHOLD, DEFAULT_MAX_RETRIES, DEFAULT_MAX_RETRY_BACKOFF,
+    DEFAULT_RETRY_BACKOFF,
+};
 use crate::stats::DownloadStats;
 
 mod driver;
@@ -89,6 +94,8 @@ pub struct EdenApiCurlClient {
     stream_data: bool,
     stream_history: bool,
     stream_trees: bool,
+    retry: RetryPolicy,
+    breaker: CircuitBreaker,
 }
 
 // Public API.
@@ -104,6 +111,25 @@ impl EdenApiCurlClient {
             None => return Err(ApiErrorKind::BadConfig("No repo name specified".into()).into()),
         };
 
+        let retry = RetryPolicy {
+            max_retries: config.max_retries.unwrap_or(DEFAULT_MAX_RETRIES),
+            backoff: config.retry_backoff.unwrap_or(DEFAULT_RETRY_BACKOFF),
+            max_backoff: config
+                .max_retry_backoff
+                .unwrap_or(DEFAULT_MAX_RETRY_BACKOFF),
+        };
+        let breaker_threshold = match config.circuit_breaker_threshold {
+            Some(0) => None,
+            Some(threshold) => Some(threshold),
+            None => Some(DEFAULT_CIRCUIT_BREAKER_THRESHOLD),
+        };
+        let breaker = CircuitBreaker::new(
+            breaker_threshold,
+            config
+                .circuit_breaker_cooldown
+                .unwrap_or(DEFAULT_CIRCUIT_BREAKER_COOLDOWN),
+        );
+
         Ok(Self {
             multi: SyncMulti::new(),
             base_url,
@@ -115,6 +141,8 @@ impl EdenApiCurlClient {
             stream_data: config.stream_data,
             stream_history: config.stream_history,
             stream_trees: config.stream_trees,
+            retry,
+            breaker,
         })
     }
 }
@@ -214,6 +242,8 @@ impl EdenApi for EdenApiCurlClient {
                 &mut multi,
                 &url,
                 self.creds.as_ref(),
+                &self.retry,
+                &self.breaker,
                 requests,
                 progress,
                 |response: Vec<(RepoPathBuf, WireHistoryEntry)>| {
@@ -231,6 +261,8 @@ impl EdenApi for EdenApiCurlClient {
                 &mut multi,
                 &url,
                 self.creds.as_ref(),
+                &self.retry,
+                &self.breaker,
                 requests,
                 progress,
                 |response: Vec<HistoryResponse>| {
@@ -285,6 +317,8 @@ impl EdenApi for EdenApiCurlClient {
                 self.multi.clone(),
                 url,
                 creds,
+                self.retry.clone(),
+                self.breaker.clone(),
                 requests,
                 progress,
                 |entries| {
@@ -297,6 +331,8 @@ impl EdenApi for EdenApiCurlClient {
                 self.multi.clone(),
                 url,
                 creds,
+                self.retry.clone(),
+                self.breaker.clone(),
                 requests,
                 progress,
                 |multi_responses: Vec<DataResponse>| {
@@ -362,6 +398,8 @@ impl EdenApiCurlClient {
                 self.multi.clone(),
                 url,
                 self.creds.as_ref(),
+                self.retry.clone(),
+                self.breaker.clone(),
                 requests,
                 progress,
                 |entries: Vec<DataEntry>| {
@@ -376,6 +414,8 @@ impl EdenApiCurlClient {
                 self.multi.clone(),
                 url,
                 self.creds.as_ref(),
+                self.retry.clone(),
+                self.breaker.clone(),
                 requests,
                 progress,
                 |multi_responses: Vec<DataResponse>| {
@@ -412,10 +452,21 @@ impl EdenApiCurlClient {
 /// CBOR payload of each respective request. Assumes that the responses are
 /// CBOR encoded, and automatically deserializes them before passing
 /// them to the given callback.
+///
+/// Requests that fail with a retryable error (see `ApiError::is_retryable`)
+/// are sent again according to the retry policy. Only the failed requests
+/// are retried; the responses to the other requests have already been
+/// passed to the callback.
+///
+/// A round of requests with failures counts as a single failure for the
+/// circuit breaker, however many of them failed. The breaker is only checked
+/// before the first round, so that a round that opens it is still retried.
 fn multi_request<'a, R, I, T, F>(
     multi: &'a mut Multi,
     url: &Url,
     creds: Option<&ClientCreds>,
+    retry: &RetryPolicy,
+    breaker: &CircuitBreaker,
     requests: I,
     progress_cb: Option<ProgressFn>,
     mut response_cb: F,
@@ -430,19 +481,7 @@ where
     let num_requests = requests.len();
 
     let mut progress = ProgressReporter::with_capacity(num_requests);
-    let mut driver = MultiDriver::with_capacity(multi, num_requests);
-    driver.fail_early(true);
-
-    for request in requests {
-        let updater = progress.new_updater();
-        let handler = Collector::with_progress(url, updater);
-        let mut easy = new_easy_handle(creds, handler)?;
-        prepare_cbor_post(&mut easy, &url, &request)?;
-        driver.add(easy)?;
-    }
-
     progress.set_callback(progress_cb);
-    driver.set_progress_reporter(progress);
 
     let span = tracing::debug_span!(
         "curl::multi_request",
@@ -450,6 +489,7 @@ where
         downloaded = "",
         uploaded = "",
         requests = num_requests,
+        retries = "",
         latency = "",
     );
     let _guard = span.enter();
@@ -457,24 +497,77 @@ where
     log::debug!("Performing {} requests", num_requests);
     let start = Instant::now();
 
-    driver.perform(|res| {
-        let mut easy = res?;
-        let code = easy.response_code()?;
-        let data = easy.get_ref().data();
+    // Indexes of the requests that still need to be sent.
+    let mut pending = (0..num_requests).collect::<Vec<_>>();
+    let mut attempt = 0;
+    breaker.check()?;
+    loop {
+        let mut driver = MultiDriver::with_capacity(&mut *multi, pending.len());
+        driver.fail_early(true);
+
+        for index in &pending {
+            let updater = progress.new_updater();
+            let handler = Collector::with_progress(url, updater);
+            let mut easy = new_easy_handle(creds, handler)?;
+            prepare_cbor_post(&mut easy, &url, &requests[*index])?;
+            driver.add(easy)?;
+        }
 
-        if code >= 400 {
-            let msg = String::from_utf8_lossy(data).into_owned();
-            return Err(ApiError::from_http(code, msg));
+        driver.set_progress_reporter(progress);
+
+        let mut failed = Vec::new();
+        let result = driver.perform(|token, res| match parse_response(res) {
+            Ok(response) => {
+                breaker.record_success();
+                response_cb(response)
+            }
+            Err(e) if e.is_retryable() => {
+                log::debug!(
+                    "Request {} failed with retryable error: {}",
+                    pending[token],
+                    &e
+                );
+                failed.push((pending[token], e));
+                Ok(())
+            }
+            Err(e) => Err(e),
+        });
+
+        progress = driver.take_progress_reporter().unwrap();
+        result?;
+        drop(driver);
+
+        if failed.is_empty() {
+            break;
+        }
+        breaker.record_failure();
+        if attempt >= retry.max_retries {
+            let (_, last_error) = failed.pop().unwrap();
+            if attempt == 0 {
+                return Err(last_error);
+            }
+            return Err(ApiError::new(
+                ApiErrorKind::RetriesExhausted(attempt),
+                last_error,
+            ));
         }
+        attempt += 1;
 
-        let response = Deserializer::from_slice(data)
-            .into_iter()
-            .collect::<Result<Vec<T>, serde_cbor::error::Error>>()?;
-        response_cb(response)
-    })?;
+        let backoff = retry.backoff(attempt);
+        log::debug!(
+            "Retrying {} failed request(s) in {:?} (retry {}/{})",
+            failed.len(),
+            &backoff,
+            attempt,
+            retry.max_retries
+        );
+        progress.record_retries(failed.len());
+        thread::sleep(backoff);
+
+        pending = failed.into_iter().map(|(index, _)| index).collect();
+    }
 
     let elapsed = start.elapsed();
-    let progress = driver.progress().unwrap();
     let progstats = progress.stats();
     let latency = progress
         .first_response_time()
@@ -485,6 +578,7 @@ where
         downloaded: progstats.downloaded,
         uploaded: progstats.uploaded,
         requests: num_requests,
+        retries: progstats.retries,
         time: elapsed,
         latency,
     };
@@ -494,12 +588,32 @@ where
     if !span.is_disabled() {
         span.record("downloaded", &dlstats.downloaded);
         span.record("uploaded", &dlstats.uploaded);
+        span.record("retries", &dlstats.retries);
         span.record("latency_ms", &(dlstats.latency.as_millis() as u64));
     }
 
     Ok(dlstats)
 }
 
+/// Check the status of a finished transfer and deserialize its
+/// CBOR-encoded response.
+fn parse_response<T: DeserializeOwned>(
+    res: Result<Easy2<Collector>, curl::Error>,
+) -> ApiResult<Vec<T>> {
+    let mut easy = res?;
+    let code = easy.response_code()?;
+    let data = easy.get_ref().data();
+
+    if code >= 400 {
+        let msg = String::from_utf8_lossy(data).into_owned();
+        return Err(ApiError::from_http(code, msg));
+    }
+
+    Ok(Deserializer::from_slice(data)
+        .into_iter()
+        .collect::<Result<Vec<T>, serde_cbor::error::Error>>()?)
+}
+
 /// Same as `multi_request`, except the HTTP transfers will be handled by
 /// separate thread, while the user-provided response callback will be
 /// run on the main thread. This allows the callback to perform potentially
@@ -509,6 +623,8 @@ fn multi_request_threaded<R, I, T, F>(
     multi: SyncMulti,
     url: Url,
     creds: Option<&ClientCreds>,
+    retry: RetryPolicy,
+    breaker: CircuitBreaker,
     requests: I,
     progress_cb: Option<ProgressFn>,
     mut response_cb: F,
@@ -533,6 +649,8 @@ where
             &mut multi,
             &url,
             creds.as_ref(),
+            &retry,
+            &breaker,
             requests,
             progress_cb,
             |response: Vec<T>| {
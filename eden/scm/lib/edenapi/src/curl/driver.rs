@@ -45,8 +45,10 @@ impl<'a, H> MultiDriver<'a, H> {
         self.progress = Some(progress);
     }
 
-    pub fn progress(&self) -> Option<&ProgressReporter> {
-        self.progress.as_ref()
+    /// Take back the progress reporter, so that it can be reused for
+    /// transfers driven by another driver (such as retries).
+    pub fn take_progress_reporter(&mut self) -> Option<ProgressReporter> {
+        self.progress.take()
     }
 
     /// Add an Easy2 handle to the Multi stack.
@@ -74,10 +76,11 @@ impl<'a, H> MultiDriver<'a, H> {
     /// Drive all of the Easy2 handles in the Multi stack to completion.
     ///
     /// The caller-supplied callback will be called whenever a transfer
-    /// completes, successfully or otherwise.
+    /// completes, successfully or otherwise, with the index of the transfer
+    /// (in the order in which they were added) and its result.
     pub fn perform<F>(&mut self, mut callback: F) -> ApiResult<()>
     where
-        F: FnMut(Result<Easy2<H>, curl::Error>) -> ApiResult<()>,
+        F: FnMut(usize, Result<Easy2<H>, curl::Error>) -> ApiResult<()>,
     {
         let mut in_progress = self.num_transfers;
         let mut i = 0;
@@ -107,7 +110,7 @@ impl<'a, H> MultiDriver<'a, H> {
                         log::trace!("Transfer {} complete", token);
                         match self.take_handle(token) {
                             Ok(Some(handle)) => {
-                                if let Err(e) = callback(Ok(handle)) {
+                                if let Err(e) = callback(token, Ok(handle)) {
                                     errors.push(e);
                                 }
                             }
@@ -121,7 +124,7 @@ impl<'a, H> MultiDriver<'a, H> {
                     }
                     Some(Err(e)) => {
                         log::trace!("Transfer {} failed: {}", token, &e);
-                        if let Err(e) = callback(Err(e)) {
+                        if let Err(e) = callback(token, Err(e)) {
                             errors.push(e);
                         }
                     }
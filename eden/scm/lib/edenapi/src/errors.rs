@@ -36,7 +36,25 @@ impl ApiError {
         &self.kind
     }
 
-    pub(crate) fn from_http(code: u32, msg: impl ToString) -> Self {
+    /// Whether a request that failed with this error might succeed if it is
+    /// sent again, such as after a server error or a dropped connection.
+    pub fn is_retryable(&self) -> bool {
+        match &self.kind {
+            ApiErrorKind::Http { code, .. } => {
+                code.is_server_error() || *code == StatusCode::TOO_MANY_REQUESTS
+            }
+            ApiErrorKind::Proxy(code) => code.is_server_error(),
+            ApiErrorKind::Curl => self
+                .cause
+                .as_ref()
+                .and_then(|e| e.downcast_ref::<curl::Error>())
+                .map_or(false, is_transient_curl_error),
+            _ => false,
+        }
+    }
+
+    /// Build an error from an HTTP status code and the response body.
+    pub fn from_http(code: u32, msg: impl ToString) -> Self {
         let code = match code.try_into() {
             Ok(code) => match StatusCode::from_u16(code) {
                 Ok(code) => code,
@@ -137,6 +155,8 @@ pub enum ApiErrorKind {
     BadConfig(String),
     #[error("The server returned an unexpected or invalid response")]
     BadResponse,
+    #[error("Too many server errors; not sending requests (failed {0} times in a row)")]
+    CircuitOpen(usize),
     #[error("libcurl returned an error")]
     Curl,
     #[error("Received HTTP status '{code}' with response: {msg:?}")]
@@ -145,6 +165,8 @@ pub enum ApiErrorKind {
     Proxy(StatusCode),
     #[error("Error during serialization/deserialization")]
     Serialization,
+    #[error("Request still failed after {0} retries")]
+    RetriesExhausted(usize),
     #[error("Failed to write data to the store")]
     Store,
     #[error("A TLS error occurred")]
@@ -183,6 +205,17 @@ impl From<&str> for ApiError {
     }
 }
 
+fn is_transient_curl_error(error: &curl::Error) -> bool {
+    error.is_couldnt_connect()
+        || error.is_operation_timedout()
+        || error.is_send_error()
+        || error.is_recv_error()
+        || error.is_partial_file()
+        || error.is_got_nothing()
+        || error.is_http2_error()
+        || error.is_http2_stream_error()
+}
+
 impl From<curl::Error> for ApiError {
     fn from(error: curl::Error) -> Self {
         if error.is_ssl_connect_error() {
@@ -10,6 +10,7 @@ mod config;
 mod curl;
 mod errors;
 mod progress;
+mod retry;
 mod stats;
 
 pub use crate::api::EdenApi;
@@ -55,6 +55,11 @@ impl ProgressReporter {
         self.inner.borrow().first_response.clone()
     }
 
+    /// Record that `count` requests are being sent again after failing.
+    pub fn record_retries(&self, count: usize) {
+        self.inner.borrow_mut().retries += count;
+    }
+
     pub fn report(&mut self) {
         let stats = self.stats();
         if let Some(ref mut callback) = self.callback {
@@ -66,6 +71,7 @@ impl ProgressReporter {
 struct ProgressInner {
     stats: Vec<ProgressStats>,
     first_response: Option<Instant>,
+    retries: usize,
 }
 
 impl ProgressInner {
@@ -73,6 +79,7 @@ impl ProgressInner {
         Self {
             stats: Vec::with_capacity(capacity),
             first_response: None,
+            retries: 0,
         }
     }
 
@@ -90,6 +97,8 @@ impl ProgressInner {
     }
 
     fn stats(&self) -> ProgressStats {
-        self.stats.iter().cloned().sum()
+        let mut stats: ProgressStats = self.stats.iter().cloned().sum();
+        stats.retries = self.retries;
+        stats
     }
 }
@@ -17,6 +17,8 @@ pub struct ProgressStats {
     pub uploaded: usize,
     pub dltotal: usize,
     pub ultotal: usize,
+    /// Number of requests that were sent again after failing.
+    pub retries: usize,
 }
 
 impl ProgressStats {
@@ -26,6 +28,7 @@ impl ProgressStats {
             uploaded,
             dltotal,
             ultotal,
+            retries: 0,
         }
     }
 
@@ -53,6 +56,7 @@ impl Add for ProgressStats {
             uploaded: self.uploaded + other.uploaded,
             dltotal: self.dltotal + other.dltotal,
             ultotal: self.ultotal + other.ultotal,
+            retries: self.retries + other.retries,
         }
     }
 }
@@ -72,6 +76,7 @@ impl Sub for ProgressStats {
             uploaded: self.uploaded - other.uploaded,
             dltotal: self.dltotal - other.dltotal,
             ultotal: self.ultotal - other.ultotal,
+            retries: self.retries - other.retries,
         }
     }
 }
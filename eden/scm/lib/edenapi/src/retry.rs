@@ -0,0 +1,167 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::{
+    cmp,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use parking_lot::Mutex;
+use rand::{thread_rng, Rng};
+
+use crate::errors::{ApiErrorKind, ApiResult};
+
+pub(crate) const DEFAULT_MAX_RETRIES: usize = 3;
+pub(crate) const DEFAULT_RETRY_BACKOFF: Duration = Duration::from_millis(100);
+pub(crate) const DEFAULT_MAX_RETRY_BACKOFF: Duration = Duration::from_secs(10);
+pub(crate) const DEFAULT_CIRCUIT_BREAKER_THRESHOLD: usize = 10;
+pub(crate) const DEFAULT_CIRCUIT_BREAKER_COOLDOWN: Duration = Duration::from_secs(30);
+
+/// How failed requests are retried.
+#[derive(Clone, Debug)]
+pub(crate) struct RetryPolicy {
+    pub(crate) max_retries: usize,
+    pub(crate) backoff: Duration,
+    pub(crate) max_backoff: Duration,
+}
+
+impl RetryPolicy {
+    /// Time to wait before the given retry (starting from 1). The delay
+    /// doubles with each retry up to `max_backoff`, and a random delay up to
+    /// that bound is chosen so that clients that failed at the same time
+    /// don't all retry at the same time.
+    pub(crate) fn backoff(&self, retry: usize) -> Duration {
+        let exponent = cmp::min(retry.saturating_sub(1), 16) as u32;
+        let backoff = self
+            .backoff
+            .checked_mul(2u32.pow(exponent))
+            .unwrap_or(self.max_backoff);
+        let bound = cmp::min(backoff, self.max_backoff);
+        if bound == Duration::from_secs(0) {
+            return bound;
+        }
+        Duration::from_secs_f64(thread_rng().gen_range(0.0, bound.as_secs_f64()))
+    }
+}
+
+/// Fails requests early once the server has returned a number of errors in
+/// a row, rather than sending it more requests that are likely to fail too.
+///
+/// After `cooldown` has passed, requests are let through again. The breaker
+/// closes as soon as one of them succeeds, and opens again if they fail.
+#[derive(Clone)]
+pub(crate) struct CircuitBreaker {
+    threshold: Option<usize>,
+    cooldown: Duration,
+    state: Arc<Mutex<BreakerState>>,
+}
+
+#[derive(Default)]
+struct BreakerState {
+    consecutive_failures: usize,
+    opened_at: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    /// Create a circuit breaker that opens after `threshold` consecutive
+    /// failures. Setting the threshold to `None` disables the breaker.
+    pub(crate) fn new(threshold: Option<usize>, cooldown: Duration) -> Self {
+        Self {
+            threshold,
+            cooldown,
+            state: Default::default(),
+        }
+    }
+
+    /// Return an error if the breaker is open.
+    pub(crate) fn check(&self) -> ApiResult<()> {
+        let state = self.state.lock();
+        match state.opened_at {
+            Some(opened_at) if opened_at.elapsed() < self.cooldown => {
+                Err(ApiErrorKind::CircuitOpen(state.consecutive_failures).into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn record_success(&self) {
+        let mut state = self.state.lock();
+        state.consecutive_failures = 0;
+        state.opened_at = None;
+    }
+
+    pub(crate) fn record_failure(&self) {
+        let threshold = match self.threshold {
+            Some(threshold) => threshold,
+            None => return,
+        };
+        let mut state = self.state.lock();
+        state.consecutive_failures += 1;
+        if state.consecutive_failures >= threshold {
+            if state.opened_at.is_none() {
+                log::warn!(
+                    "EdenAPI circuit breaker opened after {} consecutive failures",
+                    state.consecutive_failures
+                );
+            }
+            state.opened_at = Some(Instant::now());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy {
+            max_retries: 5,
+            backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(300),
+        };
+        for _ in 0..100 {
+            assert!(policy.backoff(1) < Duration::from_millis(100));
+            assert!(policy.backoff(2) < Duration::from_millis(200));
+            assert!(policy.backoff(10) < Duration::from_millis(300));
+        }
+
+        let policy = RetryPolicy {
+            max_retries: 20,
+            backoff: Duration::from_secs(u64::MAX / 4),
+            max_backoff: Duration::from_millis(300),
+        };
+        assert!(policy.backoff(20) < Duration::from_millis(300));
+    }
+
+    #[test]
+    fn test_circuit_breaker() {
+        let breaker = CircuitBreaker::new(Some(2), Duration::from_secs(3600));
+        breaker.record_failure();
+        assert!(breaker.check().is_ok());
+        breaker.record_failure();
+        match breaker.check() {
+            Err(e) => assert!(matches!(e.kind(), ApiErrorKind::CircuitOpen(2))),
+            Ok(()) => panic!("circuit breaker should be open"),
+        }
+
+        let breaker = CircuitBreaker::new(Some(2), Duration::from_secs(0));
+        breaker.record_failure();
+        breaker.record_failure();
+        assert!(breaker.check().is_ok());
+        breaker.record_success();
+        breaker.record_failure();
+        assert!(breaker.check().is_ok());
+
+        let breaker = CircuitBreaker::new(None, Duration::from_secs(3600));
+        for _ in 0..100 {
+            breaker.record_failure();
+        }
+        assert!(breaker.check().is_ok());
+    }
+}
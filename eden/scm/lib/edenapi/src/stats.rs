@@ -12,6 +12,7 @@ pub struct DownloadStats {
     pub downloaded: usize,
     pub uploaded: usize,
     pub requests: usize,
+    pub retries: usize,
     pub time: Duration,
     pub latency: Duration,
 }
@@ -37,7 +38,16 @@ impl fmt::Display for DownloadStats {
             if self.requests == 1 { "" } else { "s" },
             self.bytes_per_second() / 1_000_000.0,
             fmt_duration(self.latency)
-        )
+        )?;
+        if self.retries > 0 {
+            write!(
+                f,
+                " after {} retr{}",
+                self.retries,
+                if self.retries == 1 { "y" } else { "ies" }
+            )?;
+        }
+        Ok(())
     }
 }
 
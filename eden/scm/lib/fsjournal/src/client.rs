@@ -0,0 +1,76 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use types::RepoPathBuf;
+
+/// A query, sent as a line of JSON.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Request {
+    pub since: Option<String>,
+}
+
+/// The answer to a query, sent as a line of JSON.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Response {
+    pub clock: Option<String>,
+    pub paths: Option<Vec<String>>,
+}
+
+/// What changed in a working copy since a previous query.
+pub struct JournalChanges {
+    /// Clock to pass to the next query. `None` if the journal is not recording changes.
+    pub clock: Option<String>,
+    /// Paths that changed since the clock passed to the query, or `None` if the journal can't
+    /// tell, and every path has to be checked.
+    pub paths: Option<Vec<RepoPathBuf>>,
+}
+
+/// Ask the journal of the working copy at `root` what changed since `since`, the clock returned
+/// by a previous query.
+#[cfg(unix)]
+pub fn query(root: &Path, since: Option<&str>, timeout: Duration) -> Result<JournalChanges> {
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::net::UnixStream;
+
+    let stream = UnixStream::connect(root.join(".hg").join(crate::SOCKET_NAME))?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    let request = Request {
+        since: since.map(|since| since.to_string()),
+    };
+    serde_json::to_writer(&stream, &request)?;
+    (&stream).write_all(b"\n")?;
+
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+    let response: Response = serde_json::from_str(&line)?;
+    let paths = response
+        .paths
+        .map(|paths| {
+            paths
+                .into_iter()
+                .map(RepoPathBuf::from_string)
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .transpose()?;
+    Ok(JournalChanges {
+        clock: response.clock,
+        paths,
+    })
+}
+
+#[cfg(not(unix))]
+pub fn query(_root: &Path, _since: Option<&str>, _timeout: Duration) -> Result<JournalChanges> {
+    anyhow::bail!("the file change journal is not supported on this platform")
+}
@@ -0,0 +1,29 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use serde::Deserialize;
+use std::path::PathBuf;
+
+mod defaults {
+    pub fn capacity() -> usize {
+        100_000
+    }
+}
+
+/// Struct for decoding the file change journal configuration from TOML.
+/// Each field has default implementation, meaning that it doesn't have to be present in TOML.
+#[derive(Debug, Deserialize)]
+pub struct FsJournalConfig {
+    /// Roots of the working copies to watch
+    #[serde(default)]
+    pub repos: Vec<PathBuf>,
+
+    /// Number of changes a journal holds before it overflows, after which the next status
+    /// of that working copy falls back to a full walk
+    #[serde(default = "defaults::capacity")]
+    pub capacity: usize,
+}
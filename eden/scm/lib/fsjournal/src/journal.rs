@@ -0,0 +1,208 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::collections::{BTreeSet, VecDeque};
+use std::fmt;
+use std::process;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use thiserror::Error;
+
+use types::RepoPathBuf;
+
+#[derive(Debug, Error)]
+#[error("invalid journal clock '{0}'")]
+pub struct InvalidClock(String);
+
+/// Position in a `Journal`.
+///
+/// Every journal gets a new instance id, so that clocks handed out by a journal that was reset
+/// or belongs to a previous daemon are never mistaken for clocks of the current one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Clock {
+    instance: u64,
+    sequence: u64,
+}
+
+impl fmt::Display for Clock {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "fsj:{:x}:{}", self.instance, self.sequence)
+    }
+}
+
+impl FromStr for Clock {
+    type Err = InvalidClock;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidClock(s.to_string());
+        let mut parts = s.splitn(3, ':');
+        if parts.next() != Some("fsj") {
+            return Err(invalid());
+        }
+        let instance = parts
+            .next()
+            .and_then(|instance| u64::from_str_radix(instance, 16).ok())
+            .ok_or_else(invalid)?;
+        let sequence = parts
+            .next()
+            .and_then(|sequence| sequence.parse().ok())
+            .ok_or_else(invalid)?;
+        Ok(Clock { instance, sequence })
+    }
+}
+
+/// Answer to a `Journal::changes_since` query.
+#[derive(Debug, PartialEq)]
+pub enum Changes {
+    /// Paths that changed since the clock, sorted. Directories that were removed or renamed are
+    /// included as a whole, the files that were in them are not listed.
+    Paths(Vec<RepoPathBuf>),
+    /// The journal doesn't cover the clock. Everything has to be checked.
+    Fresh,
+}
+
+/// In-memory list of the paths changed in a working copy, in the order they changed.
+///
+/// The journal keeps at most `capacity` entries. Recording more than that, or losing events
+/// through `reset`, starts a new instance, which answers `Changes::Fresh` to every clock handed
+/// out before.
+pub struct Journal {
+    instance: u64,
+    // Sequence number of the first item in `entries`.
+    start: u64,
+    entries: VecDeque<RepoPathBuf>,
+    capacity: usize,
+}
+
+impl Journal {
+    pub fn new(capacity: usize) -> Self {
+        Journal {
+            instance: new_instance(0),
+            start: 0,
+            entries: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    /// Clock of the current end of the journal.
+    pub fn clock(&self) -> Clock {
+        Clock {
+            instance: self.instance,
+            sequence: self.start + self.entries.len() as u64,
+        }
+    }
+
+    pub fn record(&mut self, path: RepoPathBuf) {
+        if self.entries.len() >= self.capacity {
+            self.reset();
+        }
+        self.entries.push_back(path);
+    }
+
+    /// Forget every recorded path, for example because events were lost. Every clock handed out
+    /// so far becomes stale.
+    pub fn reset(&mut self) {
+        self.instance = new_instance(self.instance);
+        self.start = 0;
+        self.entries.clear();
+    }
+
+    pub fn changes_since(&self, clock: &Clock) -> Changes {
+        if clock.instance != self.instance
+            || clock.sequence < self.start
+            || clock.sequence > self.clock().sequence
+        {
+            return Changes::Fresh;
+        }
+        let skip = (clock.sequence - self.start) as usize;
+        let paths: BTreeSet<&RepoPathBuf> = self.entries.iter().skip(skip).collect();
+        Changes::Paths(paths.into_iter().cloned().collect())
+    }
+}
+
+/// Pick an instance id that is unlikely to have been used by this or another daemon.
+fn new_instance(previous: u64) -> u64 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_nanos() as u64)
+        .unwrap_or(0);
+    let instance = nanos ^ (u64::from(process::id()) << 32);
+    if instance == previous {
+        instance.wrapping_add(1)
+    } else {
+        instance
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use types::testutil::repo_path_buf;
+
+    #[test]
+    fn test_changes_since() {
+        let mut journal = Journal::new(10);
+        let start = journal.clock();
+        journal.record(repo_path_buf("b"));
+        journal.record(repo_path_buf("a"));
+        let middle = journal.clock();
+        journal.record(repo_path_buf("b"));
+        journal.record(repo_path_buf("c/d"));
+
+        assert_eq!(
+            journal.changes_since(&start),
+            Changes::Paths(vec![
+                repo_path_buf("a"),
+                repo_path_buf("b"),
+                repo_path_buf("c/d")
+            ])
+        );
+        assert_eq!(
+            journal.changes_since(&middle),
+            Changes::Paths(vec![repo_path_buf("b"), repo_path_buf("c/d")])
+        );
+        assert_eq!(
+            journal.changes_since(&journal.clock()),
+            Changes::Paths(vec![])
+        );
+    }
+
+    #[test]
+    fn test_stale_clocks() {
+        let mut journal = Journal::new(2);
+        let start = journal.clock();
+        journal.record(repo_path_buf("a"));
+        journal.record(repo_path_buf("b"));
+        let full = journal.clock();
+        assert_eq!(
+            journal.changes_since(&start),
+            Changes::Paths(vec![repo_path_buf("a"), repo_path_buf("b")])
+        );
+
+        // Overflowing the journal invalidates every clock.
+        journal.record(repo_path_buf("c"));
+        assert_eq!(journal.changes_since(&start), Changes::Fresh);
+        assert_eq!(journal.changes_since(&full), Changes::Fresh);
+
+        let clock = journal.clock();
+        journal.reset();
+        assert_eq!(journal.changes_since(&clock), Changes::Fresh);
+    }
+
+    #[test]
+    fn test_clock_roundtrip() {
+        let mut journal = Journal::new(10);
+        journal.record(repo_path_buf("a"));
+        let clock = journal.clock();
+        assert_eq!(clock.to_string().parse::<Clock>().unwrap(), clock);
+        assert!("c:1:2".parse::<Clock>().is_err());
+        assert!("fsj:xyz:2".parse::<Clock>().is_err());
+        assert!("fsj:12".parse::<Clock>().is_err());
+    }
+}
@@ -0,0 +1,34 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! A journal of working copy changes, fed by a file system watcher.
+//!
+//! The journal runs in a long lived process (`scm_daemon`), and records every path that changes
+//! in the working copy. A status query passes the clock it got from its previous query and gets
+//! back the paths that changed since, so it doesn't need to walk the whole working copy. When the
+//! journal can't answer (it overflowed, or the clock is from a previous daemon), the query asks
+//! for a full walk instead.
+
+pub mod client;
+pub mod config;
+pub mod journal;
+#[cfg(target_os = "linux")]
+pub mod service;
+#[cfg(target_os = "linux")]
+mod watcher;
+
+pub use client::{query, JournalChanges};
+pub use config::FsJournalConfig;
+pub use journal::{Changes, Clock, Journal};
+#[cfg(target_os = "linux")]
+pub use service::JournalService;
+
+/// Name of the socket, in the repo's `.hg` directory, the journal is served on.
+pub const SOCKET_NAME: &str = "fsjournal.sock";
+
+/// Treestate metadata key under which the clock of the last status is stored.
+pub const CLOCK_METADATA_KEY: &str = "journalclock";
@@ -0,0 +1,168 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::Result;
+use log::{error, info, warn};
+use parking_lot::Mutex;
+
+use crate::client::{Request, Response};
+use crate::journal::{Changes, Clock, Journal};
+use crate::watcher::{Event, Watcher};
+use crate::SOCKET_NAME;
+
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Records the changes of a working copy, and answers queries about them on a unix socket in
+/// the repo's `.hg` directory.
+pub struct JournalService {
+    root: PathBuf,
+    capacity: usize,
+}
+
+impl JournalService {
+    pub fn new(root: PathBuf, capacity: usize) -> Self {
+        JournalService { root, capacity }
+    }
+
+    /// Start watching the working copy and serving queries.
+    ///
+    /// If the watcher fails later on, for example because the user ran out of inotify watches,
+    /// the service keeps answering queries, asking for full walks.
+    pub fn serve(self) -> Result<thread::JoinHandle<Result<()>>> {
+        // `None` once the watcher stopped.
+        let journal = Arc::new(Mutex::new(Some(Journal::new(self.capacity))));
+        let watcher = Watcher::new(self.root.clone())?;
+
+        let socket_path = self.root.join(".hg").join(SOCKET_NAME);
+        // Left behind by a previous daemon.
+        let _ = fs::remove_file(&socket_path);
+        let listener = UnixListener::bind(&socket_path)?;
+        info!("Recording changes of {}", self.root.display());
+
+        thread::spawn({
+            let journal = journal.clone();
+            let root = self.root.clone();
+            move || watch(watcher, &journal, &root)
+        });
+        Ok(thread::spawn(move || {
+            for stream in listener.incoming() {
+                let result = stream
+                    .map_err(|e| e.into())
+                    .and_then(|stream| respond(stream, &journal));
+                if let Err(e) = result {
+                    warn!("Failed to answer a journal query: {}", e);
+                }
+            }
+            Ok(())
+        }))
+    }
+}
+
+fn watch(mut watcher: Watcher, journal: &Mutex<Option<Journal>>, root: &Path) {
+    loop {
+        match watcher.read_events() {
+            Ok(events) => {
+                let mut journal = journal.lock();
+                if let Some(journal) = journal.as_mut() {
+                    for event in events {
+                        match event {
+                            Event::Changed(path) => journal.record(path),
+                            Event::Overflow => journal.reset(),
+                        }
+                    }
+                }
+            }
+            Err(e) => {
+                error!("Stopped recording changes of {}: {}", root.display(), e);
+                *journal.lock() = None;
+                return;
+            }
+        }
+    }
+}
+
+fn respond(stream: UnixStream, journal: &Mutex<Option<Journal>>) -> Result<()> {
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+    let request: Request = serde_json::from_str(&line)?;
+
+    let since = request.since.and_then(|since| since.parse::<Clock>().ok());
+    let response = match journal.lock().as_ref() {
+        Some(journal) => Response {
+            clock: Some(journal.clock().to_string()),
+            paths: match since.map(|since| journal.changes_since(&since)) {
+                Some(Changes::Paths(paths)) => {
+                    Some(paths.into_iter().map(|path| path.into_string()).collect())
+                }
+                Some(Changes::Fresh) | None => None,
+            },
+        },
+        None => Response {
+            clock: None,
+            paths: None,
+        },
+    };
+
+    serde_json::to_writer(&stream, &response)?;
+    (&stream).write_all(b"\n")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs::{create_dir_all, File};
+
+    use tempfile::tempdir;
+
+    use crate::client::query;
+
+    #[test]
+    fn test_query() -> Result<()> {
+        let dir = tempdir()?;
+        create_dir_all(dir.path().join(".hg"))?;
+        let root = dir.path().to_path_buf();
+        JournalService::new(root.clone(), 100).serve()?;
+
+        let first = query(&root, None, CLIENT_TIMEOUT)?;
+        assert!(first.paths.is_none());
+        let clock = first.clock.expect("clock");
+
+        File::create(root.join("a"))?;
+        let mut paths = Vec::new();
+        for _ in 0..100 {
+            let changes = query(&root, Some(&clock), CLIENT_TIMEOUT)?;
+            paths = changes.paths.expect("paths");
+            if !paths.is_empty() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(
+            paths
+                .iter()
+                .map(|path| path.to_string())
+                .collect::<Vec<_>>(),
+            vec!["a"]
+        );
+
+        let changes = query(&root, Some("fsj:0:0"), CLIENT_TIMEOUT)?;
+        assert!(changes.paths.is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,287 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Recursive inotify watch of a working copy.
+
+use std::collections::HashMap;
+use std::ffi::{CString, OsStr};
+use std::fs;
+use std::io;
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::RawFd;
+use std::path::PathBuf;
+use std::ptr;
+
+use anyhow::Result;
+
+use types::{RepoPath, RepoPathBuf};
+
+const WATCH_MASK: u32 = libc::IN_ATTRIB
+    | libc::IN_CLOSE_WRITE
+    | libc::IN_CREATE
+    | libc::IN_DELETE
+    | libc::IN_MODIFY
+    | libc::IN_MOVED_FROM
+    | libc::IN_MOVED_TO
+    | libc::IN_DONT_FOLLOW
+    | libc::IN_EXCL_UNLINK
+    | libc::IN_ONLYDIR;
+
+const BUFFER_SIZE: usize = 64 * 1024;
+
+pub enum Event {
+    /// A file or directory was modified, created or removed.
+    Changed(RepoPathBuf),
+    /// Changes were lost, either by the kernel or because a path could not be represented.
+    Overflow,
+}
+
+pub struct Watcher {
+    fd: RawFd,
+    root: PathBuf,
+    watches: HashMap<i32, RepoPathBuf>,
+    buffer: Vec<u8>,
+}
+
+impl Watcher {
+    /// Start watching every directory of the working copy at `root`.
+    pub fn new(root: PathBuf) -> Result<Self> {
+        let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        let mut watcher = Watcher {
+            fd,
+            root,
+            watches: HashMap::new(),
+            buffer: vec![0; BUFFER_SIZE],
+        };
+        watcher.watch_tree(RepoPathBuf::new(), &mut Vec::new())?;
+        Ok(watcher)
+    }
+
+    /// Block until something changes, and return the changes.
+    pub fn read_events(&mut self) -> Result<Vec<Event>> {
+        let len = loop {
+            let len = unsafe {
+                libc::read(
+                    self.fd,
+                    self.buffer.as_mut_ptr() as *mut libc::c_void,
+                    self.buffer.len(),
+                )
+            };
+            if len >= 0 {
+                break len as usize;
+            }
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::Interrupted {
+                return Err(err.into());
+            }
+        };
+
+        // Parse the whole buffer first, handling events changes the watches.
+        let header_len = mem::size_of::<libc::inotify_event>();
+        let mut raw_events = Vec::new();
+        let mut offset = 0;
+        while offset + header_len <= len {
+            let event: libc::inotify_event = unsafe {
+                ptr::read_unaligned(self.buffer[offset..].as_ptr() as *const libc::inotify_event)
+            };
+            let name_start = offset + header_len;
+            let name_end = name_start + event.len as usize;
+            // The name is padded with NUL bytes.
+            let name = &self.buffer[name_start..name_end];
+            let name = &name[..name.iter().position(|b| *b == 0).unwrap_or(name.len())];
+            raw_events.push((event.wd, event.mask, name.to_vec()));
+            offset = name_end;
+        }
+
+        let mut events = Vec::new();
+        for (wd, mask, name) in raw_events {
+            self.handle_event(wd, mask, OsStr::from_bytes(&name), &mut events)?;
+        }
+        Ok(events)
+    }
+
+    fn handle_event(
+        &mut self,
+        wd: i32,
+        mask: u32,
+        name: &OsStr,
+        events: &mut Vec<Event>,
+    ) -> Result<()> {
+        if mask & libc::IN_Q_OVERFLOW != 0 {
+            events.push(Event::Overflow);
+            return Ok(());
+        }
+        if mask & libc::IN_IGNORED != 0 {
+            self.watches.remove(&wd);
+            return Ok(());
+        }
+        // Events about a watched directory itself are reported by the watch of its parent.
+        if name.is_empty() || name == ".hg" {
+            return Ok(());
+        }
+        // Events of a watch that was removed can still be queued.
+        let dir = match self.watches.get(&wd) {
+            Some(dir) => dir.clone(),
+            None => return Ok(()),
+        };
+        let path = match join(&dir, name) {
+            Some(path) => path,
+            None => {
+                events.push(Event::Overflow);
+                return Ok(());
+            }
+        };
+
+        if mask & libc::IN_ISDIR != 0 {
+            if mask & libc::IN_MOVED_FROM != 0 {
+                self.unwatch_tree(&path);
+            }
+            if mask & (libc::IN_CREATE | libc::IN_MOVED_TO) != 0 {
+                // Files could have been added before the directory was watched, so report
+                // everything it contains.
+                self.watch_tree(path.clone(), events)?;
+            }
+        }
+        events.push(Event::Changed(path));
+        Ok(())
+    }
+
+    /// Watch `dir` and every directory below it, reporting everything found in them.
+    fn watch_tree(&mut self, dir: RepoPathBuf, events: &mut Vec<Event>) -> Result<()> {
+        let mut dirs = vec![dir];
+        while let Some(dir) = dirs.pop() {
+            let abs_dir = self.root.join(dir.as_str());
+            // Nested repositories are not part of the working copy.
+            if !dir.is_empty() && abs_dir.join(".hg").exists() {
+                continue;
+            }
+
+            let c_path = CString::new(abs_dir.as_os_str().as_bytes())?;
+            let wd = unsafe { libc::inotify_add_watch(self.fd, c_path.as_ptr(), WATCH_MASK) };
+            if wd < 0 {
+                let err = io::Error::last_os_error();
+                match err.raw_os_error() {
+                    // The directory is already gone, its parent reports that.
+                    Some(libc::ENOENT) | Some(libc::ENOTDIR) => continue,
+                    // Most notably ENOSPC, when the user is out of watches.
+                    _ => return Err(err.into()),
+                }
+            }
+            self.watches.insert(wd, dir.clone());
+
+            let entries = match fs::read_dir(&abs_dir) {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            for entry in entries {
+                let entry = entry?;
+                let name = entry.file_name();
+                if name == ".hg" {
+                    continue;
+                }
+                let path = match join(&dir, &name) {
+                    Some(path) => path,
+                    None => {
+                        events.push(Event::Overflow);
+                        continue;
+                    }
+                };
+                match entry.file_type() {
+                    Ok(file_type) if file_type.is_dir() => dirs.push(path.clone()),
+                    Ok(_) => {}
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                    Err(e) => return Err(e.into()),
+                }
+                events.push(Event::Changed(path));
+            }
+        }
+        Ok(())
+    }
+
+    /// Stop watching `dir` and every directory below it.
+    fn unwatch_tree(&mut self, dir: &RepoPath) {
+        let wds: Vec<i32> = self
+            .watches
+            .iter()
+            .filter(|(_, path)| {
+                path.as_repo_path() == dir || path.parents().any(|parent| parent == dir)
+            })
+            .map(|(wd, _)| *wd)
+            .collect();
+        for wd in wds {
+            unsafe { libc::inotify_rm_watch(self.fd, wd) };
+            self.watches.remove(&wd);
+        }
+    }
+}
+
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+fn join(dir: &RepoPathBuf, name: &OsStr) -> Option<RepoPathBuf> {
+    let name = RepoPath::from_str(name.to_str()?).ok()?;
+    let mut path = dir.clone();
+    path.push(name);
+    Some(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::BTreeSet;
+    use std::fs::{create_dir_all, rename, File};
+
+    use tempfile::tempdir;
+
+    fn changed(watcher: &mut Watcher) -> BTreeSet<String> {
+        watcher
+            .read_events()
+            .unwrap()
+            .into_iter()
+            .filter_map(|event| match event {
+                Event::Changed(path) => Some(path.to_string()),
+                Event::Overflow => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_watch_new_directories() {
+        let root = tempdir().unwrap();
+        create_dir_all(root.path().join(".hg")).unwrap();
+        create_dir_all(root.path().join("a")).unwrap();
+        let mut watcher = Watcher::new(root.path().to_path_buf()).unwrap();
+
+        File::create(root.path().join("a/b")).unwrap();
+        assert!(changed(&mut watcher).contains("a/b"));
+
+        create_dir_all(root.path().join("c/d")).unwrap();
+        let mut seen = BTreeSet::new();
+        while !seen.contains("c/d") {
+            seen.extend(changed(&mut watcher));
+        }
+        File::create(root.path().join("c/d/e")).unwrap();
+        assert!(changed(&mut watcher).contains("c/d/e"));
+
+        rename(root.path().join("c"), root.path().join("f")).unwrap();
+        let mut seen = BTreeSet::new();
+        while !seen.contains("f/d") {
+            seen.extend(changed(&mut watcher));
+        }
+        assert!(seen.contains("c"));
+        assert!(seen.contains("f/d/e"));
+    }
+}
@@ -14,15 +14,18 @@ use clidispatch::{
 };
 use cliparser::define_flags;
 
-use blackbox::{event::Event, json, SessionId};
+use blackbox::{event::Event, json, stats::GroupBy, SessionId};
 use dynamicconfig::Generator;
 use edenapi::{Config as EdenApiConfig, EdenApi, EdenApiCurlClient};
 use revisionstore::{
     CorruptionPolicy, DataPackStore, HgIdDataStore, IndexedLogHgIdDataStore, UnionHgIdDataStore,
 };
+#[cfg(unix)]
+use revisionstore::{SharedCacheClient, SharedCacheServer};
 use std::{fs, path::Path, str::FromStr};
 use types::{HgId, Key, RepoPathBuf};
 
+use crate::completion;
 use crate::status;
 
 #[allow(dead_code)]
@@ -41,6 +44,18 @@ pub fn table() -> CommandTable {
     status::register(&mut table);
 
     table.register(dump_trace, "dump-trace", "export tracing information");
+    table.register(
+        blackbox_stats,
+        "blackbox-stats",
+        r#"summarize commands logged in blackbox
+
+    Show session counts, error rates and p50/p90/p99 durations of commands
+    started within the time range, grouped by command name (default), exit
+    code (``-g exit-code``) or a config value (``-g config:SECTION.NAME``).
+    The slowest sessions are listed after the summary.
+
+    Use ``-T json`` for JSON output."#,
+    );
 
     table.register(
         debugstore,
@@ -69,6 +84,19 @@ pub fn table() -> CommandTable {
         "debugdynamicconfig",
         "generate the dynamic configuration",
     );
+    #[cfg(unix)]
+    table.register(
+        debugsharedcache,
+        "debugsharedcache",
+        r#"run or query the local shared cache daemon
+
+    Print hit and miss counts of the shared cache daemon listening on
+    ``sharedcache.socket``.
+
+    With --serve, run the daemon in the foreground instead. It stores data in
+    ``sharedcache.path``."#,
+    );
+    completion::register(&mut table);
 
     table
 }
@@ -104,9 +132,28 @@ define_flags! {
         #[short('s')]
         session_id: i64,
 
-        /// output path (.txt, .json, .json.gz, .spans.json)
+        /// output path (.txt, .json, .json.gz, .spans.json, .folded, .otlp.json)
         #[short('o')]
         output_path: String,
+
+        /// output format (ascii, trace-event, trace-event-gz, spans, folded, otlp)
+        #[short('f')]
+        format: String,
+    }
+
+    pub struct BlackboxStatsOpts {
+        /// time range
+        #[short('t')]
+        time_range: String = "since 7 days ago",
+
+        /// group by (command, exit-code, or config:SECTION.NAME)
+        #[short('g')]
+        group_by: String = "command",
+
+        /// number of slowest sessions to show
+        top: i64 = 5,
+
+        formatter_opts: FormatterOpts,
     }
 
     pub struct DebugstoreOpts {
@@ -135,6 +182,11 @@ define_flags! {
 
     pub struct DebugHttpOpts {}
 
+    pub struct DebugSharedCacheOpts {
+        /// run the shared cache daemon
+        serve: bool,
+    }
+
     pub struct DebugDynamicConfigOpts {
     }
 }
@@ -184,8 +236,48 @@ pub fn dump_trace(opts: DumpTraceOpts, io: &mut IO, _repo: Repo) -> Result<u8> {
     }
     let merged = tracing_collector::TracingData::merge(tracing_data_list);
 
-    crate::run::write_trace(io, &opts.output_path, &merged)?;
+    crate::run::write_trace(io, &opts.output_path, &opts.format, &merged)?;
+
+    Ok(0)
+}
+
+pub fn blackbox_stats(opts: BlackboxStatsOpts, io: &mut IO, _repo: Repo) -> Result<u8> {
+    let group_by = match opts.group_by.as_str() {
+        "command" => GroupBy::Command,
+        "exit-code" => GroupBy::ExitCode,
+        s if s.starts_with("config:") => GroupBy::Config(s["config:".len()..].to_string()),
+        s => {
+            return Err(errors::Abort(format!("invalid --group-by: {}", s).into()).into());
+        }
+    };
+    let template = opts.formatter_opts.template.as_str();
+    if !template.is_empty() && template != "json" {
+        return Err(errors::Abort("only -T json is supported".into()).into());
+    }
+    let range = match hgtime::HgTime::parse_range(&opts.time_range) {
+        Some(range) => range,
+        None => return Err(errors::Abort("invalid --time-range".into()).into()),
+    };
+
+    let sessions = {
+        let blackbox = blackbox::SINGLETON.lock();
+        // Blackbox uses milliseconds. HgTime uses seconds.
+        let ratio = 1000;
+        blackbox.session_summaries(
+            range.start.unixtime.saturating_mul(ratio) as u64
+                ..range.end.unixtime.saturating_mul(ratio) as u64,
+        )
+    };
+    let stats = blackbox::stats::aggregate(&sessions, &group_by, opts.top.max(0) as usize);
 
+    if template == "json" {
+        io.write(format!(
+            "{}\n",
+            blackbox::serde_json::to_string_pretty(&stats)?
+        ))?;
+    } else {
+        io.write(stats.to_string())?;
+    }
     Ok(0)
 }
 
@@ -289,3 +381,19 @@ pub fn debugdynamicconfig(_opts: DebugDynamicConfigOpts, _io: &mut IO, repo: Rep
     fs::write(repo_path.join("hgrc.dynamic"), config_str)?;
     Ok(0)
 }
+
+#[cfg(unix)]
+pub fn debugsharedcache(opts: DebugSharedCacheOpts, io: &mut IO, repo: Repo) -> Result<u8> {
+    if opts.serve {
+        SharedCacheServer::new(repo.config())?.serve()?;
+        return Ok(0);
+    }
+
+    match SharedCacheClient::from_config(repo.config())? {
+        Some(client) => {
+            io.write(client.stats()?.to_string())?;
+            Ok(0)
+        }
+        None => Err(errors::Abort("sharedcache.socket is not set".into()).into()),
+    }
+}
@@ -0,0 +1,182 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use anyhow::Result;
+use bookmarkstore::BookmarkStore;
+use clidispatch::{
+    command::{CommandTable, Register},
+    completion::{self, Shell, ValueKind},
+    errors,
+    io::IO,
+    repo::Repo,
+};
+use cliparser::define_flags;
+use pathmatcher::{DirectoryMatch, Matcher};
+use std::{env, fs, io, path::Path};
+use types::{RepoPath, RepoPathBuf};
+use workingcopy::walker::{WalkEntry, Walker};
+
+use crate::commands;
+
+pub(crate) fn register(table: &mut CommandTable) {
+    table.register(
+        debugcompletion,
+        "debugcompletion",
+        r#"generate shell completion scripts
+
+    Print a completion script for the given shell (bash, zsh or fish).
+
+    With --values, print bookmark names ("bookmarks") or working copy
+    paths ("files") starting with the given prefix instead. Generated
+    scripts use this to complete values that depend on the repository."#,
+    );
+}
+
+define_flags! {
+    pub struct DebugCompletionOpts {
+        /// print values of the given kind (bookmarks or files)
+        values: String,
+
+        #[args]
+        args: Vec<String>,
+    }
+}
+
+pub fn debugcompletion(opts: DebugCompletionOpts, io: &mut IO, repo: Option<Repo>) -> Result<u8> {
+    if opts.values.is_empty() {
+        let shell: Shell = match opts.args.first() {
+            Some(name) => name.parse()?,
+            None => {
+                return Err(errors::Abort("specify a shell (bash, zsh or fish)".into()).into());
+            }
+        };
+        io.write(completion::generate(&commands::table(), shell, "hg"))?;
+        return Ok(0);
+    }
+
+    let kind = match ValueKind::from_name(&opts.values) {
+        Some(kind) => kind,
+        None => {
+            return Err(errors::Abort(
+                format!(
+                    "unknown value kind '{}' (use bookmarks or files)",
+                    opts.values
+                )
+                .into(),
+            )
+            .into());
+        }
+    };
+    // Completion should not print errors. Outside a repo there is just
+    // nothing to complete.
+    let repo = match repo {
+        Some(repo) => repo,
+        None => return Ok(0),
+    };
+    let prefix = opts.args.first().map(|s| s.as_str()).unwrap_or("");
+    let values = match kind {
+        ValueKind::Bookmark => bookmarks(&repo)?,
+        ValueKind::File => files(&repo, prefix)?,
+    };
+    for value in values.into_iter().filter(|v| v.starts_with(prefix)) {
+        io.write(format!("{}\n", value))?;
+    }
+    Ok(0)
+}
+
+/// Directory of the bookmark store inside the shared `.hg` directory.
+const BOOKMARK_STORE_DIR: &str = "store/bookmarkstore";
+
+fn bookmarks(repo: &Repo) -> Result<Vec<String>> {
+    let store_path = repo.shared_dot_hg_path().join(BOOKMARK_STORE_DIR);
+    if store_path.is_dir() {
+        return BookmarkStore::new(&store_path)?.list_bookmarks();
+    }
+
+    // Fall back to the plain "<hex> <name>" bookmarks file.
+    let data = match fs::read_to_string(repo.shared_dot_hg_path().join("bookmarks")) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut names: Vec<String> = data
+        .lines()
+        .filter_map(|line| line.splitn(2, ' ').nth(1))
+        .map(|name| name.to_string())
+        .collect();
+    names.sort();
+    Ok(names)
+}
+
+/// List the working copy entries in the directory part of `prefix`, like a
+/// shell would. `prefix` is relative to the current directory. Directories
+/// get a trailing `/`.
+fn files(repo: &Repo, prefix: &str) -> Result<Vec<String>> {
+    let dir_part = match prefix.rfind('/') {
+        Some(pos) => &prefix[..=pos],
+        None => "",
+    };
+    let root = fs::canonicalize(repo.path())?;
+    let dir = match fs::canonicalize(env::current_dir()?.join(dir_part)) {
+        Ok(dir) => dir,
+        Err(_) => return Ok(Vec::new()),
+    };
+    let dir = match dir.strip_prefix(&root) {
+        Ok(dir) => repo_path_from_relative(dir)?,
+        Err(_) => return Ok(Vec::new()),
+    };
+
+    let matcher = ChildrenMatcher { dir: dir.clone() };
+    let mut values = Vec::new();
+    for entry in Walker::new(root, matcher, true) {
+        // Unreadable entries are not worth failing completion for.
+        let (path, suffix) = match entry {
+            Ok(WalkEntry::File(path, _)) => (path, ""),
+            Ok(WalkEntry::Directory(path)) => (path, "/"),
+            Err(_) => continue,
+        };
+        match path.split_last_component() {
+            Some((parent, name)) if parent == dir.as_repo_path() => {
+                values.push(format!("{}{}{}", dir_part, name.as_str(), suffix));
+            }
+            _ => {}
+        }
+    }
+    values.sort();
+    Ok(values)
+}
+
+fn repo_path_from_relative(path: &Path) -> Result<RepoPathBuf> {
+    let components: Vec<String> = path
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy().into_owned())
+        .collect();
+    Ok(RepoPathBuf::from_string(components.join("/"))?)
+}
+
+/// Matches the direct children of `dir`.
+///
+/// Subdirectories of `dir` are traversed so the walker reports them, but
+/// nothing below them matches.
+struct ChildrenMatcher {
+    dir: RepoPathBuf,
+}
+
+impl Matcher for ChildrenMatcher {
+    fn matches_directory(&self, path: &RepoPath) -> DirectoryMatch {
+        let dir = self.dir.as_repo_path();
+        if path == dir || path.parent() == Some(dir) || dir.parents().any(|p| p == path) {
+            DirectoryMatch::ShouldTraverse
+        } else {
+            DirectoryMatch::Nothing
+        }
+    }
+
+    fn matches_file(&self, path: &RepoPath) -> bool {
+        path.parent() == Some(self.dir.as_repo_path())
+    }
+}
@@ -6,6 +6,7 @@
  */
 
 pub mod commands;
+mod completion;
 mod hgpython;
 mod python;
 mod run;
@@ -180,7 +180,7 @@ fn maybe_write_trace(
     if let Some(path) = path {
         // A hardcoded minimal duration (in microseconds).
         let data = tracing_data.lock();
-        match write_trace(io, &path, &data) {
+        match write_trace(io, &path, "", &data) {
             Ok(_) => io.write_err(format!("(Trace was written to {})\n", &path))?,
             Err(err) => {
                 io.write_err(format!("(Failed to write Trace to {}: {})\n", &path, &err))?
@@ -190,9 +190,15 @@ fn maybe_write_trace(
     Ok(())
 }
 
+/// Write `data` to `path`.
+///
+/// `format` is one of "ascii", "trace-event", "trace-event-gz", "spans",
+/// "folded" and "otlp". If it is empty, the format is decided by the
+/// extension of `path`.
 pub(crate) fn write_trace(
     io: &mut clidispatch::io::IO,
     path: &str,
+    format: &str,
     data: &TracingData,
 ) -> Result<()> {
     enum Format {
@@ -200,18 +206,37 @@ pub(crate) fn write_trace(
         TraceEventJSON,
         TraceEventGzip,
         SpansJSON,
+        Folded,
+        OtlpJSON,
     }
 
-    let format = if path.ends_with(".txt") {
-        Format::ASCII
-    } else if path.ends_with("spans.json") {
-        Format::SpansJSON
-    } else if path.ends_with(".json") {
-        Format::TraceEventJSON
-    } else if path.ends_with(".gz") {
-        Format::TraceEventGzip
-    } else {
-        Format::ASCII
+    let format = match format {
+        "ascii" => Format::ASCII,
+        "trace-event" => Format::TraceEventJSON,
+        "trace-event-gz" => Format::TraceEventGzip,
+        "spans" => Format::SpansJSON,
+        "folded" => Format::Folded,
+        "otlp" => Format::OtlpJSON,
+        "" => {
+            if path.ends_with(".txt") {
+                Format::ASCII
+            } else if path.ends_with("spans.json") {
+                Format::SpansJSON
+            } else if path.ends_with("otlp.json") {
+                Format::OtlpJSON
+            } else if path.ends_with(".json") {
+                Format::TraceEventJSON
+            } else if path.ends_with(".gz") {
+                Format::TraceEventGzip
+            } else if path.ends_with(".folded") {
+                Format::Folded
+            } else {
+                Format::ASCII
+            }
+        }
+        _ => {
+            return Err(errors::Abort(format!("unknown trace format '{}'", format).into()).into());
+        }
     };
 
     let mut out: Box<dyn Write> = if path == "-" || path.is_empty() {
@@ -245,6 +270,14 @@ pub(crate) fn write_trace(
             data.write_trace_event_json(&mut out, Default::default())?;
             out.flush()?;
         }
+        Format::Folded => {
+            data.write_folded(&mut out)?;
+            out.flush()?;
+        }
+        Format::OtlpJSON => {
+            data.write_otlp_json(&mut out)?;
+            out.flush()?;
+        }
     }
 
     Ok(())
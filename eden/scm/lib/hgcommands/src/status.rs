@@ -6,7 +6,8 @@
  */
 
 use crate::commands::{FormatterOpts, WalkOpts};
-use anyhow::Result;
+use anyhow::{format_err, Result};
+use bytes::Bytes;
 use clidispatch::{
     command::{CommandTable, Register},
     errors,
@@ -14,8 +15,33 @@ use clidispatch::{
     repo::Repo,
 };
 use cliparser::define_flags;
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
 
-use edenfs_client::status::{maybe_status_fastpath, PrintConfig, PrintConfigStatusTypes};
+use edenfs_client::status::{
+    maybe_status_fastpath, needs_morestatus_extension, print_status, GroupedEntries, PrintConfig,
+    PrintConfigStatusTypes,
+};
+use manifest::Manifest;
+use manifest_tree::{TreeManifest, TreeStore};
+use parking_lot::Mutex;
+use pathmatcher::{
+    build_matcher, normalize_patterns, plain_to_glob, AlwaysMatcher, DifferenceMatcher,
+    GitignoreMatcher, Matcher, PatternKind, SparseMatcher, SparseProfile, TreeMatcher,
+    UnionMatcher,
+};
+use revisionstore::{ContentStore, ContentStoreBuilder, HgIdDataStore};
+use treestate::dirstate::Dirstate;
+use types::{HgId, Key, RepoPath, RepoPathBuf};
+use workingcopy::filesystem::PhysicalFileSystem;
+use workingcopy::status::{status as working_copy_status, Status, StatusOptions};
+
+const JOURNAL_TIMEOUT: Duration = Duration::from_secs(1);
 
 /// Return the main command table including all Rust commands.
 pub(crate) fn register(table: &mut CommandTable) {
@@ -181,16 +207,11 @@ define_flags! {
 fn status(opts: StatusOpts, io: &mut IO, repo: Repo) -> Result<u8> {
     let rev_check = opts.rev.is_empty() || (opts.rev.len() == 1 && opts.rev[0] == ".");
 
-    let args_check = opts.args.is_empty() || (opts.args.len() == 1 && opts.args[0] == "re:.");
-
     if opts.all
         || !opts.change.is_empty()
         || !opts.terse.is_empty()
         || !rev_check
-        || !opts.walk_opts.include.is_empty()
-        || !opts.walk_opts.exclude.is_empty()
         || !opts.formatter_opts.template.is_empty()
-        || !args_check
     {
         return Err(errors::FallbackToPython.into());
     }
@@ -236,5 +257,446 @@ fn status(opts: StatusOpts, io: &mut IO, repo: Repo) -> Result<u8> {
     };
 
     let cwd = std::env::current_dir()?;
-    maybe_status_fastpath(repo.path(), &cwd, print_config, io)
+    if is_eden(&repo) {
+        let args_check = opts.args.is_empty() || (opts.args.len() == 1 && opts.args[0] == "re:.");
+        if !args_check || !opts.walk_opts.include.is_empty() || !opts.walk_opts.exclude.is_empty() {
+            return Err(errors::FallbackToPython.into());
+        }
+        maybe_status_fastpath(repo.path(), &cwd, print_config, io)
+    } else {
+        // Patterns this can't handle, such as filesets, are left to the Python implementation.
+        let matcher = build_matcher(
+            repo.path(),
+            &cwd,
+            &opts.args,
+            &opts.walk_opts.include,
+            &opts.walk_opts.exclude,
+            PatternKind::RelPath,
+        )
+        .map_err(|_| errors::FallbackToPython)?;
+        if names_missing_file(repo.path(), &cwd, &opts.args) {
+            return Err(errors::FallbackToPython.into());
+        }
+        native_status(&repo, &cwd, &matcher, print_config, io)
+    }
+}
+
+fn is_eden(repo: &Repo) -> bool {
+    fs::read_to_string(repo.dot_hg_path().join("requires"))
+        .map(|requires| requires.lines().any(|line| line == "eden"))
+        .unwrap_or(false)
+}
+
+/// Status for a regular working copy, from the treestate and the working copy files.
+fn native_status(
+    repo: &Repo,
+    cwd: &Path,
+    matcher: &dyn Matcher,
+    print_config: PrintConfig,
+    io: &mut IO,
+) -> Result<u8> {
+    let options = StatusOptions {
+        clean: print_config.status_types.clean,
+        unknown: print_config.status_types.unknown,
+        ignored: print_config.status_types.ignored,
+        journal: None,
+    };
+    // Anything this can't handle, such as a repo without treestate or content that is not
+    // available locally, is left to the Python implementation.
+    let Status {
+        modified,
+        added,
+        removed,
+        deleted,
+        clean,
+        unknown,
+        ignored,
+        copymap,
+        errors: walk_errors,
+    } = compute_status(repo, matcher, options).map_err(|_| errors::FallbackToPython)?;
+
+    let groups = GroupedEntries {
+        modified: to_paths(modified),
+        added: to_paths(added),
+        removed: to_paths(removed),
+        deleted: to_paths(deleted),
+        unknown: to_paths(unknown),
+        ignored: to_paths(ignored),
+        clean: to_paths(clean),
+    };
+    let copymap: HashMap<PathBuf, PathBuf> = copymap
+        .into_iter()
+        .map(|(dest, source)| (PathBuf::from(dest.as_str()), PathBuf::from(source.as_str())))
+        .collect();
+    let errors: Vec<(PathBuf, String)> = walk_errors
+        .into_iter()
+        .map(|e| (PathBuf::from(e.filename()), e.message()))
+        .collect();
+
+    print_status(
+        repo.path(),
+        cwd,
+        &print_config,
+        &groups,
+        &copymap,
+        &errors,
+        io,
+    )
+}
+
+fn compute_status(
+    repo: &Repo,
+    matcher: &dyn Matcher,
+    mut options: StatusOptions,
+) -> Result<Status> {
+    let dirstate = Dirstate::read(repo.dot_hg_path().join("dirstate"))?;
+    let mut p2 = [0; HgId::len()];
+    p2.copy_from_slice(dirstate.p2.as_ref());
+    if needs_morestatus_extension(repo.dot_hg_path(), &p2) {
+        return Err(errors::FallbackToPython.into());
+    }
+    let tree_state = dirstate
+        .tree_state
+        .ok_or_else(|| format_err!("dirstate is not a treestate"))?;
+    let treestate = tree_state.open(repo.dot_hg_path())?;
+
+    // The fsjournal extension stores the journal clock of the last status. Without an answer
+    // from the journal, every file is checked.
+    if let Some(clock) = treestate.get_metadata_by_key(fsjournal::CLOCK_METADATA_KEY)? {
+        if let Ok(changes) = fsjournal::query(repo.path(), Some(&clock), JOURNAL_TIMEOUT) {
+            options.journal = changes.paths;
+        }
+    }
+    let treestate = Arc::new(Mutex::new(treestate));
+
+    let config = repo.config();
+    let store_path = repo.shared_dot_hg_path().join("store");
+    let file_store = ContentStoreBuilder::new(config)
+        .local_path(&store_path)
+        .build()?;
+    let tree_store = ContentStoreBuilder::new(config)
+        .local_path(&store_path)
+        .suffix(Path::new("manifests"))
+        .build()?;
+    let p1_manifest = TreeManifest::durable(Arc::new(ManifestStore(tree_store)), dirstate.p1);
+    let file_store = Arc::new(file_store);
+
+    let mut global_ignores = Vec::new();
+    for name in config.keys("ui") {
+        if name == "ignore" || name.starts_with("ignore.") {
+            if let Some(path) = config.get("ui", &name) {
+                let path = path.trim_start_matches("git:");
+                global_ignores.push(repo.path().join(util::path::expand_path(path)));
+            }
+        }
+    }
+    let gitignore = GitignoreMatcher::new(
+        repo.path(),
+        global_ignores.iter().map(|path| path.as_path()).collect(),
+    );
+    let ignore = ignore_matcher(gitignore, sparse_matcher(repo, &p1_manifest, &file_store)?);
+
+    working_copy_status(
+        &PhysicalFileSystem::new(repo.path().to_path_buf())?,
+        treestate,
+        Arc::new(p1_manifest),
+        file_store,
+        matcher,
+        &ignore,
+        &options,
+    )
+}
+
+/// Untracked files that `gitignore` matches are ignored, and so are files outside of the sparse
+/// checkout, like the sparse extension does.
+fn ignore_matcher(
+    gitignore: GitignoreMatcher,
+    sparse: Option<Box<dyn Matcher + Send + Sync>>,
+) -> Box<dyn Matcher> {
+    match sparse {
+        Some(sparse) => Box::new(UnionMatcher::new(vec![
+            Box::new(gitignore) as Box<dyn Matcher>,
+            Box::new(DifferenceMatcher::new(AlwaysMatcher::new(), sparse)),
+        ])),
+        None => Box::new(gitignore),
+    }
+}
+
+/// The matcher of the sparse checkout, if the sparse extension is enabled and the working copy is
+/// sparse. Profiles are read from the working copy parent. Files included temporarily are
+/// matched too.
+fn sparse_matcher(
+    repo: &Repo,
+    manifest: &TreeManifest,
+    store: &ContentStore,
+) -> Result<Option<Box<dyn Matcher + Send + Sync>>> {
+    let enabled = repo
+        .config()
+        .get("extensions", "sparse")
+        .map_or(false, |value| !value.starts_with('!'));
+    if !enabled {
+        return Ok(None);
+    }
+    let data = match fs::read(repo.dot_hg_path().join("sparse")) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+
+    let profile = SparseProfile::from_bytes(".hg/sparse", &data)?;
+    let sparse = SparseMatcher::from_profile(profile, |path| {
+        let path = RepoPath::from_str(path)?;
+        match manifest.get_file(path)? {
+            Some(file) => {
+                let key = Key::new(path.to_owned(), file.hgid);
+                Ok(store
+                    .get_file_content(&key)?
+                    .map(|content| content.to_vec()))
+            }
+            None => Ok(None),
+        }
+    })?;
+
+    let temporary = match fs::read_to_string(repo.dot_hg_path().join("tempsparse")) {
+        Ok(temporary) => temporary,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Some(Box::new(sparse))),
+        Err(e) => return Err(e.into()),
+    };
+    let rules: Vec<String> = temporary
+        .split('\n')
+        .filter(|path| !path.is_empty())
+        .map(plain_to_glob)
+        .collect();
+    let temporary = TreeMatcher::from_rules(rules.iter())?;
+    Ok(Some(Box::new(UnionMatcher::new(vec![
+        Box::new(sparse) as Box<dyn Matcher + Send + Sync>,
+        Box::new(temporary),
+    ]))))
+}
+
+/// Whether a file named explicitly doesn't exist. The Python implementation warns about those.
+fn names_missing_file(root: &Path, cwd: &Path, args: &[String]) -> bool {
+    let patterns = match normalize_patterns(args, PatternKind::RelPath, root, cwd) {
+        Ok(patterns) => patterns,
+        Err(_) => return true,
+    };
+    patterns.iter().any(|pattern| {
+        let literal = match pattern.kind {
+            PatternKind::Path | PatternKind::RelPath => true,
+            PatternKind::Glob => !pattern.pattern.contains(|c: char| "*?[{\\".contains(c)),
+            _ => false,
+        };
+        literal && fs::symlink_metadata(root.join(&pattern.pattern)).is_err()
+    })
+}
+
+fn to_paths(paths: Vec<RepoPathBuf>) -> Vec<PathBuf> {
+    paths
+        .into_iter()
+        .map(|path| PathBuf::from(path.as_str()))
+        .collect()
+}
+
+/// Reads trees of the parent manifest from the local store.
+struct ManifestStore(ContentStore);
+
+impl TreeStore for ManifestStore {
+    fn get(&self, path: &RepoPath, hgid: HgId) -> Result<Bytes> {
+        let key = Key::new(path.to_owned(), hgid);
+        self.0
+            .get(&key)?
+            .map(Into::into)
+            .ok_or_else(|| format_err!("tree {} is not available", key))
+    }
+
+    fn insert(&self, _path: &RepoPath, _hgid: HgId, _data: Bytes) -> Result<()> {
+        Err(format_err!("insert is not implemented."))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use clidispatch::repo::OptionalRepo;
+    use configparser::config::ConfigSet;
+    use tempfile::TempDir;
+    use treestate::filestate::{FileStateV2, StateFlags};
+    use treestate::treestate::TreeState;
+
+    struct TestRepo {
+        root: TempDir,
+        _cachedir: TempDir,
+        repo: Repo,
+    }
+
+    impl TestRepo {
+        /// A repo whose working copy parent is null, with `tracked` in its treestate and `files`
+        /// in its working copy.
+        fn new(tracked: &[(&str, StateFlags)], files: &[&str]) -> Result<Self> {
+            let root = TempDir::new()?;
+            let cachedir = TempDir::new()?;
+            let dot_hg = root.path().join(".hg");
+            fs::create_dir_all(dot_hg.join("treestate"))?;
+            fs::create_dir_all(dot_hg.join("store"))?;
+            fs::write(
+                dot_hg.join("hgrc"),
+                format!(
+                    "[remotefilelog]\nreponame = repo\ncachepath = {}\n",
+                    cachedir.path().display()
+                ),
+            )?;
+
+            let mut treestate = TreeState::open(dot_hg.join("treestate").join("tree"), None)?;
+            for (path, state) in tracked {
+                let file_state = FileStateV2 {
+                    mode: 0o100644,
+                    size: -1,
+                    mtime: -1,
+                    state: *state,
+                    copied: None,
+                };
+                treestate.insert(path, &file_state)?;
+            }
+            let root_id = treestate.flush()?;
+            let mut dirstate = vec![0; 2 * HgId::len()];
+            dirstate.extend_from_slice(b"\ntreestate\n\0");
+            dirstate.extend_from_slice(format!("filename=tree\0rootid={}", root_id.0).as_bytes());
+            fs::write(dot_hg.join("dirstate"), dirstate)?;
+
+            for path in files {
+                let path = root.path().join(path);
+                fs::create_dir_all(path.parent().unwrap())?;
+                fs::write(path, "content\n")?;
+            }
+
+            let repo = match OptionalRepo::from_cwd(root.path(), ConfigSet::new())? {
+                OptionalRepo::Some(repo) => repo,
+                OptionalRepo::None(_) => panic!("repo not found"),
+            };
+            Ok(TestRepo {
+                root,
+                _cachedir: cachedir,
+                repo,
+            })
+        }
+
+        fn status(&self) -> Result<Status> {
+            let options = StatusOptions {
+                clean: false,
+                unknown: true,
+                ignored: false,
+                journal: None,
+            };
+            compute_status(&self.repo, &AlwaysMatcher::new(), options)
+        }
+    }
+
+    fn paths(paths: &[&str]) -> Vec<RepoPathBuf> {
+        paths
+            .iter()
+            .map(|path| RepoPathBuf::from_string(path.to_string()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_compute_status() -> Result<()> {
+        let repo = TestRepo::new(
+            &[
+                ("added", StateFlags::EXIST_NEXT),
+                ("missing", StateFlags::EXIST_NEXT),
+            ],
+            &["added", "unknown", "dir/unknown"],
+        )?;
+        let status = repo.status()?;
+
+        assert_eq!(status.added, paths(&["added"]));
+        assert_eq!(status.deleted, paths(&["missing"]));
+        assert_eq!(status.unknown, paths(&["dir/unknown", "unknown"]));
+        assert!(status.modified.is_empty());
+        assert!(status.removed.is_empty());
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_compute_status_behind_symlink() -> Result<()> {
+        let repo = TestRepo::new(&[("link/added", StateFlags::EXIST_NEXT)], &[])?;
+        let other = TempDir::new()?;
+        fs::write(other.path().join("added"), "content\n")?;
+        std::os::unix::fs::symlink(other.path(), repo.root.path().join("link"))?;
+        let status = repo.status()?;
+
+        // The file exists, but not in the working copy.
+        assert!(status.added.is_empty());
+        assert_eq!(status.deleted, paths(&["link/added"]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_compute_status_fallback() -> Result<()> {
+        // Merges need the morestatus extension.
+        let repo = TestRepo::new(&[], &[])?;
+        let dot_hg = repo.repo.dot_hg_path();
+        fs::create_dir_all(dot_hg.join("merge"))?;
+        fs::write(dot_hg.join("merge").join("state"), "")?;
+        let err = repo.status().err().expect("status should fall back");
+        assert!(err.downcast_ref::<errors::FallbackToPython>().is_some());
+
+        // Dirstates that are not treestates.
+        let repo = TestRepo::new(&[], &[])?;
+        fs::write(repo.repo.dot_hg_path().join("dirstate"), vec![0; 40])?;
+        assert!(repo.status().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_ignore_matcher() -> Result<()> {
+        let root = TempDir::new()?;
+        fs::write(root.path().join(".gitignore"), "*.log\n")?;
+        let path = |path: &str| RepoPathBuf::from_string(path.to_string()).unwrap();
+
+        let ignore = ignore_matcher(GitignoreMatcher::new(root.path(), Vec::new()), None);
+        assert!(ignore.matches_file(&path("sparse/a.log")));
+        assert!(!ignore.matches_file(&path("other/a")));
+
+        // Files outside of the sparse checkout are ignored as well.
+        let sparse = TreeMatcher::from_rules(["sparse/**"].iter())?;
+        let ignore = ignore_matcher(
+            GitignoreMatcher::new(root.path(), Vec::new()),
+            Some(Box::new(sparse)),
+        );
+        assert!(ignore.matches_file(&path("sparse/a.log")));
+        assert!(ignore.matches_file(&path("other/a")));
+        assert!(!ignore.matches_file(&path("sparse/a")));
+        Ok(())
+    }
+
+    #[test]
+    fn test_names_missing_file() -> Result<()> {
+        let root = TempDir::new()?;
+        let root = root.path();
+        fs::create_dir_all(root.join("dir"))?;
+        fs::write(root.join("dir").join("file"), "content\n")?;
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+
+        assert!(!names_missing_file(root, root, &args(&["dir/file", "dir"])));
+        assert!(!names_missing_file(
+            root,
+            &root.join("dir"),
+            &args(&["file"])
+        ));
+        assert!(names_missing_file(
+            root,
+            root,
+            &args(&["dir/file", "missing"])
+        ));
+        // Globs only name files if they have no special characters.
+        assert!(!names_missing_file(root, root, &args(&["glob:dir/*"])));
+        assert!(!names_missing_file(root, root, &args(&["glob:missing*"])));
+        assert!(names_missing_file(root, root, &args(&["glob:missing"])));
+        // Other kinds of patterns never name files.
+        assert!(!names_missing_file(root, root, &args(&["re:missing"])));
+        Ok(())
+    }
 }
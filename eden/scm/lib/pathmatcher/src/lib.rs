@@ -6,6 +6,10 @@
  */
 
 mod gitignore_matcher;
+mod pattern;
+mod regex_matcher;
+mod set_matcher;
+mod sparse_matcher;
 mod tree_matcher;
 mod utils;
 
@@ -85,5 +89,12 @@ impl Matcher for NeverMatcher {
 }
 
 pub use gitignore_matcher::GitignoreMatcher;
+pub use pattern::{
+    build_matcher, build_patterns_matcher, normalize_patterns, split_pattern, Pattern,
+    PatternError, PatternKind,
+};
+pub use regex_matcher::RegexMatcher;
+pub use set_matcher::{DifferenceMatcher, IntersectMatcher, UnionMatcher};
+pub use sparse_matcher::{SparseError, SparseMatcher, SparseProfile};
 pub use tree_matcher::TreeMatcher;
 pub use utils::{expand_curly_brackets, normalize_glob, plain_to_glob};
@@ -0,0 +1,716 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Mercurial file patterns
+//!
+//! Patterns are strings like `glob:*.c` or `path:foo/bar`, optionally without
+//! the `kind:` prefix, in which case a default kind is used. They are first
+//! normalized by [normalize_patterns], which makes them relative to the
+//! repository root and expands pattern files, and then turned into a matcher
+//! by [build_patterns_matcher]. [build_matcher] does both, and applies the
+//! `-I` and `-X` patterns the way commands do.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use regex::Regex;
+use thiserror::Error;
+
+use crate::{
+    expand_curly_brackets, normalize_glob, plain_to_glob, AlwaysMatcher, DifferenceMatcher,
+    IntersectMatcher, Matcher, NeverMatcher, RegexMatcher, TreeMatcher, UnionMatcher,
+};
+
+#[derive(Debug, Error)]
+pub enum PatternError {
+    #[error("unsupported pattern kind '{0}'")]
+    Unsupported(PatternKind),
+
+    #[error("{0} not under root '{1}'")]
+    NotUnderRoot(String, String),
+
+    #[error("unable to read file list ({0})")]
+    ListFile(String, #[source] io::Error),
+
+    #[error("unable to read pattern file '{0}'")]
+    PatternFile(String, #[source] io::Error),
+
+    #[error("invalid glob pattern '{0}'")]
+    InvalidGlob(String),
+
+    #[error(transparent)]
+    Glob(#[from] globset::Error),
+
+    #[error(transparent)]
+    Regex(#[from] regex::Error),
+}
+
+/// The kind of a pattern, written as a `kind:` prefix.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum PatternKind {
+    /// `re:` - a regular expression matching from the repository root
+    Regexp,
+    /// `glob:` - a glob relative to the current directory
+    Glob,
+    /// `path:` - a path relative to the repository root, matched recursively
+    Path,
+    /// `relglob:` - an unrooted glob, `*.c` matches C files in all directories
+    RelGlob,
+    /// `relpath:` - a path relative to the current directory, matched recursively
+    RelPath,
+    /// `relre:` - a regular expression that needn't match the start of a path
+    RelRegexp,
+    /// `listfile:` - a file of patterns, one per line
+    ListFile,
+    /// `listfile0:` - a file of patterns, separated by NUL characters
+    ListFile0,
+    /// `set:` - a fileset expression, not supported
+    Set,
+    /// `include:` - a pattern file, relative to the repository root
+    Include,
+    /// `subinclude:` - a pattern file applying to its own directory, not supported
+    SubInclude,
+    /// `rootfilesin:` - a path relative to the repository root, matched non-recursively
+    RootFilesIn,
+}
+
+impl PatternKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            PatternKind::Regexp => "re",
+            PatternKind::Glob => "glob",
+            PatternKind::Path => "path",
+            PatternKind::RelGlob => "relglob",
+            PatternKind::RelPath => "relpath",
+            PatternKind::RelRegexp => "relre",
+            PatternKind::ListFile => "listfile",
+            PatternKind::ListFile0 => "listfile0",
+            PatternKind::Set => "set",
+            PatternKind::Include => "include",
+            PatternKind::SubInclude => "subinclude",
+            PatternKind::RootFilesIn => "rootfilesin",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        let kind = match name {
+            "re" => PatternKind::Regexp,
+            "glob" => PatternKind::Glob,
+            "path" => PatternKind::Path,
+            "relglob" => PatternKind::RelGlob,
+            "relpath" => PatternKind::RelPath,
+            "relre" => PatternKind::RelRegexp,
+            "listfile" => PatternKind::ListFile,
+            "listfile0" => PatternKind::ListFile0,
+            "set" => PatternKind::Set,
+            "include" => PatternKind::Include,
+            "subinclude" => PatternKind::SubInclude,
+            "rootfilesin" => PatternKind::RootFilesIn,
+            _ => return None,
+        };
+        Some(kind)
+    }
+}
+
+impl fmt::Display for PatternKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// A normalized pattern.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Pattern {
+    pub kind: PatternKind,
+    /// The pattern, relative to the repository root unless it is a regular
+    /// expression or a `relglob:`.
+    pub pattern: String,
+    /// The list file or pattern file the pattern was read from.
+    pub source: Option<String>,
+}
+
+impl Pattern {
+    pub fn new(kind: PatternKind, pattern: impl ToString) -> Self {
+        Pattern {
+            kind,
+            pattern: pattern.to_string(),
+            source: None,
+        }
+    }
+}
+
+/// Split a pattern into its kind and the actual pattern. Patterns without a
+/// known `kind:` prefix are of the `default` kind.
+pub fn split_pattern(pattern: &str, default: PatternKind) -> (PatternKind, &str) {
+    if let Some(index) = pattern.find(':') {
+        if let Some(kind) = PatternKind::from_name(&pattern[..index]) {
+            return (kind, &pattern[index + 1..]);
+        }
+    }
+    (default, pattern)
+}
+
+/// Normalize patterns given relative to `cwd` in the working copy at `root`.
+///
+/// Paths and globs relative to `cwd` are made relative to `root`, paths are
+/// normalized, and `listfile:`, `listfile0:` and `include:` patterns are
+/// replaced by the patterns of their files. Both `root` and `cwd` are
+/// absolute.
+pub fn normalize_patterns(
+    patterns: &[impl AsRef<str>],
+    default: PatternKind,
+    root: &Path,
+    cwd: &Path,
+) -> Result<Vec<Pattern>, PatternError> {
+    let mut result = Vec::with_capacity(patterns.len());
+    for pattern in patterns {
+        let (kind, pat) = split_pattern(pattern.as_ref(), default);
+        let pat = match kind {
+            PatternKind::Glob | PatternKind::RelPath => canonical_path(root, cwd, pat)?,
+            PatternKind::RelGlob | PatternKind::Path | PatternKind::RootFilesIn => {
+                normalize_path(pat)
+            }
+            PatternKind::ListFile | PatternKind::ListFile0 => {
+                let content = fs::read_to_string(cwd.join(pat))
+                    .map_err(|e| PatternError::ListFile(pat.to_string(), e))?;
+                let files: Vec<&str> = if kind == PatternKind::ListFile0 {
+                    content.split('\0').filter(|f| !f.is_empty()).collect()
+                } else {
+                    content.lines().filter(|f| !f.is_empty()).collect()
+                };
+                for mut pattern in normalize_patterns(&files, default, root, cwd)? {
+                    pattern.source = Some(pat.to_string());
+                    result.push(pattern);
+                }
+                continue;
+            }
+            PatternKind::Include => {
+                let path = root.join(pat);
+                let include_patterns = read_pattern_file(&path)
+                    .map_err(|e| PatternError::PatternFile(pat.to_string(), e))?;
+                for mut pattern in normalize_patterns(&include_patterns, default, root, cwd)? {
+                    pattern.source = pattern.source.or_else(|| Some(pat.to_string()));
+                    result.push(pattern);
+                }
+                continue;
+            }
+            PatternKind::Regexp | PatternKind::RelRegexp => pat.to_string(),
+            PatternKind::Set | PatternKind::SubInclude => {
+                return Err(PatternError::Unsupported(kind));
+            }
+        };
+        result.push(Pattern::new(kind, pat));
+    }
+    Ok(result)
+}
+
+/// Read the patterns of an `include:` pattern file.
+///
+/// Comments start with `#`, and trailing white space is dropped. A
+/// `syntax: regexp` or `syntax: glob` line changes the kind of the following
+/// patterns without a prefix, which are `relre:` by default. `re:` and `glob:`
+/// prefixes stand for `relre:` and `relglob:`.
+fn read_pattern_file(path: &Path) -> io::Result<Vec<String>> {
+    const SYNTAXES: [(&str, &str); 5] = [
+        ("re", "relre:"),
+        ("regexp", "relre:"),
+        ("glob", "relglob:"),
+        ("include", "include:"),
+        ("subinclude", "subinclude:"),
+    ];
+    // Comments prefixed by an even number of escapes.
+    let comment = Regex::new(r"((?:^|[^\\])(?:\\\\)*)#.*").unwrap();
+
+    let content = fs::read_to_string(path)?;
+    let mut syntax = "relre:";
+    let mut patterns = Vec::new();
+    for line in content.lines() {
+        let mut line = line.to_string();
+        if line.contains('#') {
+            if let Some(end) = comment
+                .captures(&line)
+                .and_then(|c| c.get(1))
+                .map(|m| m.end())
+            {
+                line.truncate(end);
+            }
+            line = line.replace("\\#", "#");
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            continue;
+        }
+
+        if let Some(name) = line.strip_prefix("syntax:") {
+            let name = name.trim();
+            if let Some((_, prefix)) = SYNTAXES.iter().find(|(s, _)| *s == name) {
+                syntax = prefix;
+            }
+            continue;
+        }
+
+        let mut line_syntax = syntax;
+        let mut line = line;
+        for (name, prefix) in SYNTAXES.iter() {
+            if line.starts_with(prefix) {
+                line_syntax = prefix;
+                line = &line[prefix.len()..];
+                break;
+            } else if line.starts_with(name) && line[name.len()..].starts_with(':') {
+                line_syntax = prefix;
+                line = &line[name.len() + 1..];
+                break;
+            }
+        }
+        patterns.push(format!("{}{}", line_syntax, line));
+    }
+    Ok(patterns)
+}
+
+/// Return `name`, relative to `cwd`, as a path relative to `root`.
+fn canonical_path(root: &Path, cwd: &Path, name: &str) -> Result<String, PatternError> {
+    let path = normalize_fs_path(&cwd.join(name));
+    let root = normalize_fs_path(root);
+    if let Ok(relative) = path.strip_prefix(&root) {
+        return Ok(to_repo_path(relative));
+    }
+
+    // `root` or a parent of `name` may be a symlink.
+    let not_under_root =
+        || PatternError::NotUnderRoot(name.to_string(), root.display().to_string());
+    let real_root = fs::canonicalize(&root).map_err(|_| not_under_root())?;
+    for ancestor in path.ancestors() {
+        if fs::canonicalize(ancestor).ok().as_ref() == Some(&real_root) {
+            let relative = path.strip_prefix(ancestor).map_err(|_| not_under_root())?;
+            return Ok(to_repo_path(relative));
+        }
+    }
+    Err(not_under_root())
+}
+
+/// Lexically normalize a file system path, removing `.` and resolving `..`
+/// components.
+fn normalize_fs_path(path: &Path) -> PathBuf {
+    let mut result = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                result.pop();
+            }
+            component => result.push(component),
+        }
+    }
+    result
+}
+
+fn to_repo_path(path: &Path) -> String {
+    let components: Vec<_> = path
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect();
+    components.join("/")
+}
+
+/// Normalize a `/` separated path like `os.path.normpath`. The empty path is
+/// normalized to `.`.
+fn normalize_path(path: &str) -> String {
+    let absolute = path.starts_with('/');
+    let mut components: Vec<&str> = Vec::new();
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." if components.last().map_or(!absolute, |last| *last == "..") => {
+                components.push(component)
+            }
+            ".." => {
+                components.pop();
+            }
+            component => components.push(component),
+        }
+    }
+    let path = components.join("/");
+    if absolute {
+        format!("/{}", path)
+    } else if path.is_empty() {
+        ".".to_string()
+    } else {
+        path
+    }
+}
+
+/// Build a matcher for normalized patterns, matching a path if any of the
+/// patterns matches it, or a parent directory of it.
+///
+/// Regular expressions prevent skipping directories, other patterns allow
+/// skipping the directories they can't match.
+pub fn build_patterns_matcher(
+    patterns: &[Pattern],
+) -> Result<Box<dyn Matcher + Send + Sync>, PatternError> {
+    let mut rules = Vec::new();
+    let mut regexes = Vec::new();
+    for pattern in patterns {
+        let pat = pattern.pattern.as_str();
+        match pattern.kind {
+            PatternKind::Path | PatternKind::RelPath => {
+                if pat.is_empty() || pat == "." {
+                    rules.push("**".to_string());
+                } else {
+                    let glob = plain_to_glob(pat);
+                    rules.push(glob.clone());
+                    rules.push(format!("{}/**", glob));
+                }
+            }
+            PatternKind::RootFilesIn => {
+                if pat.is_empty() || pat == "." {
+                    rules.push("*".to_string());
+                } else {
+                    rules.push(format!("{}/*", plain_to_glob(pat)));
+                }
+            }
+            PatternKind::Glob | PatternKind::RelGlob => {
+                if pat.is_empty() {
+                    rules.push("**".to_string());
+                    continue;
+                }
+                let globs = expand_curly_brackets(pat);
+                if globs.is_empty() {
+                    return Err(PatternError::InvalidGlob(pat.to_string()));
+                }
+                for glob in globs {
+                    let mut glob = normalize_glob(&glob);
+                    if pattern.kind == PatternKind::RelGlob {
+                        glob = format!("**/{}", glob);
+                    } else if glob.starts_with('!') {
+                        glob = format!("\\{}", glob);
+                    }
+                    rules.push(glob.clone());
+                    rules.push(format!("{}/**", glob));
+                }
+            }
+            PatternKind::Regexp => regexes.push(pat.to_string()),
+            PatternKind::RelRegexp => {
+                if pat.starts_with('^') {
+                    regexes.push(pat.to_string());
+                } else {
+                    regexes.push(format!(".*{}", pat));
+                }
+            }
+            kind => return Err(PatternError::Unsupported(kind)),
+        }
+    }
+
+    let mut matchers: Vec<Box<dyn Matcher + Send + Sync>> = Vec::new();
+    if !rules.is_empty() {
+        matchers.push(Box::new(TreeMatcher::from_rules(rules.iter())?));
+    }
+    if !regexes.is_empty() {
+        matchers.push(Box::new(RegexMatcher::new(&regexes)?));
+    }
+    Ok(match matchers.len() {
+        0 => Box::new(NeverMatcher::new()),
+        1 => matchers.pop().unwrap(),
+        _ => Box::new(UnionMatcher::new(matchers)),
+    })
+}
+
+/// Build the matcher of a command's file patterns and `-I` and `-X` options.
+///
+/// Patterns without a kind prefix are of the `default` kind, and no patterns
+/// match everything. `include` and `exclude` patterns are globs by default.
+pub fn build_matcher(
+    root: &Path,
+    cwd: &Path,
+    patterns: &[impl AsRef<str>],
+    include: &[impl AsRef<str>],
+    exclude: &[impl AsRef<str>],
+    default: PatternKind,
+) -> Result<Box<dyn Matcher + Send + Sync>, PatternError> {
+    let mut matcher: Box<dyn Matcher + Send + Sync> = if patterns.is_empty() {
+        Box::new(AlwaysMatcher::new())
+    } else {
+        let patterns = normalize_patterns(patterns, default, root, cwd)?;
+        build_patterns_matcher(&patterns)?
+    };
+    if !include.is_empty() {
+        let patterns = normalize_patterns(include, PatternKind::Glob, root, cwd)?;
+        let include = build_patterns_matcher(&patterns)?;
+        matcher = Box::new(IntersectMatcher::new(matcher, include));
+    }
+    if !exclude.is_empty() {
+        let patterns = normalize_patterns(exclude, PatternKind::Glob, root, cwd)?;
+        let exclude = build_patterns_matcher(&patterns)?;
+        matcher = Box::new(DifferenceMatcher::new(matcher, exclude));
+    }
+    Ok(matcher)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs::{create_dir, write};
+
+    use tempfile::tempdir;
+    use types::testutil::repo_path;
+
+    use types::RepoPath;
+
+    use crate::DirectoryMatch;
+
+    fn matcher(patterns: &[&str]) -> Box<dyn Matcher + Send + Sync> {
+        let root = Path::new("/repo");
+        build_matcher(
+            root,
+            root,
+            patterns,
+            &[] as &[&str],
+            &[] as &[&str],
+            PatternKind::RelPath,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_split_pattern() {
+        assert_eq!(
+            split_pattern("glob:*.c", PatternKind::RelPath),
+            (PatternKind::Glob, "*.c")
+        );
+        assert_eq!(
+            split_pattern("re:a:b", PatternKind::RelPath),
+            (PatternKind::Regexp, "a:b")
+        );
+        assert_eq!(
+            split_pattern("foo:bar", PatternKind::Glob),
+            (PatternKind::Glob, "foo:bar")
+        );
+        assert_eq!(
+            split_pattern("a", PatternKind::Path),
+            (PatternKind::Path, "a")
+        );
+    }
+
+    #[test]
+    fn test_normalize_patterns() {
+        let root = Path::new("/repo");
+        let cwd = Path::new("/repo/a/b");
+        let patterns = [
+            "c",
+            "../c",
+            "glob:*.c",
+            "path:a/./b/../c",
+            "relglob:*.c",
+            "re:a/.*",
+            "rootfilesin:",
+            ".",
+            "/repo/d",
+        ];
+        let normalized = normalize_patterns(&patterns, PatternKind::RelPath, root, cwd).unwrap();
+        assert_eq!(
+            normalized,
+            vec![
+                Pattern::new(PatternKind::RelPath, "a/b/c"),
+                Pattern::new(PatternKind::RelPath, "a/c"),
+                Pattern::new(PatternKind::Glob, "a/b/*.c"),
+                Pattern::new(PatternKind::Path, "a/c"),
+                Pattern::new(PatternKind::RelGlob, "*.c"),
+                Pattern::new(PatternKind::Regexp, "a/.*"),
+                Pattern::new(PatternKind::RootFilesIn, "."),
+                Pattern::new(PatternKind::RelPath, "a/b"),
+                Pattern::new(PatternKind::RelPath, "d"),
+            ]
+        );
+
+        match normalize_patterns(&["../../../x"], PatternKind::RelPath, root, cwd) {
+            Err(PatternError::NotUnderRoot(..)) => {}
+            result => panic!("unexpected result {:?}", result),
+        }
+        match normalize_patterns(&["set:added()"], PatternKind::RelPath, root, cwd) {
+            Err(PatternError::Unsupported(PatternKind::Set)) => {}
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_pattern_files() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        create_dir(root.join("sub")).unwrap();
+        write(root.join("sub/list"), "x\n\ny\n").unwrap();
+        write(root.join("sub/list0"), "glob:x\0path:y\0").unwrap();
+        write(
+            root.join("patterns"),
+            "a.*b # comment\nglob:*.o\nsyntax: glob\n*.c\n\\#x\nre:^z\npath:p\n",
+        )
+        .unwrap();
+
+        let cwd = root.join("sub");
+        let normalized = normalize_patterns(
+            &["listfile:list", "listfile0:list0", "include:patterns"],
+            PatternKind::RelPath,
+            root,
+            &cwd,
+        )
+        .unwrap();
+        let normalized: Vec<_> = normalized
+            .iter()
+            .map(|p| {
+                (
+                    p.kind,
+                    p.pattern.as_str(),
+                    p.source.as_ref().unwrap().as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            normalized,
+            vec![
+                (PatternKind::RelPath, "sub/x", "list"),
+                (PatternKind::RelPath, "sub/y", "list"),
+                (PatternKind::Glob, "sub/x", "list0"),
+                (PatternKind::Path, "y", "list0"),
+                (PatternKind::RelRegexp, "a.*b", "patterns"),
+                (PatternKind::RelGlob, "*.o", "patterns"),
+                (PatternKind::RelGlob, "*.c", "patterns"),
+                (PatternKind::RelGlob, "#x", "patterns"),
+                (PatternKind::RelRegexp, "^z", "patterns"),
+                (PatternKind::RelGlob, "path:p", "patterns"),
+            ]
+        );
+
+        match normalize_patterns(&["include:missing"], PatternKind::RelPath, root, &cwd) {
+            Err(PatternError::PatternFile(..)) => {}
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_path_patterns() {
+        let m = matcher(&["a/b", "path:c*"]);
+        assert!(m.matches_file(repo_path("a/b")));
+        assert!(m.matches_file(repo_path("a/b/c/d")));
+        assert!(m.matches_file(repo_path("c*/d")));
+        assert!(!m.matches_file(repo_path("a/bc")));
+        assert!(!m.matches_file(repo_path("cd")));
+        assert_eq!(
+            m.matches_directory(RepoPath::empty()),
+            DirectoryMatch::ShouldTraverse
+        );
+        assert_eq!(
+            m.matches_directory(repo_path("a")),
+            DirectoryMatch::ShouldTraverse
+        );
+        assert_eq!(
+            m.matches_directory(repo_path("a/b")),
+            DirectoryMatch::Everything
+        );
+        assert_eq!(
+            m.matches_directory(repo_path("a/c")),
+            DirectoryMatch::Nothing
+        );
+        assert_eq!(
+            m.matches_directory(repo_path("cd")),
+            DirectoryMatch::Nothing
+        );
+
+        let m = matcher(&["."]);
+        assert!(m.matches_file(repo_path("a/b")));
+        assert_eq!(
+            m.matches_directory(RepoPath::empty()),
+            DirectoryMatch::Everything
+        );
+
+        let m = matcher(&["rootfilesin:a"]);
+        assert!(m.matches_file(repo_path("a/b")));
+        assert!(!m.matches_file(repo_path("a/b/c")));
+        assert!(!m.matches_file(repo_path("b/c")));
+        assert_eq!(m.matches_directory(repo_path("b")), DirectoryMatch::Nothing);
+    }
+
+    #[test]
+    fn test_glob_patterns() {
+        let m = matcher(&["glob:a/*.{c,h}", "relglob:*.o", "glob:**/d?r"]);
+        assert!(m.matches_file(repo_path("a/x.c")));
+        assert!(m.matches_file(repo_path("a/x.h")));
+        assert!(m.matches_file(repo_path("a/x.c/y")));
+        assert!(!m.matches_file(repo_path("a/b/x.c")));
+        assert!(m.matches_file(repo_path("x.o")));
+        assert!(m.matches_file(repo_path("a/b/x.o")));
+        assert!(m.matches_file(repo_path("b/dir/x")));
+        assert!(!m.matches_file(repo_path("b/x")));
+
+        let m = matcher(&["glob:a/b*"]);
+        assert!(m.matches_file(repo_path("a/bc/d")));
+        assert_eq!(
+            m.matches_directory(repo_path("a/bc")),
+            DirectoryMatch::Everything
+        );
+        assert_eq!(m.matches_directory(repo_path("b")), DirectoryMatch::Nothing);
+
+        assert!(build_patterns_matcher(&[Pattern::new(PatternKind::Glob, "a{b")]).is_err());
+    }
+
+    #[test]
+    fn test_regex_patterns() {
+        let m = matcher(&["re:a/.*\\.c$", "relre:b$", "relre:^c"]);
+        assert!(m.matches_file(repo_path("a/x.c")));
+        assert!(!m.matches_file(repo_path("x/a/x.c")));
+        assert!(m.matches_file(repo_path("x/ab")));
+        assert!(!m.matches_file(repo_path("x/ab/c")));
+        assert!(m.matches_file(repo_path("cd")));
+        assert!(!m.matches_file(repo_path("dc")));
+        assert_eq!(
+            m.matches_directory(repo_path("d")),
+            DirectoryMatch::ShouldTraverse
+        );
+
+        assert!(build_patterns_matcher(&[Pattern::new(PatternKind::Regexp, "(")]).is_err());
+    }
+
+    #[test]
+    fn test_include_exclude() {
+        let root = Path::new("/repo");
+        let m = build_matcher(
+            root,
+            root,
+            &["a", "b"],
+            &["**.c"],
+            &["a/x"],
+            PatternKind::RelPath,
+        )
+        .unwrap();
+        assert!(m.matches_file(repo_path("a/y.c")));
+        assert!(m.matches_file(repo_path("b/x.c")));
+        assert!(!m.matches_file(repo_path("a/y.h")));
+        assert!(!m.matches_file(repo_path("a/x/y.c")));
+        assert!(!m.matches_file(repo_path("c/y.c")));
+        assert_eq!(
+            m.matches_directory(repo_path("a/x")),
+            DirectoryMatch::Nothing
+        );
+        assert_eq!(m.matches_directory(repo_path("c")), DirectoryMatch::Nothing);
+
+        let m = build_matcher(
+            root,
+            root,
+            &[] as &[&str],
+            &[] as &[&str],
+            &["a"],
+            PatternKind::RelPath,
+        )
+        .unwrap();
+        assert!(m.matches_file(repo_path("b")));
+        assert!(!m.matches_file(repo_path("a/b")));
+        assert_eq!(
+            m.matches_directory(repo_path("b")),
+            DirectoryMatch::Everything
+        );
+    }
+}
@@ -0,0 +1,69 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use regex::Regex;
+
+use types::RepoPath;
+
+use crate::{DirectoryMatch, Matcher};
+
+/// Matches the paths that any of a list of regular expressions matches.
+///
+/// Like Mercurial's `re:` patterns, the regular expressions only need to
+/// match a prefix of the path.
+///
+/// A regular expression can't tell which directories can be skipped, so the
+/// whole tree has to be traversed.
+#[derive(Clone, Debug)]
+pub struct RegexMatcher {
+    regex: Regex,
+}
+
+impl RegexMatcher {
+    pub fn new(regexes: &[impl AsRef<str>]) -> Result<Self, regex::Error> {
+        let regexes: Vec<String> = regexes
+            .iter()
+            .map(|regex| format!("(?:{})", regex.as_ref()))
+            .collect();
+        let regex = Regex::new(&format!("^(?:{})", regexes.join("|")))?;
+        Ok(RegexMatcher { regex })
+    }
+}
+
+impl Matcher for RegexMatcher {
+    fn matches_directory(&self, _path: &RepoPath) -> DirectoryMatch {
+        DirectoryMatch::ShouldTraverse
+    }
+
+    fn matches_file(&self, path: &RepoPath) -> bool {
+        self.regex.is_match(path.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use types::testutil::repo_path;
+
+    #[test]
+    fn test_regex_matcher() {
+        let m = RegexMatcher::new(&["a/.*\\.c$", "b|c"]).unwrap();
+        assert!(m.matches_file(repo_path("a/b.c")));
+        assert!(m.matches_file(repo_path("a/b/c.c")));
+        assert!(!m.matches_file(repo_path("a/b.h")));
+        assert!(!m.matches_file(repo_path("x/a/b.c")));
+        assert!(m.matches_file(repo_path("bx")));
+        assert!(m.matches_file(repo_path("c/x")));
+        assert!(!m.matches_file(repo_path("xc")));
+        assert_eq!(
+            m.matches_directory(repo_path("x")),
+            DirectoryMatch::ShouldTraverse
+        );
+        assert!(RegexMatcher::new(&["a("]).is_err());
+    }
+}
@@ -0,0 +1,175 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Matchers combining other matchers.
+
+use types::RepoPath;
+
+use crate::{DirectoryMatch, Matcher};
+
+/// Matches the paths that any of the matchers matches.
+/// Matches nothing if there are no matchers.
+pub struct UnionMatcher<M> {
+    matchers: Vec<M>,
+}
+
+impl<M: Matcher> UnionMatcher<M> {
+    pub fn new(matchers: Vec<M>) -> Self {
+        UnionMatcher { matchers }
+    }
+}
+
+impl<M: Matcher> Matcher for UnionMatcher<M> {
+    fn matches_directory(&self, path: &RepoPath) -> DirectoryMatch {
+        let mut result = DirectoryMatch::Nothing;
+        for matcher in self.matchers.iter() {
+            match matcher.matches_directory(path) {
+                DirectoryMatch::Everything => return DirectoryMatch::Everything,
+                DirectoryMatch::Nothing => {}
+                DirectoryMatch::ShouldTraverse => result = DirectoryMatch::ShouldTraverse,
+            }
+        }
+        result
+    }
+
+    fn matches_file(&self, path: &RepoPath) -> bool {
+        self.matchers.iter().any(|m| m.matches_file(path))
+    }
+}
+
+/// Matches the paths that both matchers match.
+pub struct IntersectMatcher<A, B> {
+    a: A,
+    b: B,
+}
+
+impl<A: Matcher, B: Matcher> IntersectMatcher<A, B> {
+    pub fn new(a: A, b: B) -> Self {
+        IntersectMatcher { a, b }
+    }
+}
+
+impl<A: Matcher, B: Matcher> Matcher for IntersectMatcher<A, B> {
+    fn matches_directory(&self, path: &RepoPath) -> DirectoryMatch {
+        match (
+            self.a.matches_directory(path),
+            self.b.matches_directory(path),
+        ) {
+            (DirectoryMatch::Nothing, _) | (_, DirectoryMatch::Nothing) => DirectoryMatch::Nothing,
+            (DirectoryMatch::Everything, DirectoryMatch::Everything) => DirectoryMatch::Everything,
+            _ => DirectoryMatch::ShouldTraverse,
+        }
+    }
+
+    fn matches_file(&self, path: &RepoPath) -> bool {
+        self.a.matches_file(path) && self.b.matches_file(path)
+    }
+}
+
+/// Matches the paths that the first matcher matches and the second one
+/// doesn't.
+pub struct DifferenceMatcher<A, B> {
+    a: A,
+    b: B,
+}
+
+impl<A: Matcher, B: Matcher> DifferenceMatcher<A, B> {
+    pub fn new(a: A, b: B) -> Self {
+        DifferenceMatcher { a, b }
+    }
+}
+
+impl<A: Matcher, B: Matcher> Matcher for DifferenceMatcher<A, B> {
+    fn matches_directory(&self, path: &RepoPath) -> DirectoryMatch {
+        match (
+            self.a.matches_directory(path),
+            self.b.matches_directory(path),
+        ) {
+            (DirectoryMatch::Nothing, _) | (_, DirectoryMatch::Everything) => {
+                DirectoryMatch::Nothing
+            }
+            (DirectoryMatch::Everything, DirectoryMatch::Nothing) => DirectoryMatch::Everything,
+            _ => DirectoryMatch::ShouldTraverse,
+        }
+    }
+
+    fn matches_file(&self, path: &RepoPath) -> bool {
+        self.a.matches_file(path) && !self.b.matches_file(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use types::testutil::repo_path;
+
+    use crate::{AlwaysMatcher, NeverMatcher, TreeMatcher};
+
+    fn tree(rules: &[&str]) -> TreeMatcher {
+        TreeMatcher::from_rules(rules.iter()).unwrap()
+    }
+
+    #[test]
+    fn test_union_matcher() {
+        let m = UnionMatcher::new(vec![tree(&["a/**"]), tree(&["b/*.c"])]);
+        assert!(m.matches_file(repo_path("a/x")));
+        assert!(m.matches_file(repo_path("b/x.c")));
+        assert!(!m.matches_file(repo_path("b/x.h")));
+        assert_eq!(
+            m.matches_directory(repo_path("a")),
+            DirectoryMatch::Everything
+        );
+        assert_eq!(
+            m.matches_directory(repo_path("b")),
+            DirectoryMatch::ShouldTraverse
+        );
+        assert_eq!(m.matches_directory(repo_path("c")), DirectoryMatch::Nothing);
+
+        let m: UnionMatcher<NeverMatcher> = UnionMatcher::new(vec![]);
+        assert!(!m.matches_file(repo_path("a")));
+        assert_eq!(
+            m.matches_directory(RepoPath::empty()),
+            DirectoryMatch::Nothing
+        );
+    }
+
+    #[test]
+    fn test_intersect_matcher() {
+        let m = IntersectMatcher::new(tree(&["a/**", "b/**"]), tree(&["a/**", "**/*.c"]));
+        assert!(m.matches_file(repo_path("a/x")));
+        assert!(m.matches_file(repo_path("b/x.c")));
+        assert!(!m.matches_file(repo_path("b/x.h")));
+        assert!(!m.matches_file(repo_path("c/x.c")));
+        assert_eq!(
+            m.matches_directory(repo_path("a")),
+            DirectoryMatch::Everything
+        );
+        assert_eq!(
+            m.matches_directory(repo_path("b")),
+            DirectoryMatch::ShouldTraverse
+        );
+        assert_eq!(m.matches_directory(repo_path("c")), DirectoryMatch::Nothing);
+    }
+
+    #[test]
+    fn test_difference_matcher() {
+        let m = DifferenceMatcher::new(AlwaysMatcher::new(), tree(&["a/**", "b/*.c"]));
+        assert!(!m.matches_file(repo_path("a/x")));
+        assert!(!m.matches_file(repo_path("b/x.c")));
+        assert!(m.matches_file(repo_path("b/x.h")));
+        assert_eq!(m.matches_directory(repo_path("a")), DirectoryMatch::Nothing);
+        assert_eq!(
+            m.matches_directory(repo_path("b")),
+            DirectoryMatch::ShouldTraverse
+        );
+        assert_eq!(
+            m.matches_directory(repo_path("c")),
+            DirectoryMatch::Everything
+        );
+    }
+}
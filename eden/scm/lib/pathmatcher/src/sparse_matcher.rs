@@ -0,0 +1,506 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Sparse checkout profiles
+//!
+//! A profile lists patterns in `[include]` and `[exclude]` sections, and can
+//! pull in the rules of other profiles with `%include <path>`. Lines before
+//! any section are includes. Exclude rules override include rules. Lines
+//! starting with `#` or `;` are comments. A `[metadata]` section holds
+//! `key: value` or `key = value` pairs, with values continued on indented
+//! lines.
+//!
+//! Patterns are relative to the repository root, and are globs by default.
+
+use std::collections::{HashMap, HashSet};
+
+use regex::Regex;
+use thiserror::Error;
+
+use types::RepoPath;
+
+use crate::{
+    build_patterns_matcher, normalize_patterns, split_pattern, AlwaysMatcher, DifferenceMatcher,
+    DirectoryMatch, Matcher, Pattern, PatternError, PatternKind,
+};
+
+/// Pattern added to the includes of every profile that includes something,
+/// so files like `.hgignore` are always checked out.
+const IMPLICIT_INCLUDE: &str = ".hg*";
+
+#[derive(Debug, Error)]
+pub enum SparseError {
+    #[error("A sparse file cannot have includes after excludes in {0}:{1}")]
+    IncludeAfterExclude(String, usize),
+
+    #[error("cannot read sparse profile '{0}'")]
+    ReadProfile(String, #[source] anyhow::Error),
+
+    #[error("invalid sparse rule '{0}' in {1}")]
+    InvalidRule(String, String, #[source] PatternError),
+
+    #[error(transparent)]
+    Pattern(PatternError),
+}
+
+/// The content of a single sparse profile, without the profiles it includes.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SparseProfile {
+    /// Where the profile was read from, for error messages and explanations.
+    pub path: String,
+    pub includes: Vec<String>,
+    pub excludes: Vec<String>,
+    /// Paths of the profiles pulled in by `%include`.
+    pub profiles: Vec<String>,
+    pub metadata: HashMap<String, String>,
+}
+
+impl SparseProfile {
+    /// Parse a profile. Malformed metadata and rules starting with `/` are
+    /// skipped, like Mercurial does after warning about them.
+    pub fn from_bytes(path: impl ToString, data: &[u8]) -> Result<Self, SparseError> {
+        #[derive(PartialEq)]
+        enum Section {
+            Include,
+            Exclude,
+            Metadata,
+        }
+
+        let path = path.to_string();
+        let key_value = Regex::new(r"(?P<key>[^:=]*?)\s*[:=]\s*(?P<value>.*)").unwrap();
+        let mut profile = SparseProfile {
+            path,
+            ..Default::default()
+        };
+        let mut metadata: HashMap<String, Vec<String>> = HashMap::new();
+        let mut last_key: Option<String> = None;
+        let mut section = Section::Include;
+
+        let data = String::from_utf8_lossy(data);
+        for (index, line) in data.lines().enumerate() {
+            let stripped = line.trim();
+            if stripped.is_empty() || stripped.starts_with('#') || stripped.starts_with(';') {
+                continue;
+            }
+
+            if let Some(included) = stripped.strip_prefix("%include ") {
+                let included = included.trim();
+                if !included.is_empty() {
+                    profile.profiles.push(included.to_string());
+                }
+                continue;
+            }
+
+            let next_section = match stripped {
+                "[include]" => Some(Section::Include),
+                "[exclude]" => Some(Section::Exclude),
+                "[metadata]" => Some(Section::Metadata),
+                _ => None,
+            };
+            if let Some(next_section) = next_section {
+                if next_section == Section::Include && section == Section::Exclude {
+                    return Err(SparseError::IncludeAfterExclude(profile.path, index + 1));
+                }
+                section = next_section;
+                continue;
+            }
+
+            match section {
+                Section::Metadata => {
+                    if line.starts_with(' ') || line.starts_with('\t') {
+                        // A continuation of the previous value.
+                        if let Some(values) = last_key.as_ref().and_then(|k| metadata.get_mut(k)) {
+                            values.push(stripped.to_string());
+                        }
+                    } else if let Some(captures) = key_value.captures(stripped) {
+                        let key = captures["key"].trim().to_string();
+                        let value = captures["value"].trim().to_string();
+                        metadata.insert(key.clone(), vec![value]);
+                        last_key = Some(key);
+                    } else {
+                        last_key = None;
+                    }
+                }
+                _ if stripped.starts_with('/') => {}
+                Section::Include => push_unique(&mut profile.includes, stripped),
+                Section::Exclude => push_unique(&mut profile.excludes, stripped),
+            }
+        }
+
+        profile.metadata = metadata
+            .into_iter()
+            .map(|(key, values)| (key, values.join("\n").trim().to_string()))
+            .collect();
+        Ok(profile)
+    }
+}
+
+fn push_unique(rules: &mut Vec<String>, rule: &str) {
+    if !rules.iter().any(|r| r == rule) {
+        rules.push(rule.to_string());
+    }
+}
+
+/// A rule of a sparse profile, and the profile it comes from.
+#[derive(Clone, Debug)]
+struct SparseRule {
+    pattern: Pattern,
+    rule: String,
+    source: Option<String>,
+    include: bool,
+}
+
+/// Matches the files of a sparse checkout.
+///
+/// Built from a profile, usually `.hg/sparse`, and the profiles it includes
+/// transitively. A profile without any rules matches everything.
+pub struct SparseMatcher {
+    matcher: Box<dyn Matcher + Send + Sync>,
+    rules: Vec<SparseRule>,
+    profiles: Vec<String>,
+}
+
+impl SparseMatcher {
+    /// Build the matcher of `profile`.
+    ///
+    /// `read_profile` returns the content of the profiles pulled in by
+    /// `%include`, usually from the working copy parent, or `None` if there
+    /// is no such profile. Missing profiles are skipped.
+    pub fn from_profile(
+        profile: SparseProfile,
+        mut read_profile: impl FnMut(&str) -> anyhow::Result<Option<Vec<u8>>>,
+    ) -> Result<Self, SparseError> {
+        let mut rules = Vec::new();
+        let mut profiles = Vec::new();
+        let mut visited = HashSet::new();
+        let mut to_visit: Vec<String> = profile.profiles.iter().rev().cloned().collect();
+        add_rules(&mut rules, &profile)?;
+
+        while let Some(path) = to_visit.pop() {
+            if !visited.insert(path.clone()) {
+                continue;
+            }
+            let data = match read_profile(&path) {
+                Ok(Some(data)) => data,
+                Ok(None) => continue,
+                Err(e) => return Err(SparseError::ReadProfile(path, e)),
+            };
+            let included = SparseProfile::from_bytes(&path, &data)?;
+            add_rules(&mut rules, &included)?;
+            to_visit.extend(included.profiles.iter().rev().cloned());
+            profiles.push(path);
+        }
+
+        if rules.iter().any(|rule| rule.include) {
+            let pattern = Pattern::new(PatternKind::Glob, IMPLICIT_INCLUDE);
+            rules.push(SparseRule {
+                pattern,
+                rule: IMPLICIT_INCLUDE.to_string(),
+                source: None,
+                include: true,
+            });
+        }
+
+        let mut matcher: Box<dyn Matcher + Send + Sync> = if rules.iter().any(|r| r.include) {
+            build_rules_matcher(&rules, true)?
+        } else {
+            Box::new(AlwaysMatcher::new())
+        };
+        if rules.iter().any(|r| !r.include) {
+            let excludes = build_rules_matcher(&rules, false)?;
+            matcher = Box::new(DifferenceMatcher::new(matcher, excludes));
+        }
+
+        Ok(SparseMatcher {
+            matcher,
+            rules,
+            profiles,
+        })
+    }
+
+    /// Profiles included by the main profile, transitively. Missing profiles
+    /// are not listed.
+    pub fn profiles(&self) -> &[String] {
+        &self.profiles
+    }
+
+    /// Explain why a path is included in or excluded from the sparse
+    /// checkout, listing the rules that match it.
+    ///
+    /// Return human-readable text.
+    pub fn explain(&self, path: &RepoPath) -> String {
+        let mut text = String::new();
+        let matching: Vec<&SparseRule> = self
+            .rules
+            .iter()
+            .filter(|rule| rule_matches(rule, path))
+            .collect();
+
+        let mut included = false;
+        if self.rules.iter().all(|rule| !rule.include) {
+            text.push_str(&format!(
+                "{}: included because there are no include rules\n",
+                path
+            ));
+            included = true;
+        }
+        for rule in matching.iter().filter(|rule| rule.include) {
+            text.push_str(&format!(
+                "{}: included by rule {}{}\n",
+                path,
+                rule.rule,
+                from(rule)
+            ));
+            included = true;
+        }
+        if !included {
+            text.push_str(&format!("{}: not included by any rule\n", path));
+        }
+        // Exclude rules override include rules, whatever their order.
+        for rule in matching.iter().filter(|rule| !rule.include) {
+            let overrides = if included {
+                " (overrides previous rules)"
+            } else {
+                ""
+            };
+            text.push_str(&format!(
+                "{}: excluded by rule {}{}{}\n",
+                path,
+                rule.rule,
+                from(rule),
+                overrides
+            ));
+        }
+        text
+    }
+}
+
+fn from(rule: &SparseRule) -> String {
+    match &rule.source {
+        Some(source) => format!(" from {}", source),
+        None => String::new(),
+    }
+}
+
+/// Add the rules of a single profile. Includes are listed before excludes.
+fn add_rules(rules: &mut Vec<SparseRule>, profile: &SparseProfile) -> Result<(), SparseError> {
+    let mut new_rules = Vec::new();
+    for (include, lines) in [(true, &profile.includes), (false, &profile.excludes)].iter() {
+        for line in lines.iter() {
+            let invalid = |e| SparseError::InvalidRule(line.clone(), profile.path.clone(), e);
+            // These would read files from the working copy, which a profile can't depend on.
+            let kind = split_pattern(line, PatternKind::Glob).0;
+            match kind {
+                PatternKind::ListFile | PatternKind::ListFile0 | PatternKind::Include => {
+                    return Err(invalid(PatternError::Unsupported(kind)));
+                }
+                _ => {}
+            }
+            // Rules are relative to the repository root, any absolute path works as the root.
+            let root = std::path::Path::new("/");
+            let pattern = normalize_patterns(&[line], PatternKind::Glob, root, root)
+                .map_err(invalid)?
+                .pop();
+            if let Some(pattern) = pattern {
+                new_rules.push(SparseRule {
+                    pattern,
+                    rule: line.clone(),
+                    source: Some(profile.path.clone()),
+                    include: *include,
+                });
+            }
+        }
+    }
+    rules.extend(new_rules);
+    Ok(())
+}
+
+/// Build the matcher of either the include or the exclude rules.
+fn build_rules_matcher(
+    rules: &[SparseRule],
+    include: bool,
+) -> Result<Box<dyn Matcher + Send + Sync>, SparseError> {
+    let rules: Vec<&SparseRule> = rules.iter().filter(|r| r.include == include).collect();
+    let patterns: Vec<Pattern> = rules.iter().map(|r| r.pattern.clone()).collect();
+    build_patterns_matcher(&patterns).map_err(|e| {
+        // Point at the invalid rule if there is a single one.
+        for rule in rules.iter() {
+            if let Err(e) = build_patterns_matcher(std::slice::from_ref(&rule.pattern)) {
+                let source = rule.source.clone().unwrap_or_default();
+                return SparseError::InvalidRule(rule.rule.clone(), source, e);
+            }
+        }
+        SparseError::Pattern(e)
+    })
+}
+
+fn rule_matches(rule: &SparseRule, path: &RepoPath) -> bool {
+    build_patterns_matcher(std::slice::from_ref(&rule.pattern))
+        .map(|matcher| matcher.matches_file(path))
+        .unwrap_or(false)
+}
+
+impl Matcher for SparseMatcher {
+    fn matches_directory(&self, path: &RepoPath) -> DirectoryMatch {
+        self.matcher.matches_directory(path)
+    }
+
+    fn matches_file(&self, path: &RepoPath) -> bool {
+        self.matcher.matches_file(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use types::testutil::repo_path;
+
+    fn profile(path: &str, data: &str) -> SparseProfile {
+        SparseProfile::from_bytes(path, data.as_bytes()).unwrap()
+    }
+
+    fn matcher(data: &str, profiles: &[(&str, &str)]) -> SparseMatcher {
+        let profiles: HashMap<&str, &str> = profiles.iter().cloned().collect();
+        SparseMatcher::from_profile(profile(".hg/sparse", data), |path| {
+            Ok(profiles.get(path).map(|data| data.as_bytes().to_vec()))
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_parse_profile() {
+        let p = profile(
+            "p",
+            r#"# comment
+a
+%include base/profile
+[metadata]
+title: A profile
+description = multiple
+  lines
+url: http://example.com/a=b
+; hidden: true
+[include]
+b/**
+  c/**  
+/absolute
+[exclude]
+b/*.o
+ b/*.o
+"#,
+        );
+        assert_eq!(p.includes, vec!["a", "b/**", "c/**"]);
+        assert_eq!(p.excludes, vec!["b/*.o"]);
+        assert_eq!(p.profiles, vec!["base/profile"]);
+        assert_eq!(p.metadata.len(), 3);
+        assert_eq!(p.metadata["url"], "http://example.com/a=b");
+        assert_eq!(p.metadata["title"], "A profile");
+        assert_eq!(p.metadata["description"], "multiple\nlines");
+
+        match SparseProfile::from_bytes("p", b"[exclude]\na\n[include]\nb\n") {
+            Err(SparseError::IncludeAfterExclude(path, 3)) => assert_eq!(path, "p"),
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_sparse_matcher() {
+        let m = matcher(
+            "%include tools/base\n[include]\na\n[exclude]\na/build\n",
+            &[
+                (
+                    "tools/base",
+                    "%include tools/more\n%include tools/missing\nb/*.c\n",
+                ),
+                ("tools/more", "%include tools/base\n[exclude]\nb/x.c\n"),
+            ],
+        );
+        assert_eq!(m.profiles(), &["tools/base", "tools/more"]);
+        assert!(m.matches_file(repo_path("a/x")));
+        assert!(m.matches_file(repo_path("b/y.c")));
+        assert!(m.matches_file(repo_path(".hgignore")));
+        assert!(!m.matches_file(repo_path("a/build/x")));
+        assert!(!m.matches_file(repo_path("b/x.c")));
+        assert!(!m.matches_file(repo_path("b/y.h")));
+        assert!(!m.matches_file(repo_path("c")));
+        assert_eq!(
+            m.matches_directory(repo_path("a/build")),
+            DirectoryMatch::Nothing
+        );
+        assert_eq!(m.matches_directory(repo_path("c")), DirectoryMatch::Nothing);
+        assert_eq!(
+            m.matches_directory(repo_path("b")),
+            DirectoryMatch::ShouldTraverse
+        );
+
+        let m = matcher("[exclude]\na\n", &[]);
+        assert!(m.matches_file(repo_path("b")));
+        assert!(!m.matches_file(repo_path("a/b")));
+
+        let m = matcher("", &[]);
+        assert!(m.matches_file(repo_path("a")));
+        assert_eq!(
+            m.matches_directory(repo_path("a")),
+            DirectoryMatch::Everything
+        );
+    }
+
+    #[test]
+    fn test_sparse_matcher_errors() {
+        let read_error = SparseMatcher::from_profile(profile("p", "%include q\n"), |_| {
+            Err(anyhow::format_err!("no store"))
+        });
+        match read_error {
+            Err(SparseError::ReadProfile(path, _)) => assert_eq!(path, "q"),
+            _ => panic!("unexpected result"),
+        }
+
+        let invalid = SparseMatcher::from_profile(profile("p", "a\nre:(\n"), |_| Ok(None));
+        match invalid {
+            Err(SparseError::InvalidRule(rule, path, _)) => {
+                assert_eq!(rule, "re:(");
+                assert_eq!(path, "p");
+            }
+            _ => panic!("unexpected result"),
+        }
+
+        let unsupported = SparseMatcher::from_profile(profile("p", "listfile:x\n"), |_| Ok(None));
+        assert!(unsupported.is_err());
+    }
+
+    #[test]
+    fn test_explain() {
+        let m = matcher(
+            "%include base\n[include]\na\n",
+            &[("base", "a/**/*.c\n[exclude]\na/x.c\n")],
+        );
+        assert_eq!(
+            m.explain(repo_path("a/y.c")),
+            "a/y.c: included by rule a from .hg/sparse\n\
+             a/y.c: included by rule a/**/*.c from base\n"
+        );
+        assert_eq!(
+            m.explain(repo_path("a/x.c")),
+            "a/x.c: included by rule a from .hg/sparse\n\
+             a/x.c: included by rule a/**/*.c from base\n\
+             a/x.c: excluded by rule a/x.c from base (overrides previous rules)\n"
+        );
+        assert_eq!(
+            m.explain(repo_path(".hgignore")),
+            ".hgignore: included by rule .hg*\n"
+        );
+        assert_eq!(m.explain(repo_path("b")), "b: not included by any rule\n");
+
+        let m = matcher("[exclude]\nb\n", &[]);
+        assert_eq!(
+            m.explain(repo_path("b/c")),
+            "b/c: included because there are no include rules\n\
+             b/c: excluded by rule b from .hg/sparse (overrides previous rules)\n"
+        );
+    }
+}
@@ -169,7 +169,7 @@ pub fn plain_to_glob(plain: &str) -> String {
     }
     for ch in plain.chars() {
         match ch {
-            '\\' | '*' | '{' | '}' | '[' | ']' => result.push('\\'),
+            '\\' | '*' | '?' | '{' | '}' | '[' | ']' => result.push('\\'),
             _ => (),
         }
         result.push(ch);
@@ -196,5 +196,6 @@ mod tests {
         assert_eq!(plain_to_glob("a[b{c*d\\e}]"), "a\\[b\\{c\\*d\\\\e\\}\\]");
         assert_eq!(plain_to_glob(""), "");
         assert_eq!(plain_to_glob("!a!"), "\\!a!");
+        assert_eq!(plain_to_glob("a?"), "a\\?");
     }
 }
@@ -0,0 +1,443 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! A tiny HTTP server that serves a `LocalEdenApi` on localhost using the
+//! same CBOR wire format as the Mononoke API server, so that the curl client
+//! and the Python bindings can be tested end to end.
+
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+};
+
+use anyhow::{format_err, Result};
+use serde::Serialize;
+use tracing::debug;
+
+use types::api::{DataRequest, DataResponse, HistoryRequest, HistoryResponse, TreeRequest};
+
+use crate::localedenapi::{Fault, LocalEdenApi};
+
+mod paths {
+    pub const HEALTH_CHECK: &str = "/health_check";
+    pub const HOSTNAME: &str = "/hostname";
+    pub const DATA: &str = "eden/data";
+    pub const HISTORY: &str = "eden/history";
+    pub const TREES: &str = "eden/trees";
+    pub const PREFETCH_TREES: &str = "eden/trees/prefetch";
+}
+
+/// Serves a `LocalEdenApi` over HTTP/1.1 on a free port on localhost until
+/// it is dropped.
+///
+/// Every connection serves a single request and is then closed. Faults
+/// configured on the `LocalEdenApi` apply to the HTTP responses: errors are
+/// sent as HTTP errors, and truncated responses advertise their full length
+/// but close the connection early.
+pub struct EdenApiServer {
+    addr: SocketAddr,
+    shutdown: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl EdenApiServer {
+    /// Start serving `api` as the given repo.
+    pub fn start(api: Arc<LocalEdenApi>, repo: impl ToString) -> Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let repo = repo.to_string();
+
+        let thread = {
+            let shutdown = shutdown.clone();
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    if shutdown.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    let stream = match stream {
+                        Ok(stream) => stream,
+                        Err(e) => {
+                            debug!("EdenAPI test server failed to accept connection: {}", e);
+                            continue;
+                        }
+                    };
+                    let api = api.clone();
+                    let repo = repo.clone();
+                    thread::spawn(move || {
+                        if let Err(e) = handle_connection(&api, &repo, stream) {
+                            debug!("EdenAPI test server failed to handle request: {}", e);
+                        }
+                    });
+                }
+            })
+        };
+
+        Ok(Self {
+            addr,
+            shutdown,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Base URL of the server, suitable for `edenapi::Config::base_url_str`.
+    pub fn url(&self) -> String {
+        format!("http://{}/", self.addr)
+    }
+}
+
+impl Drop for EdenApiServer {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        // Wake up the accept loop so that it notices the shutdown.
+        let _ = TcpStream::connect(self.addr);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+struct Request {
+    method: String,
+    path: String,
+    stream: bool,
+    body: Vec<u8>,
+}
+
+struct Response {
+    status: u16,
+    content_type: &'static str,
+    /// Length advertised in the headers, which is larger than the body when
+    /// the response is truncated.
+    content_length: usize,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn text(status: u16, text: &str) -> Self {
+        Self {
+            status,
+            content_type: "text/plain",
+            content_length: text.len(),
+            body: text.as_bytes().to_vec(),
+        }
+    }
+}
+
+fn handle_connection(api: &LocalEdenApi, repo: &str, stream: TcpStream) -> Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+    let request = read_request(&mut reader, &mut writer)?;
+    let response = route(api, repo, &request)?;
+    write_response(&mut writer, &response)
+}
+
+fn read_request(reader: &mut impl BufRead, writer: &mut impl Write) -> Result<Request> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let method = parts
+        .next()
+        .ok_or_else(|| format_err!("empty request"))?
+        .to_string();
+    let target = parts
+        .next()
+        .ok_or_else(|| format_err!("request line has no target: {:?}", line))?;
+    let (path, query) = match target.find('?') {
+        Some(i) => (&target[..i], &target[i + 1..]),
+        None => (target, ""),
+    };
+    let path = path.to_string();
+    let stream = query.split('&').any(|param| param == "stream=true");
+
+    let mut content_length = 0;
+    let mut expect_continue = false;
+    loop {
+        let mut header = String::new();
+        reader.read_line(&mut header)?;
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(i) = header.find(':') {
+            let value = header[i + 1..].trim();
+            match header[..i].trim().to_ascii_lowercase().as_str() {
+                "content-length" => content_length = value.parse()?,
+                "expect" => expect_continue = value.eq_ignore_ascii_case("100-continue"),
+                _ => {}
+            }
+        }
+    }
+
+    if expect_continue {
+        writer.write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+        writer.flush()?;
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+
+    Ok(Request {
+        method,
+        path,
+        stream,
+        body,
+    })
+}
+
+fn route(api: &LocalEdenApi, repo: &str, request: &Request) -> Result<Response> {
+    let repo_path = |path: &str| format!("/{}/{}", repo, path);
+    let path = request.path.as_str();
+
+    Ok(match request.method.as_str() {
+        "GET" if path == paths::HEALTH_CHECK => Response::text(200, "I_AM_ALIVE"),
+        "GET" if path == paths::HOSTNAME => Response::text(200, "localhost"),
+        "POST" if path == repo_path(paths::DATA) => {
+            let req: DataRequest = serde_cbor::from_slice(&request.body)?;
+            let fault = api.begin_request();
+            let entries = api.file_entries(&req.keys)?;
+            encode(entries, request.stream, fault, DataResponse::new)?
+        }
+        "POST" if path == repo_path(paths::TREES) => {
+            let req: DataRequest = serde_cbor::from_slice(&request.body)?;
+            let fault = api.begin_request();
+            let entries = api.tree_entries(&req.keys)?;
+            encode(entries, request.stream, fault, DataResponse::new)?
+        }
+        "POST" if path == repo_path(paths::HISTORY) => {
+            let req: HistoryRequest = serde_cbor::from_slice(&request.body)?;
+            let fault = api.begin_request();
+            let entries = api.history_entries(&req.keys, req.depth)?;
+            encode(entries, request.stream, fault, HistoryResponse::new)?
+        }
+        "POST" if path == repo_path(paths::PREFETCH_TREES) => {
+            let req: TreeRequest = serde_cbor::from_slice(&request.body)?;
+            let fault = api.begin_request();
+            let keys = LocalEdenApi::prefetch_keys(&req.rootdir, &req.mfnodes);
+            let entries = api.tree_entries(&keys)?;
+            encode(entries, request.stream, fault, DataResponse::new)?
+        }
+        _ => Response::text(404, "not found"),
+    })
+}
+
+/// Encode the entries either as a single response value, or as a sequence
+/// of entries for streaming responses, applying the fault if there is one.
+fn encode<T, R>(
+    entries: Vec<T>,
+    stream: bool,
+    fault: Option<Fault>,
+    wrap: impl Fn(Vec<T>) -> R,
+) -> Result<Response>
+where
+    T: Clone + Serialize,
+    R: Serialize,
+{
+    let encode = |entries: Vec<T>| -> Result<Vec<u8>> {
+        if stream {
+            let mut body = Vec::new();
+            for entry in entries {
+                serde_cbor::to_writer(&mut body, &entry)?;
+            }
+            Ok(body)
+        } else {
+            Ok(serde_cbor::to_vec(&wrap(entries))?)
+        }
+    };
+
+    let sent = match fault {
+        Some(Fault::Error(status)) => return Ok(Response::text(status, "injected error")),
+        Some(Fault::Truncate(n)) if entries.len() > n => Some(encode(entries[..n].to_vec())?),
+        _ => None,
+    };
+    let body = encode(entries)?;
+
+    Ok(Response {
+        status: 200,
+        content_type: "application/cbor",
+        content_length: body.len(),
+        body: sent.unwrap_or(body),
+    })
+}
+
+fn write_response(writer: &mut impl Write, response: &Response) -> Result<()> {
+    let reason = match response.status {
+        200 => "OK",
+        404 => "Not Found",
+        _ => "Error",
+    };
+    write!(
+        writer,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status, reason, response.content_type, response.content_length
+    )?;
+    writer.write_all(&response.body)?;
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    use bytes::Bytes;
+    use tempfile::TempDir;
+
+    use edenapi::{ApiErrorKind, Config, EdenApi, EdenApiCurlClient};
+    use types::testutil::*;
+
+    use crate::{
+        datastore::{HgIdMutableDeltaStore, Metadata},
+        indexedlogdatastore::IndexedLogHgIdDataStore,
+        localedenapi::Faults,
+        testutil::*,
+        util::get_indexedlogdatastore_path,
+    };
+
+    fn server(dir: &TempDir, faults: Faults) -> Result<EdenApiServer> {
+        let data = IndexedLogHgIdDataStore::new(get_indexedlogdatastore_path(dir)?)?;
+        for (path, node) in &[("a", "1"), ("b", "2"), ("c", "3")] {
+            data.add(&delta(node, None, key(path, node)), &Metadata::default())?;
+        }
+        data.flush()?;
+
+        let api = LocalEdenApi::from_indexedlog(dir)?.faults(faults);
+        EdenApiServer::start(Arc::new(api), "repo")
+    }
+
+    fn client(server: &EdenApiServer, stream: bool) -> Result<EdenApiCurlClient> {
+        let config = Config::new()
+            .base_url_str(&server.url())?
+            .repo("repo")
+            .data_batch_size(Some(2))
+            .stream_data(stream)
+            .max_retries(1)
+            .retry_backoff(Duration::from_millis(0), Duration::from_millis(0));
+        Ok(EdenApiCurlClient::new(config)?)
+    }
+
+    #[test]
+    fn test_get_files() -> Result<()> {
+        let tmp = TempDir::new()?;
+        let server = server(&tmp, Faults::new())?;
+
+        for stream in &[false, true] {
+            let client = client(&server, *stream)?;
+            client.health_check()?;
+
+            let keys = vec![key("a", "1"), key("b", "2"), key("c", "3")];
+            let (entries, stats) = client.get_files(keys, None)?;
+            let mut entries = entries.collect::<Vec<_>>();
+            entries.sort();
+
+            assert_eq!(entries[0], (key("a", "1"), Bytes::from("1")));
+            assert_eq!(entries.len(), 3);
+            assert_eq!(stats.requests, 2);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_retry_faults() -> Result<()> {
+        let tmp = TempDir::new()?;
+        let faults = Faults::new()
+            .fail_request(0, 503)
+            .truncate_request(2, 1)
+            .fail_request(4, 500)
+            .fail_request(5, 500);
+        let server = server(&tmp, faults)?;
+        let client = client(&server, true)?;
+
+        // Each request fails once, either with an error or by being cut off,
+        // and succeeds when it is retried.
+        let keys = vec![key("a", "1"), key("b", "2")];
+        let (entries, stats) = client.get_files(keys.clone(), None)?;
+        assert_eq!(entries.count(), 2);
+        assert_eq!(stats.retries, 1);
+
+        let (entries, stats) = client.get_files(keys.clone(), None)?;
+        assert_eq!(entries.count(), 2);
+        assert_eq!(stats.retries, 1);
+
+        // The request still fails after it was retried once.
+        match client.get_files(keys, None) {
+            Err(e) => assert!(matches!(e.kind(), ApiErrorKind::RetriesExhausted(1))),
+            Ok(_) => panic!("request should fail after retrying"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_truncate_is_retryable() -> Result<()> {
+        let tmp = TempDir::new()?;
+        let server = server(&tmp, Faults::new().truncate_request(0, 1))?;
+        let config = Config::new()
+            .base_url_str(&server.url())?
+            .repo("repo")
+            .max_retries(0);
+        let client = EdenApiCurlClient::new(config)?;
+
+        // Curl reports a truncated response the same way as LocalEdenApi.
+        match client.get_files(vec![key("a", "1"), key("b", "2")], None) {
+            Err(e) => {
+                assert!(matches!(e.kind(), ApiErrorKind::Curl));
+                assert!(e.is_retryable());
+            }
+            Ok(_) => panic!("request should be truncated"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_circuit_breaker() -> Result<()> {
+        let tmp = TempDir::new()?;
+        let faults = Faults::new()
+            .fail_request(0, 503)
+            .fail_request(1, 503)
+            .fail_request(2, 503)
+            .fail_request(6, 503)
+            .fail_request(7, 503);
+        let server = server(&tmp, faults)?;
+        let config = Config::new()
+            .base_url_str(&server.url())?
+            .repo("repo")
+            .data_batch_size(Some(1))
+            .max_retries(1)
+            .retry_backoff(Duration::from_millis(0), Duration::from_millis(0))
+            .circuit_breaker(1, Duration::from_secs(3600));
+        let client = EdenApiCurlClient::new(config)?;
+
+        // All the batches fail at once, which opens the breaker, but they
+        // are still retried.
+        let keys = vec![key("a", "1"), key("b", "2"), key("c", "3")];
+        let (entries, stats) = client.get_files(keys, None)?;
+        assert_eq!(entries.count(), 3);
+        assert_eq!(stats.retries, 3);
+
+        // The breaker opens again once retries are exhausted, and later
+        // requests fail without being sent.
+        match client.get_files(vec![key("a", "1")], None) {
+            Err(e) => assert!(matches!(e.kind(), ApiErrorKind::RetriesExhausted(1))),
+            Ok(_) => panic!("request should fail after retrying"),
+        }
+        match client.get_files(vec![key("a", "1")], None) {
+            Err(e) => assert!(matches!(e.kind(), ApiErrorKind::CircuitOpen(_))),
+            Ok(_) => panic!("circuit breaker should be open"),
+        }
+        Ok(())
+    }
+}
@@ -11,6 +11,8 @@
 mod contentstore;
 mod dataindex;
 mod edenapi;
+#[cfg(any(test, feature = "for-tests"))]
+mod edenapiserver;
 #[cfg(all(fbcode_build, target_os = "linux"))]
 mod facebook;
 mod fanouttable;
@@ -18,10 +20,14 @@ mod historyindex;
 mod indexedloghistorystore;
 mod indexedlogutil;
 mod lfs;
+#[cfg(any(test, feature = "for-tests"))]
+mod localedenapi;
 mod memcache;
 mod metadatastore;
 mod remotestore;
 mod repack;
+#[cfg(unix)]
+mod sharedcache;
 mod sliceext;
 mod types;
 mod unionstore;
@@ -50,10 +56,14 @@ pub use crate::datastore::{
     ContentDataStore, ContentMetadata, Delta, HgIdDataStore, HgIdMutableDeltaStore, RemoteDataStore,
 };
 pub use crate::edenapi::EdenApiHgIdRemoteStore;
+#[cfg(any(test, feature = "for-tests"))]
+pub use crate::edenapiserver::EdenApiServer;
 pub use crate::historypack::{HistoryEntry, HistoryPack, HistoryPackVersion};
 pub use crate::historystore::{HgIdHistoryStore, HgIdMutableHistoryStore, RemoteHistoryStore};
 pub use crate::indexedlogdatastore::IndexedLogHgIdDataStore;
 pub use crate::indexedloghistorystore::IndexedLogHgIdHistoryStore;
+#[cfg(any(test, feature = "for-tests"))]
+pub use crate::localedenapi::{Fault, Faults, LocalEdenApi};
 pub use crate::localstore::LocalStore;
 pub use crate::memcache::MemcacheStore;
 pub use crate::metadatastore::{MetadataStore, MetadataStoreBuilder};
@@ -66,6 +76,8 @@ pub use crate::packstore::{
 };
 pub use crate::remotestore::HgIdRemoteStore;
 pub use crate::repack::{repack, RepackKind, RepackLocation, Repackable, ToKeys};
+#[cfg(unix)]
+pub use crate::sharedcache::{SharedCacheClient, SharedCacheServer, SharedCacheStats};
 pub use crate::types::{ContentHash, StoreKey};
 pub use crate::uniondatastore::UnionHgIdDataStore;
 pub use crate::util::Error;
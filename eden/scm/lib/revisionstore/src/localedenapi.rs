@@ -0,0 +1,472 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! An `EdenApi` implementation that serves data from local stores, so that
+//! consumers of the API can be tested without a Mononoke server.
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use bytes::Bytes;
+
+use edenapi::{
+    ApiError, ApiErrorKind, ApiResult, DownloadStats, EdenApi, ProgressFn, ProgressStats,
+};
+use types::{DataEntry, HgId, HistoryEntry, Key, Parents, RepoPathBuf, WireHistoryEntry};
+
+use crate::{
+    datastore::HgIdDataStore,
+    historystore::HgIdHistoryStore,
+    indexedlogdatastore::IndexedLogHgIdDataStore,
+    indexedloghistorystore::IndexedLogHgIdHistoryStore,
+    packstore::{CorruptionPolicy, DataPackStore, HistoryPackStore},
+    util::{get_indexedlogdatastore_path, get_indexedloghistorystore_path},
+};
+
+/// A failure injected into one request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Fault {
+    /// Fail the request with the given HTTP status code.
+    Error(u16),
+    /// Drop the connection after the given number of entries have been sent.
+    /// Responses that have no more entries than that are sent whole.
+    Truncate(usize),
+}
+
+/// Failures that a `LocalEdenApi` injects into its responses.
+///
+/// Requests are numbered from 0 in the order they are received, counting
+/// each batch as a separate request, so that a test can choose exactly
+/// which batch fails.
+#[derive(Clone, Debug, Default)]
+pub struct Faults {
+    requests: HashMap<usize, Fault>,
+    latency: Duration,
+}
+
+impl Faults {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Fail the given request with an HTTP error.
+    pub fn fail_request(mut self, request: usize, status: u16) -> Self {
+        self.requests.insert(request, Fault::Error(status));
+        self
+    }
+
+    /// Truncate the response to the given request after `entries` entries.
+    pub fn truncate_request(mut self, request: usize, entries: usize) -> Self {
+        self.requests.insert(request, Fault::Truncate(entries));
+        self
+    }
+
+    /// Delay every request by the given amount of time.
+    pub fn latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+}
+
+/// Serves the EdenAPI from local data and history stores.
+///
+/// Keys that aren't in the stores are left out of the responses, like the
+/// server does. Requests are split into batches the same way as the curl
+/// client would split them, and each batch counts as one request for
+/// `DownloadStats` and fault injection.
+pub struct LocalEdenApi {
+    files: Arc<dyn HgIdDataStore>,
+    trees: Arc<dyn HgIdDataStore>,
+    history: Arc<dyn HgIdHistoryStore>,
+    data_batch_size: Option<usize>,
+    history_batch_size: Option<usize>,
+    faults: Faults,
+    requests: AtomicUsize,
+}
+
+impl LocalEdenApi {
+    pub fn new(
+        files: Arc<dyn HgIdDataStore>,
+        trees: Arc<dyn HgIdDataStore>,
+        history: Arc<dyn HgIdHistoryStore>,
+    ) -> Self {
+        Self {
+            files,
+            trees,
+            history,
+            data_batch_size: None,
+            history_batch_size: None,
+            faults: Default::default(),
+            requests: AtomicUsize::new(0),
+        }
+    }
+
+    /// Serve the datapacks and historypacks in `dir`. Trees are looked up in
+    /// the same packs as files.
+    pub fn from_packs(dir: impl AsRef<Path>) -> Self {
+        let data = Arc::new(DataPackStore::new(&dir, CorruptionPolicy::IGNORE));
+        let history = Arc::new(HistoryPackStore::new(&dir, CorruptionPolicy::IGNORE));
+        Self::new(data.clone(), data, history)
+    }
+
+    /// Serve the indexedlog stores under `dir`, laid out the same way as in
+    /// the hgcache. Trees are looked up in the same store as files.
+    pub fn from_indexedlog(dir: impl AsRef<Path>) -> Result<Self> {
+        let data = Arc::new(IndexedLogHgIdDataStore::new(get_indexedlogdatastore_path(
+            &dir,
+        )?)?);
+        let history = Arc::new(IndexedLogHgIdHistoryStore::new(
+            get_indexedloghistorystore_path(&dir)?,
+        )?);
+        Ok(Self::new(data.clone(), data, history))
+    }
+
+    /// Number of keys served per file or tree request.
+    /// Setting this to `None` disables batching.
+    pub fn data_batch_size(mut self, size: Option<usize>) -> Self {
+        self.data_batch_size = size;
+        self
+    }
+
+    /// Number of keys served per history request.
+    /// Setting this to `None` disables batching.
+    pub fn history_batch_size(mut self, size: Option<usize>) -> Self {
+        self.history_batch_size = size;
+        self
+    }
+
+    pub fn faults(mut self, faults: Faults) -> Self {
+        self.faults = faults;
+        self
+    }
+
+    /// Number of requests received so far.
+    pub fn requests(&self) -> usize {
+        self.requests.load(Ordering::SeqCst)
+    }
+
+    /// Count a new request and return the fault to inject into it, if any,
+    /// after waiting for the configured latency.
+    pub(crate) fn begin_request(&self) -> Option<Fault> {
+        if self.faults.latency > Duration::from_secs(0) {
+            thread::sleep(self.faults.latency);
+        }
+        let request = self.requests.fetch_add(1, Ordering::SeqCst);
+        self.faults.requests.get(&request).copied()
+    }
+
+    pub(crate) fn file_entries(&self, keys: &[Key]) -> Result<Vec<DataEntry>> {
+        self.data_entries(&*self.files, keys)
+    }
+
+    pub(crate) fn tree_entries(&self, keys: &[Key]) -> Result<Vec<DataEntry>> {
+        self.data_entries(&*self.trees, keys)
+    }
+
+    fn data_entries(&self, store: &dyn HgIdDataStore, keys: &[Key]) -> Result<Vec<DataEntry>> {
+        let mut entries = Vec::with_capacity(keys.len());
+        for key in keys {
+            let data = match store.get(key)? {
+                Some(data) => data,
+                None => continue,
+            };
+            let parents = match self.history.get_node_info(key)? {
+                Some(info) => {
+                    let [p1, p2] = info.parents;
+                    Parents::new(p1.hgid, p2.hgid)
+                }
+                None => Parents::default(),
+            };
+            entries.push(DataEntry::new(key.clone(), Bytes::from(data), parents));
+        }
+        Ok(entries)
+    }
+
+    /// Walk the history of each key, up to `depth` entries deep.
+    pub(crate) fn history_entries(
+        &self,
+        keys: &[Key],
+        depth: Option<u32>,
+    ) -> Result<Vec<(RepoPathBuf, WireHistoryEntry)>> {
+        let mut entries = Vec::new();
+        for key in keys {
+            let mut seen = HashSet::new();
+            let mut queue = VecDeque::new();
+            queue.push_back((key.clone(), 0));
+            while let Some((key, key_depth)) = queue.pop_front() {
+                if depth.map_or(false, |depth| key_depth >= depth) || !seen.insert(key.clone()) {
+                    continue;
+                }
+                let nodeinfo = match self.history.get_node_info(&key)? {
+                    Some(nodeinfo) => nodeinfo,
+                    None => continue,
+                };
+                for parent in nodeinfo.parents.iter() {
+                    if !parent.hgid.is_null() {
+                        queue.push_back((parent.clone(), key_depth + 1));
+                    }
+                }
+                let path = key.path.clone();
+                let entry = HistoryEntry { key, nodeinfo };
+                entries.push((path, WireHistoryEntry::from(entry)));
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Only the root trees are served, since the stores don't know how trees
+    /// are laid out.
+    pub(crate) fn prefetch_keys(rootdir: &RepoPathBuf, mfnodes: &[HgId]) -> Vec<Key> {
+        mfnodes
+            .iter()
+            .map(|mfnode| Key::new(rootdir.clone(), *mfnode))
+            .collect()
+    }
+
+    fn fetch_data(
+        &self,
+        store: &dyn HgIdDataStore,
+        keys: Vec<Key>,
+        mut progress: Option<ProgressFn>,
+    ) -> ApiResult<(Box<dyn Iterator<Item = (Key, Bytes)>>, DownloadStats)> {
+        let start = Instant::now();
+        let batch_size = self.data_batch_size.unwrap_or(keys.len()).max(1);
+
+        let mut entries = Vec::with_capacity(keys.len());
+        let mut downloaded = 0;
+        let mut requests = 0;
+        for batch in keys.chunks(batch_size) {
+            let fault = self.begin_request();
+            requests += 1;
+            let batch = self
+                .data_entries(store, batch)
+                .map_err(|e| ApiError::new(ApiErrorKind::Store, e))?;
+            for entry in inject_fault(fault, batch)? {
+                let (data, _) = entry.data();
+                downloaded += data.len();
+                entries.push((entry.key().clone(), data));
+            }
+            if let Some(progress) = progress.as_mut() {
+                progress(ProgressStats::new(downloaded, 0, downloaded, 0));
+            }
+        }
+
+        let stats = self.stats(downloaded, requests, start);
+        Ok((Box::new(entries.into_iter()), stats))
+    }
+
+    fn stats(&self, downloaded: usize, requests: usize, start: Instant) -> DownloadStats {
+        DownloadStats {
+            downloaded,
+            uploaded: 0,
+            requests,
+            retries: 0,
+            time: start.elapsed(),
+            latency: self.faults.latency,
+        }
+    }
+}
+
+/// Apply a fault to a response the way a client would see it: either as an
+/// HTTP error, or as the error curl reports when the connection is closed
+/// before the whole response was received.
+fn inject_fault<T>(fault: Option<Fault>, entries: Vec<T>) -> ApiResult<Vec<T>> {
+    match fault {
+        Some(Fault::Error(status)) => Err(ApiError::from_http(status as u32, "injected error")),
+        Some(Fault::Truncate(n)) if entries.len() > n => {
+            Err(curl::Error::new(curl_sys::CURLE_PARTIAL_FILE).into())
+        }
+        _ => Ok(entries),
+    }
+}
+
+impl EdenApi for LocalEdenApi {
+    fn health_check(&self) -> ApiResult<()> {
+        Ok(())
+    }
+
+    fn hostname(&self) -> ApiResult<String> {
+        Ok("localhost".to_string())
+    }
+
+    fn get_files(
+        &self,
+        keys: Vec<Key>,
+        progress: Option<ProgressFn>,
+    ) -> ApiResult<(Box<dyn Iterator<Item = (Key, Bytes)>>, DownloadStats)> {
+        self.fetch_data(&*self.files, keys, progress)
+    }
+
+    fn get_history(
+        &self,
+        keys: Vec<Key>,
+        max_depth: Option<u32>,
+        _progress: Option<ProgressFn>,
+    ) -> ApiResult<(Box<dyn Iterator<Item = HistoryEntry>>, DownloadStats)> {
+        let start = Instant::now();
+        let batch_size = self.history_batch_size.unwrap_or(keys.len()).max(1);
+
+        let mut entries = Vec::new();
+        let mut requests = 0;
+        for batch in keys.chunks(batch_size) {
+            let fault = self.begin_request();
+            requests += 1;
+            let batch = self
+                .history_entries(batch, max_depth)
+                .map_err(|e| ApiError::new(ApiErrorKind::Store, e))?;
+            for (path, entry) in inject_fault(fault, batch)? {
+                entries.push(HistoryEntry::from_wire(entry, path));
+            }
+        }
+
+        let stats = self.stats(0, requests, start);
+        Ok((Box::new(entries.into_iter()), stats))
+    }
+
+    fn get_trees(
+        &self,
+        keys: Vec<Key>,
+        progress: Option<ProgressFn>,
+    ) -> ApiResult<(Box<dyn Iterator<Item = (Key, Bytes)>>, DownloadStats)> {
+        self.fetch_data(&*self.trees, keys, progress)
+    }
+
+    fn prefetch_trees(
+        &self,
+        rootdir: RepoPathBuf,
+        mfnodes: Vec<HgId>,
+        _basemfnodes: Vec<HgId>,
+        _depth: Option<usize>,
+        progress: Option<ProgressFn>,
+    ) -> ApiResult<(Box<dyn Iterator<Item = (Key, Bytes)>>, DownloadStats)> {
+        // Tree prefetches are sent as a single request.
+        let keys = Self::prefetch_keys(&rootdir, &mfnodes);
+        let start = Instant::now();
+        let fault = self.begin_request();
+        let entries = self
+            .data_entries(&*self.trees, &keys)
+            .map_err(|e| ApiError::new(ApiErrorKind::Store, e))?;
+        let entries = inject_fault(fault, entries)?
+            .into_iter()
+            .map(|entry| (entry.key().clone(), entry.data().0))
+            .collect::<Vec<_>>();
+        let downloaded = entries.iter().map(|(_, data)| data.len()).sum();
+        if let Some(mut progress) = progress {
+            progress(ProgressStats::new(downloaded, 0, downloaded, 0));
+        }
+
+        let stats = self.stats(downloaded, 1, start);
+        Ok((Box::new(entries.into_iter()), stats))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::TempDir;
+
+    use types::{testutil::*, NodeInfo};
+
+    use crate::{
+        datastore::{HgIdMutableDeltaStore, Metadata},
+        historystore::HgIdMutableHistoryStore,
+        testutil::*,
+    };
+
+    fn local_edenapi(dir: &TempDir) -> Result<LocalEdenApi> {
+        let data = IndexedLogHgIdDataStore::new(get_indexedlogdatastore_path(dir)?)?;
+        let history = IndexedLogHgIdHistoryStore::new(get_indexedloghistorystore_path(dir)?)?;
+
+        for (path, node) in &[("a", "1"), ("b", "2"), ("c", "3")] {
+            let k = key(path, node);
+            data.add(&delta(node, None, k.clone()), &Metadata::default())?;
+        }
+        let parent = NodeInfo {
+            parents: [null_key("a"), null_key("a")],
+            linknode: hgid("10"),
+        };
+        let child = NodeInfo {
+            parents: [key("a", "1"), null_key("a")],
+            linknode: hgid("11"),
+        };
+        history.add(&key("a", "1"), &parent)?;
+        history.add(&key("a", "4"), &child)?;
+        data.flush()?;
+        history.flush()?;
+
+        LocalEdenApi::from_indexedlog(dir)
+    }
+
+    #[test]
+    fn test_get_files() -> Result<()> {
+        let tmp = TempDir::new()?;
+        let api = local_edenapi(&tmp)?.data_batch_size(Some(2));
+
+        let keys = vec![key("a", "1"), key("b", "2"), key("c", "3"), key("d", "5")];
+        let (entries, stats) = api.get_files(keys, None)?;
+        let entries = entries.collect::<Vec<_>>();
+
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0], (key("a", "1"), Bytes::from("1")));
+        assert_eq!(stats.requests, 2);
+        assert_eq!(stats.downloaded, 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_history() -> Result<()> {
+        let tmp = TempDir::new()?;
+        let api = local_edenapi(&tmp)?;
+
+        let (entries, _) = api.get_history(vec![key("a", "4")], None, None)?;
+        let keys = entries.map(|entry| entry.key).collect::<Vec<_>>();
+        assert_eq!(keys, vec![key("a", "4"), key("a", "1")]);
+
+        let (entries, _) = api.get_history(vec![key("a", "4")], Some(1), None)?;
+        assert_eq!(entries.count(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_faults() -> Result<()> {
+        let tmp = TempDir::new()?;
+        let faults = Faults::new()
+            .fail_request(1, 503)
+            .truncate_request(2, 0)
+            .truncate_request(3, 1);
+        let api = local_edenapi(&tmp)?.data_batch_size(Some(1)).faults(faults);
+
+        let keys = vec![key("a", "1"), key("b", "2")];
+        match api.get_files(keys.clone(), None) {
+            Err(e) => assert!(e.is_retryable()),
+            Ok(_) => panic!("request 1 should fail"),
+        }
+        match api.get_files(keys.clone(), None) {
+            Err(e) => {
+                assert!(matches!(e.kind(), ApiErrorKind::Curl));
+                assert!(e.is_retryable());
+            }
+            Ok(_) => panic!("request 2 should be truncated"),
+        }
+        // A response with a single entry isn't affected by truncating it
+        // after one entry.
+        let (entries, _) = api.get_files(keys, None)?;
+        assert_eq!(entries.count(), 2);
+        assert_eq!(api.requests(), 5);
+        Ok(())
+    }
+}
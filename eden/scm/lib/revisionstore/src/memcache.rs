@@ -45,7 +45,7 @@ pub(crate) struct McHist {
     pub nodeinfo: NodeInfo,
 }
 
-#[cfg(not(all(fbcode_build, target_os = "linux")))]
+#[cfg(not(any(all(fbcode_build, target_os = "linux"), unix)))]
 mod dummy {
     use super::*;
 
@@ -75,10 +75,78 @@ mod dummy {
     }
 }
 
+#[cfg(all(unix, not(all(fbcode_build, target_os = "linux"))))]
+mod shared {
+    use super::*;
+
+    use configparser::config::ConfigSet;
+
+    use crate::sharedcache::SharedCacheClient;
+
+    /// Memcache client for when Mercurial is compiled outside of fbcode. It talks to the local
+    /// shared cache daemon if `sharedcache.socket` is set, and does nothing otherwise.
+    pub struct MemcacheStore {
+        client: Option<SharedCacheClient>,
+    }
+
+    fn into_results<T>(found: Result<Vec<T>>) -> impl Iterator<Item = Result<T>> {
+        match found {
+            Ok(found) => found.into_iter().map(Ok).collect::<Vec<_>>(),
+            Err(e) => vec![Err(e)],
+        }
+        .into_iter()
+    }
+
+    impl MemcacheStore {
+        pub fn new(config: &ConfigSet) -> Result<Self> {
+            Ok(MemcacheStore {
+                client: SharedCacheClient::from_config(config)?,
+            })
+        }
+
+        pub(super) fn get_data_iter(&self, keys: &[Key]) -> impl Iterator<Item = Result<McData>> {
+            into_results(match &self.client {
+                Some(client) => client.get_data(keys),
+                None => Ok(Vec::new()),
+            })
+        }
+
+        pub(super) fn add_data(&self, delta: &Delta, metadata: &Metadata) {
+            if let Some(client) = &self.client {
+                // Like memcache, failing to populate the cache is not an error.
+                let _ = client.add_data(McData {
+                    key: delta.key.clone(),
+                    data: delta.data.clone(),
+                    metadata: *metadata,
+                });
+            }
+        }
+
+        pub(super) fn get_hist_iter(&self, keys: &[Key]) -> impl Iterator<Item = Result<McHist>> {
+            into_results(match &self.client {
+                Some(client) => client.get_hist(keys),
+                None => Ok(Vec::new()),
+            })
+        }
+
+        pub(super) fn add_hist(&self, key: &Key, info: &NodeInfo) {
+            if let Some(client) = &self.client {
+                let _ = client.add_hist(McHist {
+                    key: key.clone(),
+                    nodeinfo: info.clone(),
+                });
+            }
+        }
+    }
+}
+
 #[cfg(all(fbcode_build, target_os = "linux"))]
 pub use crate::facebook::MemcacheStore;
 
-#[cfg(not(all(fbcode_build, target_os = "linux")))]
+#[cfg(all(unix, not(all(fbcode_build, target_os = "linux"))))]
+pub use shared::MemcacheStore;
+
+#[cfg(not(any(all(fbcode_build, target_os = "linux"), unix)))]
 pub use dummy::MemcacheStore;
 
 impl HgIdDataStore for MemcacheStore {
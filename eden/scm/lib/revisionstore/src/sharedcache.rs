@@ -0,0 +1,684 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! A cache shared by the repositories on a machine.
+//!
+//! [`SharedCacheServer`] is a small daemon that keeps file and history data in an IndexedLog and
+//! serves it over a Unix socket. [`SharedCacheClient`] talks to it. Outside of fbcode builds,
+//! `MemcacheStore` uses the client, so checkouts on a machine fetch the same data from the
+//! network only once.
+//!
+//! The config options are:
+//!
+//! ```text
+//! [sharedcache]
+//! # Socket of the daemon. The shared cache is disabled if it is not set.
+//! socket = /var/run/hgcache/socket
+//! # Where the daemon stores the data.
+//! path = /var/cache/hgcache
+//! # The oldest log is removed when there are more than `maxlogs` logs of `maxbytesperlog`.
+//! maxbytesperlog = 1GB
+//! maxlogs = 4
+//! ```
+//!
+//! Entries read from an older log are appended to the latest log again, so recently used data
+//! survives the removal of old logs. This makes the cache an approximate LRU.
+//!
+//! The socket is only accessible to the user running the daemon. Clients still check the data
+//! they get against its hgid, using the history from the shared cache, and treat data that can't
+//! be checked as a miss.
+
+use std::{
+    collections::HashMap,
+    fmt, fs,
+    io::{ErrorKind, Read, Write},
+    os::unix::{
+        fs::PermissionsExt,
+        net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+    process,
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+
+use anyhow::{bail, format_err, Result};
+use byteorder::{BigEndian, ByteOrder, ReadBytesExt, WriteBytesExt};
+use parking_lot::Mutex;
+use serde::de::DeserializeOwned;
+use serde_derive::{Deserialize, Serialize};
+use tracing::warn;
+
+use configparser::{
+    config::ConfigSet,
+    hg::{ByteCount, ConfigSetHgExt},
+};
+use indexedlog::{
+    log::IndexOutput,
+    rotate::{OpenOptions, RotateLog, RotateLowLevelExt},
+};
+use types::{DataEntry, HgId, Key, Parents, Validity};
+
+use crate::memcache::{McData, McHist};
+
+/// How long a client waits for the daemon before giving up on a request.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long the daemon waits before accepting again after a failed accept.
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Largest message that is sent or accepted, so that a peer can't make the other side allocate
+/// arbitrary amounts of memory.
+const MAX_MESSAGE_LEN: u32 = 256 * 1024 * 1024;
+
+#[derive(Serialize, Deserialize)]
+enum Request {
+    GetData(Vec<Key>),
+    AddData(McData),
+    GetHist(Vec<Key>),
+    AddHist(McHist),
+    Stats,
+}
+
+#[derive(Serialize, Deserialize)]
+enum Response {
+    Data(Vec<McData>),
+    Hist(Vec<McHist>),
+    Stats(SharedCacheStats),
+    Done,
+}
+
+/// Counters of the requests served by a [`SharedCacheServer`] since it started.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SharedCacheStats {
+    pub data_hits: u64,
+    pub data_misses: u64,
+    pub data_adds: u64,
+    pub hist_hits: u64,
+    pub hist_misses: u64,
+    pub hist_adds: u64,
+}
+
+impl fmt::Display for SharedCacheStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "data: {} hits, {} misses, {} adds",
+            self.data_hits, self.data_misses, self.data_adds
+        )?;
+        writeln!(
+            f,
+            "history: {} hits, {} misses, {} adds",
+            self.hist_hits, self.hist_misses, self.hist_adds
+        )
+    }
+}
+
+/// Write a message as a 4 bytes big-endian length followed by its mincode serialization.
+fn write_message<T: serde::Serialize>(writer: &mut impl Write, value: &T) -> Result<()> {
+    let buf = mincode::serialize(value)?;
+    if buf.len() > MAX_MESSAGE_LEN as usize {
+        bail!("shared cache message of {} bytes is too large", buf.len());
+    }
+    writer.write_u32::<BigEndian>(buf.len() as u32)?;
+    writer.write_all(&buf)?;
+    Ok(())
+}
+
+/// Read a message written by `write_message`. Return `None` if the connection was closed.
+fn read_message<T: DeserializeOwned>(reader: &mut impl Read) -> Result<Option<T>> {
+    let len = match reader.read_u32::<BigEndian>() {
+        Ok(len) => len,
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    if len > MAX_MESSAGE_LEN {
+        bail!("shared cache message of {} bytes is too large", len);
+    }
+    let mut buf = vec![0; len as usize];
+    reader.read_exact(&mut buf)?;
+    Ok(Some(mincode::deserialize(&buf)?))
+}
+
+fn socket_path(config: &ConfigSet) -> Result<Option<PathBuf>> {
+    config.get_opt::<PathBuf>("sharedcache", "socket")
+}
+
+const KIND_DATA: u8 = 0;
+const KIND_HIST: u8 = 1;
+
+/// The storage of the daemon.
+///
+/// The format of an entry is the following:
+/// - Kind: 1 byte, `KIND_DATA` or `KIND_HIST`
+/// - HgId: 20 bytes
+/// - Path len: 2 unsigned bytes, big-endian
+/// - Path: <Path len> bytes
+/// - Value: mincode serialized `McData` or `McHist`
+///
+/// Everything before the value is indexed.
+struct CacheLog {
+    log: RotateLog,
+}
+
+impl CacheLog {
+    fn open(path: &Path, max_bytes_per_log: u64, max_log_count: u8) -> Result<Self> {
+        let log = OpenOptions::new()
+            .max_log_count(max_log_count)
+            .max_bytes_per_log(max_bytes_per_log)
+            .auto_sync_threshold(10 * 1024 * 1024)
+            .create(true)
+            .index("key", |data| {
+                let header_len = 1 + HgId::len() + 2;
+                if data.len() < header_len {
+                    return Vec::new();
+                }
+                let path_len = BigEndian::read_u16(&data[header_len - 2..header_len]) as usize;
+                vec![IndexOutput::Reference(0..(header_len + path_len) as u64)]
+            })
+            .open(path)?;
+        Ok(CacheLog { log })
+    }
+
+    fn index_key(kind: u8, key: &Key) -> Result<Vec<u8>> {
+        let path = key.path.as_byte_slice();
+        if path.len() > u16::max_value() as usize {
+            bail!(
+                "path of {} bytes is too long for the shared cache",
+                path.len()
+            );
+        }
+        let mut buf = Vec::with_capacity(1 + HgId::len() + 2 + path.len());
+        buf.push(kind);
+        buf.extend_from_slice(key.hgid.as_ref());
+        buf.extend_from_slice(&(path.len() as u16).to_be_bytes());
+        buf.extend_from_slice(path);
+        Ok(buf)
+    }
+
+    fn in_latest_log(&self, index_key: &[u8]) -> Result<bool> {
+        match self.log.logs().first() {
+            Some(log) => Ok(log.lookup(0, index_key)?.next().is_some()),
+            None => Ok(false),
+        }
+    }
+
+    /// Read the value of `key`. Append it to the latest log again if it is in an older log.
+    fn get(&mut self, kind: u8, key: &Key) -> Result<Option<Vec<u8>>> {
+        let index_key = Self::index_key(kind, key)?;
+        let entry = match self.log.lookup(0, index_key.clone())?.next() {
+            None => return Ok(None),
+            Some(entry) => entry?.to_vec(),
+        };
+        if !self.in_latest_log(&index_key)? {
+            self.log.append(&entry)?;
+        }
+        Ok(Some(entry[index_key.len()..].to_vec()))
+    }
+
+    fn add(&mut self, kind: u8, key: &Key, value: &[u8]) -> Result<()> {
+        let mut entry = Self::index_key(kind, key)?;
+        if self.in_latest_log(&entry)? {
+            return Ok(());
+        }
+        entry.extend_from_slice(value);
+        Ok(self.log.append(entry)?)
+    }
+}
+
+struct ServerInner {
+    log: Mutex<CacheLog>,
+    stats: Mutex<SharedCacheStats>,
+}
+
+impl ServerInner {
+    fn handle(&self, mut stream: UnixStream) -> Result<()> {
+        while let Some(request) = read_message(&mut stream)? {
+            let response = self.respond(request)?;
+            write_message(&mut stream, &response)?;
+        }
+        self.log.lock().log.sync()?;
+        Ok(())
+    }
+
+    fn respond(&self, request: Request) -> Result<Response> {
+        let response = match request {
+            Request::GetData(keys) => {
+                let mut found = Vec::new();
+                for key in keys.iter() {
+                    if let Some(value) = self.log.lock().get(KIND_DATA, key)? {
+                        found.push(mincode::deserialize::<McData>(&value)?);
+                    }
+                }
+                let mut stats = self.stats.lock();
+                stats.data_hits += found.len() as u64;
+                stats.data_misses += (keys.len() - found.len()) as u64;
+                Response::Data(found)
+            }
+            Request::GetHist(keys) => {
+                let mut found = Vec::new();
+                for key in keys.iter() {
+                    if let Some(value) = self.log.lock().get(KIND_HIST, key)? {
+                        found.push(mincode::deserialize::<McHist>(&value)?);
+                    }
+                }
+                let mut stats = self.stats.lock();
+                stats.hist_hits += found.len() as u64;
+                stats.hist_misses += (keys.len() - found.len()) as u64;
+                Response::Hist(found)
+            }
+            Request::AddData(data) => {
+                let value = mincode::serialize(&data)?;
+                self.log.lock().add(KIND_DATA, &data.key, &value)?;
+                self.stats.lock().data_adds += 1;
+                Response::Done
+            }
+            Request::AddHist(hist) => {
+                let value = mincode::serialize(&hist)?;
+                self.log.lock().add(KIND_HIST, &hist.key, &value)?;
+                self.stats.lock().hist_adds += 1;
+                Response::Done
+            }
+            Request::Stats => Response::Stats(self.stats.lock().clone()),
+        };
+        Ok(response)
+    }
+}
+
+/// The shared cache daemon.
+pub struct SharedCacheServer {
+    listener: UnixListener,
+    inner: Arc<ServerInner>,
+}
+
+impl SharedCacheServer {
+    /// Open the storage and listen on the socket configured in `config`.
+    pub fn new(config: &ConfigSet) -> Result<Self> {
+        let socket =
+            socket_path(config)?.ok_or_else(|| format_err!("sharedcache.socket is not set"))?;
+        let path = config
+            .get_opt::<PathBuf>("sharedcache", "path")?
+            .ok_or_else(|| format_err!("sharedcache.path is not set"))?;
+        let max_bytes_per_log = config
+            .get_or("sharedcache", "maxbytesperlog", || {
+                ByteCount::from(1000 * 1000 * 1000)
+            })?
+            .value();
+        let max_log_count = config.get_or("sharedcache", "maxlogs", || 4)?;
+
+        let log = CacheLog::open(&path, max_bytes_per_log, max_log_count)?;
+        Self::bind(&socket, log)
+    }
+
+    fn bind(socket: &Path, log: CacheLog) -> Result<Self> {
+        if socket.exists() {
+            if UnixStream::connect(socket).is_ok() {
+                bail!("another shared cache is listening on {}", socket.display());
+            }
+            // Left behind by a daemon that did not exit cleanly.
+            std::fs::remove_file(socket)?;
+        }
+        // Bind to a temporary path and only make the socket visible once it is restricted to
+        // the current user, so that nobody else can connect in between.
+        let tmp = socket.with_extension(format!("tmp{}", process::id()));
+        let _ = fs::remove_file(&tmp);
+        let listener = UnixListener::bind(&tmp)?;
+        fs::set_permissions(&tmp, fs::Permissions::from_mode(0o600))?;
+        fs::rename(&tmp, socket)?;
+        Ok(SharedCacheServer {
+            listener,
+            inner: Arc::new(ServerInner {
+                log: Mutex::new(log),
+                stats: Mutex::new(Default::default()),
+            }),
+        })
+    }
+
+    /// Serve clients forever. Each client is served by a thread.
+    ///
+    /// Errors accepting a connection (too many open files, a client that went away before it was
+    /// accepted) are logged and retried after a short pause instead of stopping the daemon.
+    pub fn serve(&self) -> Result<()> {
+        for stream in self.listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    warn!(error = %e, "shared cache failed to accept a connection");
+                    thread::sleep(ACCEPT_RETRY_DELAY);
+                    continue;
+                }
+            };
+            let inner = self.inner.clone();
+            thread::spawn(move || {
+                // An error only affects the client of this connection.
+                let _ = inner.handle(stream);
+            });
+        }
+        Ok(())
+    }
+
+    pub fn stats(&self) -> SharedCacheStats {
+        self.inner.stats.lock().clone()
+    }
+}
+
+/// A client of [`SharedCacheServer`]. The connection is reused across requests.
+pub struct SharedCacheClient {
+    socket: PathBuf,
+    stream: Mutex<Option<UnixStream>>,
+}
+
+impl SharedCacheClient {
+    pub fn new(socket: impl AsRef<Path>) -> Self {
+        SharedCacheClient {
+            socket: socket.as_ref().to_path_buf(),
+            stream: Mutex::new(None),
+        }
+    }
+
+    /// Return `None` if the shared cache is not configured.
+    pub fn from_config(config: &ConfigSet) -> Result<Option<Self>> {
+        Ok(socket_path(config)?.map(SharedCacheClient::new))
+    }
+
+    fn connect(&self) -> Result<UnixStream> {
+        let stream = UnixStream::connect(&self.socket)?;
+        stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+        stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+        Ok(stream)
+    }
+
+    fn request(&self, request: &Request) -> Result<Response> {
+        let mut stream = self.stream.lock();
+        if let Some(existing) = stream.as_mut() {
+            if let Ok(Some(response)) =
+                write_message(existing, request).and_then(|()| read_message(existing))
+            {
+                return Ok(response);
+            }
+            // The daemon might have been restarted. Retry with a new connection.
+        }
+
+        *stream = None;
+        let mut new_stream = self.connect()?;
+        write_message(&mut new_stream, request)?;
+        let response = read_message(&mut new_stream)?
+            .ok_or_else(|| format_err!("shared cache closed the connection"))?;
+        *stream = Some(new_stream);
+        Ok(response)
+    }
+
+    /// Return the data of `keys` that is in the shared cache and matches its hgid.
+    pub(crate) fn get_data(&self, keys: &[Key]) -> Result<Vec<McData>> {
+        let found = match self.request(&Request::GetData(keys.to_vec()))? {
+            Response::Data(found) => found,
+            _ => bail!("unexpected response from shared cache"),
+        };
+        if found.is_empty() {
+            return Ok(found);
+        }
+
+        let found_keys = found
+            .iter()
+            .map(|data| data.key.clone())
+            .collect::<Vec<_>>();
+        let parents = self
+            .get_hist(&found_keys)?
+            .into_iter()
+            .map(|hist| {
+                let [p1, p2] = hist.nodeinfo.parents;
+                (hist.key, Parents::new(p1.hgid, p2.hgid))
+            })
+            .collect::<HashMap<_, _>>();
+        Ok(found
+            .into_iter()
+            .filter(|data| match parents.get(&data.key) {
+                Some(parents) => is_valid(data, parents),
+                None => false,
+            })
+            .collect())
+    }
+
+    pub(crate) fn add_data(&self, data: McData) -> Result<()> {
+        match self.request(&Request::AddData(data))? {
+            Response::Done => Ok(()),
+            _ => bail!("unexpected response from shared cache"),
+        }
+    }
+
+    pub(crate) fn get_hist(&self, keys: &[Key]) -> Result<Vec<McHist>> {
+        match self.request(&Request::GetHist(keys.to_vec()))? {
+            Response::Hist(found) => Ok(found),
+            _ => bail!("unexpected response from shared cache"),
+        }
+    }
+
+    pub(crate) fn add_hist(&self, hist: McHist) -> Result<()> {
+        match self.request(&Request::AddHist(hist))? {
+            Response::Done => Ok(()),
+            _ => bail!("unexpected response from shared cache"),
+        }
+    }
+
+    pub fn stats(&self) -> Result<SharedCacheStats> {
+        match self.request(&Request::Stats)? {
+            Response::Stats(stats) => Ok(stats),
+            _ => bail!("unexpected response from shared cache"),
+        }
+    }
+}
+
+fn is_valid(data: &McData, parents: &Parents) -> bool {
+    let entry = DataEntry::new(data.key.clone(), data.data.clone(), parents.clone());
+    matches!(entry.data().1, Validity::Valid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bytes::Bytes;
+    use sha1::{Digest, Sha1};
+    use tempfile::TempDir;
+
+    use types::{
+        testutil::{hgid, key, null_key, repo_path_buf},
+        NodeInfo,
+    };
+
+    use crate::datastore::Metadata;
+
+    fn start_server(dir: &TempDir, max_bytes_per_log: u64) -> SharedCacheClient {
+        let socket = dir.path().join("socket");
+        let log = CacheLog::open(&dir.path().join("cache"), max_bytes_per_log, 2).unwrap();
+        let server = SharedCacheServer::bind(&socket, log).unwrap();
+        thread::spawn(move || server.serve());
+        SharedCacheClient::new(socket)
+    }
+
+    fn mcdata(path: &str, hgid: &str, data: &'static [u8]) -> McData {
+        McData {
+            key: key(path, hgid),
+            data: Bytes::from_static(data),
+            metadata: Metadata::default(),
+        }
+    }
+
+    /// Data of a root revision of `path`, keyed by its real hgid, along with its history.
+    fn valid_mcdata(path: &str, data: &'static [u8]) -> (McData, McHist) {
+        let mut hasher = Sha1::new();
+        hasher.input(HgId::null_id().as_ref());
+        hasher.input(HgId::null_id().as_ref());
+        hasher.input(data);
+        let hash: [u8; 20] = hasher.result().into();
+        let key = Key::new(repo_path_buf(path), HgId::from_byte_array(hash));
+
+        let hist = McHist {
+            key: key.clone(),
+            nodeinfo: NodeInfo {
+                parents: [null_key(path), null_key(path)],
+                linknode: hgid("1"),
+            },
+        };
+        let data = McData {
+            key,
+            data: Bytes::from_static(data),
+            metadata: Metadata::default(),
+        };
+        (data, hist)
+    }
+
+    fn add_valid(client: &SharedCacheClient, path: &str, data: &'static [u8]) -> Result<McData> {
+        let (data, hist) = valid_mcdata(path, data);
+        client.add_hist(hist)?;
+        client.add_data(McData {
+            key: data.key.clone(),
+            data: data.data.clone(),
+            metadata: data.metadata,
+        })?;
+        Ok(data)
+    }
+
+    #[test]
+    fn test_data() -> Result<()> {
+        let dir = TempDir::new()?;
+        let client = start_server(&dir, 1000 * 1000);
+
+        let a = add_valid(&client, "a", b"content of a")?;
+        let b = add_valid(&client, "b", b"content of b")?;
+
+        let found = client.get_data(&[a.key.clone(), key("c", "3"), b.key.clone()])?;
+        assert_eq!(found, vec![a, b]);
+        // Same node, different path.
+        assert_eq!(client.get_data(&[key("b", "1")])?, vec![]);
+
+        let stats = client.stats()?;
+        assert_eq!(stats.data_hits, 2);
+        assert_eq!(stats.data_misses, 2);
+        assert_eq!(stats.data_adds, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_poisoned_data() -> Result<()> {
+        let dir = TempDir::new()?;
+        let client = start_server(&dir, 1000 * 1000);
+
+        // Content that doesn't match its hgid.
+        let (valid, hist) = valid_mcdata("a", b"content of a");
+        client.add_hist(hist)?;
+        client.add_data(McData {
+            key: valid.key.clone(),
+            data: Bytes::from_static(b"something else"),
+            metadata: Metadata::default(),
+        })?;
+        // Content without history can't be checked.
+        client.add_data(mcdata("b", "2", b"content of b"))?;
+
+        assert_eq!(client.get_data(&[valid.key, key("b", "2")])?, vec![]);
+        assert_eq!(client.stats()?.data_hits, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_message_too_large() {
+        let mut frame = Vec::new();
+        frame.write_u32::<BigEndian>(u32::max_value()).unwrap();
+        assert!(read_message::<Request>(&mut &frame[..]).is_err());
+    }
+
+    #[test]
+    fn test_path_too_long() {
+        let path = "a".repeat(u16::max_value() as usize + 1);
+        assert!(CacheLog::index_key(KIND_DATA, &key(&path, "1")).is_err());
+        assert!(CacheLog::index_key(KIND_DATA, &key("a", "1")).is_ok());
+    }
+
+    #[test]
+    fn test_socket_permissions() -> Result<()> {
+        let dir = TempDir::new()?;
+        start_server(&dir, 1000 * 1000);
+        let mode = fs::metadata(dir.path().join("socket"))?
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o600);
+        Ok(())
+    }
+
+    #[test]
+    fn test_hist() -> Result<()> {
+        let dir = TempDir::new()?;
+        let client = start_server(&dir, 1000 * 1000);
+
+        let hist = McHist {
+            key: key("a", "1"),
+            nodeinfo: NodeInfo {
+                parents: [key("a", "2"), key("a", "3")],
+                linknode: hgid("4"),
+            },
+        };
+        client.add_hist(McHist {
+            key: hist.key.clone(),
+            nodeinfo: hist.nodeinfo.clone(),
+        })?;
+        assert_eq!(client.get_hist(&[key("a", "1")])?, vec![hist]);
+        // History and data of the same key are separate.
+        assert_eq!(client.get_data(&[key("a", "1")])?, vec![]);
+
+        let stats = client.stats()?;
+        assert_eq!((stats.hist_hits, stats.hist_adds), (1, 1));
+        assert_eq!(stats.data_misses, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_recently_used_survive_rotation() -> Result<()> {
+        let dir = TempDir::new()?;
+        let mut log = CacheLog::open(dir.path(), 1000, 2)?;
+        let a = key("a", "1");
+        let b = key("b", "2");
+        log.add(KIND_DATA, &a, &[1; 80])?;
+        log.add(KIND_DATA, &b, &[2; 80])?;
+        log.log.sync()?;
+        log.log.force_rotate()?;
+
+        // Reading `a` moves it to the latest log.
+        assert!(log.get(KIND_DATA, &a)?.is_some());
+        log.log.sync()?;
+        log.log.force_rotate()?;
+
+        // The log with the original `a` and `b` is removed.
+        assert_eq!(log.get(KIND_DATA, &a)?, Some(vec![1; 80]));
+        assert_eq!(log.get(KIND_DATA, &b)?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_client_without_server() {
+        let dir = TempDir::new().unwrap();
+        let client = SharedCacheClient::new(dir.path().join("socket"));
+        assert!(client.get_data(&[key("a", "1")]).is_err());
+    }
+
+    #[test]
+    fn test_reconnect() -> Result<()> {
+        let dir = TempDir::new()?;
+        let client = start_server(&dir, 1000 * 1000);
+        let a = add_valid(&client, "a", b"a")?;
+
+        // Break the connection. The next request reconnects.
+        client
+            .stream
+            .lock()
+            .as_ref()
+            .unwrap()
+            .shutdown(std::net::Shutdown::Both)?;
+        assert_eq!(client.get_data(&[a.key])?.len(), 1);
+        Ok(())
+    }
+}
@@ -187,6 +187,7 @@ impl FakeEdenApi {
             downloaded: 0,
             uploaded: 0,
             requests: 0,
+            retries: 0,
             time: Duration::from_secs(0),
             latency: Duration::from_secs(0),
         }
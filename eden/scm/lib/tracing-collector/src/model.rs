@@ -9,7 +9,7 @@ use crate::log;
 use indexmap::{IndexMap, IndexSet};
 use serde::{Deserialize, Serialize};
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt;
 use std::hash::Hash;
 use std::io;
@@ -205,6 +205,35 @@ impl TracingData {
         }
     }
 
+    /// Record that `id` follows from `follows`.
+    ///
+    /// Stored as a comma-separated list of [`EspanId`]s in the
+    /// `follows_from` metadata, so it survives serialization.
+    fn push_follows_from(&mut self, id: EspanId, follows: EspanId) {
+        if self.get_espan(id).is_none() || self.get_espan(follows).is_none() {
+            return;
+        }
+        let espan_index = (id.0 - self.espan_id_offset.0) as usize;
+        let key = self.strings.id(FOLLOWS_FROM);
+        let value = match self.espans[espan_index].meta.get(&key) {
+            Some(value) => format!("{},{}", self.strings.get(*value), follows.0),
+            None => follows.0.to_string(),
+        };
+        let value = self.strings.id(value);
+        self.espans[espan_index].meta.insert(key, value);
+    }
+
+    /// [`EspanId`]s recorded by `push_follows_from`.
+    fn follows_from(&self, espan: &Espan) -> Vec<EspanId> {
+        match self.strings.0.get_full(FOLLOWS_FROM) {
+            Some((key_id, _)) => match espan.meta.get(&StringId(key_id as u64)) {
+                Some(value) => parse_espan_ids(self.strings.get(*value)),
+                None => Vec::new(),
+            },
+            None => Vec::new(),
+        }
+    }
+
     /// Fetch a `Espan`. Does some minimal `EspanId` validation.
     /// Return `None` if the `Espan` is unknown to this [`TracingData`].
     fn get_espan(&self, id: EspanId) -> Option<&Espan> {
@@ -228,6 +257,17 @@ impl TracingData {
     }
 }
 
+/// Metadata key for "follows from" relationships between spans.
+const FOLLOWS_FROM: &str = "follows_from";
+
+fn parse_espan_ids(value: &str) -> Vec<EspanId> {
+    value
+        .split(',')
+        .filter_map(|id| id.parse().ok())
+        .map(EspanId)
+        .collect()
+}
+
 /// Used for new TracingData
 static PROCESS_ESPAN_ID_FIRST: AtomicU64 = AtomicU64::new(0);
 
@@ -289,7 +329,7 @@ impl TracingData {
 
     /// Matches `tracing::Subscriber::record_follows_from`.
     pub fn record_follows_from(&mut self, id: &tracing::span::Id, follows: &tracing::span::Id) {
-        // TODO: Implement this.
+        self.push_follows_from(id.clone().into(), follows.clone().into());
     }
 
     /// Matches `tracing::Subscriber::event`.
@@ -596,7 +636,7 @@ impl TracingData {
 
     /// Mark `new_span_id` as following `old_span_id`.
     pub fn set_follows_from(&mut self, old_span_id: EspanId, new_span_id: EspanId) {
-        // TODO: Implement this.
+        self.push_follows_from(new_span_id, old_span_id);
     }
 
     /// Return the process id that created this [`TracingData`].
@@ -636,6 +676,16 @@ impl TracingData {
                     .map(|(key_id, value_id)| {
                         let key = data.strings.get(*key_id);
                         let value = data.strings.get(*value_id);
+                        if key == FOLLOWS_FROM {
+                            // EspanIds are rewritten. Update references to them.
+                            let ids: Vec<String> = parse_espan_ids(value)
+                                .into_iter()
+                                .map(|id| {
+                                    (id.0 + espan_offset - data.espan_id_offset.0).to_string()
+                                })
+                                .collect();
+                            return (strings.id(key), strings.id(ids.join(",")));
+                        }
                         (strings.id(key), strings.id(value))
                     })
                     .collect();
@@ -1670,6 +1720,281 @@ impl<S: Eq + Hash> TreeSpan<S> {
     }
 }
 
+// -------- Folded stacks (flamegraph) --------
+
+/// Time spent in a span path, aggregated across processes and threads.
+#[derive(Debug, PartialEq, Eq)]
+pub struct FoldedStack {
+    /// Span names, from the outermost span to the innermost span.
+    pub frames: Vec<String>,
+
+    /// Time spent in spans of this path, excluding child spans.
+    pub self_micros: u64,
+
+    /// Time spent in spans of this path, including child spans.
+    pub total_micros: u64,
+
+    /// How many times spans of this path were entered.
+    pub call_count: usize,
+}
+
+impl TracingData {
+    /// Aggregate span durations by span path, sorted by path.
+    ///
+    /// Spans that never exited are considered to end at the last timestamp
+    /// of their thread. Events are ignored.
+    pub fn folded_stacks(&self) -> Vec<FoldedStack> {
+        // Path -> (self_micros, total_micros, call_count)
+        let mut stacks = BTreeMap::<Vec<String>, (u64, u64, usize)>::new();
+        for eventus_list in self.eventus_group_by_pid_tid().values() {
+            let end_time = eventus_list.last().map(|e| e.timestamp.0).unwrap_or(0);
+            let tree_spans = self.build_tree_spans(eventus_list);
+            let duration = |span: &RawTreeSpan| {
+                if span.is_incomplete() {
+                    end_time.saturating_sub(span.start_time)
+                } else {
+                    span.duration
+                }
+            };
+
+            // (span, path of the span)
+            let mut to_visit: Vec<(RawTreeSpanId, Vec<String>)> = vec![(0, Vec::new())];
+            while let Some((id, frames)) = to_visit.pop() {
+                let span = &tree_spans[id];
+                let children: Vec<RawTreeSpanId> = span
+                    .children
+                    .iter()
+                    .cloned()
+                    .filter(|&child_id| !tree_spans[child_id].is_event)
+                    .collect();
+                if span.espan_id.is_some() {
+                    let total = duration(span);
+                    let children_total: u64 =
+                        children.iter().map(|&c| duration(&tree_spans[c])).sum();
+                    let stack = stacks.entry(frames.clone()).or_default();
+                    stack.0 += total.saturating_sub(children_total);
+                    stack.1 += total;
+                    stack.2 += 1;
+                }
+                for child_id in children.into_iter().rev() {
+                    let mut child_frames = frames.clone();
+                    child_frames.push(self.espan_name(tree_spans[child_id].espan_id).to_string());
+                    to_visit.push((child_id, child_frames));
+                }
+            }
+        }
+        stacks
+            .into_iter()
+            .map(
+                |(frames, (self_micros, total_micros, call_count))| FoldedStack {
+                    frames,
+                    self_micros,
+                    total_micros,
+                    call_count,
+                },
+            )
+            .collect()
+    }
+
+    /// Write [`FoldedStack`]s in the "folded" format used by flamegraph
+    /// tools: one `outer;inner self_micros` line per span path.
+    pub fn write_folded(&self, out: &mut dyn io::Write) -> io::Result<()> {
+        for stack in self.folded_stacks() {
+            if stack.self_micros == 0 {
+                continue;
+            }
+            // ';' separates frames. '\n' separates stacks.
+            let frames: Vec<String> = stack
+                .frames
+                .iter()
+                .map(|f| f.replace(';', ",").replace('\n', " "))
+                .collect();
+            writeln!(out, "{} {}", frames.join(";"), stack.self_micros)?;
+        }
+        Ok(())
+    }
+
+    /// Name of an [`Espan`], or "(unnamed)".
+    fn espan_name(&self, espan_id: Option<EspanId>) -> &str {
+        espan_id
+            .and_then(|id| self.get_espan(id))
+            .and_then(|espan| {
+                let (key_id, _) = self.strings.0.get_full("name")?;
+                espan.meta.get(&StringId(key_id as u64))
+            })
+            .map(|value_id| self.strings.get(*value_id))
+            .unwrap_or("(unnamed)")
+    }
+}
+
+// -------- OpenTelemetry (OTLP JSON) --------
+
+impl TracingData {
+    /// Write spans as an OTLP/JSON `ExportTraceServiceRequest`, which an
+    /// OpenTelemetry collector can ingest (ex. using its "otlpjsonfile"
+    /// receiver).
+    ///
+    /// All spans belong to a single trace. Events become span events of
+    /// their parent spans, and "follows from" relationships become links.
+    pub fn write_otlp_json(&self, out: &mut dyn io::Write) -> Result<(), serde_json::Error> {
+        serde_json::to_writer(out, &self.otlp_json())
+    }
+
+    /// Return the OTLP/JSON `ExportTraceServiceRequest` object.
+    pub fn otlp_json(&self) -> serde_json::Value {
+        use serde_json::{json, Value};
+
+        let (start_nanos, trace_seed) = if self.test_clock_step > 0 {
+            (0, 1)
+        } else {
+            let start_nanos = self
+                .start
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_nanos() as u64)
+                .unwrap_or(0);
+            (start_nanos, self.default_process_id)
+        };
+        let trace_id = format!("{:016x}{:016x}", start_nanos, trace_seed);
+        let time = |micros: u64| (start_nanos + micros * 1000).to_string();
+        let attributes = |espan: Option<&Espan>| -> Vec<Value> {
+            match espan {
+                None => Vec::new(),
+                Some(espan) => espan
+                    .meta
+                    .iter()
+                    .map(|(k, v)| (self.strings.get(*k), self.strings.get(*v)))
+                    .filter(|(k, _)| *k != "name" && *k != FOLLOWS_FROM)
+                    .map(|(k, v)| json!({"key": k, "value": {"stringValue": v}}))
+                    .collect(),
+            }
+        };
+
+        // Assign span ids first so links can refer to spans of other threads.
+        let eventus_by_pid_tid = self.eventus_group_by_pid_tid();
+        let mut next_span_id = 1u64;
+        let mut first_span_ids = HashMap::<EspanId, u64>::new();
+        let mut threads = Vec::with_capacity(eventus_by_pid_tid.len());
+        for (&(pid, tid), eventus_list) in eventus_by_pid_tid.iter() {
+            let end_time = eventus_list.last().map(|e| e.timestamp.0).unwrap_or(0);
+            let tree_spans = self.build_tree_spans(eventus_list);
+            // Events only become spans if they have no parent span.
+            let top_level = &tree_spans[0].children;
+            let mut span_ids = vec![0u64; tree_spans.len()];
+            for (i, span) in tree_spans.iter().enumerate() {
+                if let Some(espan_id) = span.espan_id {
+                    if span.is_event && !top_level.contains(&i) {
+                        continue;
+                    }
+                    span_ids[i] = next_span_id;
+                    if !span.is_event {
+                        first_span_ids.entry(espan_id).or_insert(next_span_id);
+                    }
+                    next_span_id += 1;
+                }
+            }
+            threads.push((pid, tid, end_time, tree_spans, span_ids));
+        }
+
+        let mut spans_by_pid = IndexMap::<u64, Vec<Value>>::new();
+        for (pid, tid, end_time, tree_spans, span_ids) in threads {
+            let spans = spans_by_pid.entry(pid).or_default();
+            // (span, parent span id)
+            let mut to_visit: Vec<(RawTreeSpanId, u64)> = vec![(0, 0)];
+            while let Some((id, parent_span_id)) = to_visit.pop() {
+                let span = &tree_spans[id];
+                let span_id = span_ids[id];
+                for &child_id in span.children.iter().rev() {
+                    to_visit.push((child_id, span_id));
+                }
+                let espan_id = match span.espan_id {
+                    Some(espan_id) => espan_id,
+                    None => continue,
+                };
+                let espan = self.get_espan(espan_id);
+                let name = self.espan_name(Some(espan_id));
+                if span.is_event && parent_span_id != 0 {
+                    // Handled by the parent span.
+                    continue;
+                }
+
+                let end = if span.is_incomplete() {
+                    end_time
+                } else {
+                    span.end_time()
+                };
+                let mut thread_attribute = vec![json!({
+                    "key": "thread.id",
+                    "value": {"intValue": tid.to_string()},
+                })];
+                let mut obj = json!({
+                    "traceId": trace_id,
+                    "spanId": format!("{:016x}", span_id),
+                    "name": name,
+                    "kind": 1, // SPAN_KIND_INTERNAL
+                    "startTimeUnixNano": time(span.start_time),
+                    "endTimeUnixNano": time(end),
+                });
+                thread_attribute.extend(attributes(espan));
+                obj["attributes"] = Value::Array(thread_attribute);
+                if parent_span_id != 0 {
+                    obj["parentSpanId"] = Value::String(format!("{:016x}", parent_span_id));
+                }
+
+                let events: Vec<Value> = span
+                    .children
+                    .iter()
+                    .map(|&child_id| &tree_spans[child_id])
+                    .filter(|child| child.is_event)
+                    .map(|child| {
+                        let espan_id = child.espan_id;
+                        json!({
+                            "timeUnixNano": time(child.start_time),
+                            "name": self.espan_name(espan_id),
+                            "attributes": attributes(espan_id.and_then(|id| self.get_espan(id))),
+                        })
+                    })
+                    .collect();
+                if !events.is_empty() {
+                    obj["events"] = Value::Array(events);
+                }
+
+                let links: Vec<Value> = espan
+                    .map(|espan| self.follows_from(espan))
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter_map(|id| first_span_ids.get(&id))
+                    .map(|span_id| json!({"traceId": trace_id, "spanId": format!("{:016x}", span_id)}))
+                    .collect();
+                if !links.is_empty() {
+                    obj["links"] = Value::Array(links);
+                }
+
+                spans.push(obj);
+            }
+        }
+
+        let resource_spans: Vec<Value> = spans_by_pid
+            .into_iter()
+            .map(|(pid, spans)| {
+                json!({
+                    "resource": {
+                        "attributes": [
+                            {"key": "process.pid", "value": {"intValue": pid.to_string()}},
+                        ],
+                    },
+                    "scopeSpans": [
+                        {
+                            "scope": {"name": "tracing-collector"},
+                            "spans": spans,
+                        },
+                    ],
+                })
+            })
+            .collect();
+        json!({ "resourceSpans": resource_spans })
+    }
+}
+
 // -------- Tests --------
 
 #[cfg(test)]
@@ -1759,6 +2084,217 @@ Start Dur.ms | Name                         Source
         );
     }
 
+    #[test]
+    fn test_folded_stacks() {
+        let mut data = TracingData::new_for_test();
+        let span_id1 = data.add_espan(&meta("foo", "a.py", "10"), None);
+        let span_id2 = data.add_espan(&meta("bar;baz", "a.py", "20"), None);
+
+        data.add_action(span_id1, Action::EnterSpan); // 2
+        data.add_action(span_id2, Action::EnterSpan); // 4
+        data.add_action(span_id2, Action::ExitSpan); // 6
+        data.add_action(span_id2, Action::Event); // 8
+        data.add_action(span_id2, Action::EnterSpan); // 10
+        data.add_action(span_id1, Action::EnterSpan); // 12
+        data.add_action(span_id1, Action::ExitSpan); // 14
+        data.add_action(span_id2, Action::ExitSpan); // 16
+        data.add_action(span_id1, Action::ExitSpan); // 18
+        data.add_action(span_id2, Action::EnterSpan); // 20, incomplete
+        data.add_action(span_id1, Action::Event); // 22
+
+        let stacks = data.folded_stacks();
+        let summary: Vec<_> = stacks
+            .iter()
+            .map(|s| {
+                (
+                    s.frames.join("/"),
+                    s.self_micros,
+                    s.total_micros,
+                    s.call_count,
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("bar;baz".to_string(), 2000, 2000, 1),
+                ("foo".to_string(), 8000, 16000, 1),
+                ("foo/bar;baz".to_string(), 6000, 8000, 2),
+                ("foo/bar;baz/foo".to_string(), 2000, 2000, 1),
+            ]
+        );
+
+        let mut out = Vec::new();
+        data.write_folded(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            r#"bar,baz 2000
+foo 8000
+foo;bar,baz 6000
+foo;bar,baz;foo 2000
+"#
+        );
+    }
+
+    #[test]
+    fn test_follows_from() {
+        let mut data1 = TracingData::new_for_test();
+        let span_id1 = data1.add_espan(&meta("foo", "a.py", "10"), None);
+        let span_id2 = data1.add_espan(&meta("bar", "a.py", "20"), None);
+        let span_id3 = data1.add_espan(&meta("baz", "a.py", "30"), None);
+        data1.set_follows_from(span_id1, span_id3);
+        data1.set_follows_from(span_id2, span_id3);
+        data1.set_follows_from(span_id1, EspanId(span_id3.0 + 100)); // ignored
+        assert_eq!(
+            data1.follows_from(data1.get_espan(span_id3).unwrap()),
+            vec![span_id1, span_id2]
+        );
+
+        // EspanIds change after merging. The relationship is kept.
+        let data2 = TracingData::new_for_test();
+        let merged = TracingData::merge(vec![data2, data1]);
+        let ids: Vec<Vec<&str>> = merged
+            .espans
+            .iter()
+            .map(|espan| {
+                merged
+                    .follows_from(espan)
+                    .into_iter()
+                    .map(|id| merged.espan_name(Some(id)))
+                    .collect()
+            })
+            .collect();
+        assert_eq!(ids, vec![vec![], vec![], vec!["foo", "bar"]]);
+    }
+
+    #[test]
+    fn test_otlp_json() {
+        let mut data = TracingData::new_for_test();
+        let span_id1 = data.add_espan(&meta("foo", "a.py", "10"), None);
+        let span_id2 = data.add_espan(&meta("bar", "a.py", "20"), None);
+        let span_id3 = data.add_espan(&[("name", "log"), ("message", "hi")], None);
+        data.set_follows_from(span_id1, span_id2);
+
+        data.add_action(span_id1, Action::EnterSpan);
+        data.add_action(span_id3, Action::Event);
+        data.add_action(span_id2, Action::EnterSpan);
+        data.add_action(span_id2, Action::ExitSpan);
+        data.add_action(span_id1, Action::ExitSpan);
+        data.add_action(span_id3, Action::Event);
+
+        let mut json = data.otlp_json();
+        // Remove nondeterministic thread and process ids.
+        let resource = &mut json["resourceSpans"][0];
+        resource["resource"] = serde_json::Value::Null;
+        for span in resource["scopeSpans"][0]["spans"]
+            .as_array_mut()
+            .unwrap()
+            .iter_mut()
+        {
+            span["attributes"].as_array_mut().unwrap().remove(0);
+        }
+        assert_eq!(
+            serde_json::to_string_pretty(&json).unwrap(),
+            r#"{
+  "resourceSpans": [
+    {
+      "resource": null,
+      "scopeSpans": [
+        {
+          "scope": {
+            "name": "tracing-collector"
+          },
+          "spans": [
+            {
+              "attributes": [
+                {
+                  "key": "module_path",
+                  "value": {
+                    "stringValue": "a.py"
+                  }
+                },
+                {
+                  "key": "line",
+                  "value": {
+                    "stringValue": "10"
+                  }
+                }
+              ],
+              "endTimeUnixNano": "10000000",
+              "events": [
+                {
+                  "attributes": [
+                    {
+                      "key": "message",
+                      "value": {
+                        "stringValue": "hi"
+                      }
+                    }
+                  ],
+                  "name": "log",
+                  "timeUnixNano": "4000000"
+                }
+              ],
+              "kind": 1,
+              "name": "foo",
+              "spanId": "0000000000000001",
+              "startTimeUnixNano": "2000000",
+              "traceId": "00000000000000000000000000000001"
+            },
+            {
+              "attributes": [
+                {
+                  "key": "module_path",
+                  "value": {
+                    "stringValue": "a.py"
+                  }
+                },
+                {
+                  "key": "line",
+                  "value": {
+                    "stringValue": "20"
+                  }
+                }
+              ],
+              "endTimeUnixNano": "8000000",
+              "kind": 1,
+              "links": [
+                {
+                  "spanId": "0000000000000001",
+                  "traceId": "00000000000000000000000000000001"
+                }
+              ],
+              "name": "bar",
+              "parentSpanId": "0000000000000001",
+              "spanId": "0000000000000002",
+              "startTimeUnixNano": "6000000",
+              "traceId": "00000000000000000000000000000001"
+            },
+            {
+              "attributes": [
+                {
+                  "key": "message",
+                  "value": {
+                    "stringValue": "hi"
+                  }
+                }
+              ],
+              "endTimeUnixNano": "12000000",
+              "kind": 1,
+              "name": "log",
+              "spanId": "0000000000000003",
+              "startTimeUnixNano": "12000000",
+              "traceId": "00000000000000000000000000000001"
+            }
+          ]
+        }
+      ]
+    }
+  ]
+}"#
+        );
+    }
+
     #[test]
     fn test_tree_span_serialize() {
         let mut data = TracingData::new_for_test();
@@ -7,6 +7,142 @@
 
 //! Directory State.
 
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use anyhow::Result;
+use types::HgId;
+
+use crate::errors::ErrorKind;
+use crate::store::BlockId;
+use crate::treestate::TreeState;
+
+/// Header that follows the parents in a `.hg/dirstate` file pointing to a treestate.
+const TREE_STATE_HEADER: &[u8] = b"\ntreestate\n\0";
+
 /// A dirstate object. This maintains .hg/dirstate file
+///
+/// The file starts with the working copy parents. For treestate, they are
+/// followed by a header and `key=value` metadata separated by NUL bytes,
+/// which locate the treestate file under `.hg/treestate` and its root.
+pub struct Dirstate {
+    pub p1: HgId,
+    pub p2: HgId,
+    /// Location of the treestate, or `None` if the dirstate is in another format.
+    pub tree_state: Option<TreeStateFields>,
+}
+
+pub struct TreeStateFields {
+    pub tree_filename: String,
+    pub tree_root_id: BlockId,
+    pub repack_threshold: Option<u64>,
+}
+
+impl Dirstate {
+    /// Read `.hg/dirstate` at `path`.
+    pub fn read(path: impl AsRef<Path>) -> Result<Self> {
+        Self::deserialize(&fs::read(path)?)
+    }
+
+    pub fn deserialize(data: &[u8]) -> Result<Self> {
+        let id_len = HgId::len();
+        if data.len() < id_len * 2 {
+            return Err(ErrorKind::CorruptDirstate.into());
+        }
+        let p1 = HgId::from_slice(&data[..id_len])?;
+        let p2 = HgId::from_slice(&data[id_len..id_len * 2])?;
+
+        let rest = &data[id_len * 2..];
+        let tree_state = if rest.starts_with(TREE_STATE_HEADER) {
+            let metadata = parse_metadata(&rest[TREE_STATE_HEADER.len()..])
+                .ok_or(ErrorKind::CorruptDirstate)?;
+            let tree_filename = metadata
+                .get("filename")
+                .ok_or(ErrorKind::CorruptDirstate)?
+                .to_string();
+            let tree_root_id = metadata
+                .get("rootid")
+                .and_then(|id| id.parse().ok())
+                .ok_or(ErrorKind::CorruptDirstate)?;
+            let repack_threshold = metadata
+                .get("threshold")
+                .map(|threshold| threshold.parse())
+                .transpose()
+                .map_err(|_| ErrorKind::CorruptDirstate)?;
+            Some(TreeStateFields {
+                tree_filename,
+                tree_root_id: BlockId(tree_root_id),
+                repack_threshold,
+            })
+        } else {
+            None
+        };
+
+        Ok(Dirstate { p1, p2, tree_state })
+    }
+}
+
+impl TreeStateFields {
+    /// Open the treestate in `dot_hg_path` that these fields point to.
+    pub fn open(&self, dot_hg_path: impl AsRef<Path>) -> Result<TreeState> {
+        let path = dot_hg_path
+            .as_ref()
+            .join("treestate")
+            .join(&self.tree_filename);
+        // A zero root id means nothing was written yet.
+        let root_id = match self.tree_root_id {
+            BlockId(0) => None,
+            root_id => Some(root_id),
+        };
+        TreeState::open(path, root_id)
+    }
+}
+
+/// Parse `key=value` entries separated by NUL bytes. Returns `None` if they are not UTF-8.
+pub(crate) fn parse_metadata(data: &[u8]) -> Option<HashMap<&str, &str>> {
+    let data = std::str::from_utf8(data).ok()?;
+    Some(
+        data.split('\0')
+            .filter_map(|entry| {
+                let mut parts = entry.splitn(2, '=');
+                Some((parts.next()?, parts.next()?))
+            })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deserialize_treestate() {
+        let mut data = vec![1; 20];
+        data.extend_from_slice(&[0; 20]);
+        data.extend_from_slice(TREE_STATE_HEADER);
+        data.extend_from_slice(b"filename=abc\0rootid=42\0threshold=10");
+
+        let dirstate = Dirstate::deserialize(&data).expect("deserialize");
+        assert_eq!(dirstate.p1, HgId::from_byte_array([1; 20]));
+        assert!(dirstate.p2.is_null());
+        let tree_state = dirstate.tree_state.expect("treestate");
+        assert_eq!(tree_state.tree_filename, "abc");
+        assert_eq!(tree_state.tree_root_id, BlockId(42));
+        assert_eq!(tree_state.repack_threshold, Some(10));
+    }
+
+    #[test]
+    fn test_deserialize_other_formats() {
+        let data = vec![0; 40];
+        let dirstate = Dirstate::deserialize(&data).expect("deserialize");
+        assert!(dirstate.tree_state.is_none());
+
+        let mut data = vec![0; 40];
+        data.extend_from_slice(TREE_STATE_HEADER);
+        data.extend_from_slice(b"rootid=42");
+        assert!(Dirstate::deserialize(&data).is_err());
 
-pub struct Dirstate {}
+        assert!(Dirstate::deserialize(&[0; 10]).is_err());
+    }
+}
@@ -5,6 +5,8 @@
  * GNU General Public License version 2.
  */
 
+use crate::dirstate::parse_metadata;
+use crate::errors::ErrorKind;
 use crate::filestate::FileStateV2;
 use crate::filestore::FileStore;
 use crate::serialization::Serializable;
@@ -112,6 +114,13 @@ impl TreeState {
         self.root.metadata.deref()
     }
 
+    /// Get a value from the metadata, which Python stores as `key=value` entries separated by
+    /// NUL bytes.
+    pub fn get_metadata_by_key(&self, key: &str) -> Result<Option<String>> {
+        let metadata = parse_metadata(self.get_metadata()).ok_or(ErrorKind::CorruptTree)?;
+        Ok(metadata.get(key).map(|value| value.to_string()))
+    }
+
     pub fn has_dir<P: AsRef<[u8]>>(&mut self, path: P) -> Result<bool> {
         self.tree.has_dir(&self.store, path.as_ref())
     }
@@ -209,6 +218,20 @@ mod tests {
         assert_eq!(state.get_metadata()[..], b"foobar"[..]);
     }
 
+    #[test]
+    fn test_get_metadata_by_key() {
+        let dir = TempDir::new("treestate").expect("tempdir");
+        let mut state = TreeState::open(dir.path().join("1"), None).expect("open");
+        state.set_metadata(b"p1=abc\0clock=c:1=2");
+        assert_eq!(
+            state.get_metadata_by_key("clock").expect("get"),
+            Some("c:1=2".to_string())
+        );
+        assert_eq!(state.get_metadata_by_key("p2").expect("get"), None);
+        state.set_metadata(b"p1=\xff");
+        assert!(state.get_metadata_by_key("p1").is_err());
+    }
+
     // Some random paths extracted from fb-hgext, plus some manually added entries, shuffled.
     const SAMPLE_PATHS: [&[u8]; 22] = [
         b".fbarcanist",
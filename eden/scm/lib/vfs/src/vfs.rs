@@ -70,6 +70,13 @@ impl VFS {
         self.join(path).symlink_metadata().map_err(|e| e.into())
     }
 
+    /// Like `metadata`, but fails if one of the directories of `path` is a symlink, in which case
+    /// `path` isn't part of the working copy.
+    pub fn audited_metadata(&self, path: &RepoPath) -> Result<Metadata> {
+        let filepath = self.auditor.audit(path)?;
+        filepath.symlink_metadata().map_err(|e| e.into())
+    }
+
     /// The file `path` can't be written to, attempt to fixup the directories and files so the file can
     /// be created.
     ///
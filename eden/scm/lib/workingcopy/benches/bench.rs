@@ -0,0 +1,74 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::fs::{create_dir_all, File};
+use std::path::Path;
+
+use minibench::{bench, elapsed};
+use pathmatcher::AlwaysMatcher;
+use tempfile::tempdir;
+
+use workingcopy::walker::{ParallelWalker, Walker};
+
+const DIR_FANOUT: usize = 20;
+const DIR_DEPTH: usize = 3;
+const FILES_PER_DIR: usize = 10;
+
+/// Create a tree with `DIR_FANOUT` subdirectories per directory, `DIR_DEPTH` levels deep, and
+/// `FILES_PER_DIR` files in every directory.
+fn create_tree(path: &Path, depth: usize) {
+    create_dir_all(path).unwrap();
+    for i in 0..FILES_PER_DIR {
+        File::create(path.join(format!("file{}", i))).unwrap();
+    }
+    if depth > 0 {
+        for i in 0..DIR_FANOUT {
+            create_tree(&path.join(format!("dir{}", i)), depth - 1);
+        }
+    }
+}
+
+fn main() {
+    let dir = tempdir().unwrap();
+    create_tree(dir.path(), DIR_DEPTH);
+    let root = dir.path().to_path_buf();
+
+    bench("walk", || {
+        elapsed(|| {
+            let walker = Walker::new(root.clone(), AlwaysMatcher::new(), false);
+            for entry in walker {
+                entry.unwrap();
+            }
+        })
+    });
+
+    for &num_threads in &[1, 2, 4, 8, 16] {
+        bench(format!("parallel walk ({} threads)", num_threads), || {
+            elapsed(|| {
+                let walker = ParallelWalker::new(
+                    root.clone(),
+                    AlwaysMatcher::new(),
+                    false,
+                    num_threads,
+                    false,
+                );
+                for entry in walker {
+                    entry.unwrap();
+                }
+            })
+        });
+    }
+
+    bench("parallel walk (8 threads, sorted)", || {
+        elapsed(|| {
+            let walker = ParallelWalker::new(root.clone(), AlwaysMatcher::new(), false, 8, true);
+            for entry in walker {
+                entry.unwrap();
+            }
+        })
+    });
+}
@@ -17,14 +17,15 @@ use std::{
 use anyhow::{Error, Result};
 use parking_lot::Mutex;
 
-use pathmatcher::Matcher;
+use pathmatcher::{DirectoryMatch, Matcher};
 use treestate::filestate::StateFlags;
 use treestate::tree::VisitorResult;
 use treestate::treestate::TreeState;
 use types::{RepoPath, RepoPathBuf};
 use vfs::{is_executable, is_symlink, VFS};
 
-use crate::walker::{WalkEntry, WalkError, Walker};
+use crate::lookup::{LookupResult, ManifestLookup};
+use crate::walker::{ParallelWalker, WalkEntry, WalkError, Walker};
 
 /// Represents a file modification time in Mercurial, in seconds since the unix epoch.
 #[derive(PartialEq)]
@@ -79,6 +80,48 @@ impl PhysicalFileSystem {
         last_write: HgModifiedTime,
     ) -> PendingChanges<M> {
         let walker = Walker::new(self.vfs.root().to_path_buf(), matcher.clone(), false);
+        self.make_pending_changes(
+            PendingWalker::Serial(walker),
+            treestate,
+            matcher,
+            include_directories,
+            last_write,
+        )
+    }
+
+    /// Like `pending_changes`, but walks the working copy with `num_threads` threads.
+    pub fn parallel_pending_changes<M: Matcher + Clone + Send + Sync + 'static>(
+        &self,
+        treestate: Arc<Mutex<TreeState>>,
+        matcher: M,
+        include_directories: bool,
+        last_write: HgModifiedTime,
+        num_threads: usize,
+    ) -> PendingChanges<M> {
+        let walker = ParallelWalker::new(
+            self.vfs.root().to_path_buf(),
+            matcher.clone(),
+            false,
+            num_threads,
+            false,
+        );
+        self.make_pending_changes(
+            PendingWalker::Parallel(walker),
+            treestate,
+            matcher,
+            include_directories,
+            last_write,
+        )
+    }
+
+    fn make_pending_changes<M: Matcher + Clone>(
+        &self,
+        walker: PendingWalker<M>,
+        treestate: Arc<Mutex<TreeState>>,
+        matcher: M,
+        include_directories: bool,
+        last_write: HgModifiedTime,
+    ) -> PendingChanges<M> {
         PendingChanges {
             vfs: self.vfs.clone(),
             walker,
@@ -88,25 +131,52 @@ impl PhysicalFileSystem {
             include_directories,
             seen: HashSet::new(),
             lookups: vec![],
+            lookup: None,
+            journal: None,
             tree_iter: None,
             last_write,
         }
     }
+
+    pub(crate) fn vfs(&self) -> &VFS {
+        &self.vfs
+    }
 }
 
 pub struct PendingChanges<M: Matcher + Clone> {
     vfs: VFS,
-    walker: Walker<M>,
+    walker: PendingWalker<M>,
     matcher: M,
     treestate: Arc<Mutex<TreeState>>,
     stage: PendingChangesStage,
     include_directories: bool,
     seen: HashSet<RepoPathBuf>,
     lookups: Vec<RepoPathBuf>,
+    lookup: Option<Arc<ManifestLookup>>,
+    // Paths reported by a file change journal. Nothing else needs to be checked but the files
+    // marked NEED_CHECK.
+    journal: Option<HashSet<RepoPathBuf>>,
     tree_iter: Option<Box<dyn Iterator<Item = Result<PendingChangeResult>> + Send>>,
     last_write: HgModifiedTime,
 }
 
+enum PendingWalker<M> {
+    Serial(Walker<M>),
+    Parallel(ParallelWalker),
+    Journal(std::vec::IntoIter<Result<WalkEntry>>),
+}
+
+impl<M: Matcher> Iterator for PendingWalker<M> {
+    type Item = Result<WalkEntry>;
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            PendingWalker::Serial(walker) => walker.next(),
+            PendingWalker::Parallel(walker) => walker.next(),
+            PendingWalker::Journal(entries) => entries.next(),
+        }
+    }
+}
+
 #[derive(PartialEq)]
 enum PendingChangesStage {
     Walk,
@@ -137,6 +207,69 @@ pub enum PendingChangeResult {
 }
 
 impl<M: Matcher + Clone> PendingChanges<M> {
+    /// Resolve files that need their content compared with the parent using `lookup`. Without
+    /// it, such files are not reported.
+    pub fn with_lookup(mut self, lookup: Arc<ManifestLookup>) -> Self {
+        self.lookup = Some(lookup);
+        self
+    }
+
+    /// Only check the paths reported by a file change journal, and the files marked NEED_CHECK,
+    /// instead of walking the whole working copy. A path in `changed` stands for everything
+    /// below it too, as a directory can be removed or renamed as a whole.
+    pub fn with_journal(mut self, changed: Vec<RepoPathBuf>) -> Result<Self> {
+        let mut journal: HashSet<RepoPathBuf> = changed.into_iter().collect();
+        journal.extend(self.get_need_check()?);
+
+        let mut paths: Vec<&RepoPathBuf> = journal.iter().collect();
+        // Sorted in reverse so that the walk yields them in order, like `prepare_lookups`.
+        paths.sort_unstable_by(|a, b| b.as_str().cmp(a.as_str()));
+        let entries: Vec<Result<WalkEntry>> = paths
+            .into_iter()
+            .filter_map(|path| self.walk_journal_path(path).transpose())
+            .collect();
+
+        self.walker = PendingWalker::Journal(entries.into_iter());
+        self.journal = Some(journal);
+        Ok(self)
+    }
+
+    /// Returns the entry the walk would have found at `path`, if any.
+    fn walk_journal_path(&self, path: &RepoPath) -> Result<Option<WalkEntry>> {
+        if !self.matcher.matches_file(path) {
+            return Ok(None);
+        }
+        // Like the walk, skip .hg directories, ignored directories, directories behind symlinks,
+        // and nested repositories.
+        for dir in path.parents().skip(1) {
+            let is_dir = match self.vfs.metadata(dir) {
+                Ok(metadata) => metadata.file_type().is_dir(),
+                Err(_) => false,
+            };
+            if !is_dir
+                || dir.last_component().map(|name| name.as_str()) == Some(".hg")
+                || self.matcher.matches_directory(dir) == DirectoryMatch::Nothing
+                || self.vfs.join(dir).join(".hg").exists()
+            {
+                return Ok(None);
+            }
+        }
+
+        // Missing files are found by the tree iteration.
+        let metadata = match self.vfs.metadata(path) {
+            Ok(metadata) => metadata,
+            Err(_) => return Ok(None),
+        };
+        let file_type = metadata.file_type();
+        if file_type.is_file() || file_type.is_symlink() {
+            Ok(Some(WalkEntry::File(path.to_owned(), metadata)))
+        } else if file_type.is_dir() {
+            Ok(None)
+        } else {
+            Err(WalkError::InvalidFileType(path.to_owned()).into())
+        }
+    }
+
     fn is_changed(&mut self, path: &RepoPath, metadata: &Metadata) -> Result<bool> {
         let mut treestate = self.treestate.lock();
         let state = treestate.get(path)?;
@@ -250,19 +383,20 @@ impl<M: Matcher + Clone> PendingChanges<M> {
             results.push(Err(e));
             return results;
         }
-        let tracked = tracked.unwrap();
+        let mut tracked = tracked.unwrap();
+        if let Some(journal) = &self.journal {
+            tracked.retain(|path| {
+                journal.contains(path) || path.parents().any(|dir| journal.contains(dir))
+            });
+        }
 
         for path in tracked.into_iter() {
             if self.seen.contains(&path) || !self.matcher.matches_file(&path) {
                 continue;
             }
 
-            // If it's behind a symlink consider it deleted.
-            let metadata = self.vfs.metadata(&path);
-
-            // TODO: audit the path for symlinks and weirdness
-            // If it's missing or not readable, consider it deleted.
-            let metadata = match metadata {
+            // If it's missing, not readable or behind a symlink, consider it deleted.
+            let metadata = match self.vfs.audited_metadata(&path) {
                 Ok(metadata) => metadata,
                 Err(_) => {
                     results.push(Ok(PendingChangeResult::File(ChangeType::Deleted(path))));
@@ -321,7 +455,53 @@ impl<M: Matcher + Clone> PendingChanges<M> {
         Ok(result)
     }
 
+    /// Returns the files in the treestate that are marked NEED_CHECK.
+    fn get_need_check(&mut self) -> Result<Vec<RepoPathBuf>> {
+        let mut treestate = self.treestate.lock();
+
+        let mut result = Vec::new();
+        let mask = StateFlags::NEED_CHECK;
+
+        treestate.visit(
+            &mut |components, _| {
+                let path = components.concat();
+                let path = RepoPathBuf::from_utf8(path)?;
+                result.push(path);
+                Ok(VisitorResult::NotChanged)
+            },
+            &|_path, dir| match dir.get_aggregated_state() {
+                None => true,
+                Some(state) => state.union.intersects(mask),
+            },
+            &|_path, file| file.state.intersects(mask),
+        )?;
+        Ok(result)
+    }
+
+    fn prepare_lookups(&mut self) -> Result<()> {
+        // Sorted in reverse so that popping yields them in order.
+        self.lookups
+            .sort_unstable_by(|a, b| b.as_str().cmp(a.as_str()));
+        if let Some(lookup) = &self.lookup {
+            lookup.prefetch(&self.lookups)?;
+        }
+        Ok(())
+    }
+
     fn next_lookup(&mut self) -> Option<Result<PendingChangeResult>> {
+        let lookup = self.lookup.clone()?;
+        while let Some(path) = self.lookups.pop() {
+            match lookup.check(&self.vfs, &path) {
+                Ok(LookupResult::Changed) => {
+                    return Some(Ok(PendingChangeResult::File(ChangeType::Changed(path))));
+                }
+                Ok(LookupResult::Deleted) => {
+                    return Some(Ok(PendingChangeResult::File(ChangeType::Deleted(path))));
+                }
+                Ok(LookupResult::Clean) => {}
+                Err(e) => return Some(Err(e)),
+            }
+        }
         None
     }
 }
@@ -347,6 +527,11 @@ impl<M: Matcher + Clone> Iterator for PendingChanges<M> {
             if self.stage == PendingChangesStage::Finished {
                 return None;
             }
+            if self.stage == PendingChangesStage::Lookups && self.lookup.is_some() {
+                if let Err(e) = self.prepare_lookups() {
+                    return Some(Err(e));
+                }
+            }
         }
     }
 }
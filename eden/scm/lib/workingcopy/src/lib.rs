@@ -6,4 +6,6 @@
  */
 
 pub mod filesystem;
+pub mod lookup;
+pub mod status;
 pub mod walker;
@@ -0,0 +1,107 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::{fs, io, sync::Arc};
+
+use anyhow::{format_err, Result};
+
+use manifest::{FileType, Manifest};
+use manifest_tree::TreeManifest;
+use revisionstore::{ContentStore, RemoteDataStore, StoreKey};
+use types::{Key, RepoPath, RepoPathBuf};
+use vfs::{is_executable, is_symlink, VFS};
+
+pub enum LookupResult {
+    Changed,
+    Clean,
+    Deleted,
+}
+
+/// Resolves files whose size and mtime can't tell whether they changed, by comparing their
+/// content and flags with the first working parent.
+pub struct ManifestLookup {
+    manifest: Arc<TreeManifest>,
+    store: Arc<ContentStore>,
+}
+
+impl ManifestLookup {
+    pub fn new(manifest: Arc<TreeManifest>, store: Arc<ContentStore>) -> Self {
+        ManifestLookup { manifest, store }
+    }
+
+    /// Fetch the parent's content of the given files in bulk, ahead of `check`.
+    pub fn prefetch(&self, paths: &[RepoPathBuf]) -> Result<()> {
+        let mut keys = Vec::new();
+        for path in paths {
+            if let Some(file) = self.manifest.get_file(path)? {
+                keys.push(StoreKey::from(Key::new(path.clone(), file.hgid)));
+            }
+        }
+        self.store.prefetch(&keys)
+    }
+
+    pub fn check(&self, vfs: &VFS, path: &RepoPath) -> Result<LookupResult> {
+        // If it's missing or not a normal file or a symlink, consider it deleted.
+        let metadata = match vfs.metadata(path) {
+            Ok(metadata) => metadata,
+            Err(_) => return Ok(LookupResult::Deleted),
+        };
+        let file_type = metadata.file_type();
+        if !file_type.is_file() && !file_type.is_symlink() {
+            return Ok(LookupResult::Deleted);
+        }
+
+        let parent = match self.manifest.get_file(path)? {
+            Some(parent) => parent,
+            None => return Ok(LookupResult::Changed),
+        };
+
+        let symlink = is_symlink(&metadata);
+        let symlink_different =
+            vfs.supports_symlinks() && symlink != (parent.file_type == FileType::Symlink);
+        // Symlinks always have the executable bits set, so only compare them for regular files.
+        let exec_different = vfs.supports_executables()
+            && !symlink
+            && is_executable(&metadata) != (parent.file_type == FileType::Executable);
+        if symlink_different || exec_different {
+            return Ok(LookupResult::Changed);
+        }
+
+        let local = if symlink {
+            match fs::read_link(vfs.join(path)) {
+                Ok(target) => match target.to_str() {
+                    Some(target) => target.as_bytes().to_vec(),
+                    None => return Ok(LookupResult::Changed),
+                },
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    return Ok(LookupResult::Deleted);
+                }
+                Err(e) => return Err(e.into()),
+            }
+        } else {
+            match fs::read(vfs.join(path)) {
+                Ok(content) => content,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    return Ok(LookupResult::Deleted);
+                }
+                Err(e) => return Err(e.into()),
+            }
+        };
+
+        let key = Key::new(path.to_owned(), parent.hgid);
+        let content = self
+            .store
+            .get_file_content(&key)?
+            .ok_or_else(|| format_err!("content of {} is not available", key))?;
+
+        if content.as_ref() == local.as_slice() {
+            Ok(LookupResult::Clean)
+        } else {
+            Ok(LookupResult::Changed)
+        }
+    }
+}
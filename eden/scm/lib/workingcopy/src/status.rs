@@ -0,0 +1,509 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+use anyhow::Result;
+use parking_lot::Mutex;
+
+use manifest::Manifest;
+use manifest_tree::TreeManifest;
+use pathmatcher::{DirectoryMatch, Matcher};
+use revisionstore::ContentStore;
+use treestate::filestate::{FileStateV2, StateFlags};
+use treestate::tree::VisitorResult;
+use treestate::treestate::TreeState;
+use types::{RepoPath, RepoPathBuf};
+
+use crate::filesystem::{ChangeType, HgModifiedTime, PendingChangeResult, PhysicalFileSystem};
+use crate::lookup::ManifestLookup;
+use crate::walker::WalkError;
+
+/// Which of the optional, and potentially expensive, statuses to compute.
+#[derive(Default)]
+pub struct StatusOptions {
+    pub clean: bool,
+    pub unknown: bool,
+    pub ignored: bool,
+    /// Paths changed since the treestate was last written, according to a file change journal.
+    /// If set, only those and the files marked NEED_CHECK are examined, unless ignored files are
+    /// requested: these are not recorded in the treestate.
+    pub journal: Option<Vec<RepoPathBuf>>,
+}
+
+/// Status of the working copy relative to its first parent. Each list is sorted.
+#[derive(Default)]
+pub struct Status {
+    pub modified: Vec<RepoPathBuf>,
+    pub added: Vec<RepoPathBuf>,
+    pub removed: Vec<RepoPathBuf>,
+    pub deleted: Vec<RepoPathBuf>,
+    pub clean: Vec<RepoPathBuf>,
+    pub unknown: Vec<RepoPathBuf>,
+    pub ignored: Vec<RepoPathBuf>,
+    /// Maps files marked as copied to their copy source.
+    pub copymap: HashMap<RepoPathBuf, RepoPathBuf>,
+    /// Paths that could not be walked.
+    pub errors: Vec<WalkError>,
+}
+
+/// State of a file in the dirstate, in terms of Mercurial's dirstate states.
+#[derive(Clone, Copy, PartialEq)]
+enum HgState {
+    Normal,
+    Merged,
+    Added,
+    Removed,
+    Untracked,
+}
+
+impl From<StateFlags> for HgState {
+    fn from(flags: StateFlags) -> Self {
+        match (
+            flags.intersects(StateFlags::EXIST_P1 | StateFlags::EXIST_P2),
+            flags.contains(StateFlags::EXIST_P1 | StateFlags::EXIST_P2),
+            flags.contains(StateFlags::EXIST_NEXT),
+        ) {
+            (true, true, true) => HgState::Merged,
+            (true, false, true) => HgState::Normal,
+            (true, _, false) => HgState::Removed,
+            (false, _, true) => HgState::Added,
+            (false, _, false) => HgState::Untracked,
+        }
+    }
+}
+
+/// Skips the files and directories `matcher` doesn't match during the walk, and the ignored
+/// ones unless they are to be listed.
+#[derive(Clone)]
+struct StatusMatcher<'a> {
+    matcher: &'a dyn Matcher,
+    ignore: Option<&'a dyn Matcher>,
+}
+
+impl<'a> Matcher for StatusMatcher<'a> {
+    fn matches_directory(&self, path: &RepoPath) -> DirectoryMatch {
+        let unignored = match self.ignore.map(|ignore| ignore.matches_directory(path)) {
+            Some(DirectoryMatch::Everything) => DirectoryMatch::Nothing,
+            Some(DirectoryMatch::Nothing) | None => DirectoryMatch::Everything,
+            Some(DirectoryMatch::ShouldTraverse) => DirectoryMatch::ShouldTraverse,
+        };
+        if unignored == DirectoryMatch::Nothing {
+            return DirectoryMatch::Nothing;
+        }
+        match self.matcher.matches_directory(path) {
+            DirectoryMatch::Everything => unignored,
+            result => result,
+        }
+    }
+
+    fn matches_file(&self, path: &RepoPath) -> bool {
+        self.matcher.matches_file(path)
+            && self
+                .ignore
+                .map_or(true, |ignore| !ignore.matches_file(path))
+    }
+}
+
+/// Compute the status of the files in the working copy that `matcher` matches.
+///
+/// Files whose stat information is inconclusive are compared with `p1_manifest`, reading the
+/// parent's content from `file_store`. Tracked files are never ignored; `ignore` only decides
+/// between unknown and ignored for untracked files.
+pub fn status(
+    filesystem: &PhysicalFileSystem,
+    treestate: Arc<Mutex<TreeState>>,
+    p1_manifest: Arc<TreeManifest>,
+    file_store: Arc<ContentStore>,
+    matcher: &dyn Matcher,
+    ignore: &dyn Matcher,
+    options: &StatusOptions,
+) -> Result<Status> {
+    let mut status = Status::default();
+    let lookup = Arc::new(ManifestLookup::new(p1_manifest.clone(), file_store));
+    let status_matcher = StatusMatcher {
+        matcher,
+        ignore: if options.ignored { None } else { Some(ignore) },
+    };
+    // Nothing is written back to the treestate, so there is no write that mtimes could race
+    // with.
+    let mut pending_changes = filesystem
+        .pending_changes(
+            treestate.clone(),
+            status_matcher,
+            false,
+            HgModifiedTime::from(0u64),
+        )
+        .with_lookup(lookup);
+    if let (Some(journal), false) = (&options.journal, options.ignored) {
+        pending_changes = pending_changes.with_journal(journal.clone())?;
+    }
+
+    // Step 1: Files that differ from the clean checkout of p1.
+    let mut seen = HashSet::new();
+    for change in pending_changes {
+        let (path, exists) = match change {
+            Ok(PendingChangeResult::File(ChangeType::Changed(path))) => (path, true),
+            Ok(PendingChangeResult::File(ChangeType::Deleted(path))) => (path, false),
+            Ok(PendingChangeResult::SeenDirectory(_)) => continue,
+            Err(e) => match e.downcast::<WalkError>() {
+                Ok(e) => {
+                    status.errors.push(e);
+                    continue;
+                }
+                Err(e) => return Err(e),
+            },
+        };
+
+        let state = match treestate.lock().get(path.as_byte_slice())? {
+            Some(state) => HgState::from(state.state),
+            None => HgState::Untracked,
+        };
+        match state {
+            HgState::Untracked => {
+                if ignore.matches_file(&path) {
+                    if options.ignored {
+                        status.ignored.push(path);
+                    }
+                } else if options.unknown {
+                    status.unknown.push(path);
+                }
+            }
+            HgState::Normal | HgState::Merged | HgState::Added if !exists => {
+                seen.insert(path.clone());
+                status.deleted.push(path);
+            }
+            HgState::Normal => {
+                seen.insert(path.clone());
+                status.modified.push(path);
+            }
+            // Handled below, along with the unchanged files in those states.
+            HgState::Merged | HgState::Added | HgState::Removed => {}
+        }
+    }
+
+    // Step 2: Files whose status doesn't only depend on their content.
+    let mask = StateFlags::EXIST_P1 | StateFlags::EXIST_P2 | StateFlags::EXIST_NEXT;
+    let mut nonnormal: Vec<(RepoPathBuf, FileStateV2)> = Vec::new();
+    treestate.lock().visit(
+        &mut |components, state| {
+            let path = RepoPathBuf::from_utf8(components.concat())?;
+            nonnormal.push((path, state.clone()));
+            Ok(VisitorResult::NotChanged)
+        },
+        &|_path, dir| match dir.get_aggregated_state() {
+            None => true,
+            Some(state) => state.union.intersects(mask),
+        },
+        &|_path, file| {
+            file.state.contains(StateFlags::COPIED)
+                || file.state.contains(StateFlags::EXIST_P2)
+                || HgState::from(file.state) != HgState::Normal
+        },
+    )?;
+
+    let vfs = filesystem.vfs();
+    // Files behind a symlink are not part of the working copy.
+    let exists = |path: &RepoPath| vfs.audited_metadata(path).is_ok();
+    for (path, state) in nonnormal {
+        if !matcher.matches_file(&path) {
+            continue;
+        }
+        if let Some(source) = state.copied.as_ref() {
+            if state.state.contains(StateFlags::COPIED) {
+                status
+                    .copymap
+                    .insert(path.clone(), RepoPathBuf::from_utf8(source.to_vec())?);
+            }
+        }
+        if seen.contains(&path) {
+            continue;
+        }
+
+        let from_p2_only = state.state.contains(StateFlags::EXIST_P2)
+            && !state.state.contains(StateFlags::EXIST_P1);
+        match HgState::from(state.state) {
+            // Files from p2 are considered modified, pending changes only compare with p1.
+            HgState::Normal if from_p2_only => {
+                if exists(&path) {
+                    status.modified.push(path.clone());
+                } else {
+                    status.deleted.push(path.clone());
+                }
+            }
+            HgState::Merged => status.modified.push(path.clone()),
+            HgState::Added => {
+                if exists(&path) {
+                    status.added.push(path.clone());
+                } else {
+                    status.deleted.push(path.clone());
+                }
+            }
+            HgState::Removed => status.removed.push(path.clone()),
+            // A clean file that is retroactively marked as copied.
+            HgState::Normal if state.state.contains(StateFlags::COPIED) => {
+                status.modified.push(path.clone());
+            }
+            HgState::Normal | HgState::Untracked => continue,
+        }
+        seen.insert(path);
+    }
+
+    // Step 3: Everything else in p1 is clean.
+    if options.clean {
+        for file in p1_manifest.files(&matcher) {
+            let path = file?.path;
+            if !seen.contains(&path) {
+                status.clean.push(path);
+            }
+        }
+    }
+
+    for list in &mut [
+        &mut status.modified,
+        &mut status.added,
+        &mut status.removed,
+        &mut status.deleted,
+        &mut status.clean,
+        &mut status.unknown,
+        &mut status.ignored,
+    ] {
+        list.sort_unstable_by(|a, b| a.as_str().cmp(b.as_str()));
+    }
+
+    Ok(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs::{create_dir_all, write};
+
+    use bytes::Bytes;
+    use manifest_tree::testutil::make_tree_manifest;
+    use pathmatcher::{AlwaysMatcher, GitignoreMatcher, TreeMatcher};
+    use revisionstore::testutil::{make_config, FakeHgIdRemoteStore};
+    use revisionstore::ContentStoreBuilder;
+    use tempfile::{tempdir, TempDir};
+    use types::testutil::{key, repo_path_buf};
+
+    fn file_state(state: StateFlags, size: i32, copied: Option<&str>) -> FileStateV2 {
+        FileStateV2 {
+            mode: 0o100644,
+            size,
+            mtime: -1,
+            state,
+            copied: copied.map(|path| path.as_bytes().to_vec().into_boxed_slice()),
+        }
+    }
+
+    fn paths(paths: &[&str]) -> Vec<RepoPathBuf> {
+        paths.iter().map(|path| repo_path_buf(path)).collect()
+    }
+
+    struct TestRepo {
+        root: TempDir,
+        _treestate_dir: TempDir,
+        _cachedir: TempDir,
+        _localdir: TempDir,
+        treestate: Arc<Mutex<TreeState>>,
+        manifest: Arc<TreeManifest>,
+        store: Arc<ContentStore>,
+    }
+
+    impl TestRepo {
+        fn new() -> Result<Self> {
+            let root = tempdir()?;
+            let files = [
+                ("clean", "clean\n"),
+                ("modified", "after\n"),
+                ("added", "added\n"),
+                ("copied", "clean\n"),
+                ("unknown", "unknown\n"),
+                ("build/ignored", "ignored\n"),
+                (".gitignore", "build/\n"),
+            ];
+            create_dir_all(root.path().join("build"))?;
+            for (path, content) in files.iter() {
+                write(root.path().join(path), content)?;
+            }
+
+            let treestate_dir = tempdir()?;
+            let mut treestate = TreeState::open(treestate_dir.path().join("tree"), None)?;
+            let normal = StateFlags::EXIST_P1 | StateFlags::EXIST_NEXT;
+            treestate.insert(
+                "clean",
+                &file_state(normal | StateFlags::NEED_CHECK, 6, None),
+            )?;
+            treestate.insert("modified", &file_state(normal, -1, None))?;
+            treestate.insert("added", &file_state(StateFlags::EXIST_NEXT, -1, None))?;
+            treestate.insert(
+                "copied",
+                &file_state(
+                    StateFlags::EXIST_NEXT | StateFlags::COPIED,
+                    -1,
+                    Some("clean"),
+                ),
+            )?;
+            treestate.insert("removed", &file_state(StateFlags::EXIST_P1, 0, None))?;
+            treestate.insert("deleted", &file_state(normal, 7, None))?;
+            let treestate = Arc::new(Mutex::new(treestate));
+
+            let manifest = make_tree_manifest(&[
+                ("clean", "1"),
+                ("modified", "2"),
+                ("removed", "3"),
+                ("deleted", "4"),
+            ]);
+            let mut contents = HashMap::new();
+            contents.insert(key("clean", "1"), (Bytes::from("clean\n"), None));
+            contents.insert(key("modified", "2"), (Bytes::from("before\n"), None));
+            let mut remotestore = FakeHgIdRemoteStore::new();
+            remotestore.data(contents);
+            let cachedir = tempdir()?;
+            let localdir = tempdir()?;
+            let config = make_config(&cachedir);
+            let store = ContentStoreBuilder::new(&config)
+                .local_path(&localdir)
+                .remotestore(Arc::new(remotestore))
+                .build()?;
+
+            Ok(TestRepo {
+                root,
+                _treestate_dir: treestate_dir,
+                _cachedir: cachedir,
+                _localdir: localdir,
+                treestate,
+                manifest: Arc::new(manifest),
+                store: Arc::new(store),
+            })
+        }
+
+        fn status(&self, matcher: &dyn Matcher, options: &StatusOptions) -> Result<Status> {
+            let filesystem = PhysicalFileSystem::new(self.root.path().to_path_buf())?;
+            let ignore = GitignoreMatcher::new(self.root.path(), Vec::new());
+            status(
+                &filesystem,
+                self.treestate.clone(),
+                self.manifest.clone(),
+                self.store.clone(),
+                matcher,
+                &ignore,
+                options,
+            )
+        }
+    }
+
+    #[test]
+    fn test_status() -> Result<()> {
+        let repo = TestRepo::new()?;
+        let options = StatusOptions {
+            clean: true,
+            unknown: true,
+            ignored: true,
+            journal: None,
+        };
+        let status = repo.status(&AlwaysMatcher::new(), &options)?;
+
+        assert_eq!(status.modified, paths(&["modified"]));
+        assert_eq!(status.added, paths(&["added", "copied"]));
+        assert_eq!(status.removed, paths(&["removed"]));
+        assert_eq!(status.deleted, paths(&["deleted"]));
+        assert_eq!(status.clean, paths(&["clean"]));
+        assert_eq!(status.unknown, paths(&[".gitignore", "unknown"]));
+        assert_eq!(status.ignored, paths(&["build/ignored"]));
+        assert_eq!(
+            status.copymap.get(&repo_path_buf("copied")),
+            Some(&repo_path_buf("clean"))
+        );
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_status_behind_symlink() -> Result<()> {
+        let repo = TestRepo::new()?;
+        let other = tempdir()?;
+        for path in &["normal", "added"] {
+            write(other.path().join(path), "content\n")?;
+        }
+        std::os::unix::fs::symlink(other.path(), repo.root.path().join("link"))?;
+        {
+            let mut treestate = repo.treestate.lock();
+            let normal = StateFlags::EXIST_P1 | StateFlags::EXIST_NEXT;
+            treestate.insert("link/normal", &file_state(normal, 8, None))?;
+            treestate.insert("link/added", &file_state(StateFlags::EXIST_NEXT, -1, None))?;
+        }
+        let options = StatusOptions {
+            clean: false,
+            unknown: true,
+            ignored: false,
+            journal: None,
+        };
+        let status = repo.status(&AlwaysMatcher::new(), &options)?;
+
+        // The files exist, but not in the working copy.
+        assert_eq!(status.added, paths(&["added", "copied"]));
+        assert_eq!(
+            status.deleted,
+            paths(&["deleted", "link/added", "link/normal"])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_status_journal() -> Result<()> {
+        let repo = TestRepo::new()?;
+        // "deleted" and ".gitignore" changed before the journal's clock, the treestate should
+        // have marked them NEED_CHECK.
+        let options = StatusOptions {
+            clean: false,
+            unknown: true,
+            ignored: false,
+            journal: Some(paths(&["modified", "unknown", "build"])),
+        };
+        let status = repo.status(&AlwaysMatcher::new(), &options)?;
+
+        assert_eq!(status.modified, paths(&["modified"]));
+        assert_eq!(status.added, paths(&["added", "copied"]));
+        assert_eq!(status.removed, paths(&["removed"]));
+        assert!(status.deleted.is_empty());
+        assert_eq!(status.unknown, paths(&["unknown"]));
+        assert!(status.ignored.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_status_matcher() -> Result<()> {
+        let repo = TestRepo::new()?;
+        let options = StatusOptions {
+            clean: true,
+            unknown: true,
+            ignored: true,
+            journal: None,
+        };
+        let matcher = TreeMatcher::from_rules(["c*/**", "build/**", "deleted"].iter())?;
+        let status = repo.status(&matcher, &options)?;
+
+        assert!(status.modified.is_empty());
+        assert_eq!(status.added, paths(&["copied"]));
+        assert!(status.removed.is_empty());
+        assert_eq!(status.deleted, paths(&["deleted"]));
+        assert_eq!(status.clean, paths(&["clean"]));
+        assert!(status.unknown.is_empty());
+        assert_eq!(status.ignored, paths(&["build/ignored"]));
+        assert_eq!(
+            status.copymap.get(&repo_path_buf("copied")),
+            Some(&repo_path_buf("clean"))
+        );
+        Ok(())
+    }
+}
@@ -5,11 +5,16 @@
  * GNU General Public License version 2.
  */
 
+use std::cmp::Reverse;
 use std::fs::{self, DirEntry, Metadata};
 use std::io;
 use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
 
 use anyhow::Result;
+use parking_lot::{Condvar, Mutex};
 use thiserror::Error;
 
 use pathmatcher::{DirectoryMatch, Matcher};
@@ -28,6 +33,8 @@ pub enum WalkError {
     InvalidFileType(RepoPathBuf),
     #[error("invalid mtime for '{0}': {1}")]
     InvalidMTime(RepoPathBuf, #[source] anyhow::Error),
+    #[error("walker thread panicked: {0}")]
+    WorkerPanic(String),
 }
 
 impl WalkError {
@@ -38,6 +45,7 @@ impl WalkError {
             WalkError::RepoPathError(path, _) => path.to_string(),
             WalkError::InvalidFileType(path) => path.to_string(),
             WalkError::InvalidMTime(path, _) => path.to_string(),
+            WalkError::WorkerPanic(_) => String::new(),
         }
     }
 
@@ -48,6 +56,7 @@ impl WalkError {
             WalkError::RepoPathError(_, error) => error.to_string(),
             WalkError::InvalidFileType(_) => "invalid file type".to_string(),
             WalkError::InvalidMTime(_, error) => format!("invalid mtime - {}", error.to_string()),
+            WalkError::WorkerPanic(message) => format!("walker thread panicked - {}", message),
         }
     }
 }
@@ -66,6 +75,67 @@ impl AsRef<RepoPath> for WalkEntry {
     }
 }
 
+fn match_entry<M: Matcher>(
+    matcher: &M,
+    next_dir: &RepoPathBuf,
+    entry: DirEntry,
+    results: &mut Vec<Result<WalkEntry>>,
+    dir_matches: &mut Vec<RepoPathBuf>,
+) -> Result<()> {
+    // It'd be nice to move all this conversion noise to a function, but having it here saves
+    // us from allocating filename repeatedly.
+    let filename = entry.file_name();
+    let filename = filename.to_str().ok_or(WalkError::FsUtf8Error(
+        filename.to_string_lossy().into_owned(),
+    ))?;
+    let filename = RepoPath::from_str(filename)
+        .map_err(|e| WalkError::RepoPathError(filename.to_owned(), e))?;
+    let filetype = entry
+        .file_type()
+        .map_err(|e| WalkError::IOError(filename.to_owned(), e))?;
+
+    let mut candidate_path = next_dir.clone();
+    candidate_path.push(filename);
+    if filetype.is_file() || filetype.is_symlink() {
+        if matcher.matches_file(candidate_path.as_repo_path()) {
+            results.push(Ok(WalkEntry::File(candidate_path, entry.metadata()?)));
+        }
+    } else if filetype.is_dir() {
+        if filename.as_str() != ".hg"
+            && matcher.matches_directory(candidate_path.as_repo_path()) != DirectoryMatch::Nothing
+        {
+            dir_matches.push(candidate_path);
+        }
+    } else if matcher.matches_file(candidate_path.as_repo_path()) {
+        return Err(WalkError::InvalidFileType(filename.to_owned()).into());
+    }
+    Ok(())
+}
+
+/// Read a single directory, adding matching files to `results` and the subdirectories that
+/// still need to be walked to `dir_matches`.
+fn walk_directory<M: Matcher>(
+    root: &Path,
+    matcher: &M,
+    next_dir: &RepoPathBuf,
+    results: &mut Vec<Result<WalkEntry>>,
+    dir_matches: &mut Vec<RepoPathBuf>,
+) -> Result<()> {
+    let abs_next_dir = root.join(next_dir.as_str());
+    // Don't process the directory if it contains a .hg directory, unless it's the root.
+    if next_dir.is_empty() || !Path::exists(&abs_next_dir.join(".hg")) {
+        for entry in
+            fs::read_dir(abs_next_dir).map_err(|e| WalkError::IOError(next_dir.clone(), e))?
+        {
+            let entry = entry.map_err(|e| WalkError::IOError(next_dir.clone(), e))?;
+            if let Err(e) = match_entry(matcher, next_dir, entry, results, dir_matches) {
+                results.push(Err(e));
+            }
+        }
+    }
+    Ok(())
+}
+
 /// Walker traverses the working copy, starting at the root of the repo,
 /// finding files matched by matcher
 pub struct Walker<M> {
@@ -94,41 +164,6 @@ where
         }
     }
 
-    fn match_entry(&mut self, next_dir: &RepoPathBuf, entry: DirEntry) -> Result<()> {
-        // It'd be nice to move all this conversion noise to a function, but having it here saves
-        // us from allocating filename repeatedly.
-        let filename = entry.file_name();
-        let filename = filename.to_str().ok_or(WalkError::FsUtf8Error(
-            filename.to_string_lossy().into_owned(),
-        ))?;
-        let filename = RepoPath::from_str(filename)
-            .map_err(|e| WalkError::RepoPathError(filename.to_owned(), e))?;
-        let filetype = entry
-            .file_type()
-            .map_err(|e| WalkError::IOError(filename.to_owned(), e))?;
-
-        let mut candidate_path = next_dir.clone();
-        candidate_path.push(filename);
-        if filetype.is_file() || filetype.is_symlink() {
-            if self.matcher.matches_file(candidate_path.as_repo_path()) {
-                self.results
-                    .push(Ok(WalkEntry::File(candidate_path, entry.metadata()?)));
-            }
-        } else if filetype.is_dir() {
-            if filename.as_str() != ".hg"
-                && self
-                    .matcher
-                    .matches_directory(candidate_path.as_repo_path())
-                    != DirectoryMatch::Nothing
-            {
-                self.dir_matches.push(candidate_path);
-            }
-        } else if self.matcher.matches_file(candidate_path.as_repo_path()) {
-            return Err(WalkError::InvalidFileType(filename.to_owned()).into());
-        }
-        Ok(())
-    }
-
     /// Lazy traversal to find matching files
     fn walk(&mut self) -> Result<()> {
         while self.results.is_empty() && !self.dir_matches.is_empty() {
@@ -137,18 +172,13 @@ where
                 self.results
                     .push(Ok(WalkEntry::Directory(next_dir.clone())));
             }
-            let abs_next_dir = self.root.join(next_dir.as_str());
-            // Don't process the directory if it contains a .hg directory, unless it's the root.
-            if next_dir.is_empty() || !Path::exists(&abs_next_dir.join(".hg")) {
-                for entry in fs::read_dir(abs_next_dir)
-                    .map_err(|e| WalkError::IOError(next_dir.clone(), e))?
-                {
-                    let entry = entry.map_err(|e| WalkError::IOError(next_dir.clone(), e))?;
-                    if let Err(e) = self.match_entry(&next_dir, entry) {
-                        self.results.push(Err(e));
-                    }
-                }
-            }
+            walk_directory(
+                &self.root,
+                &self.matcher,
+                &next_dir,
+                &mut self.results,
+                &mut self.dir_matches,
+            )?;
         }
         Ok(())
     }
@@ -167,6 +197,231 @@ where
     }
 }
 
+/// Directories waiting to be read by the `ParallelWalker` threads.
+struct WalkQueue {
+    state: Mutex<WalkQueueState>,
+    condvar: Condvar,
+}
+
+struct WalkQueueState {
+    dirs: Vec<RepoPathBuf>,
+    // Number of directories currently being read. Their subdirectories aren't queued yet.
+    busy: usize,
+    cancelled: bool,
+}
+
+impl WalkQueue {
+    fn new(dirs: Vec<RepoPathBuf>) -> Self {
+        WalkQueue {
+            state: Mutex::new(WalkQueueState {
+                dirs,
+                busy: 0,
+                cancelled: false,
+            }),
+            condvar: Condvar::new(),
+        }
+    }
+
+    /// Take the next directory to read, waiting for other threads if the queue is empty.
+    /// Returns `None` once there is nothing left to walk.
+    fn pop(&self) -> Option<RepoPathBuf> {
+        let mut state = self.state.lock();
+        loop {
+            if state.cancelled {
+                return None;
+            }
+            if let Some(dir) = state.dirs.pop() {
+                state.busy += 1;
+                return Some(dir);
+            }
+            if state.busy == 0 {
+                return None;
+            }
+            self.condvar.wait(&mut state);
+        }
+    }
+
+    /// Mark a directory returned by `pop` as read, queueing its matching subdirectories.
+    fn finish(&self, dirs: Vec<RepoPathBuf>, cancelled: bool) {
+        let mut state = self.state.lock();
+        state.busy -= 1;
+        state.cancelled |= cancelled;
+        state.dirs.extend(dirs);
+        self.condvar.notify_all();
+    }
+
+    /// Stop all threads after the directories they are reading.
+    fn cancel(&self) {
+        let mut state = self.state.lock();
+        state.cancelled = true;
+        self.condvar.notify_all();
+    }
+}
+
+/// Finishes a directory returned by `WalkQueue::pop` when dropped, so that a panicking thread
+/// doesn't leave the other threads waiting for it. A panic cancels the walk.
+struct ReadingDirectory<'a> {
+    queue: &'a WalkQueue,
+    dir_matches: Vec<RepoPathBuf>,
+    cancelled: bool,
+}
+
+impl Drop for ReadingDirectory<'_> {
+    fn drop(&mut self) {
+        let dir_matches = std::mem::take(&mut self.dir_matches);
+        self.queue
+            .finish(dir_matches, self.cancelled || thread::panicking());
+    }
+}
+
+fn walk_worker<M: Matcher>(
+    root: &Path,
+    matcher: &M,
+    include_directories: bool,
+    queue: &WalkQueue,
+    sender: Sender<Result<WalkEntry>>,
+) {
+    while let Some(next_dir) = queue.pop() {
+        let mut reading = ReadingDirectory {
+            queue,
+            dir_matches: Vec::new(),
+            cancelled: false,
+        };
+        let mut results = Vec::new();
+        if include_directories {
+            results.push(Ok(WalkEntry::Directory(next_dir.clone())));
+        }
+        if let Err(e) = walk_directory(
+            root,
+            matcher,
+            &next_dir,
+            &mut results,
+            &mut reading.dir_matches,
+        ) {
+            results.push(Err(e));
+        }
+        // The receiver is gone if the ParallelWalker was dropped. Stop all threads in that case.
+        reading.cancelled = results
+            .into_iter()
+            .any(|result| sender.send(result).is_err());
+    }
+}
+
+/// ParallelWalker finds the same files as Walker, but reads directories from several threads.
+///
+/// Entries are yielded as soon as a thread finds them, so their order varies between walks
+/// unless `sorted` is set. A sorted walk waits for the traversal to complete, then yields
+/// entries by path, followed by errors. A thread that panics stops the walk, and the panic is
+/// yielded as a `WalkError::WorkerPanic` after the entries that were found.
+pub struct ParallelWalker {
+    receiver: Receiver<Result<WalkEntry>>,
+    sorted: bool,
+    // Collected results of a sorted walk, in reverse order.
+    results: Option<Vec<Result<WalkEntry>>>,
+    queue: Arc<WalkQueue>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl ParallelWalker {
+    pub fn new<M>(
+        root: PathBuf,
+        matcher: M,
+        include_directories: bool,
+        num_threads: usize,
+        sorted: bool,
+    ) -> Self
+    where
+        M: Matcher + Send + Sync + 'static,
+    {
+        let mut dir_matches = vec![];
+        if matcher.matches_directory(&RepoPathBuf::new()) != DirectoryMatch::Nothing {
+            dir_matches.push(RepoPathBuf::new());
+        }
+
+        let root = Arc::new(root);
+        let matcher = Arc::new(matcher);
+        let queue = Arc::new(WalkQueue::new(dir_matches));
+        let (sender, receiver) = channel();
+        let threads = (0..num_threads.max(1))
+            .map(|_| {
+                let root = root.clone();
+                let matcher = matcher.clone();
+                let queue = queue.clone();
+                let sender = sender.clone();
+                thread::spawn(move || {
+                    walk_worker(&root, &matcher, include_directories, &queue, sender)
+                })
+            })
+            .collect();
+
+        ParallelWalker {
+            receiver,
+            sorted,
+            results: None,
+            queue,
+            threads,
+        }
+    }
+
+    /// Wait for the threads to exit. Returns an error if one of them panicked.
+    fn join(&mut self) -> Option<WalkError> {
+        let mut panic = None;
+        for thread in self.threads.drain(..) {
+            if let Err(payload) = thread.join() {
+                let message = payload
+                    .downcast_ref::<&str>()
+                    .map(|s| s.to_string())
+                    .or_else(|| payload.downcast_ref::<String>().cloned())
+                    .unwrap_or_default();
+                panic.get_or_insert(WalkError::WorkerPanic(message));
+            }
+        }
+        panic
+    }
+}
+
+impl Iterator for ParallelWalker {
+    type Item = Result<WalkEntry>;
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.sorted {
+            // Fails once every thread is done and has dropped its sender.
+            return match self.receiver.recv() {
+                Ok(result) => Some(result),
+                Err(_) => self.join().map(|e| Err(e.into())),
+            };
+        }
+
+        if self.results.is_none() {
+            let mut results: Vec<_> = self.receiver.iter().collect();
+            results.sort_by_cached_key(|result| Reverse(sort_key(result)));
+            if let Some(e) = self.join() {
+                results.insert(0, Err(e.into()));
+            }
+            self.results = Some(results);
+        }
+        self.results.as_mut().and_then(|results| results.pop())
+    }
+}
+
+impl Drop for ParallelWalker {
+    fn drop(&mut self) {
+        self.queue.cancel();
+        self.join();
+    }
+}
+
+fn sort_key(result: &Result<WalkEntry>) -> (bool, String) {
+    match result {
+        Ok(entry) => (false, entry.as_ref().to_string()),
+        Err(e) => (
+            true,
+            e.downcast_ref::<WalkError>()
+                .map(|e| e.filename())
+                .unwrap_or_default(),
+        ),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -223,4 +478,103 @@ mod tests {
         assert!(walked_files.is_empty());
         Ok(())
     }
+
+    #[test]
+    fn test_parallel_walker() -> Result<()> {
+        let directories = vec!["dirA", "dirB/dirC/dirD", "dirE/dirF"];
+        let files = vec![
+            "dirA/a.txt",
+            "dirA/b.txt",
+            "dirB/dirC/dirD/c.txt",
+            "dirB/d.txt",
+            "dirE/dirF/e.txt",
+            "f.txt",
+        ];
+        let root_dir = create_directory(&directories, &files)?;
+        let root_path = PathBuf::from(root_dir.path());
+        let walker = ParallelWalker::new(root_path, AlwaysMatcher::new(), false, 4, true);
+        let walked_files: Result<Vec<_>> = walker.collect();
+        let walked_files: Vec<_> = walked_files?
+            .iter()
+            .map(|file| file.as_ref().to_string())
+            .collect();
+        let mut expected: Vec<_> = files.iter().map(|file| file.to_string()).collect();
+        expected.sort();
+        assert_eq!(walked_files, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parallel_walker_directories() -> Result<()> {
+        let directories = vec!["dirA", "dirB/dirC"];
+        let files = vec!["dirA/a.txt", "dirB/dirC/c.txt"];
+        let root_dir = create_directory(&directories, &files)?;
+        let root_path = PathBuf::from(root_dir.path());
+        let walker = ParallelWalker::new(root_path, AlwaysMatcher::new(), true, 2, false);
+        let mut walked: Vec<_> = walker
+            .map(|entry| match entry? {
+                WalkEntry::File(file, _) => Ok(format!("f {}", file)),
+                WalkEntry::Directory(dir) => Ok(format!("d {}", dir)),
+            })
+            .collect::<Result<_>>()?;
+        walked.sort();
+        assert_eq!(
+            walked,
+            vec![
+                "d ",
+                "d dirA",
+                "d dirB",
+                "d dirB/dirC",
+                "f dirA/a.txt",
+                "f dirB/dirC/c.txt",
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parallel_match_nothing() -> Result<()> {
+        let directories = vec!["dirA"];
+        let files = vec!["dirA/a.txt", "b.txt"];
+        let root_dir = create_directory(&directories, &files)?;
+        let root_path = PathBuf::from(root_dir.path());
+        let walker = ParallelWalker::new(root_path, NeverMatcher::new(), false, 4, false);
+        let walked_files: Vec<_> = walker.collect();
+        assert!(walked_files.is_empty());
+        Ok(())
+    }
+
+    /// Panics when asked about `panic.txt`.
+    struct PanicMatcher;
+
+    impl Matcher for PanicMatcher {
+        fn matches_directory(&self, _path: &RepoPath) -> DirectoryMatch {
+            DirectoryMatch::ShouldTraverse
+        }
+
+        fn matches_file(&self, path: &RepoPath) -> bool {
+            if path.as_str().ends_with("panic.txt") {
+                panic!("matcher panicked");
+            }
+            true
+        }
+    }
+
+    #[test]
+    fn test_parallel_walker_panic() -> Result<()> {
+        let directories = vec!["dirA", "dirB/dirC"];
+        let files = vec!["dirA/a.txt", "dirB/panic.txt", "dirB/dirC/c.txt"];
+        let root_dir = create_directory(&directories, &files)?;
+        for &sorted in &[false, true] {
+            let root_path = PathBuf::from(root_dir.path());
+            let walker = ParallelWalker::new(root_path, PanicMatcher, false, 2, sorted);
+            let errors: Vec<_> = walker.filter_map(|result| result.err()).collect();
+            assert_eq!(errors.len(), 1);
+            match errors[0].downcast_ref::<WalkError>() {
+                Some(WalkError::WorkerPanic(message)) => assert_eq!(message, "matcher panicked"),
+                _ => panic!("unexpected error {:?}", errors[0]),
+            }
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,506 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Diff algorithms and whitespace handling.
+//!
+//! The xdiff core only implements the Myers algorithm. The patience and
+//! histogram algorithms split the texts at lines they match up, and use the
+//! core for the regions between those lines that they can't split further.
+//! Whitespace is ignored by diffing normalized lines, which keeps the line
+//! numbers of the hunks the same as in the original texts.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use crate::{xdiff_hunks, Hunk};
+use xdiff_sys as ffi;
+
+/// Algorithm used to find the differences between two texts.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DiffAlgorithm {
+    /// The default xdiff algorithm, which is fast but may give up on finding
+    /// the smallest diff for large inputs.
+    Myers,
+    /// Myers algorithm that always finds the smallest diff.
+    Minimal,
+    /// Patience diff, which matches up lines that are unique in both texts
+    /// first. Tends to give more readable diffs of reordered code.
+    Patience,
+    /// Histogram diff, an extension of patience diff that also matches up
+    /// lines that are rare but not unique.
+    Histogram,
+}
+
+/// Which whitespace differences are ignored.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum IgnoreWhitespace {
+    /// Whitespace differences are significant.
+    None,
+    /// Ignore changes in the amount of whitespace, and whitespace at the end
+    /// of lines.
+    Change,
+    /// Ignore all whitespace.
+    All,
+}
+
+/// Options that control how hunks are found.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct HunkOpts {
+    pub algorithm: DiffAlgorithm,
+    pub ignore_whitespace: IgnoreWhitespace,
+    /// Drop hunks that only add or remove blank lines.
+    pub ignore_blank_lines: bool,
+    /// Shift hunks to make them line up with the indentation of the text,
+    /// which usually makes them easier to read.
+    pub indent_heuristic: bool,
+}
+
+impl Default for HunkOpts {
+    fn default() -> Self {
+        Self {
+            algorithm: DiffAlgorithm::Myers,
+            ignore_whitespace: IgnoreWhitespace::None,
+            ignore_blank_lines: false,
+            indent_heuristic: true,
+        }
+    }
+}
+
+impl HunkOpts {
+    fn xdiff_flags(&self) -> u64 {
+        let mut flags = 0;
+        if self.indent_heuristic {
+            flags |= ffi::XDF_INDENT_HEURISTIC as u64;
+        }
+        if self.algorithm == DiffAlgorithm::Minimal {
+            flags |= ffi::XDF_NEED_MINIMAL as u64;
+        }
+        flags
+    }
+}
+
+/// Maximum number of occurrences of a line in the old text for the histogram
+/// algorithm to use it to split the texts.
+const HISTOGRAM_MAX_CHAIN: usize = 64;
+
+/// Number of lines the patience and histogram algorithms look at to split
+/// the texts before leaving the rest to the xdiff core.
+const MAX_SPLIT_WORK: usize = 4_000_000;
+
+/// Splits a text into lines, keeping the newlines.
+fn split_lines(text: &[u8]) -> Vec<&[u8]> {
+    let mut lines = Vec::new();
+    let mut start = 0;
+    for (index, c) in text.iter().enumerate() {
+        if *c == b'\n' {
+            lines.push(&text[start..index + 1]);
+            start = index + 1;
+        }
+    }
+    if start < text.len() {
+        lines.push(&text[start..]);
+    }
+    lines
+}
+
+fn is_space(c: u8) -> bool {
+    c == b' ' || c == b'\t' || c == b'\r' || c == 0x0b || c == 0x0c
+}
+
+fn is_blank(line: &[u8]) -> bool {
+    line.iter().all(|c| is_space(*c) || *c == b'\n')
+}
+
+/// Normalizes the whitespace of a line, keeping its newline.
+fn normalize_line(line: &[u8], ignore_whitespace: IgnoreWhitespace) -> Cow<'_, [u8]> {
+    match ignore_whitespace {
+        IgnoreWhitespace::None => Cow::Borrowed(line),
+        IgnoreWhitespace::All => {
+            Cow::Owned(line.iter().cloned().filter(|c| !is_space(*c)).collect())
+        }
+        IgnoreWhitespace::Change => {
+            let (content, newline) = match line.last() {
+                Some(b'\n') => line.split_at(line.len() - 1),
+                _ => (line, &b""[..]),
+            };
+            let mut normalized = Vec::with_capacity(line.len());
+            let mut in_space = false;
+            for c in content {
+                if is_space(*c) {
+                    in_space = true;
+                } else {
+                    // Runs of whitespace become a single space, and trailing
+                    // whitespace is dropped.
+                    if in_space {
+                        normalized.push(b' ');
+                    }
+                    in_space = false;
+                    normalized.push(*c);
+                }
+            }
+            normalized.extend_from_slice(newline);
+            Cow::Owned(normalized)
+        }
+    }
+}
+
+/// Computes the hunks of differences between two texts with `opts`.
+pub(crate) fn diff_hunks_with_opts(old_text: &[u8], new_text: &[u8], opts: &HunkOpts) -> Vec<Hunk> {
+    let flags = opts.xdiff_flags();
+    let simple = opts.ignore_whitespace == IgnoreWhitespace::None
+        && (opts.algorithm == DiffAlgorithm::Myers || opts.algorithm == DiffAlgorithm::Minimal);
+    let mut hunks = if simple {
+        xdiff_hunks(old_text, new_text, flags)
+    } else {
+        let normalize = |text| {
+            split_lines(text)
+                .into_iter()
+                .map(|line| normalize_line(line, opts.ignore_whitespace))
+                .collect::<Vec<_>>()
+        };
+        let old_lines = normalize(old_text);
+        let new_lines = normalize(new_text);
+        match opts.algorithm {
+            DiffAlgorithm::Myers | DiffAlgorithm::Minimal => {
+                xdiff_hunks(&old_lines.concat(), &new_lines.concat(), flags)
+            }
+            DiffAlgorithm::Patience | DiffAlgorithm::Histogram => {
+                let old_lines = old_lines.iter().map(|l| l.as_ref()).collect::<Vec<_>>();
+                let new_lines = new_lines.iter().map(|l| l.as_ref()).collect::<Vec<_>>();
+                let mut hunks = Vec::new();
+                diff_lines(&old_lines, &new_lines, opts.algorithm, flags, &mut hunks);
+                hunks
+            }
+        }
+    };
+    if opts.ignore_blank_lines {
+        let old_lines = split_lines(old_text);
+        let new_lines = split_lines(new_text);
+        hunks.retain(|hunk| {
+            !(old_lines[hunk.remove.clone()].iter().all(|l| is_blank(l))
+                && new_lines[hunk.add.clone()].iter().all(|l| is_blank(l)))
+        });
+    }
+    hunks
+}
+
+/// Diffs two lists of lines and appends the hunks to `hunks`.
+///
+/// Regions between anchors are kept on an explicit stack rather than
+/// recursed into, since there can be as many levels as there are lines.
+/// Finding anchors costs time proportional to the size of the region, so
+/// once `MAX_SPLIT_WORK` lines have been looked at, the remaining regions
+/// are diffed by the xdiff core.
+fn diff_lines(
+    old: &[&[u8]],
+    new: &[&[u8]],
+    algorithm: DiffAlgorithm,
+    flags: u64,
+    hunks: &mut Vec<Hunk>,
+) {
+    // (old_start, old_end, new_start, new_end), the last one is diffed next.
+    let mut regions = vec![(0, old.len(), 0, new.len())];
+    let mut work = 0;
+    while let Some((old_start, old_end, new_start, new_end)) = regions.pop() {
+        let (old_region, new_region) = (&old[old_start..old_end], &new[new_start..new_end]);
+        let prefix = old_region
+            .iter()
+            .zip(new_region)
+            .take_while(|(o, n)| o == n)
+            .count();
+        let (old_region, new_region) = (&old_region[prefix..], &new_region[prefix..]);
+        let (old_start, new_start) = (old_start + prefix, new_start + prefix);
+        let suffix = old_region
+            .iter()
+            .rev()
+            .zip(new_region.iter().rev())
+            .take_while(|(o, n)| o == n)
+            .count();
+        let old_region = &old_region[..old_region.len() - suffix];
+        let new_region = &new_region[..new_region.len() - suffix];
+
+        if old_region.is_empty() && new_region.is_empty() {
+            continue;
+        }
+        if old_region.is_empty() || new_region.is_empty() {
+            hunks.push(Hunk {
+                add: new_start..new_start + new_region.len(),
+                remove: old_start..old_start + old_region.len(),
+            });
+            continue;
+        }
+
+        work += old_region.len() + new_region.len();
+        let anchors = if work > MAX_SPLIT_WORK {
+            Vec::new()
+        } else {
+            match algorithm {
+                DiffAlgorithm::Patience => patience_anchors(old_region, new_region),
+                DiffAlgorithm::Histogram => histogram_anchor(old_region, new_region)
+                    .into_iter()
+                    .collect(),
+                DiffAlgorithm::Myers | DiffAlgorithm::Minimal => Vec::new(),
+            }
+        };
+        if anchors.is_empty() {
+            // Nothing to split the texts at, fall back to the xdiff core.
+            hunks.extend(
+                xdiff_hunks(&old_region.concat(), &new_region.concat(), flags)
+                    .into_iter()
+                    .map(|hunk| Hunk {
+                        add: hunk.add.start + new_start..hunk.add.end + new_start,
+                        remove: hunk.remove.start + old_start..hunk.remove.end + old_start,
+                    }),
+            );
+            continue;
+        }
+
+        // Push the regions around the anchors last to first, so that they
+        // are diffed, and their hunks appended, in order.
+        let (mut old_pos, mut new_pos) = (old_region.len(), new_region.len());
+        for (old_index, new_index, len) in anchors.into_iter().rev() {
+            regions.push((
+                old_start + old_index + len,
+                old_start + old_pos,
+                new_start + new_index + len,
+                new_start + new_pos,
+            ));
+            old_pos = old_index;
+            new_pos = new_index;
+        }
+        regions.push((
+            old_start,
+            old_start + old_pos,
+            new_start,
+            new_start + new_pos,
+        ));
+    }
+}
+
+/// Finds the longest sequence of lines that are unique in both texts and
+/// appear in the same order in both. Returns `(old_index, new_index, 1)`
+/// for each of them.
+fn patience_anchors(old: &[&[u8]], new: &[&[u8]]) -> Vec<(usize, usize, usize)> {
+    // Number of occurrences and position in the old and the new text.
+    let mut lines: HashMap<&[u8], (usize, usize, usize, usize)> = HashMap::new();
+    for (index, line) in old.iter().enumerate() {
+        let entry = lines.entry(line).or_insert((0, 0, 0, 0));
+        entry.0 += 1;
+        entry.1 = index;
+    }
+    for (index, line) in new.iter().enumerate() {
+        if let Some(entry) = lines.get_mut(line) {
+            entry.2 += 1;
+            entry.3 = index;
+        }
+    }
+    let mut unique = lines
+        .values()
+        .filter(|(old_count, _, new_count, _)| *old_count == 1 && *new_count == 1)
+        .map(|(_, old_index, _, new_index)| (*old_index, *new_index))
+        .collect::<Vec<_>>();
+    unique.sort();
+
+    // Longest increasing subsequence of the new positions, by patience sorting.
+    // `piles[k]` is the index in `unique` of the smallest possible end of an
+    // increasing subsequence of length `k + 1`.
+    let mut piles: Vec<usize> = Vec::new();
+    let mut previous = vec![None; unique.len()];
+    for (index, (_, new_index)) in unique.iter().enumerate() {
+        let pile = piles
+            .binary_search_by(|top| unique[*top].1.cmp(new_index))
+            .unwrap_or_else(|pile| pile);
+        if pile > 0 {
+            previous[index] = Some(piles[pile - 1]);
+        }
+        if pile == piles.len() {
+            piles.push(index);
+        } else {
+            piles[pile] = index;
+        }
+    }
+    let mut anchors = Vec::new();
+    let mut current = piles.last().cloned();
+    while let Some(index) = current {
+        let (old_index, new_index) = unique[index];
+        anchors.push((old_index, new_index, 1));
+        current = previous[index];
+    }
+    anchors.reverse();
+    anchors
+}
+
+/// Finds the longest run of common lines that contains a line that is as
+/// rare as possible in the old text. Returns `(old_index, new_index, len)`.
+fn histogram_anchor(old: &[&[u8]], new: &[&[u8]]) -> Option<(usize, usize, usize)> {
+    let mut positions: HashMap<&[u8], Vec<usize>> = HashMap::new();
+    for (index, line) in old.iter().enumerate() {
+        positions.entry(line).or_default().push(index);
+    }
+
+    // (occurrences, old_index, new_index, len)
+    let mut best: Option<(usize, usize, usize, usize)> = None;
+    let mut new_index = 0;
+    while new_index < new.len() {
+        let mut next = new_index + 1;
+        if let Some(old_indexes) = positions.get(new[new_index]) {
+            let count = old_indexes.len();
+            if count <= HISTOGRAM_MAX_CHAIN {
+                for old_index in old_indexes {
+                    // Extend the match in both directions.
+                    let mut start = 0;
+                    while start < *old_index
+                        && start < new_index
+                        && old[old_index - start - 1] == new[new_index - start - 1]
+                    {
+                        start += 1;
+                    }
+                    let mut len = start;
+                    while old_index - start + len < old.len()
+                        && new_index - start + len < new.len()
+                        && old[old_index - start + len] == new[new_index - start + len]
+                    {
+                        len += 1;
+                    }
+                    let better = match best {
+                        None => true,
+                        Some((best_count, _, _, best_len)) => {
+                            count < best_count || (count == best_count && len > best_len)
+                        }
+                    };
+                    if better {
+                        best = Some((count, old_index - start, new_index - start, len));
+                        next = next.max(new_index - start + len);
+                    }
+                }
+            }
+        }
+        new_index = next;
+    }
+    best.map(|(_, old_index, new_index, len)| (old_index, new_index, len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hunks(old: &str, new: &str, opts: HunkOpts) -> Vec<(usize, usize, usize, usize)> {
+        diff_hunks_with_opts(old.as_bytes(), new.as_bytes(), &opts)
+            .into_iter()
+            .map(|h| (h.remove.start, h.remove.end, h.add.start, h.add.end))
+            .collect()
+    }
+
+    #[test]
+    fn test_algorithms_agree_on_simple_diffs() {
+        for algorithm in &[
+            DiffAlgorithm::Myers,
+            DiffAlgorithm::Minimal,
+            DiffAlgorithm::Patience,
+            DiffAlgorithm::Histogram,
+        ] {
+            let opts = HunkOpts {
+                algorithm: *algorithm,
+                ..Default::default()
+            };
+            assert_eq!(
+                hunks("a\nb\nc\nd\n", "a\nc\nd\ne\n", opts.clone()),
+                vec![(1, 2, 1, 1), (4, 4, 3, 4)],
+                "{:?}",
+                algorithm
+            );
+            assert_eq!(hunks("a\nb\n", "a\nb\n", opts.clone()), vec![]);
+            assert_eq!(hunks("", "a\n", opts.clone()), vec![(0, 0, 0, 1)]);
+            assert_eq!(hunks("a\nb", "a\nb\n", opts), vec![(1, 2, 1, 2)]);
+        }
+    }
+
+    #[test]
+    fn test_patience() {
+        // Patience diff matches up the unique function headers, instead of
+        // the braces that are common to both functions.
+        let old = "fn a() {\n    1\n}\n\nfn b() {\n    2\n}\n";
+        let new = "fn b() {\n    2\n}\n\nfn a() {\n    1\n}\n";
+        let opts = HunkOpts {
+            algorithm: DiffAlgorithm::Patience,
+            ..Default::default()
+        };
+        let result = diff_hunks_with_opts(old.as_bytes(), new.as_bytes(), &opts);
+        let removed: usize = result.iter().map(|h| h.remove.len()).sum();
+        let added: usize = result.iter().map(|h| h.add.len()).sum();
+        assert_eq!((removed, added), (4, 4));
+    }
+
+    #[test]
+    fn test_histogram() {
+        let opts = HunkOpts {
+            algorithm: DiffAlgorithm::Histogram,
+            ..Default::default()
+        };
+        assert_eq!(
+            hunks("x\na\nb\nx\nc\n", "a\nb\ny\nc\nz\n", opts),
+            vec![(0, 1, 0, 0), (3, 4, 2, 3), (5, 5, 4, 5)]
+        );
+    }
+
+    #[test]
+    fn test_large_diff_on_small_stack() {
+        let old: String = (0..50_000).map(|i| format!("{}\n", i)).collect();
+        let new: String = (0..50_000).map(|i| format!("{}\nnew\n", i)).collect();
+        for algorithm in &[DiffAlgorithm::Patience, DiffAlgorithm::Histogram] {
+            let opts = HunkOpts {
+                algorithm: *algorithm,
+                ..Default::default()
+            };
+            let (old, new) = (old.clone(), new.clone());
+            let result = std::thread::Builder::new()
+                .stack_size(2 << 20)
+                .spawn(move || hunks(&old, &new, opts))
+                .unwrap()
+                .join()
+                .unwrap();
+            let removed: usize = result.iter().map(|h| h.1 - h.0).sum();
+            let added: usize = result.iter().map(|h| h.3 - h.2).sum();
+            assert_eq!((removed, added), (0, 50_000), "{:?}", algorithm);
+        }
+    }
+
+    #[test]
+    fn test_ignore_whitespace() {
+        let old = "a b\n  c\nd \n";
+        let new = "a  b\n\tc\nd\n";
+        assert_eq!(hunks(old, new, HunkOpts::default()).len(), 1);
+        let opts = HunkOpts {
+            ignore_whitespace: IgnoreWhitespace::Change,
+            ..Default::default()
+        };
+        assert_eq!(hunks(old, new, opts), vec![]);
+
+        let opts = HunkOpts {
+            ignore_whitespace: IgnoreWhitespace::Change,
+            ..Default::default()
+        };
+        assert_eq!(hunks("ab\n", "a b\n", opts), vec![(0, 1, 0, 1)]);
+        let opts = HunkOpts {
+            ignore_whitespace: IgnoreWhitespace::All,
+            ..Default::default()
+        };
+        assert_eq!(hunks("ab\n", "a b\n", opts), vec![]);
+    }
+
+    #[test]
+    fn test_ignore_blank_lines() {
+        let opts = HunkOpts {
+            ignore_blank_lines: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            hunks("a\nb\nc\nd\n", "a\n\nb\nc\nD\n", opts),
+            vec![(3, 4, 4, 5)]
+        );
+    }
+}
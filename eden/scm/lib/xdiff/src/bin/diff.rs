@@ -12,7 +12,9 @@ use std::fs;
 use std::os::unix::fs::PermissionsExt;
 use std::path::{Path, PathBuf};
 use structopt::StructOpt;
-use xdiff::{diff_unified, CopyInfo, DiffFile, DiffOpts, FileType};
+use xdiff::{
+    diff_unified, CopyInfo, DiffAlgorithm, DiffFile, DiffOpts, FileType, HunkOpts, IgnoreWhitespace,
+};
 
 const EXEC_BIT: u32 = 0o0000100;
 
@@ -43,6 +45,38 @@ struct Opt {
     /// Number of lines of unified context (default: 3)
     #[structopt(short = "U", long, default_value = "3")]
     unified: usize,
+
+    /// Spend extra time to make sure the smallest possible diff is produced
+    #[structopt(long)]
+    minimal: bool,
+
+    /// Use the patience diff algorithm
+    #[structopt(long)]
+    patience: bool,
+
+    /// Use the histogram diff algorithm
+    #[structopt(long)]
+    histogram: bool,
+
+    /// Ignore all whitespace
+    #[structopt(short = "w", long)]
+    ignore_all_space: bool,
+
+    /// Ignore changes in the amount of whitespace
+    #[structopt(short = "b", long)]
+    ignore_space_change: bool,
+
+    /// Ignore changes whose lines are all blank
+    #[structopt(short = "B", long)]
+    ignore_blank_lines: bool,
+
+    /// Disable the indent heuristic
+    #[structopt(long)]
+    no_indent_heuristic: bool,
+
+    /// Show the changed words within the lines
+    #[structopt(long)]
+    word_diff: bool,
 }
 
 fn main() -> Result<(), std::io::Error> {
@@ -82,6 +116,21 @@ fn main() -> Result<(), std::io::Error> {
         (true, true) => panic!("file can't be marked as both copy and move"),
     };
 
+    let algorithm = match (opt.minimal, opt.patience, opt.histogram) {
+        (false, false, false) => DiffAlgorithm::Myers,
+        (true, false, false) => DiffAlgorithm::Minimal,
+        (false, true, false) => DiffAlgorithm::Patience,
+        (false, false, true) => DiffAlgorithm::Histogram,
+        _ => panic!("only one diff algorithm can be chosen"),
+    };
+    let ignore_whitespace = if opt.ignore_all_space {
+        IgnoreWhitespace::All
+    } else if opt.ignore_space_change {
+        IgnoreWhitespace::Change
+    } else {
+        IgnoreWhitespace::None
+    };
+
     let a_path_str = opt.file_a.to_string_lossy();
     let a = if opt.file_a.is_file() {
         let (mode, contents) = file_mode_and_contents(&opt, &opt.file_a)?;
@@ -103,6 +152,13 @@ fn main() -> Result<(), std::io::Error> {
         DiffOpts {
             context: opt.unified,
             copy_info,
+            hunk_opts: HunkOpts {
+                algorithm,
+                ignore_whitespace,
+                ignore_blank_lines: opt.ignore_blank_lines,
+                indent_heuristic: !opt.no_indent_heuristic,
+            },
+            word_diff: opt.word_diff,
         },
     );
 
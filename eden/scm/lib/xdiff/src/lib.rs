@@ -13,6 +13,16 @@ use std::ops::Range;
 use std::os::raw::{c_char, c_int, c_void};
 use xdiff_sys as ffi;
 
+mod algorithm;
+mod patch;
+mod words;
+
+pub use algorithm::{DiffAlgorithm, HunkOpts, IgnoreWhitespace};
+pub use patch::{
+    apply_hunks, parse_patch, ApplyOpts, ApplyResult, FilePatch, HunkResult, Patch, PatchError,
+    PatchHunk, PatchLine, PatchedFile,
+};
+
 /// An individual difference between two texts. Consists of two
 /// line ranges that specify which parts of the texts differ.
 ///
@@ -64,6 +74,23 @@ pub fn diff_hunks<T>(old_text: T, new_text: T) -> Vec<Hunk>
 where
     T: AsRef<[u8]>,
 {
+    diff_hunks_with_opts(old_text, new_text, &HunkOpts::default())
+}
+
+/// Computes the hunks of differences between two texts, using the algorithm
+/// and whitespace handling from `opts`.
+///
+/// The line numbers of the hunks always refer to the original texts, even
+/// when whitespace is ignored.
+pub fn diff_hunks_with_opts<T>(old_text: T, new_text: T, opts: &HunkOpts) -> Vec<Hunk>
+where
+    T: AsRef<[u8]>,
+{
+    algorithm::diff_hunks_with_opts(old_text.as_ref(), new_text.as_ref(), opts)
+}
+
+/// Runs the xdiff core on two texts with the given xdiff flags.
+pub(crate) fn xdiff_hunks(old_text: &[u8], new_text: &[u8], flags: u64) -> Vec<Hunk> {
     extern "C" fn hunk_consumer(a1: i64, a2: i64, b1: i64, b2: i64, _priv: *mut c_void) -> c_int {
         let mut _priv = unsafe { (_priv as *mut Vec<Hunk>).as_mut() };
         let a1 = a1 as usize;
@@ -79,19 +106,15 @@ where
         return 0;
     }
 
-    let old_text = old_text.as_ref();
     let mut old_mmfile = ffi::mmfile_t {
         ptr: old_text.as_ptr() as *mut c_char,
         size: old_text.len() as i64,
     };
-    let new_text = new_text.as_ref();
     let mut new_mmfile = ffi::mmfile_t {
         ptr: new_text.as_ptr() as *mut c_char,
         size: new_text.len() as i64,
     };
-    let xpp = ffi::xpparam_t {
-        flags: ffi::XDF_INDENT_HEURISTIC as u64,
-    };
+    let xpp = ffi::xpparam_t { flags };
     let xecfg = ffi::xdemitconf_t {
         flags: 0,
         hunk_func: Some(hunk_consumer),
@@ -111,6 +134,21 @@ where
 pub struct HeaderlessDiffOpts {
     /// Number of context lines
     pub context: usize,
+    /// How the hunks are found
+    pub hunk_opts: HunkOpts,
+    /// Mark the changed words within the lines instead of emitting the
+    /// removed and added lines
+    pub word_diff: bool,
+}
+
+impl Default for HeaderlessDiffOpts {
+    fn default() -> Self {
+        Self {
+            context: 3,
+            hunk_opts: HunkOpts::default(),
+            word_diff: false,
+        }
+    }
 }
 
 #[derive(Clone, PartialEq, Eq, Debug)]
@@ -128,6 +166,22 @@ pub struct DiffOpts {
     /// Number of context lines
     pub context: usize,
     pub copy_info: CopyInfo,
+    /// How the hunks are found
+    pub hunk_opts: HunkOpts,
+    /// Mark the changed words within the lines instead of emitting the
+    /// removed and added lines
+    pub word_diff: bool,
+}
+
+impl Default for DiffOpts {
+    fn default() -> Self {
+        Self {
+            context: 3,
+            copy_info: CopyInfo::None,
+            hunk_opts: HunkOpts::default(),
+            word_diff: false,
+        }
+    }
 }
 
 const MISSING_NEWLINE_MARKER: &[u8] = b"\\ No newline at end of file\n";
@@ -217,12 +271,67 @@ where
             }
             previous_hunk = Some(hunk);
         }
+        self.emit_trailing_context(payload, &cluster_bounds, previous_hunk, b" ");
+    }
+
+    /// Emits a hunk cluster with the changed words marked within the lines,
+    /// like `git diff --word-diff`: `[-removed-]` and `{+added+}`.
+    pub fn emit_word_diff_cluster(
+        &mut self,
+        payload: &DiffPayload,
+        cluster_bounds: Hunk,
+        included_hunks: &[Hunk],
+    ) {
+        self.emit(
+            format!(
+                "@@ -{},{} +{},{} @@\n",
+                &cluster_bounds.remove.start + 1,
+                &cluster_bounds.remove.len(),
+                &cluster_bounds.add.start + 1,
+                &cluster_bounds.add.len()
+            )
+            .as_bytes(),
+        );
+
+        let mut previous_hunk: Option<&Hunk> = None;
+
+        for hunk in included_hunks {
+            let context_start = previous_hunk
+                .map(|h| h.remove.end)
+                .unwrap_or(cluster_bounds.remove.start);
+            payload.old_lines[context_start..hunk.remove.start]
+                .iter()
+                .for_each(|line| self.emit_line(b"", line));
+
+            let join = |lines: &[&[u8]]| {
+                lines.iter().fold(Vec::new(), |mut text, line| {
+                    text.extend_from_slice(line);
+                    text.push(b'\n');
+                    text
+                })
+            };
+            let old_text = join(&payload.old_lines[hunk.remove.clone()]);
+            let new_text = join(&payload.new_lines[hunk.add.clone()]);
+            self.emit(&words::diff_words(&old_text, &new_text));
+            previous_hunk = Some(hunk);
+        }
+        // Context lines aren't prefixed in word diffs.
+        self.emit_trailing_context(payload, &cluster_bounds, previous_hunk, b"");
+    }
+
+    fn emit_trailing_context(
+        &mut self,
+        payload: &DiffPayload,
+        cluster_bounds: &Hunk,
+        previous_hunk: Option<&Hunk>,
+        prefix: &[u8],
+    ) {
         // After the last chunk emit the remaining context.
         if let Some(previous_hunk) = previous_hunk {
             payload.old_lines[previous_hunk.remove.end..cluster_bounds.remove.end]
                 .iter()
                 .for_each(|line| {
-                    self.emit_line(b" ", *line);
+                    self.emit_line(prefix, *line);
                 });
             // If the last line is the same in both files and it's missing newline we print the marker
             if cluster_bounds.remove.end == payload.old_lines.len()
@@ -292,7 +401,7 @@ where
     if new_has_trailing_newline {
         new_lines.pop();
     }
-    let hunks = diff_hunks(old_text, new_text);
+    let hunks = diff_hunks_with_opts(old_text, new_text, &opts.hunk_opts);
 
     if hunks.is_empty() {
         return seed;
@@ -318,6 +427,13 @@ where
 
     // Helper for emitting a single hunk cluster.
     // (a group of chunks with overlapping contexts and shared header)
+    let emit_cluster = |state: &mut DiffState<S, F>, cluster_bounds, included_hunks: &[Hunk]| {
+        if opts.word_diff {
+            state.emit_word_diff_cluster(&payload, cluster_bounds, included_hunks)
+        } else {
+            state.emit_hunk_cluster(&payload, cluster_bounds, included_hunks)
+        }
+    };
 
     let mut cluster: Option<(Hunk, Range<usize>)> = None;
     for (hunk_no, hunk) in hunks.iter().enumerate() {
@@ -346,7 +462,7 @@ where
             _ => {
                 // No overlap with previous hunk. Emit current cluster and start a new one.
                 if let Some((cluster_bounds, included_hunks_range)) = cluster {
-                    state.emit_hunk_cluster(&payload, cluster_bounds, &hunks[included_hunks_range]);
+                    emit_cluster(&mut state, cluster_bounds, &hunks[included_hunks_range]);
                 }
                 Some((
                     Hunk {
@@ -362,7 +478,7 @@ where
     }
     // Emit the last cluster.
     if let Some((cluster_bounds, included_hunks_range)) = cluster {
-        state.emit_hunk_cluster(&payload, cluster_bounds, &hunks[included_hunks_range]);
+        emit_cluster(&mut state, cluster_bounds, &hunks[included_hunks_range]);
     }
 
     state.collect()
@@ -666,6 +782,8 @@ where
             // Typical case, we need to call actual diff function to get the diff.
             let opts = HeaderlessDiffOpts {
                 context: diff_opts.context,
+                hunk_opts: diff_opts.hunk_opts,
+                word_diff: diff_opts.word_diff,
             };
             gen_diff_unified_headerless(old_file, new_file, opts, seed, reduce)
         }
@@ -750,7 +868,14 @@ d
 e
 z"#;
         assert_eq!(
-            diff_unified_headerless(&a, &b, HeaderlessDiffOpts { context: 10 }),
+            diff_unified_headerless(
+                &a,
+                &b,
+                HeaderlessDiffOpts {
+                    context: 10,
+                    ..Default::default()
+                }
+            ),
             r"@@ -1,4 +1,5 @@
  a
 -b
@@ -764,6 +889,24 @@ z"#;
         );
     }
 
+    #[test]
+    fn test_diff_unified_headerless_word_diff() {
+        let a = "a\nb c\nd\ne\n";
+        let b = "a\nb x\nd\ne\n";
+        assert_eq!(
+            String::from_utf8_lossy(&diff_unified_headerless(
+                &a,
+                &b,
+                HeaderlessDiffOpts {
+                    context: 1,
+                    word_diff: true,
+                    ..Default::default()
+                }
+            )),
+            "@@ -1,3 +1,3 @@\na\nb [-c-]{+x+}\nd\n"
+        );
+    }
+
     #[test]
     fn test_diff_unified() {
         let a = r#"a
@@ -794,6 +937,7 @@ z"#;
                 DiffOpts {
                     context: 10,
                     copy_info: CopyInfo::None,
+                    ..Default::default()
                 }
             )),
             r"diff --git a/x b/y
@@ -833,6 +977,7 @@ d
                 DiffOpts {
                     context: 10,
                     copy_info: CopyInfo::None,
+                    ..Default::default()
                 }
             )),
             r"diff --git a/x b/x
@@ -870,6 +1015,7 @@ d
                 DiffOpts {
                     context: 10,
                     copy_info: CopyInfo::None,
+                    ..Default::default()
                 }
             )),
             r"diff --git a/x b/x
@@ -909,6 +1055,7 @@ d
                 DiffOpts {
                     context: 10,
                     copy_info: CopyInfo::None,
+                    ..Default::default()
                 }
             )),
             r"diff --git a/x b/x
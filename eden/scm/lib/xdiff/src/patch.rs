@@ -0,0 +1,1014 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Parsing and applying of unified diffs.
+//!
+//! Understands the git extended diff format (the one produced by `diff_unified`
+//! and by `hg diff --git`), including renames, copies, mode changes, new and
+//! deleted files and `\ No newline at end of file` markers, as well as plain
+//! unified diffs. Patches are applied to in-memory file contents. Hunks that
+//! don't apply where the patch says they should are looked for nearby, and
+//! may be applied with fuzz; hunks that can't be applied are returned as
+//! rejects.
+
+use std::cmp::min;
+use std::error::Error;
+use std::fmt;
+
+use crate::{CopyInfo, FileType};
+
+/// A patch, consisting of changes to one or more files.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct Patch {
+    pub files: Vec<FilePatch>,
+}
+
+/// Changes to a single file.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct FilePatch {
+    /// Path of the file before the change, `None` if the file is added.
+    pub old_path: Option<Vec<u8>>,
+    /// Path of the file after the change, `None` if the file is deleted.
+    pub new_path: Option<Vec<u8>>,
+    /// File type before the change, if the patch specifies it.
+    pub old_file_type: Option<FileType>,
+    /// File type after the change, if the patch specifies it.
+    pub new_file_type: Option<FileType>,
+    /// Whether the new file is a copy or a move of the old one.
+    pub copy_info: CopyInfo,
+    /// Binary changes are recognized, but their content can't be applied.
+    pub is_binary: bool,
+    pub hunks: Vec<PatchHunk>,
+}
+
+/// A line of a hunk.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum PatchLine {
+    /// Line that is in both the old and the new text.
+    Context(Vec<u8>),
+    /// Line that is removed from the old text.
+    Remove(Vec<u8>),
+    /// Line that is added in the new text.
+    Add(Vec<u8>),
+}
+
+/// A single `@@ -a,b +c,d @@` section of a file patch.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct PatchHunk {
+    /// First line of the hunk in the old text (starting from 1, or 0 if the
+    /// old side of the hunk is empty and it's at the start of the text).
+    pub old_start: usize,
+    pub old_len: usize,
+    /// First line of the hunk in the new text, numbered like `old_start`.
+    pub new_start: usize,
+    pub new_len: usize,
+    /// Text following the line ranges in the hunk header.
+    pub section: Vec<u8>,
+    pub lines: Vec<PatchLine>,
+    /// The last line of the old side of the hunk has no newline.
+    pub old_missing_newline: bool,
+    /// The last line of the new side of the hunk has no newline.
+    pub new_missing_newline: bool,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum PatchError {
+    /// The patch is malformed. Contains the line number (starting from 1)
+    /// where parsing failed, and a description of the problem.
+    Parse(usize, String),
+    /// The patch can't be applied to the file.
+    Apply(Vec<u8>, String),
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PatchError::Parse(line, msg) => write!(f, "malformed patch at line {}: {}", line, msg),
+            PatchError::Apply(path, msg) => write!(
+                f,
+                "cannot apply patch to {}: {}",
+                String::from_utf8_lossy(path),
+                msg
+            ),
+        }
+    }
+}
+
+impl Error for PatchError {}
+
+/// Options that control how a patch is applied.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ApplyOpts {
+    /// Maximum number of context lines at the start and the end of a hunk
+    /// that may be ignored to apply it.
+    pub fuzz: usize,
+}
+
+impl Default for ApplyOpts {
+    fn default() -> Self {
+        Self { fuzz: 2 }
+    }
+}
+
+/// How a hunk was applied.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum HunkResult {
+    /// The hunk was applied `offset` lines away from where the patch says it
+    /// should be, ignoring `fuzz` lines of its context.
+    Applied { offset: isize, fuzz: usize },
+    /// The hunk couldn't be applied.
+    Rejected,
+}
+
+/// Result of applying hunks to the content of a file.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ApplyResult {
+    /// Content with all the hunks that could be applied.
+    pub content: Vec<u8>,
+    /// How each hunk of the patch was applied, in the order of the patch.
+    pub hunks: Vec<HunkResult>,
+    /// Hunks that couldn't be applied.
+    pub rejects: Vec<PatchHunk>,
+}
+
+/// A file changed by applying a patch.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct PatchedFile {
+    /// Path of the file. For deleted files this is the path of the deleted file.
+    pub path: Vec<u8>,
+    /// Path of the file this one was moved or copied from.
+    pub copy_from: Option<Vec<u8>>,
+    /// New content and type of the file, `None` if the file is deleted.
+    pub content: Option<(Vec<u8>, FileType)>,
+    pub hunks: Vec<HunkResult>,
+    pub rejects: Vec<PatchHunk>,
+}
+
+const DEV_NULL: &[u8] = b"/dev/null";
+const MISSING_NEWLINE_PREFIX: &[u8] = b"\\ ";
+
+fn strip_prefix<'a>(line: &'a [u8], prefix: &[u8]) -> Option<&'a [u8]> {
+    if line.starts_with(prefix) {
+        Some(&line[prefix.len()..])
+    } else {
+        None
+    }
+}
+
+fn parse_mode(mode: &[u8]) -> Option<FileType> {
+    match mode {
+        b"100644" | b"100664" => Some(FileType::Regular),
+        b"100755" => Some(FileType::Executable),
+        b"120000" => Some(FileType::Symlink),
+        _ => None,
+    }
+}
+
+/// Parse the path of a `---` or `+++` line, dropping the `a/` or `b/` prefix
+/// of git diffs and the timestamp of plain unified diffs.
+fn parse_file_path(path: &[u8], git_prefix: &[u8]) -> Option<Vec<u8>> {
+    let path = match path.iter().position(|c| *c == b'\t') {
+        Some(tab) => &path[..tab],
+        None => path,
+    };
+    if path == DEV_NULL {
+        return None;
+    }
+    Some(strip_prefix(path, git_prefix).unwrap_or(path).to_vec())
+}
+
+/// Parse the paths of a `diff --git a/X b/Y` line. Paths can contain spaces,
+/// so if there are several ways to split the line, the one with equal paths
+/// is preferred.
+fn parse_git_paths(paths: &[u8]) -> Option<(Vec<u8>, Vec<u8>)> {
+    let paths = strip_prefix(paths, b"a/")?;
+    let splits = (0..paths.len())
+        .filter(|i| paths[*i..].starts_with(b" b/"))
+        .collect::<Vec<_>>();
+    let split = splits
+        .iter()
+        .find(|i| paths[..**i] == paths[**i + 3..])
+        .or_else(|| splits.first())?;
+    Some((paths[..*split].to_vec(), paths[*split + 3..].to_vec()))
+}
+
+/// Parse a `start,len` range of a hunk header. The length defaults to 1.
+fn parse_range(range: &[u8]) -> Option<(usize, usize)> {
+    let range = std::str::from_utf8(range).ok()?;
+    let mut parts = range.splitn(2, ',');
+    let start = parts.next()?.parse().ok()?;
+    let len = match parts.next() {
+        Some(len) => len.parse().ok()?,
+        None => 1,
+    };
+    Some((start, len))
+}
+
+/// Parse a `@@ -a,b +c,d @@ section` hunk header.
+fn parse_hunk_header(line: &[u8]) -> Option<PatchHunk> {
+    let rest = strip_prefix(line, b"@@ -")?;
+    let end = (0..rest.len()).find(|i| rest[*i..].starts_with(b" @@"))?;
+    let mut ranges = rest[..end].split(|c| *c == b' ');
+    let (old_start, old_len) = parse_range(ranges.next()?)?;
+    let (new_start, new_len) = parse_range(strip_prefix(ranges.next()?, b"+")?)?;
+    if ranges.next().is_some() {
+        return None;
+    }
+    let section = &rest[end + 3..];
+    Some(PatchHunk {
+        old_start,
+        old_len,
+        new_start,
+        new_len,
+        section: strip_prefix(section, b" ").unwrap_or(section).to_vec(),
+        lines: Vec::new(),
+        old_missing_newline: false,
+        new_missing_newline: false,
+    })
+}
+
+struct Parser<'a> {
+    lines: Vec<&'a [u8]>,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&'a [u8]> {
+        self.lines.get(self.pos).cloned()
+    }
+
+    fn error(&self, msg: impl Into<String>) -> PatchError {
+        PatchError::Parse(self.pos + 1, msg.into())
+    }
+
+    fn new_file_patch(old_path: Option<Vec<u8>>, new_path: Option<Vec<u8>>) -> FilePatch {
+        FilePatch {
+            old_path,
+            new_path,
+            old_file_type: None,
+            new_file_type: None,
+            copy_info: CopyInfo::None,
+            is_binary: false,
+            hunks: Vec::new(),
+        }
+    }
+
+    /// Parse the extended headers that follow a `diff --git` line.
+    fn parse_git_headers(&mut self, file: &mut FilePatch) -> Result<(), PatchError> {
+        while let Some(line) = self.peek() {
+            if let Some(mode) = strip_prefix(line, b"new file mode ") {
+                file.old_path = None;
+                file.new_file_type = parse_mode(mode);
+            } else if let Some(mode) = strip_prefix(line, b"deleted file mode ") {
+                file.new_path = None;
+                file.old_file_type = parse_mode(mode);
+            } else if let Some(mode) = strip_prefix(line, b"old mode ") {
+                file.old_file_type = parse_mode(mode);
+            } else if let Some(mode) = strip_prefix(line, b"new mode ") {
+                file.new_file_type = parse_mode(mode);
+            } else if let Some(path) = strip_prefix(line, b"rename from ") {
+                file.old_path = Some(path.to_vec());
+                file.copy_info = CopyInfo::Move;
+            } else if let Some(path) = strip_prefix(line, b"rename to ") {
+                file.new_path = Some(path.to_vec());
+                file.copy_info = CopyInfo::Move;
+            } else if let Some(path) = strip_prefix(line, b"copy from ") {
+                file.old_path = Some(path.to_vec());
+                file.copy_info = CopyInfo::Copy;
+            } else if let Some(path) = strip_prefix(line, b"copy to ") {
+                file.new_path = Some(path.to_vec());
+                file.copy_info = CopyInfo::Copy;
+            } else if line.starts_with(b"similarity index ")
+                || line.starts_with(b"dissimilarity index ")
+                || line.starts_with(b"index ")
+            {
+                // Nothing to do.
+            } else if line.starts_with(b"Binary file") {
+                file.is_binary = true;
+            } else if line == b"GIT binary patch" {
+                file.is_binary = true;
+                self.pos += 1;
+                // Skip the encoded data, which ends at an empty line after
+                // the literal or delta for each side of the change.
+                let mut blocks = 0;
+                while let Some(line) = self.peek() {
+                    if line.is_empty() {
+                        blocks += 1;
+                        if blocks == 2 {
+                            break;
+                        }
+                    }
+                    self.pos += 1;
+                }
+            } else if line.starts_with(b"--- ") {
+                return self.parse_file_headers(file, true);
+            } else {
+                return Ok(());
+            }
+            self.pos += 1;
+        }
+        Ok(())
+    }
+
+    /// Parse `---` and `+++` lines, and the hunks that follow them.
+    fn parse_file_headers(&mut self, file: &mut FilePatch, git: bool) -> Result<(), PatchError> {
+        let old = self.peek().and_then(|line| strip_prefix(line, b"--- "));
+        let old = old.ok_or_else(|| self.error("expected '---' line"))?;
+        self.pos += 1;
+        let new = self.peek().and_then(|line| strip_prefix(line, b"+++ "));
+        let new = new.ok_or_else(|| self.error("expected '+++' line"))?;
+        self.pos += 1;
+        let (old_prefix, new_prefix): (&[u8], &[u8]) =
+            if git { (b"a/", b"b/") } else { (b"", b"") };
+        let old_path = parse_file_path(old, old_prefix);
+        let new_path = parse_file_path(new, new_prefix);
+        if git {
+            // The extended headers take precedence, but `---` and `+++` are
+            // authoritative for added and deleted files.
+            if old_path.is_none() {
+                file.old_path = None;
+            }
+            if new_path.is_none() {
+                file.new_path = None;
+            }
+        } else {
+            file.old_path = old_path;
+            file.new_path = new_path;
+        }
+        self.parse_hunks(file)
+    }
+
+    fn parse_hunks(&mut self, file: &mut FilePatch) -> Result<(), PatchError> {
+        while let Some(line) = self.peek() {
+            let mut hunk = match parse_hunk_header(line) {
+                Some(hunk) => hunk,
+                None if line.starts_with(b"@@") => return Err(self.error("invalid hunk header")),
+                None => break,
+            };
+            self.pos += 1;
+            let (mut old_left, mut new_left) = (hunk.old_len, hunk.new_len);
+            while old_left > 0 || new_left > 0 {
+                let line = self
+                    .peek()
+                    .ok_or_else(|| self.error("hunk is shorter than its header says"))?;
+                let patch_line = match line.first() {
+                    // Some tools strip the trailing whitespace of empty context lines.
+                    None => PatchLine::Context(Vec::new()),
+                    Some(b' ') => PatchLine::Context(line[1..].to_vec()),
+                    Some(b'-') => PatchLine::Remove(line[1..].to_vec()),
+                    Some(b'+') => PatchLine::Add(line[1..].to_vec()),
+                    Some(b'\\') => {
+                        self.parse_missing_newline(&mut hunk);
+                        continue;
+                    }
+                    Some(_) => return Err(self.error("unexpected line in hunk")),
+                };
+                match patch_line {
+                    PatchLine::Context(_) if old_left > 0 && new_left > 0 => {
+                        old_left -= 1;
+                        new_left -= 1;
+                    }
+                    PatchLine::Remove(_) if old_left > 0 => old_left -= 1,
+                    PatchLine::Add(_) if new_left > 0 => new_left -= 1,
+                    _ => return Err(self.error("hunk is longer than its header says")),
+                }
+                hunk.lines.push(patch_line);
+                self.pos += 1;
+            }
+            if matches!(self.peek(), Some(line) if line.starts_with(MISSING_NEWLINE_PREFIX)) {
+                self.parse_missing_newline(&mut hunk);
+            }
+            file.hunks.push(hunk);
+        }
+        Ok(())
+    }
+
+    /// A `\ No newline at end of file` marker applies to the line before it.
+    fn parse_missing_newline(&mut self, hunk: &mut PatchHunk) {
+        match hunk.lines.last() {
+            Some(PatchLine::Context(_)) => {
+                hunk.old_missing_newline = true;
+                hunk.new_missing_newline = true;
+            }
+            Some(PatchLine::Remove(_)) => hunk.old_missing_newline = true,
+            Some(PatchLine::Add(_)) => hunk.new_missing_newline = true,
+            None => {}
+        }
+        self.pos += 1;
+    }
+}
+
+/// Parse a patch in the git extended or plain unified diff format. Text
+/// that is not part of a diff, like a commit message before it, is ignored.
+pub fn parse_patch(patch: &[u8]) -> Result<Patch, PatchError> {
+    let mut lines: Vec<&[u8]> = patch.split(|c| *c == b'\n').collect();
+    if patch.is_empty() || patch.ends_with(b"\n") {
+        lines.pop();
+    }
+    let mut parser = Parser { lines, pos: 0 };
+    let mut files = Vec::new();
+    while let Some(line) = parser.peek() {
+        if let Some(paths) = strip_prefix(line, b"diff --git ") {
+            let (old_path, new_path) =
+                parse_git_paths(paths).ok_or_else(|| parser.error("invalid 'diff --git' line"))?;
+            parser.pos += 1;
+            let mut file = Parser::new_file_patch(Some(old_path), Some(new_path));
+            parser.parse_git_headers(&mut file)?;
+            files.push(file);
+        } else if line.starts_with(b"--- ")
+            && matches!(parser.lines.get(parser.pos + 1), Some(next) if next.starts_with(b"+++ "))
+        {
+            let mut file = Parser::new_file_patch(None, None);
+            parser.parse_file_headers(&mut file, false)?;
+            files.push(file);
+        } else {
+            parser.pos += 1;
+        }
+    }
+    Ok(Patch { files })
+}
+
+impl PatchHunk {
+    fn old_lines(&self) -> Vec<&[u8]> {
+        self.lines
+            .iter()
+            .filter_map(|line| match line {
+                PatchLine::Context(line) | PatchLine::Remove(line) => Some(line.as_slice()),
+                PatchLine::Add(_) => None,
+            })
+            .collect()
+    }
+
+    fn new_lines(&self) -> Vec<&[u8]> {
+        self.lines
+            .iter()
+            .filter_map(|line| match line {
+                PatchLine::Context(line) | PatchLine::Add(line) => Some(line.as_slice()),
+                PatchLine::Remove(_) => None,
+            })
+            .collect()
+    }
+
+    /// Number of context lines at the start and at the end of the hunk.
+    fn context_len(&self) -> (usize, usize) {
+        let is_context = |line: &&PatchLine| matches!(line, PatchLine::Context(_));
+        let leading = self.lines.iter().take_while(is_context).count();
+        let trailing = self.lines.iter().rev().take_while(is_context).count();
+        if leading == self.lines.len() {
+            (leading, 0)
+        } else {
+            (leading, trailing)
+        }
+    }
+
+    /// Formats the hunk the way it appears in a patch, e.g. to write rejects
+    /// to a file.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = format!(
+            "@@ -{},{} +{},{} @@",
+            self.old_start, self.old_len, self.new_start, self.new_len
+        )
+        .into_bytes();
+        if !self.section.is_empty() {
+            out.push(b' ');
+            out.extend_from_slice(&self.section);
+        }
+        out.push(b'\n');
+        let old_end = self
+            .lines
+            .iter()
+            .rposition(|line| !matches!(line, PatchLine::Add(_)));
+        let new_end = self
+            .lines
+            .iter()
+            .rposition(|line| !matches!(line, PatchLine::Remove(_)));
+        for (index, line) in self.lines.iter().enumerate() {
+            let (prefix, text) = match line {
+                PatchLine::Context(text) => (b' ', text),
+                PatchLine::Remove(text) => (b'-', text),
+                PatchLine::Add(text) => (b'+', text),
+            };
+            out.push(prefix);
+            out.extend_from_slice(text);
+            out.push(b'\n');
+            if (self.old_missing_newline && Some(index) == old_end)
+                || (self.new_missing_newline && Some(index) == new_end)
+            {
+                out.extend_from_slice(crate::MISSING_NEWLINE_MARKER);
+            }
+        }
+        out
+    }
+}
+
+/// Content of a file as lines without their newlines.
+struct Lines<'a> {
+    lines: Vec<&'a [u8]>,
+    has_trailing_newline: bool,
+}
+
+impl<'a> Lines<'a> {
+    fn new(content: &'a [u8]) -> Self {
+        let mut lines: Vec<&[u8]> = content.split(|c| *c == b'\n').collect();
+        let has_trailing_newline = content.is_empty() || content.ends_with(b"\n");
+        if has_trailing_newline {
+            lines.pop();
+        }
+        Self {
+            lines,
+            has_trailing_newline,
+        }
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        let mut out = Vec::new();
+        for (index, line) in self.lines.iter().enumerate() {
+            out.extend_from_slice(line);
+            if index + 1 < self.lines.len() || self.has_trailing_newline {
+                out.push(b'\n');
+            }
+        }
+        out
+    }
+
+    fn matches_at(&self, pos: usize, pattern: &[&[u8]]) -> bool {
+        pos + pattern.len() <= self.lines.len() && &self.lines[pos..pos + pattern.len()] == pattern
+    }
+}
+
+/// Apply the hunks of a file patch to `content`.
+///
+/// Each hunk is first looked for where the patch says it should be, then at
+/// increasing distances from there. If it can't be found, up to `opts.fuzz`
+/// context lines are dropped from its start and end and it's looked for
+/// again.
+pub fn apply_hunks(content: &[u8], hunks: &[PatchHunk], opts: &ApplyOpts) -> ApplyResult {
+    let mut text = Lines::new(content);
+    let mut results = Vec::new();
+    let mut rejects = Vec::new();
+    // Difference between line numbers in the patched and in the original text.
+    let mut line_delta: isize = 0;
+    // Offset of the previous hunk, later hunks are likely to have the same one.
+    let mut last_offset: isize = 0;
+    // Hunks are never applied before the end of the previous hunk.
+    let mut min_pos = 0;
+
+    for hunk in hunks {
+        let old_lines = hunk.old_lines();
+        let new_lines = hunk.new_lines();
+        let (leading, trailing) = hunk.context_len();
+        // An empty old side is inserted after its start line.
+        let start = if hunk.old_len == 0 {
+            hunk.old_start
+        } else {
+            hunk.old_start.saturating_sub(1)
+        };
+        let planned = start as isize + line_delta;
+
+        let mut applied = None;
+        for fuzz in 0..=opts.fuzz {
+            let skip_start = min(fuzz, leading);
+            let skip_end = min(fuzz, trailing);
+            if fuzz > 0 && skip_start + skip_end == 0 {
+                break;
+            }
+            let pattern = &old_lines[skip_start..old_lines.len() - skip_end];
+            let expected = planned + last_offset + skip_start as isize;
+            // A hunk that removes the last line without a newline must be at
+            // the end of the text.
+            let at_end = hunk.old_missing_newline && skip_end == 0;
+            // The dropped leading context must still fit before the pattern.
+            let found = find_nearest(&text, pattern, expected, min_pos.max(skip_start), at_end);
+            if let Some(pos) = found {
+                applied = Some((pos - skip_start, fuzz, skip_start, skip_end));
+                break;
+            }
+        }
+
+        match applied {
+            Some((pos, fuzz, skip_start, skip_end)) => {
+                let replace = pos + skip_start..pos + old_lines.len() - skip_end;
+                let replacement = &new_lines[skip_start..new_lines.len() - skip_end];
+                let at_end = replace.end == text.lines.len();
+                text.lines
+                    .splice(replace.clone(), replacement.iter().cloned());
+                if at_end && skip_end == 0 {
+                    text.has_trailing_newline = !hunk.new_missing_newline;
+                }
+                let offset = pos as isize - planned;
+                results.push(HunkResult::Applied { offset, fuzz });
+                last_offset = offset;
+                line_delta += replacement.len() as isize - replace.len() as isize;
+                min_pos = replace.start + replacement.len();
+            }
+            None => {
+                results.push(HunkResult::Rejected);
+                rejects.push(hunk.clone());
+            }
+        }
+    }
+
+    ApplyResult {
+        content: text.into_bytes(),
+        hunks: results,
+        rejects,
+    }
+}
+
+/// Find the position of `pattern` in `text` that is nearest to `expected`
+/// and not before `min_pos`. If `at_end` is set, the pattern must be at the
+/// end of a text that has no trailing newline.
+fn find_nearest(
+    text: &Lines,
+    pattern: &[&[u8]],
+    expected: isize,
+    min_pos: usize,
+    at_end: bool,
+) -> Option<usize> {
+    let len = text.lines.len();
+    if pattern.len() > len {
+        return None;
+    }
+    let max_pos = len - pattern.len();
+    if min_pos > max_pos {
+        return None;
+    }
+    let expected = expected.max(min_pos as isize).min(max_pos as isize) as usize;
+    let candidate = |pos: usize| {
+        let fits = !at_end || (pos == max_pos && !text.has_trailing_newline);
+        fits && text.matches_at(pos, pattern)
+    };
+    let distance = (expected - min_pos).max(max_pos - expected);
+    for step in 0..=distance {
+        if expected >= min_pos + step && candidate(expected - step) {
+            return Some(expected - step);
+        }
+        if step > 0 && expected + step <= max_pos && candidate(expected + step) {
+            return Some(expected + step);
+        }
+    }
+    None
+}
+
+impl FilePatch {
+    /// Path of the file after the change, or of the deleted file.
+    pub fn path(&self) -> &[u8] {
+        self.new_path
+            .as_ref()
+            .or(self.old_path.as_ref())
+            .map_or(&[], |path| path.as_slice())
+    }
+
+    /// Apply the patch to `old`, the content and type of the old file, or
+    /// `None` if the file doesn't exist.
+    pub fn apply(
+        &self,
+        old: Option<(&[u8], FileType)>,
+        opts: &ApplyOpts,
+    ) -> Result<PatchedFile, PatchError> {
+        let error = |msg: &str| Err(PatchError::Apply(self.path().to_vec(), msg.to_string()));
+        if self.is_binary {
+            return error("binary patches are not supported");
+        }
+        let (content, file_type) = match (&self.old_path, old) {
+            (Some(_), Some(old)) => old,
+            (Some(_), None) => return error("file doesn't exist"),
+            (None, Some(_)) => return error("file already exists"),
+            (None, None) => (&b""[..], FileType::Regular),
+        };
+        let result = apply_hunks(content, &self.hunks, opts);
+        let copy_from = match self.copy_info {
+            CopyInfo::None => None,
+            CopyInfo::Copy | CopyInfo::Move => self.old_path.clone(),
+        };
+        let content = match self.new_path {
+            Some(_) => {
+                let file_type = self.new_file_type.unwrap_or(file_type);
+                Some((result.content, file_type))
+            }
+            None => {
+                if result.rejects.is_empty() && !result.content.is_empty() {
+                    return error("deleted file still has content after applying the patch");
+                }
+                None
+            }
+        };
+        Ok(PatchedFile {
+            path: self.path().to_vec(),
+            copy_from,
+            content,
+            hunks: result.hunks,
+            rejects: result.rejects,
+        })
+    }
+}
+
+impl Patch {
+    /// Apply the patch to files whose content and type are returned by
+    /// `read`, which returns `None` for files that don't exist.
+    ///
+    /// Returns the changed files. Files that are moved away are returned as
+    /// deleted, after the files that are moved from them.
+    pub fn apply<F>(&self, mut read: F, opts: &ApplyOpts) -> Result<Vec<PatchedFile>, PatchError>
+    where
+        F: FnMut(&[u8]) -> Option<(Vec<u8>, FileType)>,
+    {
+        let mut patched = Vec::new();
+        let mut moved = Vec::new();
+        for file in self.files.iter() {
+            let old = match &file.old_path {
+                Some(path) => read(path),
+                None => None,
+            };
+            let old = old
+                .as_ref()
+                .map(|(content, file_type)| (content.as_slice(), *file_type));
+            patched.push(file.apply(old, opts)?);
+            if let (CopyInfo::Move, Some(old_path)) = (&file.copy_info, &file.old_path) {
+                moved.push(old_path.clone());
+            }
+        }
+        for path in moved {
+            if !patched.iter().any(|file| file.path == path) {
+                patched.push(PatchedFile {
+                    path,
+                    copy_from: None,
+                    content: None,
+                    hunks: Vec::new(),
+                    rejects: Vec::new(),
+                });
+            }
+        }
+        Ok(patched)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{diff_unified, DiffFile, DiffOpts};
+
+    fn roundtrip(old: &str, new: &str) {
+        let diff = diff_unified(
+            Some(DiffFile::new("x", old, FileType::Regular)),
+            Some(DiffFile::new("x", new, FileType::Regular)),
+            DiffOpts::default(),
+        );
+        let patch = parse_patch(&diff).unwrap();
+        assert_eq!(patch.files.len(), 1);
+        let result = apply_hunks(old.as_bytes(), &patch.files[0].hunks, &ApplyOpts::default());
+        assert!(result.rejects.is_empty());
+        assert_eq!(String::from_utf8_lossy(&result.content), new);
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        roundtrip("a\nb\nc\nd\n", "a\nc\nd\ne\n");
+        roundtrip(
+            "a\nb\nc\nd\n1\n2\n3\n4\n5\n6\n7\n",
+            "a\nB\nc\nd\n1\n2\n3\n4\n5\n6\n7\n8\n",
+        );
+        roundtrip("a\nb", "a\nb\n");
+        roundtrip("a\nb\n", "a\nc");
+        roundtrip("a\nb", "a\nc");
+        roundtrip("", "a\n");
+        roundtrip("a\n", "");
+    }
+
+    #[test]
+    fn test_parse_git_headers() {
+        let patch = parse_patch(
+            br"commit message
+
+diff --git a/old name b/new name
+old mode 100644
+new mode 100755
+similarity index 90%
+rename from old name
+rename to new name
+--- a/old name
++++ b/new name
+@@ -1,2 +1,2 @@ section
+ a
+-b
++c
+\ No newline at end of file
+diff --git a/x b/y
+copy from x
+copy to y
+diff --git a/added b/added
+new file mode 120000
+--- /dev/null
++++ b/added
+@@ -0,0 +1 @@
++target
+\ No newline at end of file
+diff --git a/deleted b/deleted
+deleted file mode 100644
+Binary file deleted has changed
+",
+        )
+        .unwrap();
+        assert_eq!(patch.files.len(), 4);
+
+        let renamed = &patch.files[0];
+        assert_eq!(renamed.old_path.as_deref(), Some(&b"old name"[..]));
+        assert_eq!(renamed.new_path.as_deref(), Some(&b"new name"[..]));
+        assert_eq!(renamed.old_file_type, Some(FileType::Regular));
+        assert_eq!(renamed.new_file_type, Some(FileType::Executable));
+        assert_eq!(renamed.copy_info, CopyInfo::Move);
+        assert_eq!(
+            renamed.hunks,
+            vec![PatchHunk {
+                old_start: 1,
+                old_len: 2,
+                new_start: 1,
+                new_len: 2,
+                section: b"section".to_vec(),
+                lines: vec![
+                    PatchLine::Context(b"a".to_vec()),
+                    PatchLine::Remove(b"b".to_vec()),
+                    PatchLine::Add(b"c".to_vec()),
+                ],
+                old_missing_newline: false,
+                new_missing_newline: true,
+            }]
+        );
+
+        let copied = &patch.files[1];
+        assert_eq!(copied.old_path.as_deref(), Some(&b"x"[..]));
+        assert_eq!(copied.new_path.as_deref(), Some(&b"y"[..]));
+        assert_eq!(copied.copy_info, CopyInfo::Copy);
+        assert!(copied.hunks.is_empty());
+
+        let added = &patch.files[2];
+        assert_eq!(added.old_path, None);
+        assert_eq!(added.new_file_type, Some(FileType::Symlink));
+        assert_eq!(added.hunks[0].old_start, 0);
+        assert_eq!(added.hunks[0].new_len, 1);
+
+        let deleted = &patch.files[3];
+        assert_eq!(deleted.new_path, None);
+        assert!(deleted.is_binary);
+    }
+
+    #[test]
+    fn test_parse_plain_unified() {
+        let patch = parse_patch(
+            b"--- x\t2020-01-01 00:00:00\n+++ x\t2020-01-02 00:00:00\n@@ -1 +1 @@\n-a\n+b\n",
+        )
+        .unwrap();
+        assert_eq!(patch.files.len(), 1);
+        assert_eq!(patch.files[0].path(), b"x");
+        assert_eq!(patch.files[0].hunks[0].lines.len(), 2);
+
+        assert_eq!(
+            parse_patch(b"--- x\n+++ x\n@@ -1,2 +1 @@\n-a\n+b\n"),
+            Err(PatchError::Parse(
+                6,
+                "hunk is shorter than its header says".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_apply_with_offset_and_fuzz() {
+        let patch = parse_patch(
+            b"--- x\n+++ x\n@@ -2,3 +2,3 @@\n 1\n-2\n+two\n 3\n@@ -8,3 +8,3 @@\n 7\n-8\n+eight\n 9\n",
+        )
+        .unwrap();
+        let hunks = &patch.files[0].hunks;
+
+        // Two lines were added at the start of the file.
+        let content = b"x\ny\n0\n1\n2\n3\n4\n5\n6\n7\n8\n9\n";
+        let result = apply_hunks(content, hunks, &ApplyOpts::default());
+        assert_eq!(
+            result.hunks,
+            vec![
+                HunkResult::Applied { offset: 2, fuzz: 0 },
+                HunkResult::Applied { offset: 2, fuzz: 0 },
+            ]
+        );
+        assert_eq!(
+            result.content,
+            b"x\ny\n0\n1\ntwo\n3\n4\n5\n6\n7\neight\n9\n".to_vec()
+        );
+
+        // The context of the second hunk changed.
+        let content = b"0\n1\n2\n3\n4\n5\n6\nseven\n8\n9\n";
+        let result = apply_hunks(content, hunks, &ApplyOpts::default());
+        assert_eq!(result.hunks[1], HunkResult::Applied { offset: 0, fuzz: 1 });
+        assert_eq!(
+            result.content,
+            b"0\n1\ntwo\n3\n4\n5\n6\nseven\neight\n9\n".to_vec()
+        );
+
+        let result = apply_hunks(content, hunks, &ApplyOpts { fuzz: 0 });
+        assert_eq!(result.hunks[1], HunkResult::Rejected);
+        assert_eq!(result.rejects, vec![hunks[1].clone()]);
+        assert_eq!(
+            result.content,
+            b"0\n1\ntwo\n3\n4\n5\n6\nseven\n8\n9\n".to_vec()
+        );
+        assert_eq!(
+            String::from_utf8_lossy(&result.rejects[0].to_bytes()),
+            "@@ -8,3 +8,3 @@\n 7\n-8\n+eight\n 9\n"
+        );
+    }
+
+    #[test]
+    fn test_apply_fuzz_near_start() {
+        let patch = parse_patch(b"--- x\n+++ x\n@@ -3,3 +3,3 @@\n p\n q\n-a\n+b\n").unwrap();
+        let hunks = &patch.files[0].hunks;
+
+        // Without its leading context the hunk matches the first line, where
+        // that context wouldn't fit.
+        let content = b"a\nz\n";
+        let result = apply_hunks(content, hunks, &ApplyOpts::default());
+        assert_eq!(result.hunks, vec![HunkResult::Rejected]);
+        assert_eq!(result.content, content.to_vec());
+
+        let content = b"x\na\nz\n";
+        let result = apply_hunks(content, hunks, &ApplyOpts::default());
+        assert_eq!(result.hunks, vec![HunkResult::Rejected]);
+
+        let content = b"x\ny\na\nz\n";
+        let result = apply_hunks(content, hunks, &ApplyOpts::default());
+        assert_eq!(
+            result.hunks,
+            vec![HunkResult::Applied {
+                offset: -2,
+                fuzz: 2
+            }]
+        );
+        assert_eq!(result.content, b"x\ny\nb\nz\n".to_vec());
+    }
+
+    #[test]
+    fn test_apply_patch() {
+        let patch = parse_patch(
+            br"diff --git a/a b/b
+rename from a
+rename to b
+--- a/a
++++ b/b
+@@ -1,1 +1,1 @@
+-a
++b
+diff --git a/new b/new
+new file mode 100755
+--- /dev/null
++++ b/new
+@@ -0,0 +1,1 @@
++new
+diff --git a/gone b/gone
+deleted file mode 100644
+--- a/gone
++++ /dev/null
+@@ -1,1 +0,0 @@
+-gone
+",
+        )
+        .unwrap();
+        let read = |path: &[u8]| match path {
+            b"a" => Some((b"a\n".to_vec(), FileType::Regular)),
+            b"gone" => Some((b"gone\n".to_vec(), FileType::Regular)),
+            _ => None,
+        };
+        let files = patch.apply(read, &ApplyOpts::default()).unwrap();
+        let summary = files
+            .iter()
+            .map(|file| {
+                (
+                    file.path.clone(),
+                    file.copy_from.clone(),
+                    file.content.clone(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec![
+                (
+                    b"b".to_vec(),
+                    Some(b"a".to_vec()),
+                    Some((b"b\n".to_vec(), FileType::Regular))
+                ),
+                (
+                    b"new".to_vec(),
+                    None,
+                    Some((b"new\n".to_vec(), FileType::Executable))
+                ),
+                (b"gone".to_vec(), None, None),
+                (b"a".to_vec(), None, None),
+            ]
+        );
+
+        let read = |_: &[u8]| None;
+        assert!(patch.apply(read, &ApplyOpts::default()).is_err());
+    }
+}
@@ -0,0 +1,152 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Word diffs, which mark the words that changed within lines.
+
+use std::collections::HashMap;
+
+use crate::xdiff_hunks;
+
+const REMOVED_START: &[u8] = b"[-";
+const REMOVED_END: &[u8] = b"-]";
+const ADDED_START: &[u8] = b"{+";
+const ADDED_END: &[u8] = b"+}";
+
+fn is_word_byte(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'_' || c >= 0x80
+}
+
+fn is_space(c: u8) -> bool {
+    c == b' ' || c == b'\t' || c == b'\r'
+}
+
+/// Splits a text into words, runs of whitespace, newlines and single
+/// punctuation characters.
+fn tokenize(text: &[u8]) -> Vec<&[u8]> {
+    let mut tokens = Vec::new();
+    let mut start = 0;
+    while start < text.len() {
+        let c = text[start];
+        let len = if is_word_byte(c) {
+            text[start..]
+                .iter()
+                .take_while(|c| is_word_byte(**c))
+                .count()
+        } else if is_space(c) {
+            text[start..].iter().take_while(|c| is_space(**c)).count()
+        } else {
+            1
+        };
+        tokens.push(&text[start..start + len]);
+        start += len;
+    }
+    tokens
+}
+
+/// Renders the tokens as text that has one line per token, so that the
+/// xdiff core can diff the tokens like lines.
+fn token_lines<'a>(tokens: &[&'a [u8]], ids: &mut HashMap<&'a [u8], usize>) -> Vec<u8> {
+    let mut lines = Vec::new();
+    for token in tokens {
+        let next_id = ids.len();
+        let id = *ids.entry(token).or_insert(next_id);
+        lines.extend_from_slice(format!("{}\n", id).as_bytes());
+    }
+    lines
+}
+
+/// Appends the tokens surrounded by the markers. The markers never span
+/// newlines so that every line of the output is readable on its own.
+fn extend_marked(out: &mut Vec<u8>, start: &[u8], end: &[u8], tokens: &[&[u8]]) {
+    let mut marked = false;
+    for token in tokens {
+        if *token == b"\n" {
+            if marked {
+                out.extend_from_slice(end);
+                marked = false;
+            }
+            out.push(b'\n');
+        } else {
+            if !marked {
+                out.extend_from_slice(start);
+                marked = true;
+            }
+            out.extend_from_slice(token);
+        }
+    }
+    if marked {
+        out.extend_from_slice(end);
+    }
+}
+
+/// Diffs two texts word by word. Returns the new text with the removed words
+/// marked as `[-removed-]` and the added words marked as `{+added+}`.
+pub(crate) fn diff_words(old_text: &[u8], new_text: &[u8]) -> Vec<u8> {
+    let old_tokens = tokenize(old_text);
+    let new_tokens = tokenize(new_text);
+    let mut ids = HashMap::new();
+    let old_lines = token_lines(&old_tokens, &mut ids);
+    let new_lines = token_lines(&new_tokens, &mut ids);
+
+    let mut out = Vec::with_capacity(old_text.len() + new_text.len());
+    let mut old_pos = 0;
+    for hunk in xdiff_hunks(&old_lines, &new_lines, 0) {
+        old_tokens[old_pos..hunk.remove.start]
+            .iter()
+            .for_each(|token| out.extend_from_slice(token));
+        extend_marked(
+            &mut out,
+            REMOVED_START,
+            REMOVED_END,
+            &old_tokens[hunk.remove.clone()],
+        );
+        extend_marked(&mut out, ADDED_START, ADDED_END, &new_tokens[hunk.add]);
+        old_pos = hunk.remove.end;
+    }
+    old_tokens[old_pos..]
+        .iter()
+        .for_each(|token| out.extend_from_slice(token));
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(
+            tokenize(b"let x  = foo(1);\n"),
+            vec![
+                &b"let"[..],
+                b" ",
+                b"x",
+                b"  ",
+                b"=",
+                b" ",
+                b"foo",
+                b"(",
+                b"1",
+                b")",
+                b";",
+                b"\n"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_diff_words() {
+        assert_eq!(
+            String::from_utf8_lossy(&diff_words(b"let x = foo(1);\n", b"let y = foo(1, 2);\n")),
+            "let [-x-]{+y+} = foo(1{+, 2+});\n"
+        );
+        assert_eq!(
+            String::from_utf8_lossy(&diff_words(b"a\nb\n", b"a\nc\nd\n")),
+            "a\n[-b-]{+c+}\n{+d+}\n"
+        );
+    }
+}